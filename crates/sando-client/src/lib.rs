@@ -0,0 +1,773 @@
+/**
+ * K1.0 Sando Client
+ * =================
+ *
+ * Typed Rust client for the Sando JSON API. The types mirror the schemas in
+ * `openapi.json` (checked in next to this crate and generated by the server
+ * from its handlers); the tests below fail if the two drift apart.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: K1.1, K1.2, K1.3, K1.4, K1.5
+ */
+// K1.1 Dependencies
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// K1.2 API Types
+// Mirrors `ConnectionResource`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Connection {
+    pub id: i64,
+    // Only sent to requests with the admin token
+    #[serde(default)]
+    pub connection_string: Option<String>,
+    pub port: i32,
+    pub subdomain: Option<String>,
+    pub url: String,
+    pub created_at: String,
+    pub label: Option<String>,
+    pub description: Option<String>,
+    pub owner: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    pub response_rewrite: ResponseRewrite,
+    pub tier: Option<String>,
+    pub limits: TunnelLimits,
+    pub ip_rules: IpRules,
+}
+
+// Mirrors `TunnelLimits`: requests per second (and bursts) for the tunnel
+// and for each client address, and a cap on requests in flight. `None` is
+// unlimited.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct TunnelLimits {
+    pub rate: Option<f64>,
+    pub burst: Option<u32>,
+    pub client_rate: Option<f64>,
+    pub client_burst: Option<u32>,
+    pub max_in_flight: Option<u32>,
+}
+
+// Mirrors `IpRules`: CIDR ranges (or single addresses) whose clients may
+// reach the tunnel. Deny ranges always win; with any allow range, only
+// clients in one get through.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct IpRules {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+// Mirrors `ResponseRewrite`: how much of the tunnel's responses is rewritten
+// so `localhost` URLs point at the public hostname.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseRewrite {
+    Off,
+    #[default]
+    Headers,
+    Content,
+}
+
+// Mirrors `ConnectionForm`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct NewConnection {
+    // Holesail key: 64 hex characters or an hs:// URL
+    pub connection: String,
+    pub subdomain: Option<String>,
+    // Owner contact; nested subdomains like `svc.alice` need the owner of `alice`.
+    // Only operators assign owners, so the server refuses it here; see `set_owner`.
+    pub owner: Option<String>,
+    // Hashcash stamp `1:bits:date:resource:ext:rand:counter`, for servers that
+    // ask for proof of work before issuing a payment request
+    pub pow: Option<String>,
+}
+
+// Mirrors `ConnectionUpdateForm`. `None` fields are left unchanged; an empty
+// label, description or owner clears it, and `tags`, `limits` and `ip_rules`
+// replace the current ones. `owner`, `tier` and `ip_rules` need the admin
+// token; see `set_owner`, `set_tier` and `set_ip_rules`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ConnectionUpdate {
+    pub connection: Option<String>,
+    pub subdomain: Option<String>,
+    pub label: Option<String>,
+    pub description: Option<String>,
+    pub owner: Option<String>,
+    pub tags: Option<BTreeMap<String, String>>,
+    pub response_rewrite: Option<ResponseRewrite>,
+    pub tier: Option<String>,
+    pub limits: Option<TunnelLimits>,
+    pub ip_rules: Option<IpRules>,
+}
+
+// Mirrors `ConnectionList`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ConnectionList {
+    pub connections: Vec<Connection>,
+    pub next_cursor: Option<String>,
+}
+
+// Mirrors the `ConnectionQuery` parameters of the list endpoint. `None`
+// fields are omitted; see the spec for the accepted status/sort/order values.
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct ListConnections {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    // `key` or `key=value`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+// Mirrors `ConnectionHostname`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Hostname {
+    pub id: i64,
+    pub connection_id: i64,
+    pub hostname: String,
+    pub is_canonical: bool,
+    pub redirect: bool,
+    pub created_at: String,
+}
+
+// Mirrors `HostnameForm`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct NewHostname {
+    pub hostname: String,
+    pub redirect: bool,
+    pub canonical: bool,
+}
+
+// Mirrors `HostnameUpdateForm`. `None` fields are left unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct HostnameUpdate {
+    pub redirect: Option<bool>,
+    pub canonical: Option<bool>,
+}
+
+// Mirrors `HostnameList`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct HostnameList {
+    pub hostnames: Vec<Hostname>,
+}
+
+// Mirrors `VerificationMethod`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationMethod {
+    #[default]
+    Dns,
+    Http,
+}
+
+// Mirrors `CustomDomainResource`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct CustomDomain {
+    pub id: i64,
+    pub connection_id: i64,
+    pub domain: String,
+    pub method: VerificationMethod,
+    pub verified: bool,
+    pub verified_at: Option<String>,
+    pub created_at: String,
+    pub challenge: DomainChallenge,
+}
+
+// Mirrors `DomainChallenge`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct DomainChallenge {
+    pub dns_record_name: String,
+    pub dns_record_value: String,
+    pub http_url: String,
+    pub http_body: String,
+}
+
+// Mirrors `CustomDomainForm`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct NewCustomDomain {
+    pub domain: String,
+    pub method: VerificationMethod,
+}
+
+// Mirrors `DomainList`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct DomainList {
+    pub domains: Vec<CustomDomain>,
+}
+
+// Mirrors `CredentialKind`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialKind {
+    #[default]
+    Basic,
+    Token,
+}
+
+// Mirrors `ConnectionCredential`. `token` is only set in the answer to
+// creating a token; it can't be fetched again.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Credential {
+    pub id: i64,
+    pub connection_id: i64,
+    pub kind: CredentialKind,
+    pub name: String,
+    pub created_at: String,
+    pub token: Option<String>,
+}
+
+// Mirrors `CredentialForm`. Basic users need a password; tokens are
+// generated by the server.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct NewCredential {
+    pub kind: CredentialKind,
+    pub name: String,
+    pub password: Option<String>,
+}
+
+// Mirrors `CredentialList`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct CredentialList {
+    pub credentials: Vec<Credential>,
+}
+
+// Mirrors `SignedLinkForm`: lifetime in seconds and landing path.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct NewSignedLink {
+    pub expires_in: Option<u64>,
+    pub path: Option<String>,
+}
+
+// Mirrors `SignedLink`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct SignedLink {
+    pub url: String,
+    pub expires_at: String,
+}
+
+// Mirrors `ExportDocument`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ExportDocument {
+    pub version: u32,
+    pub exported_at: String,
+    pub connections: Vec<ExportedConnection>,
+}
+
+// Mirrors `ExportedConnection`. `connection_string` and `link_secret` are
+// `sando-enc1:...` when exported with a passphrase and `None` without one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ExportedConnection {
+    pub subdomain: String,
+    pub connection_string: Option<String>,
+    pub created_at: Option<String>,
+    pub label: Option<String>,
+    pub description: Option<String>,
+    pub owner: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    pub response_rewrite: ResponseRewrite,
+    pub tier: Option<String>,
+    pub limits: TunnelLimits,
+    pub ip_rules: IpRules,
+    pub hostnames: Vec<ExportedHostname>,
+    pub domains: Vec<ExportedDomain>,
+    pub credentials: Vec<ExportedCredential>,
+    pub link_secret: Option<String>,
+    pub signed_links: bool,
+}
+
+// Mirrors `ExportedHostname`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ExportedHostname {
+    pub hostname: String,
+    pub redirect: bool,
+}
+
+// Mirrors `ExportedDomain`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ExportedDomain {
+    pub domain: String,
+    pub method: VerificationMethod,
+    pub token: String,
+    pub verified_at: Option<String>,
+}
+
+// Mirrors `ExportedCredential`. `secret_hash` is the stored hash, never the
+// password or token itself.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ExportedCredential {
+    pub kind: CredentialKind,
+    pub name: String,
+    pub secret_hash: String,
+}
+
+// Mirrors `ImportReport`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+}
+
+// Mirrors `AuditEntry`. `actor` and `action` are the snake_case names
+// listed in the spec, e.g. "api" and "batch_delete".
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: String,
+    pub actor: String,
+    pub action: String,
+    pub connection_id: Option<i64>,
+    pub subdomain: Option<String>,
+    pub owner: Option<String>,
+    pub details: serde_json::Map<String, serde_json::Value>,
+}
+
+// Mirrors `AuditLog`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct AuditLog {
+    pub entries: Vec<AuditEntry>,
+    pub next_cursor: Option<i64>,
+}
+
+// Mirrors the `AuditQuery` parameters of the audit endpoint. `since` and
+// `until` take YYYY-MM-DD or YYYY-MM-DD HH:MM:SS (UTC).
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct AuditLogQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+// Mirrors `SubdomainAvailability`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct SubdomainAvailability {
+    pub name: String,
+    pub valid: bool,
+    pub available: bool,
+    pub errors: Vec<String>,
+    pub price: Price,
+    pub suggestions: Vec<String>,
+}
+
+// Mirrors `Price`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Price {
+    pub amount: u64,
+    pub unit: String,
+}
+
+// Mirrors `ErrorBody` / `ErrorDetail`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ErrorBody {
+    pub error: ErrorDetail,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ErrorDetail {
+    pub code: String,
+    pub message: String,
+}
+
+// Mirrors `PaymentRequiredBody`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct PaymentRequiredBody {
+    pub error: ErrorDetail,
+    pub payment_request: String,
+}
+
+// K1.3 Errors
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    // Creating a connection needs a Cashu token paying this NUT-18 request
+    #[error("payment required")]
+    PaymentRequired { payment_request: String },
+    #[error("{status} {code}: {message}")]
+    Api {
+        status: StatusCode,
+        code: String,
+        message: String,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// K1.4 Client
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+impl Client {
+    // `base_url` is the Sando root, e.g. "https://sando.blue"
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    pub fn with_http_client(base_url: impl Into<String>, http: reqwest::Client) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http,
+        }
+    }
+
+    // Returns one page; pass `next_cursor` back as `cursor` for the next one.
+    pub async fn list_connections(&self, query: &ListConnections) -> Result<ConnectionList> {
+        self.send(self.request(Method::GET, "/api/v1/connections").query(query)).await
+    }
+
+    pub async fn get_connection(&self, id: i64) -> Result<Connection> {
+        self.send(self.request(Method::GET, &format!("/api/v1/connections/{}", id))).await
+    }
+
+    // Without a token the server answers with `Error::PaymentRequired`; pay the
+    // request and call again with the resulting Cashu token.
+    pub async fn create_connection(&self, connection: &NewConnection, cashu_token: Option<&str>) -> Result<Connection> {
+        let mut request = self.request(Method::POST, "/api/v1/connections").json(connection);
+        if let Some(token) = cashu_token {
+            request = request.header("X-Cashu", token);
+        }
+        self.send(request).await
+    }
+
+    // `key` is the connection's current holesail key, proving it is yours
+    pub async fn update_connection(&self, key: &str, id: i64, update: &ConnectionUpdate) -> Result<Connection> {
+        self.send(self.request(Method::PATCH, &format!("/api/v1/connections/{}", id)).header("X-Sando-Key", key).json(update))
+            .await
+    }
+
+    pub async fn delete_connection(&self, id: i64) -> Result<()> {
+        let response = self.request(Method::DELETE, &format!("/api/v1/connections/{}", id)).send().await?;
+        check_status(response).await.map(|_| ())
+    }
+
+    // Canonical hostname first, then aliases
+    pub async fn list_hostnames(&self, id: i64) -> Result<Vec<Hostname>> {
+        let list: HostnameList = self.send(self.request(Method::GET, &format!("/api/v1/connections/{}/hostnames", id))).await?;
+        Ok(list.hostnames)
+    }
+
+    // Hostname changes take the connection's current key, like `update_connection`
+    pub async fn add_hostname(&self, key: &str, id: i64, hostname: &NewHostname) -> Result<Hostname> {
+        let path = format!("/api/v1/connections/{}/hostnames", id);
+        self.send(self.request(Method::POST, &path).header("X-Sando-Key", key).json(hostname)).await
+    }
+
+    pub async fn update_hostname(&self, key: &str, id: i64, hostname_id: i64, update: &HostnameUpdate) -> Result<Hostname> {
+        let path = format!("/api/v1/connections/{}/hostnames/{}", id, hostname_id);
+        self.send(self.request(Method::PATCH, &path).header("X-Sando-Key", key).json(update)).await
+    }
+
+    pub async fn delete_hostname(&self, key: &str, id: i64, hostname_id: i64) -> Result<()> {
+        let path = format!("/api/v1/connections/{}/hostnames/{}", id, hostname_id);
+        let response = self.request(Method::DELETE, &path).header("X-Sando-Key", key).send().await?;
+        check_status(response).await.map(|_| ())
+    }
+
+    pub async fn list_domains(&self, id: i64) -> Result<Vec<CustomDomain>> {
+        let list: DomainList = self.send(self.request(Method::GET, &format!("/api/v1/connections/{}/domains", id))).await?;
+        Ok(list.domains)
+    }
+
+    // The returned challenge must be published before calling `verify_domain`
+    pub async fn add_domain(&self, id: i64, domain: &NewCustomDomain) -> Result<CustomDomain> {
+        self.send(self.request(Method::POST, &format!("/api/v1/connections/{}/domains", id)).json(domain)).await
+    }
+
+    pub async fn verify_domain(&self, id: i64, domain_id: i64) -> Result<CustomDomain> {
+        let path = format!("/api/v1/connections/{}/domains/{}/verify", id, domain_id);
+        self.send(self.request(Method::POST, &path)).await
+    }
+
+    pub async fn delete_domain(&self, id: i64, domain_id: i64) -> Result<()> {
+        let path = format!("/api/v1/connections/{}/domains/{}", id, domain_id);
+        let response = self.request(Method::DELETE, &path).send().await?;
+        check_status(response).await.map(|_| ())
+    }
+
+    // Tunnel access needs the admin token, like the operator endpoints below.
+    // Basic users and tokens, without their secrets:
+    pub async fn list_credentials(&self, admin_token: &str, id: i64) -> Result<Vec<Credential>> {
+        let path = format!("/api/v1/connections/{}/credentials", id);
+        let list: CredentialList = self.send(self.request(Method::GET, &path).bearer_auth(admin_token)).await?;
+        Ok(list.credentials)
+    }
+
+    // A new token is only returned here, in `Credential::token`
+    pub async fn add_credential(&self, admin_token: &str, id: i64, credential: &NewCredential) -> Result<Credential> {
+        let path = format!("/api/v1/connections/{}/credentials", id);
+        self.send(self.request(Method::POST, &path).bearer_auth(admin_token).json(credential)).await
+    }
+
+    pub async fn delete_credential(&self, admin_token: &str, id: i64, credential_id: i64) -> Result<()> {
+        let path = format!("/api/v1/connections/{}/credentials/{}", id, credential_id);
+        let response = self.request(Method::DELETE, &path).bearer_auth(admin_token).send().await?;
+        check_status(response).await.map(|_| ())
+    }
+
+    pub async fn create_link(&self, admin_token: &str, id: i64, link: &NewSignedLink) -> Result<SignedLink> {
+        let path = format!("/api/v1/connections/{}/links", id);
+        self.send(self.request(Method::POST, &path).bearer_auth(admin_token).json(link)).await
+    }
+
+    // Invalidates every signed link created so far
+    pub async fn revoke_links(&self, admin_token: &str, id: i64) -> Result<()> {
+        let path = format!("/api/v1/connections/{}/links", id);
+        let response = self.request(Method::DELETE, &path).bearer_auth(admin_token).send().await?;
+        check_status(response).await.map(|_| ())
+    }
+
+    // Operator endpoints; `admin_token` is the server's SANDO_ADMIN_TOKEN.
+    // Keys are only exported, encrypted, when a passphrase is given.
+    pub async fn export_connections(
+        &self,
+        admin_token: &str,
+        owner: Option<&str>,
+        passphrase: Option<&str>,
+    ) -> Result<ExportDocument> {
+        let mut request = self.request(Method::GET, "/api/v1/export").bearer_auth(admin_token);
+        if let Some(owner) = owner {
+            request = request.query(&[("owner", owner)]);
+        }
+        if let Some(passphrase) = passphrase {
+            request = request.header("X-Sando-Passphrase", passphrase);
+        }
+        self.send(request).await
+    }
+
+    // Sets the owner contact, which decides the namespaces the connection
+    // may use; an empty owner clears it
+    pub async fn set_owner(&self, admin_token: &str, id: i64, owner: &str) -> Result<Connection> {
+        let update = ConnectionUpdate { owner: Some(owner.to_string()), ..Default::default() };
+        self.send(self.request(Method::PATCH, &format!("/api/v1/connections/{}", id)).bearer_auth(admin_token).json(&update))
+            .await
+    }
+
+    // Assigns a limits tier configured on the server; an empty tier goes
+    // back to the default limits
+    pub async fn set_tier(&self, admin_token: &str, id: i64, tier: &str) -> Result<Connection> {
+        let update = ConnectionUpdate { tier: Some(tier.to_string()), ..Default::default() };
+        self.send(self.request(Method::PATCH, &format!("/api/v1/connections/{}", id)).bearer_auth(admin_token).json(&update))
+            .await
+    }
+
+    // Replaces a connection's allow and deny lists
+    pub async fn set_ip_rules(&self, admin_token: &str, id: i64, ip_rules: &IpRules) -> Result<Connection> {
+        let update = ConnectionUpdate { ip_rules: Some(ip_rules.clone()), ..Default::default() };
+        self.send(self.request(Method::PATCH, &format!("/api/v1/connections/{}", id)).bearer_auth(admin_token).json(&update))
+            .await
+    }
+
+    pub async fn import_connections(
+        &self,
+        admin_token: &str,
+        document: &ExportDocument,
+        passphrase: Option<&str>,
+        dry_run: bool,
+    ) -> Result<ImportReport> {
+        let mut request = self
+            .request(Method::POST, "/api/v1/import")
+            .bearer_auth(admin_token)
+            .query(&[("dry_run", dry_run)])
+            .json(document);
+        if let Some(passphrase) = passphrase {
+            request = request.header("X-Sando-Passphrase", passphrase);
+        }
+        self.send(request).await
+    }
+
+    // One page of the audit log, newest first; pass `next_cursor` back as
+    // `cursor` for older entries.
+    pub async fn audit_log(&self, admin_token: &str, query: &AuditLogQuery) -> Result<AuditLog> {
+        self.send(self.request(Method::GET, "/api/v1/audit").bearer_auth(admin_token).query(query)).await
+    }
+
+    pub async fn check_subdomain(&self, name: &str) -> Result<SubdomainAvailability> {
+        // Subdomain names are restricted to [a-z0-9-], so no escaping is needed
+        self.send(self.request(Method::GET, &format!("/api/subdomains/{}", name))).await
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http.request(method, format!("{}{}", self.base_url, path))
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let response = check_status(request.send().await?).await?;
+        Ok(response.json().await?)
+    }
+}
+
+// Turns non-2xx answers into `Error`, using the API's error body when present.
+async fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await?;
+    Err(error_from_body(status, &body))
+}
+
+fn error_from_body(status: StatusCode, body: &str) -> Error {
+    if status == StatusCode::PAYMENT_REQUIRED {
+        if let Ok(body) = serde_json::from_str::<PaymentRequiredBody>(body) {
+            return Error::PaymentRequired { payment_request: body.payment_request };
+        }
+    }
+
+    match serde_json::from_str::<ErrorBody>(body) {
+        Ok(body) => Error::Api { status, code: body.error.code, message: body.error.message },
+        Err(_) => Error::Api { status, code: "unknown".to_string(), message: body.to_string() },
+    }
+}
+
+// K1.5 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::collections::BTreeSet;
+
+    const SPEC: &str = include_str!("../openapi.json");
+
+    fn field_names<T: Serialize>(value: &T) -> BTreeSet<String> {
+        match serde_json::to_value(value).unwrap() {
+            Value::Object(map) => map.keys().cloned().collect(),
+            other => panic!("expected an object, got {}", other),
+        }
+    }
+
+    fn spec_properties(schema: &str) -> BTreeSet<String> {
+        let spec: Value = serde_json::from_str(SPEC).unwrap();
+        spec["components"]["schemas"][schema]["properties"]
+            .as_object()
+            .unwrap_or_else(|| panic!("schema {} missing from openapi.json", schema))
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn test_types_match_openapi_spec() {
+        assert_eq!(field_names(&Connection::default()), spec_properties("ConnectionResource"));
+        assert_eq!(field_names(&NewConnection::default()), spec_properties("ConnectionForm"));
+        assert_eq!(field_names(&ConnectionUpdate::default()), spec_properties("ConnectionUpdateForm"));
+        assert_eq!(field_names(&ConnectionList::default()), spec_properties("ConnectionList"));
+        assert_eq!(field_names(&IpRules::default()), spec_properties("IpRules"));
+        assert_eq!(field_names(&Hostname::default()), spec_properties("ConnectionHostname"));
+        assert_eq!(field_names(&NewHostname::default()), spec_properties("HostnameForm"));
+        assert_eq!(field_names(&HostnameUpdate::default()), spec_properties("HostnameUpdateForm"));
+        assert_eq!(field_names(&HostnameList::default()), spec_properties("HostnameList"));
+        assert_eq!(field_names(&CustomDomain::default()), spec_properties("CustomDomainResource"));
+        assert_eq!(field_names(&DomainChallenge::default()), spec_properties("DomainChallenge"));
+        assert_eq!(field_names(&NewCustomDomain::default()), spec_properties("CustomDomainForm"));
+        assert_eq!(field_names(&DomainList::default()), spec_properties("DomainList"));
+        assert_eq!(field_names(&Credential::default()), spec_properties("ConnectionCredential"));
+        assert_eq!(field_names(&NewCredential::default()), spec_properties("CredentialForm"));
+        assert_eq!(field_names(&CredentialList::default()), spec_properties("CredentialList"));
+        assert_eq!(field_names(&NewSignedLink::default()), spec_properties("SignedLinkForm"));
+        assert_eq!(field_names(&SignedLink::default()), spec_properties("SignedLink"));
+        assert_eq!(field_names(&ExportDocument::default()), spec_properties("ExportDocument"));
+        assert_eq!(field_names(&ExportedConnection::default()), spec_properties("ExportedConnection"));
+        assert_eq!(field_names(&ExportedHostname::default()), spec_properties("ExportedHostname"));
+        assert_eq!(field_names(&ExportedDomain::default()), spec_properties("ExportedDomain"));
+        assert_eq!(field_names(&ExportedCredential::default()), spec_properties("ExportedCredential"));
+        assert_eq!(field_names(&ImportReport::default()), spec_properties("ImportReport"));
+        assert_eq!(field_names(&AuditEntry::default()), spec_properties("AuditEntry"));
+        assert_eq!(field_names(&AuditLog::default()), spec_properties("AuditLog"));
+        assert_eq!(field_names(&SubdomainAvailability::default()), spec_properties("SubdomainAvailability"));
+        assert_eq!(field_names(&Price::default()), spec_properties("Price"));
+        assert_eq!(field_names(&ErrorBody::default()), spec_properties("ErrorBody"));
+        assert_eq!(field_names(&ErrorDetail::default()), spec_properties("ErrorDetail"));
+        assert_eq!(field_names(&PaymentRequiredBody::default()), spec_properties("PaymentRequiredBody"));
+    }
+
+    #[test]
+    fn test_list_parameters_match_openapi_spec() {
+        let spec: Value = serde_json::from_str(SPEC).unwrap();
+        let documented: BTreeSet<String> = spec["paths"]["/api/v1/connections"]["get"]["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|parameter| parameter["name"].as_str().unwrap().to_string())
+            .collect();
+        let query = ListConnections {
+            q: Some(String::new()),
+            status: Some(String::new()),
+            owner: Some(String::new()),
+            tag: Some(String::new()),
+            sort: Some(String::new()),
+            order: Some(String::new()),
+            cursor: Some(String::new()),
+            limit: Some(1),
+        };
+        assert_eq!(field_names(&query), documented);
+    }
+
+    #[test]
+    fn test_audit_parameters_match_openapi_spec() {
+        let spec: Value = serde_json::from_str(SPEC).unwrap();
+        let documented: BTreeSet<String> = spec["paths"]["/api/v1/audit"]["get"]["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|parameter| parameter["name"].as_str().unwrap().to_string())
+            .filter(|name| name != "format")
+            .collect();
+        let query = AuditLogQuery {
+            action: Some(String::new()),
+            actor: Some(String::new()),
+            connection_id: Some(1),
+            q: Some(String::new()),
+            since: Some(String::new()),
+            until: Some(String::new()),
+            cursor: Some(1),
+            limit: Some(1),
+        };
+        assert_eq!(field_names(&query), documented);
+    }
+
+    #[test]
+    fn test_error_from_body() {
+        let error = error_from_body(
+            StatusCode::NOT_FOUND,
+            r#"{"error": {"code": "not_found", "message": "Connection 7 not found"}}"#,
+        );
+        assert!(matches!(error, Error::Api { status: StatusCode::NOT_FOUND, ref code, .. } if code == "not_found"));
+
+        let error = error_from_body(
+            StatusCode::PAYMENT_REQUIRED,
+            r#"{"error": {"code": "payment_required", "message": "pay"}, "payment_request": "creqA..."}"#,
+        );
+        assert!(matches!(error, Error::PaymentRequired { ref payment_request } if payment_request == "creqA..."));
+
+        let error = error_from_body(StatusCode::BAD_GATEWAY, "Bad Gateway");
+        assert!(matches!(error, Error::Api { ref code, .. } if code == "unknown"));
+    }
+}
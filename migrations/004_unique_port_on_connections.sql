@@ -0,0 +1,45 @@
+-- Sando Database Migration: 004
+-- ===================================
+--
+-- Agent Instructions:
+-- This migration makes local ports unique per connection so two tunnels can never share one.
+-- The tag for this migration is D4.1.
+--
+-- D4.1: Enforce Unique Ports on Connections Table
+
+-- Rows that share a port (all but the oldest) are moved, in id order, onto
+-- the lowest free ports of the default allocator range 3001-8000, where
+-- ports were picked at random before. If the range has no room left the
+-- CHECK below aborts the migration instead of writing an invalid port.
+CREATE TEMP TABLE port_moves (
+    id INTEGER PRIMARY KEY,
+    port INTEGER,
+    CONSTRAINT no_free_port_in_3001_8000_for_duplicate CHECK (port IS NOT NULL)
+);
+
+WITH RECURSIVE candidates(port) AS (
+    SELECT 3001
+    UNION ALL
+    SELECT port + 1 FROM candidates WHERE port < 8000
+),
+free AS (
+    SELECT port, ROW_NUMBER() OVER (ORDER BY port) AS n
+    FROM candidates
+    WHERE port NOT IN (SELECT port FROM connections)
+),
+duplicates AS (
+    SELECT id, ROW_NUMBER() OVER (ORDER BY id) AS n
+    FROM connections
+    WHERE id NOT IN (SELECT MIN(id) FROM connections GROUP BY port)
+)
+INSERT INTO port_moves (id, port)
+SELECT duplicates.id, free.port
+FROM duplicates LEFT JOIN free ON free.n = duplicates.n;
+
+UPDATE connections
+SET port = (SELECT port FROM port_moves WHERE port_moves.id = connections.id)
+WHERE id IN (SELECT id FROM port_moves);
+
+DROP TABLE port_moves;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_connections_port ON connections (port);
@@ -0,0 +1,17 @@
+-- Sando Database Migration: 005
+-- ===================================
+--
+-- Agent Instructions:
+-- This migration adds descriptive metadata to connections so each tunnel can be traced to a team and service.
+-- The tag for this migration is D5.1.
+--
+-- D5.1: Add Label, Description, Owner and Tags Columns to Connections Table
+
+ALTER TABLE connections ADD COLUMN label TEXT;
+ALTER TABLE connections ADD COLUMN description TEXT;
+ALTER TABLE connections ADD COLUMN owner TEXT; -- Contact for the team or person running the service
+
+-- Free-form key/value tags stored as a JSON object
+ALTER TABLE connections ADD COLUMN tags TEXT NOT NULL DEFAULT '{}';
+
+CREATE INDEX IF NOT EXISTS idx_connections_owner ON connections (owner COLLATE NOCASE);
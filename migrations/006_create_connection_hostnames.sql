@@ -0,0 +1,27 @@
+-- Sando Database Migration: 006
+-- ===================================
+--
+-- Agent Instructions:
+-- This migration lets one connection answer on several subdomains. Each connection has exactly one
+-- canonical hostname (mirrored in connections.subdomain); the others are aliases that either serve
+-- the tunnel directly or redirect to the canonical one.
+-- The tag for this migration is D6.1.
+--
+-- D6.1: Create Connection Hostnames Table
+
+CREATE TABLE IF NOT EXISTS connection_hostnames (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    connection_id INTEGER NOT NULL REFERENCES connections (id) ON DELETE CASCADE,
+    hostname TEXT NOT NULL COLLATE NOCASE UNIQUE,
+    is_canonical BOOLEAN NOT NULL DEFAULT 0,
+    redirect BOOLEAN NOT NULL DEFAULT 0, -- Aliases only: answer with a redirect to the canonical hostname
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_connection_hostnames_canonical
+    ON connection_hostnames (connection_id) WHERE is_canonical;
+
+-- Every existing subdomain becomes its connection's canonical hostname. Older rows could share a
+-- subdomain; the first one keeps it, as it did for routing.
+INSERT OR IGNORE INTO connection_hostnames (connection_id, hostname, is_canonical)
+SELECT id, subdomain, 1 FROM connections WHERE subdomain IS NOT NULL ORDER BY id;
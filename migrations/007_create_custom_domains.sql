@@ -0,0 +1,24 @@
+-- Sando Database Migration: 007
+-- ===================================
+--
+-- Agent Instructions:
+-- This migration lets owners attach their own domains to a connection. A domain only routes to the
+-- tunnel once ownership has been proven with a DNS TXT record or an HTTP challenge file.
+-- The tag for this migration is D7.1.
+--
+-- D7.1: Create Custom Domains Table
+
+CREATE TABLE IF NOT EXISTS custom_domains (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    connection_id INTEGER NOT NULL REFERENCES connections (id) ON DELETE CASCADE,
+    domain TEXT NOT NULL COLLATE NOCASE,
+    method TEXT NOT NULL DEFAULT 'dns', -- 'dns' (TXT record) or 'http' (challenge file)
+    token TEXT NOT NULL,
+    verified_at DATETIME, -- NULL until ownership is proven
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (connection_id, domain)
+);
+
+-- Several connections may claim a domain, but only one can prove ownership
+CREATE UNIQUE INDEX IF NOT EXISTS idx_custom_domains_verified
+    ON custom_domains (domain) WHERE verified_at IS NOT NULL;
@@ -0,0 +1,24 @@
+-- Sando Database Migration: 008
+-- ===================================
+--
+-- Agent Instructions:
+-- This migration records who did what to which connection, and when: creates, updates, deletes,
+-- accepted payments and operator actions such as imports. Rows outlive the connections they
+-- describe, so the subdomain and owner are copied in rather than referenced.
+-- The tag for this migration is D8.1.
+--
+-- D8.1: Create Audit Log Table
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    actor TEXT NOT NULL, -- 'web', 'api', 'admin', 'cli' or 'system'
+    action TEXT NOT NULL, -- e.g. 'create', 'update', 'batch_delete', 'payment_accepted'
+    connection_id INTEGER, -- no foreign key: entries stay after the connection is deleted
+    subdomain TEXT,
+    owner TEXT COLLATE NOCASE,
+    details TEXT NOT NULL DEFAULT '{}' -- JSON object describing the change
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_action ON audit_log (action, id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_connection ON audit_log (connection_id, id);
@@ -0,0 +1,11 @@
+-- Sando Database Migration: 009
+-- ===================================
+--
+-- Agent Instructions:
+-- This migration lets each connection choose how its responses are rewritten for its public hostname:
+-- 'off', 'headers' (Location, Content-Location, Refresh and Set-Cookie) or 'content' (also HTML, CSS and JS bodies).
+-- The tag for this migration is D9.1.
+--
+-- D9.1: Add Response Rewrite Column to Connections Table
+
+ALTER TABLE connections ADD COLUMN response_rewrite TEXT NOT NULL DEFAULT 'headers';
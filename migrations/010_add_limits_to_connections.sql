@@ -0,0 +1,14 @@
+-- Sando Database Migration: 010
+-- ===================================
+--
+-- Agent Instructions:
+-- This migration adds request limits to connections: an operator-assigned tier naming a set of limits
+-- from the server configuration, and the owner's own limits, which can only tighten the tier's.
+-- The tag for this migration is D10.1.
+--
+-- D10.1: Add Tier and Limits Columns to Connections Table
+
+ALTER TABLE connections ADD COLUMN tier TEXT;
+
+-- Rate, burst and in-flight limits stored as a JSON object
+ALTER TABLE connections ADD COLUMN limits TEXT NOT NULL DEFAULT '{}';
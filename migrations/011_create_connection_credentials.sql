@@ -0,0 +1,25 @@
+-- Sando Database Migration: 011
+-- ===================================
+--
+-- Agent Instructions:
+-- This migration lets owners protect a tunnel. Credentials are HTTP Basic users or bearer tokens and
+-- are only ever stored hashed. Signed links are checked against a per-connection secret instead, so
+-- clearing the secret revokes every link handed out for the connection.
+-- The tag for this migration is D11.1.
+--
+-- D11.1: Create Connection Credentials Table and Add Link Secret Column to Connections Table
+
+CREATE TABLE IF NOT EXISTS connection_credentials (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    connection_id INTEGER NOT NULL REFERENCES connections (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL, -- 'basic' (user name and password) or 'token' (bearer token)
+    name TEXT NOT NULL, -- The Basic user name, or a label for the token
+    secret_hash TEXT NOT NULL, -- PBKDF2 for passwords, SHA-256 for tokens
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (connection_id, kind, name)
+);
+
+CREATE INDEX IF NOT EXISTS idx_connection_credentials_secret ON connection_credentials (secret_hash);
+
+-- Key for signed links; NULL when the connection has no links
+ALTER TABLE connections ADD COLUMN link_secret TEXT;
@@ -0,0 +1,12 @@
+-- Sando Database Migration: 012
+-- ===================================
+--
+-- Agent Instructions:
+-- This migration adds per-connection IP rules: CIDR ranges whose clients may (allow) or may not
+-- (deny) reach the tunnel. The server's global blocklist is configuration, not data, so it isn't stored.
+-- The tag for this migration is D12.1.
+--
+-- D12.1: Add IP Rules Column to Connections Table
+
+-- Allow and deny lists stored as a JSON object
+ALTER TABLE connections ADD COLUMN ip_rules TEXT NOT NULL DEFAULT '{}';
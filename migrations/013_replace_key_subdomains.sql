@@ -0,0 +1,19 @@
+-- Sando Database Migration: 013
+-- ===================================
+--
+-- Agent Instructions:
+-- Connections created without a custom subdomain used to get their holesail key as the subdomain.
+-- A 64-character key is longer than a DNS label may be, so those hostnames never resolved, and it
+-- published the key. This migration gives them the random tunnel-xxxxxxxx names new connections get.
+-- The tag for this migration is D13.1.
+--
+-- D13.1: Replace Key Subdomains
+
+UPDATE connections SET subdomain = 'tunnel-' || lower(hex(randomblob(4)))
+WHERE length(subdomain) = 64 AND instr(lower(connection_string), lower(subdomain)) > 0;
+
+-- Keep the canonical hostname in step with the subdomain it mirrors
+UPDATE connection_hostnames
+SET hostname = (SELECT subdomain FROM connections WHERE connections.id = connection_hostnames.connection_id)
+WHERE is_canonical AND length(hostname) = 64
+  AND hostname <> (SELECT subdomain FROM connections WHERE connections.id = connection_hostnames.connection_id);
@@ -0,0 +1,190 @@
+/**
+ * C6.0 Audit Log Component
+ * ========================
+ *
+ * Renders the operator's view of the audit log: filters, one page of
+ * entries (newest first) and links to export the filtered log.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: C6.1, C6.2, C6.3
+ */
+// C6.1 Dependencies
+use crate::models::{AuditAction, AuditActor, AuditEntry, AuditQuery};
+use maud::{html, Markup, DOCTYPE};
+
+// C6.2 Audit Log Function
+// Generates the Maud Markup for /admin/audit.
+pub fn audit_log(entries: &[AuditEntry], query: &AuditQuery, next_cursor: Option<i64>) -> Markup {
+    let filtered = query.action.is_some()
+        || query.actor.is_some()
+        || query.connection_id.is_some()
+        || query.q.is_some()
+        || query.since.is_some()
+        || query.until.is_some()
+        || query.cursor.is_some();
+
+    html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                title { "Sando.Blue - Ship's Log" }
+                link rel="preconnect" href="https://fonts.googleapis.com";
+                link rel="preconnect" href="https://fonts.gstatic.com" crossorigin;
+                link href="https://fonts.googleapis.com/css2?family=Inter:wght@400;600;700&display=swap" rel="stylesheet";
+                link rel="stylesheet" href="/static/styles.css";
+                style {
+                    "
+                    .audit-filter {
+                        display: flex;
+                        flex-wrap: wrap;
+                        gap: 0.5rem;
+                        align-items: center;
+                        margin-bottom: 1rem;
+                    }
+
+                    .audit-filter input[type='search'] {
+                        flex: 1 1 12rem;
+                    }
+
+                    .audit-table {
+                        width: 100%;
+                        border-collapse: collapse;
+                        font-size: 0.9rem;
+                    }
+
+                    .audit-table th, .audit-table td {
+                        text-align: left;
+                        padding: 0.4rem 0.5rem;
+                        border-bottom: 1px solid rgba(59, 130, 246, 0.3);
+                        vertical-align: top;
+                    }
+
+                    .audit-details {
+                        font-family: monospace;
+                        font-size: 0.8rem;
+                        color: #93C5FD;
+                        word-break: break-all;
+                    }
+                    "
+                }
+            }
+            body {
+                div class="container container-wide" {
+                    h1 { "📜 Ship's Log" }
+                    form class="audit-filter" method="get" action="/admin/audit" {
+                        input type="search" name="q" placeholder="Search subdomain, owner or details" value=[query.q.as_deref()];
+                        select name="action" title="Action" {
+                            option value="" { "any action" }
+                            @for action in AuditAction::ALL {
+                                option value=(action.as_str()) selected[query.action == Some(action)] { (action.as_str().replace('_', " ")) }
+                            }
+                        }
+                        select name="actor" title="Actor" {
+                            option value="" { "any actor" }
+                            @for actor in AuditActor::ALL {
+                                option value=(actor.as_str()) selected[query.actor == Some(actor)] { (actor.as_str()) }
+                            }
+                        }
+                        input type="number" name="connection_id" placeholder="Connection id" min="1" value=[query.connection_id];
+                        input type="text" name="since" placeholder="Since (YYYY-MM-DD)" value=[query.since.as_deref()];
+                        input type="text" name="until" placeholder="Until (YYYY-MM-DD)" value=[query.until.as_deref()];
+                        button type="submit" class="btn btn-small" { "🔭 Search" }
+                        @if filtered {
+                            a href="/admin/audit" class="btn btn-small btn-secondary" { "Clear" }
+                        }
+                    }
+                    @if entries.is_empty() {
+                        div class="empty-state" {
+                            p { "Nothing logged yet." }
+                        }
+                    } @else {
+                        table class="audit-table" {
+                            thead {
+                                tr {
+                                    th { "Time (UTC)" }
+                                    th { "Actor" }
+                                    th { "Action" }
+                                    th { "Connection" }
+                                    th { "Owner" }
+                                    th { "Details" }
+                                }
+                            }
+                            tbody {
+                                @for entry in entries {
+                                    tr {
+                                        td { (entry.created_at) }
+                                        td { (entry.actor.as_str()) }
+                                        td { (entry.action.as_str().replace('_', " ")) }
+                                        td {
+                                            @if let Some(connection_id) = entry.connection_id {
+                                                a href={ "/admin/audit?connection_id=" (connection_id) } { "#" (connection_id) }
+                                                " "
+                                            }
+                                            (entry.subdomain.as_deref().unwrap_or_default())
+                                        }
+                                        td { (entry.owner.as_deref().unwrap_or_default()) }
+                                        td class="audit-details" {
+                                            @if !entry.details.0.is_empty() {
+                                                (serde_json::to_string(&entry.details).unwrap_or_default())
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    div class="actions mt-4" {
+                        @if let Some(cursor) = next_cursor {
+                            a href={ "/admin/audit?" (filter_query(query, Some(cursor))) } class="btn btn-secondary" {
+                                span { "➡️" }
+                                "Older entries"
+                            }
+                        }
+                        a href={ "/api/v1/audit?format=csv&" (filter_query(query, None)) } class="btn btn-secondary" {
+                            span { "📥" }
+                            "Export CSV"
+                        }
+                        a href={ "/api/v1/audit?" (filter_query(query, None)) } class="btn btn-secondary" {
+                            span { "🧾" }
+                            "JSON"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// C6.3 Filter Links
+// Query string with the current filters and, for the next page, the cursor.
+// Exports drop the cursor so they cover the whole filtered log.
+fn filter_query(query: &AuditQuery, cursor: Option<i64>) -> String {
+    let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+    if let Some(q) = &query.q {
+        serializer.append_pair("q", q);
+    }
+    if let Some(action) = query.action {
+        serializer.append_pair("action", action.as_str());
+    }
+    if let Some(actor) = query.actor {
+        serializer.append_pair("actor", actor.as_str());
+    }
+    if let Some(connection_id) = query.connection_id {
+        serializer.append_pair("connection_id", &connection_id.to_string());
+    }
+    if let Some(since) = &query.since {
+        serializer.append_pair("since", since);
+    }
+    if let Some(until) = &query.until {
+        serializer.append_pair("until", until);
+    }
+    if let Some(limit) = query.limit {
+        serializer.append_pair("limit", &limit.to_string());
+    }
+    if let Some(cursor) = cursor {
+        serializer.append_pair("cursor", &cursor.to_string());
+    }
+    serializer.finish()
+}
@@ -2,18 +2,33 @@
  * C3.0 Connections List Component
  * ===============================
  *
- * Renders a list of all connections stored in the database.
+ * Renders one page of connections with search, filter and sort controls.
  * This file is tagged for machine-readability.
  *
- * Tags: C3.1, C3.2
+ * Tags: C3.1, C3.2, C3.3
  */
 // C3.1 Dependencies
+use crate::models::{ConnectionQuery, ConnectionSort, SortOrder, TunnelStatus};
 use crate::Connection;
 use maud::{html, Markup, DOCTYPE};
 
 // C3.2 Connections List Function
 // Generates the Maud Markup for the connections list page.
-pub fn connections_list(connections: &[Connection], host: &str, port: u16) -> Markup {
+pub fn connections_list(
+    connections: &[Connection],
+    host: &str,
+    port: u16,
+    query: &ConnectionQuery,
+    next_cursor: Option<&str>,
+) -> Markup {
+    let filtered = query.q.is_some()
+        || query.status.is_some()
+        || query.owner.is_some()
+        || query.tag.is_some()
+        || query.cursor.is_some();
+    let sort = query.sort.unwrap_or_default();
+    let order = query.order.unwrap_or_default();
+
     html! {
         (DOCTYPE)
         html lang="en" {
@@ -51,13 +66,74 @@ pub fn connections_list(connections: &[Connection], host: &str, port: u16) -> Ma
                     .connection-header input[type='checkbox'] {
                         margin: 0 !important;
                     }
+
+                    .connections-filter {
+                        display: flex;
+                        flex-wrap: wrap;
+                        gap: 0.5rem;
+                        align-items: center;
+                        margin-bottom: 1rem;
+                    }
+
+                    .connections-filter input[type='search'] {
+                        flex: 1 1 12rem;
+                    }
+
+                    .connection-meta {
+                        color: #93C5FD;
+                        font-size: 0.85rem;
+                        margin-top: 0.25rem;
+                    }
+
+                    .connection-tag {
+                        display: inline-block;
+                        padding: 0 0.4rem;
+                        margin-right: 0.25rem;
+                        border: 1px solid #3B82F6;
+                        border-radius: 0.5rem;
+                        text-decoration: none;
+                        color: inherit;
+                    }
                     "
                 }
             }
             body {
                 div class="container container-wide" {
                     h1 { "🌊 Ocean Harbor" }
-                    @if connections.is_empty() {
+                    @if !connections.is_empty() || filtered {
+                        form class="connections-filter" method="get" action="/connections" {
+                            input type="search" name="q" placeholder="Search subdomain, key, label or owner" value=[query.q.as_deref()];
+                            input type="text" name="owner" placeholder="Owner" value=[query.owner.as_deref()];
+                            input type="text" name="tag" placeholder="Tag (key or key=value)" value=[query.tag.as_deref()];
+                            select name="status" title="Tunnel status" {
+                                option value="" { "any status" }
+                                @for status in TunnelStatus::ALL {
+                                    option value=(status.as_str()) selected[query.status == Some(status)] { (status.as_str()) }
+                                }
+                            }
+                            select name="sort" title="Sort by" {
+                                @for option_sort in ConnectionSort::ALL {
+                                    option value=(option_sort.as_str()) selected[sort == option_sort] { (option_sort.as_str().replace('_', " ")) }
+                                }
+                            }
+                            select name="order" title="Order" {
+                                option value="desc" selected[order == SortOrder::Desc] { "descending" }
+                                option value="asc" selected[order == SortOrder::Asc] { "ascending" }
+                            }
+                            @if let Some(limit) = query.limit {
+                                input type="hidden" name="limit" value=(limit);
+                            }
+                            button type="submit" class="btn btn-small" { "🔭 Search" }
+                            @if filtered {
+                                a href="/connections" class="btn btn-small btn-secondary" { "Clear" }
+                            }
+                        }
+                    }
+                    @if connections.is_empty() && filtered {
+                        div class="empty-state" {
+                            p { "No vessels match these filters." }
+                        }
+                    } @else if connections.is_empty() {
                         div class="empty-state" {
                             p { "No vessels in harbor. Launch your first tunnel to set sail." }
                             a href="/" class="btn" { 
@@ -93,18 +169,38 @@ pub fn connections_list(connections: &[Connection], host: &str, port: u16) -> Ma
                                     div class="connection-header" {
                                         input type="checkbox" class="connection-select" value=(connection.id) onchange="updateSelection()";
                                         div class="connection-info" {
-                                            @let display_subdomain = connection.subdomain.as_ref().unwrap_or(&connection.connection_string);
-                                            @let truncated_subdomain = if display_subdomain.len() > 20 {
-                                                format!("{}...", &display_subdomain[..20])
-                                            } else {
-                                                display_subdomain.clone()
-                                            };
-                                            @let full_url = format!("https://{}.{}", display_subdomain, host);
-                                            a href=(full_url) target="_blank" title=(format!("{}.{}", display_subdomain, host)) {
-                                                "🚢 " (truncated_subdomain) "." (host)
+                                            // Never the key: it would open protected tunnels to anyone
+                                            @if let Some(display_subdomain) = &connection.subdomain {
+                                                @let truncated_subdomain = if display_subdomain.len() > 20 {
+                                                    format!("{}...", &display_subdomain[..20])
+                                                } else {
+                                                    display_subdomain.clone()
+                                                };
+                                                @let full_url = format!("https://{}.{}", display_subdomain, host);
+                                                a href=(full_url) target="_blank" title=(format!("{}.{}", display_subdomain, host)) {
+                                                    "🚢 " (truncated_subdomain) "." (host)
+                                                }
+                                            } @else {
+                                                span { "🚢 Vessel #" (connection.id) " (no subdomain)" }
+                                            }
+                                            @if let Some(label) = &connection.label {
+                                                div class="connection-meta" title=[connection.description.as_deref()] { "🏷️ " (label) }
+                                            }
+                                            @if connection.owner.is_some() || !connection.tags.0.is_empty() {
+                                                div class="connection-meta" {
+                                                    @if let Some(owner) = &connection.owner {
+                                                        a href={ "/connections?owner=" (encode(owner)) } class="connection-tag" { "👤 " (owner) }
+                                                    }
+                                                    @for (key, value) in &connection.tags.0 {
+                                                        a href={ "/connections?tag=" (encode(&format!("{}={}", key, value))) } class="connection-tag" {
+                                                            (key) "=" (value)
+                                                        }
+                                                    }
+                                                }
                                             }
                                         }
                                         div class="connection-actions" {
+                                            a href={ "/connections/" (connection.id) "/edit" } class="btn btn-small btn-secondary" title="Refit vessel" { "🛠️" }
                                             button type="button" class="btn btn-small btn-danger" onclick={ "deleteConnection(" (connection.id) ")" } { "⚓" }
                                         }
                                     }
@@ -115,6 +211,12 @@ pub fn connections_list(connections: &[Connection], host: &str, port: u16) -> Ma
                             }
                         }
                         div class="actions mt-4" {
+                            @if let Some(cursor) = next_cursor {
+                                a href={ "/connections?" (page_query(query, cursor)) } class="btn btn-secondary" {
+                                    span { "➡️" }
+                                    "Next page"
+                                }
+                            }
                             a href="/" class="btn btn-secondary" { 
                                 span { "🌊" }
                                 "New Dive" 
@@ -210,4 +312,37 @@ pub fn connections_list(connections: &[Connection], host: &str, port: u16) -> Ma
             }
         }
     }
-} 
\ No newline at end of file
+}
+
+// C3.3 Pagination Links
+// Query string for the next page: the current filters plus the new cursor.
+fn page_query(query: &ConnectionQuery, cursor: &str) -> String {
+    let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+    if let Some(q) = &query.q {
+        serializer.append_pair("q", q);
+    }
+    if let Some(status) = query.status {
+        serializer.append_pair("status", status.as_str());
+    }
+    if let Some(owner) = &query.owner {
+        serializer.append_pair("owner", owner);
+    }
+    if let Some(tag) = &query.tag {
+        serializer.append_pair("tag", tag);
+    }
+    if let Some(sort) = query.sort {
+        serializer.append_pair("sort", sort.as_str());
+    }
+    if let Some(order) = query.order {
+        serializer.append_pair("order", order.as_str());
+    }
+    if let Some(limit) = query.limit {
+        serializer.append_pair("limit", &limit.to_string());
+    }
+    serializer.append_pair("cursor", cursor);
+    serializer.finish()
+}
+
+fn encode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
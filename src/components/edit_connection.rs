@@ -0,0 +1,511 @@
+/**
+ * C5.0 Edit Connection Component
+ * ==============================
+ *
+ * Renders a form for changing an existing connection in place: rotating its
+ * holesail key, moving it to another subdomain or editing its metadata, plus
+ * managing the subdomain aliases and custom domains it also answers on and
+ * who may open it.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: C5.1, C5.2
+ */
+// C5.1 Dependencies
+use crate::models::{ConnectionCredential, ConnectionHostname, CredentialKind, CustomDomain, ResponseRewrite, VerificationMethod};
+use crate::services::holesail::KEY_PATTERN;
+use crate::Connection;
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+
+// C5.2 Edit Connection Function
+// Returns the Maud Markup for the edit page. The form is sent as a PATCH
+// request, so it is submitted from script like the delete buttons.
+// `credentials` is `None` for visitors without the admin token, who don't get
+// to see them. `fresh_secret` is a token or signed link that was just created
+// and is shown this once.
+pub fn edit_connection(
+    connection: &Connection,
+    host: &str,
+    hostnames: &[ConnectionHostname],
+    domains: &[CustomDomain],
+    credentials: Option<&[ConnectionCredential]>,
+    fresh_secret: Option<&str>,
+) -> Markup {
+    // Never the key: it would open protected tunnels to anyone
+    let subdomain = connection.subdomain.as_deref().unwrap_or_default();
+
+    html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                title { "Sando.Blue - Refit Vessel" }
+                link rel="preconnect" href="https://fonts.googleapis.com";
+                link rel="preconnect" href="https://fonts.gstatic.com" crossorigin;
+                link href="https://fonts.googleapis.com/css2?family=Inter:wght@400;600;700&display=swap" rel="stylesheet";
+                link rel="stylesheet" href="/static/styles.css";
+                style {
+                    "
+                    .form-group input, .form-group textarea {
+                        margin-bottom: 1.25rem;
+                    }
+                    .form-group textarea {
+                        width: 100%;
+                        min-height: 5rem;
+                        font-family: inherit;
+                    }
+                    .hostname-item {
+                        display: flex;
+                        align-items: center;
+                        justify-content: space-between;
+                        gap: 0.5rem;
+                        margin-bottom: 0.5rem;
+                    }
+                    .hostname-item .connection-actions {
+                        display: flex;
+                        gap: 0.25rem;
+                    }
+                    .form-hint {
+                        color: #93C5FD;
+                        font-size: 0.9rem;
+                        margin: -0.75rem 0 1.25rem 0;
+                    }
+                    "
+                }
+            }
+            body {
+                div class="container" {
+                    h1 { "🛠️ Refit Vessel" }
+                    p style="text-align: center; margin-bottom: 2rem; color: #93C5FD;" {
+                        "🚢 " (subdomain) "." (host)
+                    }
+
+                    form id="edit-form" data-id=(connection.id) {
+                        div class="form-group" {
+                            label for="current_key" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" {
+                                "Current Connection String"
+                            }
+                            // No name: it travels in the X-Sando-Key header, not the form
+                            input type="password" id="current_key" autocomplete="off" placeholder="Proves this vessel is yours";
+                            p class="form-hint" {
+                                "Changes need the vessel's current key, or the admin token (the browser asks for it). Owner, IP rules and tier need the admin token."
+                            }
+
+                            label for="connection" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" {
+                                "New Connection String (Optional)"
+                            }
+                            input
+                                type="text"
+                                id="connection"
+                                name="connection"
+                                pattern=(KEY_PATTERN)
+                                title="The key holesail prints: 64 hex characters or an hs:// URL"
+                                placeholder="Leave empty to keep the current key";
+                            p class="form-hint" {
+                                "The new key goes live only once its tunnel is confirmed online; until then the old one keeps serving."
+                            }
+
+                            label for="subdomain" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" {
+                                "Subdomain"
+                            }
+                            input
+                                type="text"
+                                id="subdomain"
+                                name="subdomain"
+                                value=(subdomain)
+                                pattern="[a-zA-Z0-9.-]+"
+                                title="Letters, numbers and hyphens; use dots for names in your namespace, e.g. svc.alice";
+
+                            label for="label" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" {
+                                "Label"
+                            }
+                            input
+                                type="text"
+                                id="label"
+                                name="label"
+                                maxlength="100"
+                                value=[connection.label.as_deref()]
+                                placeholder="e.g. Payments staging API";
+
+                            label for="owner" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" {
+                                "Owner Contact"
+                            }
+                            input
+                                type="text"
+                                id="owner"
+                                name="owner"
+                                maxlength="200"
+                                value=[connection.owner.as_deref()]
+                                placeholder="e.g. payments-team@example.com";
+
+                            label for="description" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" {
+                                "Description"
+                            }
+                            textarea id="description" name="description" maxlength="1000" {
+                                (connection.description.as_deref().unwrap_or_default())
+                            }
+
+                            label for="tags" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" {
+                                "Tags"
+                            }
+                            textarea id="tags" name="tags" placeholder="team=payments\nservice=checkout" {
+                                (connection.tags.to_lines())
+                            }
+                            p class="form-hint" {
+                                "One key=value per line. Keys use a-z, 0-9, '_', '.' or '-'."
+                            }
+
+                            label for="response_rewrite" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" {
+                                "Rewrite localhost URLs"
+                            }
+                            select id="response_rewrite" name="response_rewrite" {
+                                @for rewrite in ResponseRewrite::ALL {
+                                    option value=(rewrite.as_str()) selected[rewrite == connection.response_rewrite] {
+                                        @match rewrite {
+                                            ResponseRewrite::Off => "Off",
+                                            ResponseRewrite::Headers => "Redirects and cookies",
+                                            ResponseRewrite::Content => "Redirects, cookies and page content",
+                                        }
+                                    }
+                                }
+                            }
+                            p class="form-hint" {
+                                "Points Location, Refresh and cookies the app sets for localhost at this vessel's hostname; page content also rewrites links in HTML, CSS and JavaScript."
+                            }
+
+                            label for="limits" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" {
+                                "Limits"
+                            }
+                            textarea id="limits" name="limits" placeholder="rate=20\nclient_rate=5\nmax_in_flight=32" {
+                                (connection.limits.to_lines())
+                            }
+                            p class="form-hint" {
+                                "One key=value per line: rate and client_rate in requests per second, burst, client_burst and max_in_flight. "
+                                "They can only tighten the "
+                                @match &connection.tier {
+                                    Some(tier) => { "'" (tier) "' tier's limits" }
+                                    None => "server's default limits",
+                                }
+                                "; tiers are assigned by operators."
+                            }
+
+                            label for="ip_rules" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" {
+                                "IP Rules"
+                            }
+                            textarea id="ip_rules" name="ip_rules" placeholder="allow=10.0.0.0/8\nallow=203.0.113.7\ndeny=10.6.6.0/24" {
+                                (connection.ip_rules.to_lines())
+                            }
+                            p class="form-hint" {
+                                "One allow=range or deny=range per line, as CIDR ranges or single addresses. "
+                                "Denied clients are always refused; with any allow rule, only those clients get through. "
+                                "Changing them needs the admin token."
+                            }
+                        }
+                        div id="edit-error" class="error-message" style="display: none;" {}
+                        button type="submit" id="save-btn" class="btn-full" style="padding: 0.75rem 1.5rem; font-size: 1rem; font-weight: 600;" {
+                            span class="icon" { "⚓" }
+                            "Save Changes"
+                        }
+                    }
+
+                    h2 style="margin-top: 2rem;" { "🧭 Hostnames" }
+                    p class="form-hint" style="margin: 0 0 1rem 0;" {
+                        "Aliases answer for this vessel too, or redirect to its main hostname. Renaming keeps the old name as a redirecting alias."
+                    }
+                    div id="hostnames" data-id=(connection.id) {
+                        @for hostname in hostnames {
+                            div class="hostname-item" {
+                                span {
+                                    @if hostname.is_canonical { "⭐ " } @else if hostname.redirect { "↪️ " } @else { "🔗 " }
+                                    (hostname.hostname) "." (host)
+                                }
+                                @if !hostname.is_canonical {
+                                    div class="connection-actions" {
+                                        button type="button" class="btn btn-small btn-secondary" title="Make main hostname"
+                                            onclick={ "updateHostname(" (hostname.id) ", 'canonical=true')" } { "⭐" }
+                                        button type="button" class="btn btn-small btn-secondary"
+                                            title=(if hostname.redirect { "Serve directly" } else { "Redirect to main hostname" })
+                                            onclick={ "updateHostname(" (hostname.id) ", 'redirect=" (!hostname.redirect) "')" } {
+                                            @if hostname.redirect { "🔗" } @else { "↪️" }
+                                        }
+                                        button type="button" class="btn btn-small btn-danger" title="Remove alias"
+                                            onclick={ "removeHostname(" (hostname.id) ")" } { "⚓" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    form id="add-hostname" method="post" action={ "/connections/" (connection.id) "/hostnames" } class="form-group" {
+                        input
+                            type="text"
+                            name="hostname"
+                            placeholder="New alias"
+                            required
+                            pattern="[a-zA-Z0-9.-]+"
+                            title="Letters, numbers and hyphens; use dots for names in your namespace, e.g. svc.alice";
+                        label class="checkbox-label" style="margin-bottom: 1rem;" {
+                            input type="checkbox" name="redirect" value="true";
+                            span { "Redirect to the main hostname" }
+                        }
+                        button type="submit" class="btn btn-small" { "➕ Add Alias" }
+                    }
+
+                    h2 style="margin-top: 2rem;" { "🏝️ Custom Domains" }
+                    p class="form-hint" style="margin: 0 0 1rem 0;" {
+                        "Point a domain you own at this harbor, publish the challenge, then verify. Only verified domains are routed."
+                    }
+                    div id="domains" data-id=(connection.id) {
+                        @for domain in domains {
+                            div class="hostname-item" {
+                                div {
+                                    span {
+                                        @if domain.verified_at.is_some() { "✅ " } @else { "⏳ " }
+                                        (domain.domain)
+                                    }
+                                    @if domain.verified_at.is_none() {
+                                        div class="form-hint" {
+                                            @match domain.method {
+                                                VerificationMethod::Dns => {
+                                                    "TXT record " code { (domain.dns_record_name()) }
+                                                    " with value " code { (domain.dns_record_value()) }
+                                                }
+                                                VerificationMethod::Http => {
+                                                    "Serve " code { (domain.token) } " at " code { (domain.http_challenge_url()) }
+                                                    " from the domain's current web server, before pointing it here"
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                                div class="connection-actions" {
+                                    @if domain.verified_at.is_none() {
+                                        form method="post" action={ "/connections/" (connection.id) "/domains/" (domain.id) "/verify" } style="display: inline;" {
+                                            button type="submit" class="btn btn-small btn-secondary" title="Check the challenge" { "🔍" }
+                                        }
+                                    }
+                                    button type="button" class="btn btn-small btn-danger" title="Remove domain"
+                                        onclick={ "removeDomain(" (domain.id) ")" } { "⚓" }
+                                }
+                            }
+                        }
+                    }
+                    form method="post" action={ "/connections/" (connection.id) "/domains" } class="form-group" {
+                        input
+                            type="text"
+                            name="domain"
+                            placeholder="app.example.com"
+                            required;
+                        select name="method" style="margin-bottom: 1rem;" {
+                            option value="dns" selected { "DNS TXT record" }
+                            option value="http" { "HTTP challenge file on your own server" }
+                        }
+                        button type="submit" class="btn btn-small" { "➕ Add Domain" }
+                    }
+
+                    h2 style="margin-top: 2rem;" { "🔒 Access" }
+                    p class="form-hint" style="margin: 0 0 1rem 0;" {
+                        @if credentials.is_none() {
+                            "Managing access needs the admin token; the buttons below ask for it."
+                        } @else if credentials.is_some_and(|credentials| credentials.is_empty()) && connection.link_secret.is_none() {
+                            "Anyone can open this vessel. Add a user, a token or a signed link to keep it private."
+                        } @else {
+                            "Only visitors with a user, a token or an unexpired signed link can open this vessel."
+                        }
+                    }
+                    @if let Some(secret) = fresh_secret {
+                        div class="form-group" {
+                            label for="fresh-secret" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" {
+                                "Copy it now, it won't be shown again"
+                            }
+                            input type="text" id="fresh-secret" value=(secret) readonly onclick="this.select()";
+                        }
+                    }
+                    div id="credentials" data-id=(connection.id) {
+                        @for credential in credentials.unwrap_or_default() {
+                            div class="hostname-item" {
+                                span {
+                                    @match credential.kind {
+                                        CredentialKind::Basic => { "👤 " }
+                                        CredentialKind::Token => { "🔑 " }
+                                    }
+                                    (credential.name)
+                                }
+                                div class="connection-actions" {
+                                    button type="button" class="btn btn-small btn-danger" title="Remove credential"
+                                        onclick={ "removeCredential(" (credential.id) ")" } { "⚓" }
+                                }
+                            }
+                        }
+                    }
+                    form method="post" action={ "/connections/" (connection.id) "/credentials" } class="form-group" {
+                        select name="kind" style="margin-bottom: 1rem;" {
+                            option value="basic" selected { "User and password (Basic auth)" }
+                            option value="token" { "Bearer token" }
+                        }
+                        input type="text" name="name" placeholder="User name, or a label for the token" required;
+                        input type="password" name="password" placeholder="Password (users only, 8+ characters)" autocomplete="new-password";
+                        button type="submit" class="btn btn-small" { "➕ Add Credential" }
+                    }
+                    form method="post" action={ "/connections/" (connection.id) "/links" } class="form-group" {
+                        input type="text" name="path" placeholder="/ (where the link lands)";
+                        select name="expires_in" style="margin-bottom: 1rem;" {
+                            option value="3600" { "Expires in 1 hour" }
+                            option value="86400" selected { "Expires in 1 day" }
+                            option value="604800" { "Expires in 7 days" }
+                            option value="2592000" { "Expires in 30 days" }
+                        }
+                        button type="submit" class="btn btn-small" { "🔗 Create Signed Link" }
+                        @if connection.link_secret.is_some() {
+                            " "
+                            button type="button" class="btn btn-small btn-danger" onclick="revokeLinks()" { "Revoke All Links" }
+                        }
+                    }
+
+                    div class="actions mt-4" {
+                        a href="/connections" class="btn btn-secondary" {
+                            span { "🌊" }
+                            "Back to Harbor"
+                        }
+                    }
+                }
+
+                script {
+                    (PreEscaped(r#"
+                        // The current key, when given, proves the vessel is yours
+                        function keyHeaders() {
+                            const headers = { 'Content-Type': 'application/x-www-form-urlencoded' };
+                            const currentKey = document.getElementById('current_key').value.trim();
+                            if (currentKey) {
+                                headers['X-Sando-Key'] = currentKey;
+                            }
+                            return headers;
+                        }
+
+                        document.getElementById('edit-form').addEventListener('submit', event => {
+                            event.preventDefault();
+                            const form = event.target;
+                            const error = document.getElementById('edit-error');
+                            const save = document.getElementById('save-btn');
+
+                            save.disabled = true;
+                            error.style.display = 'none';
+
+                            fetch(`/connections/${form.dataset.id}`, {
+                                method: 'PATCH',
+                                headers: keyHeaders(),
+                                body: new URLSearchParams(new FormData(form)),
+                            })
+                            .then(async response => {
+                                if (response.ok) {
+                                    window.location = '/connections';
+                                    return;
+                                }
+                                error.textContent = await response.text();
+                                error.style.display = 'block';
+                                save.disabled = false;
+                            })
+                            .catch(err => {
+                                console.error('Update failed:', err);
+                                error.textContent = 'Failed to refit vessel';
+                                error.style.display = 'block';
+                                save.disabled = false;
+                            });
+                        });
+
+                        document.getElementById('add-hostname').addEventListener('submit', event => {
+                            event.preventDefault();
+                            const form = event.target;
+                            fetch(form.action, {
+                                method: 'POST',
+                                headers: keyHeaders(),
+                                body: new URLSearchParams(new FormData(form)),
+                            })
+                            .then(async response => {
+                                if (response.ok) {
+                                    window.location.reload();
+                                } else {
+                                    alert(await response.text());
+                                }
+                            })
+                            .catch(err => {
+                                console.error('Adding alias failed:', err);
+                                alert('Failed to add alias');
+                            });
+                        });
+
+                        function hostnameRequest(hostnameId, method, body) {
+                            const id = document.getElementById('hostnames').dataset.id;
+                            fetch(`/connections/${id}/hostnames/${hostnameId}`, {
+                                method,
+                                headers: keyHeaders(),
+                                body,
+                            })
+                            .then(async response => {
+                                if (response.ok) {
+                                    window.location.reload();
+                                } else {
+                                    alert(await response.text());
+                                }
+                            })
+                            .catch(err => {
+                                console.error('Hostname update failed:', err);
+                                alert('Failed to update hostname');
+                            });
+                        }
+
+                        function updateHostname(hostnameId, body) {
+                            hostnameRequest(hostnameId, 'PATCH', body);
+                        }
+
+                        function removeHostname(hostnameId) {
+                            if (!confirm('Remove this alias?')) return;
+                            hostnameRequest(hostnameId, 'DELETE');
+                        }
+
+                        function accessRequest(path, confirmation) {
+                            if (!confirm(confirmation)) return;
+                            const id = document.getElementById('credentials').dataset.id;
+                            fetch(`/connections/${id}/${path}`, { method: 'DELETE' })
+                            .then(async response => {
+                                if (response.ok) {
+                                    window.location = `/connections/${id}/edit`;
+                                } else {
+                                    alert(await response.text());
+                                }
+                            })
+                            .catch(err => {
+                                console.error('Access update failed:', err);
+                                alert('Failed to update access');
+                            });
+                        }
+
+                        function removeCredential(credentialId) {
+                            accessRequest(`credentials/${credentialId}`, 'Remove this credential?');
+                        }
+
+                        function revokeLinks() {
+                            accessRequest('links', 'Revoke every signed link shared so far?');
+                        }
+
+                        function removeDomain(domainId) {
+                            if (!confirm('Remove this domain?')) return;
+                            const id = document.getElementById('domains').dataset.id;
+                            fetch(`/connections/${id}/domains/${domainId}`, { method: 'DELETE' })
+                            .then(async response => {
+                                if (response.ok) {
+                                    window.location.reload();
+                                } else {
+                                    alert(await response.text());
+                                }
+                            })
+                            .catch(err => {
+                                console.error('Domain removal failed:', err);
+                                alert('Failed to remove domain');
+                            });
+                        }
+                    "#))
+                }
+            }
+        }
+    }
+}
@@ -8,11 +8,13 @@
  * Tags: C1.1, C1.2
  */
 // C1.1 Dependencies
-use maud::{html, Markup, DOCTYPE};
+use crate::services::holesail::KEY_PATTERN;
+use maud::{html, Markup, PreEscaped, DOCTYPE};
 
 // C1.2 Home Page Function
-// Returns the Maud Markup for the main page.
-pub fn home_page() -> Markup {
+// Returns the Maud Markup for the main page. With `proof_of_work` (bits and
+// resource), the form mints a hashcash stamp before it is submitted.
+pub fn home_page(proof_of_work: Option<(u8, &str)>) -> Markup {
     html! {
         (DOCTYPE)
         html lang="en" {
@@ -88,10 +90,27 @@ pub fn home_page() -> Markup {
                         max-height: 500px;
                         padding: 1.5rem;
                     }
+                    .subdomain-status {
+                        font-size: 0.9rem;
+                        margin: -0.75rem 0 1.25rem 0;
+                        min-height: 1.2rem;
+                    }
+                    .subdomain-status.available {
+                        color: #86EFAC;
+                    }
+                    .subdomain-status.unavailable {
+                        color: #FECACA;
+                    }
+                    .subdomain-suggestion {
+                        color: #60A5FA;
+                        cursor: pointer;
+                        text-decoration: underline;
+                        margin-left: 0.5rem;
+                    }
                     "
                 }
                 script {
-                    "
+                    (PreEscaped(r#"
                     function toggleInstructions() {
                         const content = document.getElementById('instructions-content');
                         const toggle = document.getElementById('instructions-toggle');
@@ -107,7 +126,91 @@ pub fn home_page() -> Markup {
                             toggle.querySelector('.toggle-text').textContent = 'Hide Setup Instructions';
                         }
                     }
-                    "
+
+                    let subdomainCheckTimer = null;
+
+                    function checkSubdomain() {
+                        const input = document.getElementById('subdomain');
+                        const status = document.getElementById('subdomain-status');
+                        const submit = document.getElementById('submit-btn');
+                        const name = input.value.trim();
+
+                        clearTimeout(subdomainCheckTimer);
+                        if (name === '') {
+                            status.textContent = '';
+                            status.className = 'subdomain-status';
+                            submit.disabled = false;
+                            return;
+                        }
+
+                        subdomainCheckTimer = setTimeout(() => {
+                            fetch(`/api/subdomains/${encodeURIComponent(name)}`)
+                                .then(response => response.json())
+                                .then(result => {
+                                    if (input.value.trim() !== name) return;
+                                    status.innerHTML = '';
+                                    if (result.available) {
+                                        status.className = 'subdomain-status available';
+                                        status.textContent = `✓ ${result.name} is available (${result.price.amount} ${result.price.unit})`;
+                                        submit.disabled = false;
+                                        return;
+                                    }
+                                    status.className = 'subdomain-status unavailable';
+                                    status.textContent = result.valid
+                                        ? `✗ ${result.name} is already taken.`
+                                        : `✗ ${result.errors.join('. ')}.`;
+                                    if (result.suggestions.length > 0) {
+                                        status.appendChild(document.createTextNode(' Try:'));
+                                        result.suggestions.forEach(suggestion => {
+                                            const link = document.createElement('span');
+                                            link.className = 'subdomain-suggestion';
+                                            link.textContent = suggestion;
+                                            link.onclick = () => {
+                                                input.value = suggestion;
+                                                checkSubdomain();
+                                            };
+                                            status.appendChild(link);
+                                        });
+                                    }
+                                    submit.disabled = true;
+                                })
+                                .catch(err => console.error('Subdomain check failed:', err));
+                        }, 300);
+                    }
+
+                    // Hashcash: 1:bits:date:resource::rand:counter whose SHA-256
+                    // starts with `bits` zero bits
+                    async function mintStamp(bits, resource) {
+                        const pad = n => String(n).padStart(2, '0');
+                        const now = new Date();
+                        const date = [now.getUTCFullYear() % 100, now.getUTCMonth() + 1, now.getUTCDate(),
+                            now.getUTCHours(), now.getUTCMinutes(), now.getUTCSeconds()].map(pad).join('');
+                        const rand = Array.from(crypto.getRandomValues(new Uint8Array(8)), b => b.toString(16).padStart(2, '0')).join('');
+                        const encoder = new TextEncoder();
+                        for (let counter = 0; ; counter++) {
+                            const stamp = `1:${bits}:${date}:${resource}::${rand}:${counter.toString(16)}`;
+                            const hash = new Uint8Array(await crypto.subtle.digest('SHA-256', encoder.encode(stamp)));
+                            let zeros = 0;
+                            for (const byte of hash) {
+                                if (byte === 0) { zeros += 8; continue; }
+                                zeros += Math.clz32(byte) - 24;
+                                break;
+                            }
+                            if (zeros >= bits) return stamp;
+                        }
+                    }
+
+                    async function submitWithProofOfWork(event) {
+                        const form = event.target;
+                        if (!form.dataset.powBits || form.elements.pow.value) return;
+                        event.preventDefault();
+                        const submit = document.getElementById('submit-btn');
+                        submit.disabled = true;
+                        submit.lastChild.textContent = 'Preparing...';
+                        form.elements.pow.value = await mintStamp(Number(form.dataset.powBits), form.dataset.powResource);
+                        form.submit();
+                    }
+                    "#))
                 }
             }
             body {
@@ -125,7 +228,10 @@ pub fn home_page() -> Markup {
                         h2 style="text-align: center; margin-bottom: 1.5rem; color: #60A5FA; font-size: 1.5rem;" { 
                             "🌊 Connect Your Service" 
                         }
-                        form method="post" action="/submit" {
+                        form method="post" action="/submit" onsubmit="submitWithProofOfWork(event)"
+                            data-pow-bits=[proof_of_work.map(|(bits, _)| bits)]
+                            data-pow-resource=[proof_of_work.map(|(_, resource)| resource)] {
+                            input type="hidden" name="pow";
                             div class="form-group" {
                                 label for="connection" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" { 
                                     "Connection String" 
@@ -135,6 +241,8 @@ pub fn home_page() -> Markup {
                                     id="connection" 
                                     name="connection" 
                                     placeholder="holesail --live <port_to_make_live> --background" 
+                                    pattern=(KEY_PATTERN)
+                                    title="The key holesail prints: 64 hex characters or an hs:// URL"
                                     required;
 
                                 label for="subdomain" style="display: block; margin-bottom: 0.5rem; font-weight: 600;" {
@@ -144,11 +252,13 @@ pub fn home_page() -> Markup {
                                     type="text"
                                     id="subdomain"
                                     name="subdomain"
-                                    placeholder="my-app (defaults to connection string)"
+                                    placeholder="my-app (defaults to a random name)"
                                     pattern="[a-zA-Z0-9-]+"
-                                    title="Only letters, numbers, and hyphens allowed";
+                                    title="Only letters, numbers, and hyphens allowed"
+                                    oninput="checkSubdomain()";
+                                div id="subdomain-status" class="subdomain-status" {}
                             }
-                            button type="submit" class="btn-full" style="padding: 0.75rem 1.5rem; font-size: 1rem; font-weight: 600;" {
+                            button type="submit" id="submit-btn" class="btn-full" style="padding: 0.75rem 1.5rem; font-size: 1rem; font-weight: 600;" {
                                 span class="icon" { "🚀" }
                                 "Dive Deep"
                             }
@@ -1,4 +1,6 @@
 pub mod home_page;
 pub mod status_page;
 pub mod connections_list;
-pub mod payment_page; 
\ No newline at end of file
+pub mod payment_page;
+pub mod edit_connection;
+pub mod audit_log;
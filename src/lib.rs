@@ -0,0 +1,503 @@
+/**
+ * Sando Library
+ * =============
+ *
+ * Agent Instructions:
+ * This file is documented for machine-readability. Use the table of contents
+ * and search for the section number (e.g., "L1.2") to navigate.
+ * The tunnel proxy and payment gate can be embedded in other services by
+ * building a `Router` with `SandoBuilder`; `main.rs` is a thin binary on top.
+ *
+ * Table of Contents:
+ * ------------------
+ * L1. SETUP
+ *     L1.1 Dependencies      (Imports)
+ *     L1.2 Data Structures   (Structs & Types)
+ *
+ * L2. APPLICATION LOGIC
+ *     L2.1 Routes            (Router Setup)
+ *     L2.2 API Routes        (JSON API Router)
+ *     L2.3 Root Handler      (Subdomain Dispatch)
+ *
+ * L3. EMBEDDING
+ *     L3.1 Migrations        (Schema Setup)
+ *     L3.2 Builder           (Router Construction)
+ */
+// ==========================================================================
+// L1. SETUP
+// ==========================================================================
+
+// L1.1 Dependencies
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Host, OriginalUri, State},
+    http::{Request, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get, patch, post},
+    Router,
+};
+use hyper::upgrade::OnUpgrade;
+use ipnet::IpNet;
+use sqlx::sqlite::SqlitePool;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tower::util::ServiceExt;
+use tower_http::{services::ServeDir, trace::TraceLayer};
+
+pub mod components;
+pub mod models;
+pub mod routes;
+pub mod services;
+
+// L1.2 Data Structures
+pub use models::{Connection, TunnelLimits};
+use routes::proxy::DEFAULT_WEBSOCKET_IDLE_TIMEOUT;
+use services::antispam::{limit_control_plane, ProofOfWork};
+use services::forwarding::client_ip;
+use services::ip_filter::IpFilter;
+use services::limits::RateLimiter;
+use services::ports::{PortAllocator, DEFAULT_PORT_RANGE};
+pub use services::dns::{DnsResolver, StaticResolver, SystemResolver};
+pub use services::tunnel::{HolesailBackend, TunnelBackend};
+pub use routes::proxy::UpstreamTimeouts;
+pub use services::antispam::{ControlPlaneLimits, RateLimit};
+
+#[derive(Clone)]
+pub struct AppConfig {
+    pub pool: Arc<SqlitePool>,
+    pub host: String,       // Primary base host, used in generated URLs
+    pub hosts: Vec<String>, // Every base host tunnels are served under, primary first
+    pub port: u16,
+    pub port_allocator: Arc<PortAllocator>,
+    pub tunnels: Arc<dyn TunnelBackend>,
+    pub dns: Arc<dyn DnsResolver>, // Used to verify custom domains
+    pub admin_token: Option<String>, // Bearer token for operator endpoints; they're disabled without one
+    pub connect_probe: Option<Duration>, // Check new keys are reachable before asking for payment
+    pub upstream_timeouts: UpstreamTimeouts, // Waits on the tunnel when proxying HTTP
+    pub upstream_client: reqwest::Client, // Pooled connections to tunnels for the axum proxy
+    pub websocket_idle_timeout: Duration, // Proxied WebSockets silent for this long are closed
+    pub trusted_proxies: Vec<IpNet>, // Peers whose X-Forwarded-* and Forwarded headers are kept
+    pub ip_filter: Arc<IpFilter>, // Global blocklist and counts of turned away clients
+    pub tunnel_limits: TunnelLimits, // Limits for connections without a tier
+    pub tiers: BTreeMap<String, TunnelLimits>, // Named limits operators can assign to connections
+    pub rate_limiter: Arc<RateLimiter>,
+    pub control_plane_limits: ControlPlaneLimits, // Per-client limits on the app's own routes
+    pub proof_of_work: Option<Arc<ProofOfWork>>, // Hashcash stamps required before issuing a payment request
+    pub static_dir: PathBuf,
+}
+
+pub type AppState = Arc<AppConfig>;
+
+// ==========================================================================
+// L2. APPLICATION LOGIC
+// ==========================================================================
+
+// L2.1 App Router
+// This router handles the main application logic for non-proxy requests.
+// Every route but the static files is rate limited per client.
+fn create_app_router(app_state: AppState) -> Router {
+    Router::new()
+        .route("/", get(routes::index::index))
+        .route("/submit", post(routes::submit::submit_connection))
+        .route("/connections", get(routes::connections::list_connections))
+        .route("/connections/:id", delete(routes::connections::delete_connection).patch(routes::connections::update_connection))
+        .route("/connections/:id/edit", get(routes::connections::edit_connection_page))
+        .route("/connections/batch-delete", post(routes::connections::batch_delete_connections))
+        .route("/connections/:id/hostnames", post(routes::hostnames::add_hostname_handler))
+        .route(
+            "/connections/:id/hostnames/:hostname_id",
+            delete(routes::hostnames::remove_hostname_handler).patch(routes::hostnames::update_hostname_handler),
+        )
+        .route("/connections/:id/domains", post(routes::domains::add_domain_handler))
+        .route("/connections/:id/domains/:domain_id", delete(routes::domains::remove_domain_handler))
+        .route("/connections/:id/domains/:domain_id/verify", post(routes::domains::verify_domain_handler))
+        .route("/connections/:id/credentials", post(routes::access::add_credential_handler))
+        .route("/connections/:id/credentials/:credential_id", delete(routes::access::remove_credential_handler))
+        .route(
+            "/connections/:id/links",
+            post(routes::access::create_link_handler).delete(routes::access::revoke_links_handler),
+        )
+        .route("/admin/audit", get(routes::admin::audit_log_page))
+        .route("/status/connections", get(routes::proxy::get_connection_status))
+        .route("/status/websockets", get(routes::proxy::get_websocket_status))
+        .route("/status/ip-filter", get(routes::proxy::get_ip_filter_status))
+        .route("/api/subdomains/:name", get(routes::subdomains::check_subdomain))
+        .route("/api/openapi.json", get(routes::openapi::openapi_json))
+        .nest("/api/v1", create_api_router())
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), limit_control_plane))
+        .nest_service("/static", ServeDir::new(&app_state.static_dir))
+        .with_state(app_state)
+        .fallback(|| async { (StatusCode::NOT_FOUND, "Not Found") })
+}
+
+// L2.2 API Router
+// Versioned JSON API for managing connections programmatically.
+fn create_api_router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/connections",
+            get(routes::api::list_connections).post(routes::api::create_connection),
+        )
+        .route(
+            "/connections/:id",
+            get(routes::api::get_connection)
+                .patch(routes::api::update_connection)
+                .delete(routes::api::delete_connection),
+        )
+        .route(
+            "/connections/:id/hostnames",
+            get(routes::api::list_hostnames).post(routes::api::add_hostname),
+        )
+        .route(
+            "/connections/:id/hostnames/:hostname_id",
+            patch(routes::api::update_hostname).delete(routes::api::remove_hostname),
+        )
+        .route(
+            "/connections/:id/domains",
+            get(routes::api::list_domains).post(routes::api::add_domain),
+        )
+        .route("/connections/:id/domains/:domain_id", delete(routes::api::remove_domain))
+        .route("/connections/:id/domains/:domain_id/verify", post(routes::api::verify_domain))
+        .route(
+            "/connections/:id/credentials",
+            get(routes::api::list_credentials).post(routes::api::add_credential),
+        )
+        .route("/connections/:id/credentials/:credential_id", delete(routes::api::remove_credential))
+        .route(
+            "/connections/:id/links",
+            post(routes::api::create_link).delete(routes::api::revoke_links),
+        )
+        .route("/export", get(routes::api::export_connections))
+        .route("/import", post(routes::api::import_connections))
+        .route("/audit", get(routes::api::audit_log))
+        .fallback(routes::api::not_found)
+}
+
+// L2.3 Root Handler
+// This is the main entry point for all incoming requests. It checks if the
+// request is for a subdomain or a verified custom domain and either proxies
+// it or forwards it to the main app router. Proxied requests get forwarding
+// headers for the peer from `ConnectInfo`, when the server provides it, once
+// the proxy has admitted them. Clients on the global blocklist are turned
+// away before any of that.
+#[tracing::instrument(name = "root_handler", skip(app_state, request))]
+pub async fn root_handler(
+    State(app_state): State<AppState>,
+    Host(host): Host,
+    request: Request<Body>,
+) -> Response {
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(address)| address.ip());
+    let client = client_ip(request.headers(), peer, &app_state.trusted_proxies);
+    if app_state.ip_filter.check_blocklist(client).is_err() {
+        return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+    }
+    let host_without_port = host.split(':').next().unwrap_or(&host).trim_end_matches('.').to_ascii_lowercase();
+
+    // Check if the request is for a subdomain of one of the base hosts.
+    let is_base_host = app_state.hosts.contains(&host_without_port);
+    let is_subdomain = !is_base_host && routes::proxy::extract_subdomain(&host, &app_state.hosts).is_ok();
+    // Anything else may be a verified custom domain.
+    let custom_domain_connection = if is_subdomain || is_base_host {
+        None
+    } else {
+        routes::domains::verified_domain_connection(app_state.pool.as_ref(), &host_without_port)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("Custom domain lookup failed: {}", e);
+                None
+            })
+    };
+
+    if is_subdomain {
+        // It's a subdomain; let the proxy handler manage it.
+        let (mut parts, body) = request.into_parts();
+        let on_upgrade = parts.extensions.remove::<OnUpgrade>();
+
+        let result = routes::proxy::proxy_handler_subdomain(
+            State(app_state),
+            Host(host),
+            OriginalUri(parts.uri),
+            peer,
+            parts.method,
+            parts.headers,
+            on_upgrade,
+            body,
+        )
+        .await;
+        proxy_response(result)
+    } else if let Some(connection_id) = custom_domain_connection {
+        // It's a verified custom domain; proxy it to its connection.
+        let (mut parts, body) = request.into_parts();
+        let on_upgrade = parts.extensions.remove::<OnUpgrade>();
+
+        let result = routes::proxy::proxy_handler_custom_domain(
+            State(app_state),
+            connection_id,
+            OriginalUri(parts.uri),
+            peer,
+            parts.method,
+            parts.headers,
+            on_upgrade,
+            body,
+        )
+        .await;
+        proxy_response(result)
+    } else {
+        // It's a standard request; forward it to the main app router.
+        let app_router = create_app_router(app_state);
+        match app_router.oneshot(request).await {
+            Ok(response) => response.into_response(),
+            Err(_) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Internal Server Error"))
+                .unwrap(),
+        }
+    }
+}
+
+fn proxy_response(result: Result<Response, StatusCode>) -> Response {
+    match result {
+        Ok(response) => response,
+        Err(status_code) => Response::builder()
+            .status(status_code)
+            .body(Body::from(format!("Error: {}", status_code)))
+            .unwrap(),
+    }
+}
+
+// ==========================================================================
+// L3. EMBEDDING
+// ==========================================================================
+
+// L3.1 Migrations
+// Brings the database schema up to date. Run this once on the pool before
+// building the router.
+pub async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::migrate::MigrateError> {
+    sqlx::migrate!("./migrations").run(pool).await
+}
+
+// L3.2 Builder
+// Assembles the Sando `Router` from a database pool, configuration and a
+// tunnel backend, e.g.:
+//
+//     let app = SandoBuilder::new(pool)
+//         .host("sando.blue")
+//         .tunnel_backend(HolesailBackend)
+//         .build();
+pub struct SandoBuilder {
+    pool: SqlitePool,
+    hosts: Vec<String>,
+    port: u16,
+    port_range: RangeInclusive<u16>,
+    tunnels: Arc<dyn TunnelBackend>,
+    dns: Arc<dyn DnsResolver>,
+    admin_token: Option<String>,
+    connect_probe: Option<Duration>,
+    upstream_timeouts: UpstreamTimeouts,
+    websocket_idle_timeout: Duration,
+    trusted_proxies: Vec<IpNet>,
+    ip_blocklist: Vec<IpNet>,
+    tunnel_limits: TunnelLimits,
+    tiers: BTreeMap<String, TunnelLimits>,
+    control_plane_limits: ControlPlaneLimits,
+    proof_of_work_bits: u8,
+    static_dir: PathBuf,
+}
+
+impl SandoBuilder {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self {
+            pool,
+            hosts: vec!["localhost".to_string()],
+            port: 3000,
+            port_range: DEFAULT_PORT_RANGE,
+            tunnels: Arc::new(HolesailBackend),
+            dns: Arc::new(SystemResolver),
+            admin_token: None,
+            connect_probe: None,
+            upstream_timeouts: UpstreamTimeouts::default(),
+            websocket_idle_timeout: DEFAULT_WEBSOCKET_IDLE_TIMEOUT,
+            trusted_proxies: Vec::new(),
+            ip_blocklist: Vec::new(),
+            tunnel_limits: TunnelLimits::default(),
+            tiers: BTreeMap::new(),
+            control_plane_limits: ControlPlaneLimits::default(),
+            proof_of_work_bits: 0,
+            static_dir: PathBuf::from("static"),
+        }
+    }
+
+    // Primary base host; requests for `*.{host}` are proxied to tunnels
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.hosts[0] = normalize_host(host.into());
+        self
+    }
+
+    // Another base host serving the same tunnels, e.g. an internal domain next
+    // to the public one. When base hosts nest, the longest match wins.
+    pub fn additional_host(mut self, host: impl Into<String>) -> Self {
+        let host = normalize_host(host.into());
+        if !self.hosts.contains(&host) {
+            self.hosts.push(host);
+        }
+        self
+    }
+
+    // Port the server listens on
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    // Local ports handed out to tunnels
+    pub fn port_range(mut self, port_range: RangeInclusive<u16>) -> Self {
+        self.port_range = port_range;
+        self
+    }
+
+    pub fn tunnel_backend(mut self, tunnels: impl TunnelBackend + 'static) -> Self {
+        self.tunnels = Arc::new(tunnels);
+        self
+    }
+
+    // TXT lookups for custom domain verification; defaults to the system resolver
+    pub fn dns_resolver(mut self, dns: impl DnsResolver + 'static) -> Self {
+        self.dns = Arc::new(dns);
+        self
+    }
+
+    // Bearer token for operator endpoints such as import/export
+    pub fn admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = Some(token.into()).filter(|token| !token.is_empty());
+        self
+    }
+
+    // Before issuing a payment request for a new connection, ask the tunnel
+    // backend whether its peer can be reached within `timeout`. Off by default.
+    pub fn connect_probe(mut self, timeout: Duration) -> Self {
+        self.connect_probe = Some(timeout).filter(|timeout| !timeout.is_zero());
+        self
+    }
+
+    // Connect, first-byte and idle timeouts for proxied requests; see
+    // `UpstreamTimeouts` for the defaults
+    pub fn upstream_timeouts(mut self, timeouts: UpstreamTimeouts) -> Self {
+        self.upstream_timeouts = timeouts;
+        self
+    }
+
+    // Proxied WebSockets with no traffic either way for `timeout` are closed.
+    // Five minutes by default.
+    pub fn websocket_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.websocket_idle_timeout = timeout;
+        self
+    }
+
+    // Load balancers or proxies in front of Sando. Their X-Forwarded-* and
+    // Forwarded headers are passed on to tunnels; from anyone else they are
+    // replaced. None by default.
+    pub fn trusted_proxies(mut self, proxies: impl IntoIterator<Item = IpNet>) -> Self {
+        self.trusted_proxies.extend(proxies);
+        self
+    }
+
+    // Client addresses and ranges turned away from everything Sando serves,
+    // tunnels and control plane alike. Behind trusted proxies the forwarded
+    // address is checked. Empty by default.
+    pub fn ip_blocklist(mut self, ranges: impl IntoIterator<Item = IpNet>) -> Self {
+        self.ip_blocklist.extend(ranges);
+        self
+    }
+
+    // Rate and in-flight limits for tunnels without a tier. Unlimited by
+    // default; owners can tighten them per connection.
+    pub fn tunnel_limits(mut self, limits: TunnelLimits) -> Self {
+        self.tunnel_limits = limits;
+        self
+    }
+
+    // A named set of limits operators can assign to connections instead of
+    // the default ones, e.g. a "pro" tier with higher rates
+    pub fn tier(mut self, name: impl Into<String>, limits: TunnelLimits) -> Self {
+        self.tiers.insert(name.into().trim().to_ascii_lowercase(), limits);
+        self
+    }
+
+    // Per-client limits on the app's own pages and API, by route class. On
+    // by default (see `ControlPlaneLimits::default`); requests with the admin
+    // token are never limited.
+    pub fn control_plane_limits(mut self, limits: ControlPlaneLimits) -> Self {
+        self.control_plane_limits = limits;
+        self
+    }
+
+    // Require a hashcash stamp with `bits` bits of work before issuing a
+    // payment request for an unpaid submission. Off (0) by default.
+    pub fn proof_of_work(mut self, bits: u8) -> Self {
+        self.proof_of_work_bits = bits;
+        self
+    }
+
+    // Directory served under /static
+    pub fn static_dir(mut self, static_dir: impl Into<PathBuf>) -> Self {
+        self.static_dir = static_dir.into();
+        self
+    }
+
+    pub fn build_state(self) -> AppState {
+        // One client for every proxied request, so connections to tunnels are
+        // pooled. Redirects go back to the client, not followed here.
+        let upstream_client = reqwest::Client::builder()
+            .connect_timeout(self.upstream_timeouts.connect)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Failed to build the upstream HTTP client");
+        Arc::new(AppConfig {
+            pool: Arc::new(self.pool),
+            host: self.hosts[0].clone(),
+            hosts: self.hosts,
+            port: self.port,
+            port_allocator: Arc::new(PortAllocator::new(self.port_range)),
+            tunnels: self.tunnels,
+            dns: self.dns,
+            admin_token: self.admin_token,
+            connect_probe: self.connect_probe,
+            upstream_timeouts: self.upstream_timeouts,
+            upstream_client,
+            websocket_idle_timeout: self.websocket_idle_timeout,
+            trusted_proxies: self.trusted_proxies,
+            ip_filter: Arc::new(IpFilter::new(self.ip_blocklist)),
+            tunnel_limits: self.tunnel_limits,
+            tiers: self.tiers,
+            rate_limiter: Arc::new(RateLimiter::default()),
+            control_plane_limits: self.control_plane_limits,
+            proof_of_work: Some(self.proof_of_work_bits).filter(|bits| *bits > 0).map(|bits| Arc::new(ProofOfWork::new(bits))),
+            static_dir: self.static_dir,
+        })
+    }
+
+    pub fn build(self) -> Router {
+        router(self.build_state())
+    }
+}
+
+// The root handler decides per request whether to proxy to a tunnel or
+// serve the app, so everything goes through the fallback. Takes the state
+// from `SandoBuilder::build_state` when it is shared with another data plane.
+// Serve it with `into_make_service_with_connect_info::<SocketAddr>()` so
+// tunnels learn the client's address.
+pub fn router(app_state: AppState) -> Router {
+    Router::new()
+        .fallback(root_handler)
+        .layer(TraceLayer::new_for_http())
+        .with_state(app_state)
+}
+
+fn normalize_host(host: String) -> String {
+    host.trim().trim_end_matches('.').to_ascii_lowercase()
+}
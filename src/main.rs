@@ -4,126 +4,40 @@
  *
  * Agent Instructions:
  * This file is documented for machine-readability. Use the table of contents
- * and search for the section number (e.g., "M1.2") to navigate.
- * Each section is tagged to link its purpose to the broader application structure.
+ * and search for the section number (e.g., "M1.1") to navigate.
+ * The application itself lives in the `sando` library (`src/lib.rs`); this
+ * binary reads configuration from the environment and serves it.
  *
  * Table of Contents:
  * ------------------
  * M1. SETUP
  *     M1.1 Dependencies      (Imports)
- *     M1.2 Data Structures   (Structs & Types)
  *
- * M2. APPLICATION LOGIC
- *     M2.1 Routes            (Router Setup)
- *
- * M3. INITIALIZATION
- *     M3.1 Main Function     (Server Setup)
+ * M2. INITIALIZATION
+ *     M2.1 Main Function     (Server Setup)
+ *     M2.2 Command Line      (Import/Export)
  */
 // ==========================================================================
 // M1. SETUP
 // ==========================================================================
 
 // M1.1 Dependencies
-use axum::{
-    body::Body,
-    extract::{Host, OriginalUri, State},
-    http::{Request, StatusCode},
-    response::{IntoResponse, Response},
-    routing::{delete, get, post},
-    Router,
-};
+use sando::models::{AuditAction, AuditActor};
+use sando::services::audit::AuditEvent;
+use sando::services::backup::{self, ExportDocument};
+use sando::services::forwarding::parse_trusted_proxies;
+use sando::services::ip_filter::parse_ip_list;
+use sando::services::ports::{parse_port_range, DEFAULT_PORT_RANGE};
+use sando::{routes, AppState, ControlPlaneLimits, HolesailBackend, SandoBuilder, TunnelLimits, UpstreamTimeouts};
 use sqlx::sqlite::SqlitePool;
-use std::sync::Arc;
-use tower::util::ServiceExt;
-use tower_http::{services::ServeDir, trace::TraceLayer};
-
-mod components;
-mod models;
-mod routes;
-
-// M1.2 Data Structures
-pub use models::{Connection};
-
-#[derive(Clone)]
-pub struct AppConfig {
-    pub pool: Arc<SqlitePool>,
-    pub host: String,
-    pub port: u16,
-}
-
-pub type AppState = Arc<AppConfig>;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
 
 // ==========================================================================
-// M2. APPLICATION LOGIC
+// M2. INITIALIZATION
 // ==========================================================================
 
-// M2.1 App Router
-// This router handles the main application logic for non-proxy requests.
-fn create_app_router(app_state: AppState) -> Router {
-    Router::new()
-        .route("/", get(routes::index::index))
-        .route("/submit", post(routes::submit::submit_connection))
-        .route("/connections", get(routes::connections::list_connections))
-        .route("/connections/:id", delete(routes::connections::delete_connection))
-        .route("/connections/batch-delete", post(routes::connections::batch_delete_connections))
-        .route("/status/connections", get(routes::proxy::get_connection_status))
-        .nest_service("/static", ServeDir::new("static"))
-        .with_state(app_state)
-        .fallback(|| async { (StatusCode::NOT_FOUND, "Not Found") })
-}
-
-// M2.2 Root Handler
-// This is the main entry point for all incoming requests. It checks if the
-// request is for a subdomain and either proxies it or forwards it to the main
-// app router.
-#[tracing::instrument(name = "root_handler", skip(app_state, request))]
-async fn root_handler(
-    State(app_state): State<AppState>,
-    Host(host): Host,
-    request: Request<Body>,
-) -> Response {
-    let host_without_port = host.split(':').next().unwrap_or(&host);
-
-    // Check if the request is for a subdomain using the configured host.
-    let base_host = &app_state.host;
-    if host_without_port.ends_with(&format!(".{}", base_host)) && host_without_port != base_host {
-        // It's a subdomain; let the proxy handler manage it.
-        let (parts, body) = request.into_parts();
-        
-        match routes::proxy::proxy_handler_subdomain(
-            State(app_state),
-            Host(host),
-            OriginalUri(parts.uri),
-            parts.method,
-            parts.headers,
-            body,
-        )
-        .await
-        {
-            Ok(response) => response,
-            Err(status_code) => Response::builder()
-                .status(status_code)
-                .body(Body::from(format!("Error: {}", status_code)))
-                .unwrap(),
-        }
-    } else {
-        // It's a standard request; forward it to the main app router.
-        let app_router = create_app_router(app_state);
-        match app_router.oneshot(request).await {
-            Ok(response) => response.into_response(),
-            Err(_) => Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from("Internal Server Error"))
-                .unwrap(),
-        }
-    }
-}
-
-// ==========================================================================
-// M3. INITIALIZATION
-// ==========================================================================
-
-// M3.1 Main Function (Server Setup)
+// M2.1 Main Function (Server Setup)
 // Initializes the database, runs migrations, and starts the web server.
 #[tokio::main]
 async fn main() {
@@ -139,8 +53,7 @@ async fn main() {
         .expect("Failed to create pool");
 
     // Run migrations
-    sqlx::migrate!("./migrations")
-        .run(&pool)
+    sando::migrate(&pool)
         .await
         .expect("Failed to run migrations");
 
@@ -149,30 +62,216 @@ async fn main() {
         .parse()
         .expect("PORT must be a valid number");
 
-    let app_state = Arc::new(AppConfig {
-        pool: Arc::new(pool),
-        host: std::env::var("HOST").unwrap_or("localhost".to_string()),
-        port,
-    });
+    // Local ports handed out to holesail tunnels, e.g. SANDO_PORT_RANGE=3001-8000
+    let port_range = match std::env::var("SANDO_PORT_RANGE") {
+        Ok(value) => parse_port_range(&value).expect("SANDO_PORT_RANGE must look like START-END"),
+        Err(_) => DEFAULT_PORT_RANGE,
+    };
+
+    // Base hosts, primary first, e.g. HOST=sando.blue,sando.internal
+    let hosts_var = std::env::var("HOST").unwrap_or("localhost".to_string());
+    let mut hosts = hosts_var.split(',').map(str::trim).filter(|host| !host.is_empty());
+    let host = hosts.next().unwrap_or("localhost").to_string();
+    let additional_hosts: Vec<String> = hosts.map(str::to_string).collect();
+
+    // The router uses a fallback to the root handler, which will
+    // intelligently dispatch requests to either the proxy or the main app.
+    let mut builder = SandoBuilder::new(pool)
+        .host(host.clone())
+        .port(port)
+        .port_range(port_range)
+        .tunnel_backend(HolesailBackend)
+        .admin_token(std::env::var("SANDO_ADMIN_TOKEN").unwrap_or_default());
+    for additional_host in &additional_hosts {
+        builder = builder.additional_host(additional_host.clone());
+    }
+    // Seconds to wait for a new key's peer before asking for payment; 0 skips the check
+    if let Ok(value) = std::env::var("SANDO_CONNECT_PROBE_TIMEOUT") {
+        let seconds: u64 = value.parse().expect("SANDO_CONNECT_PROBE_TIMEOUT must be a number of seconds");
+        builder = builder.connect_probe(Duration::from_secs(seconds));
+    }
+    // Seconds to wait on tunnels: to connect, for response headers and between body chunks
+    let mut upstream_timeouts = UpstreamTimeouts::default();
+    for (name, timeout) in [
+        ("SANDO_UPSTREAM_CONNECT_TIMEOUT", &mut upstream_timeouts.connect),
+        ("SANDO_UPSTREAM_FIRST_BYTE_TIMEOUT", &mut upstream_timeouts.first_byte),
+        ("SANDO_UPSTREAM_IDLE_TIMEOUT", &mut upstream_timeouts.idle),
+    ] {
+        if let Ok(value) = std::env::var(name) {
+            let seconds: u64 = value.parse().unwrap_or_else(|_| panic!("{} must be a number of seconds", name));
+            *timeout = Duration::from_secs(seconds);
+        }
+    }
+    builder = builder.upstream_timeouts(upstream_timeouts);
+    // Seconds a proxied WebSocket may stay silent in both directions before it is closed
+    if let Ok(value) = std::env::var("SANDO_WEBSOCKET_IDLE_TIMEOUT") {
+        let seconds: u64 = value.parse().expect("SANDO_WEBSOCKET_IDLE_TIMEOUT must be a number of seconds");
+        builder = builder.websocket_idle_timeout(Duration::from_secs(seconds));
+    }
+    // Load balancers in front of Sando, e.g. SANDO_TRUSTED_PROXIES=10.0.0.0/8,192.168.1.1
+    if let Ok(value) = std::env::var("SANDO_TRUSTED_PROXIES") {
+        let proxies = parse_trusted_proxies(&value).unwrap_or_else(|e| panic!("SANDO_TRUSTED_PROXIES: {}", e));
+        builder = builder.trusted_proxies(proxies);
+    }
+    // Clients turned away from everything, e.g. SANDO_IP_BLOCKLIST=198.51.100.0/24,203.0.113.7
+    if let Ok(value) = std::env::var("SANDO_IP_BLOCKLIST") {
+        let ranges = parse_ip_list(&value).unwrap_or_else(|e| panic!("SANDO_IP_BLOCKLIST: {}", e));
+        builder = builder.ip_blocklist(ranges);
+    }
+    // Tunnel limits, e.g. SANDO_TUNNEL_LIMITS=rate=20,client_rate=5,max_in_flight=32,
+    // and named tiers operators can assign, e.g. SANDO_TIER_PRO=rate=200,max_in_flight=256
+    let parse_limits = |name: &str, value: &str| {
+        let limits = TunnelLimits::parse_lines(value).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        if let Some(error) = limits.validate().first() {
+            panic!("{}: {}", name, error);
+        }
+        limits
+    };
+    if let Ok(value) = std::env::var("SANDO_TUNNEL_LIMITS") {
+        builder = builder.tunnel_limits(parse_limits("SANDO_TUNNEL_LIMITS", &value));
+    }
+    for (name, value) in std::env::vars() {
+        if let Some(tier) = name.strip_prefix("SANDO_TIER_").filter(|tier| !tier.is_empty()) {
+            builder = builder.tier(tier, parse_limits(&name, &value));
+        }
+    }
+    // Per-client limits on the app's own routes, e.g.
+    // SANDO_CONTROL_PLANE_LIMITS=payment_requests=0.05/5,reads=off
+    if let Ok(value) = std::env::var("SANDO_CONTROL_PLANE_LIMITS") {
+        let limits = ControlPlaneLimits::parse(&value).unwrap_or_else(|e| panic!("SANDO_CONTROL_PLANE_LIMITS: {}", e));
+        builder = builder.control_plane_limits(limits);
+    }
+    // Hashcash bits required before a payment request is issued, e.g. SANDO_POW_BITS=18
+    if let Ok(value) = std::env::var("SANDO_POW_BITS") {
+        let bits: u8 = value.parse().ok().filter(|bits| *bits <= 32).expect("SANDO_POW_BITS must be a number of bits up to 32");
+        builder = builder.proof_of_work(bits);
+    }
+
+    // `sando export ...` / `sando import ...` run against the database and exit
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = run_command(&builder.build_state(), &args).await {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
     // Start background cleanup task for holesail connections
     tokio::spawn(routes::proxy::cleanup_unused_connections());
 
-    // The main router now uses a fallback to our root handler, which will
-    // intelligently dispatch requests to either the proxy or the main app.
-    let app = Router::new()
-        .fallback(root_handler)
-        .layer(TraceLayer::new_for_http())
-        .with_state(app_state.clone());
+    // With Pingora, it owns the public port and the app listens on loopback
+    // for the requests Pingora hands it; SANDO_DATA_PLANE=axum serves
+    // everything from the app, tunnels included.
+    let use_pingora = cfg!(feature = "pingora") && std::env::var("SANDO_DATA_PLANE").map_or(true, |plane| plane != "axum");
+    if use_pingora {
+        // The app only sees Pingora, which sends the client's address along
+        builder = builder.trusted_proxies([IpAddr::from(Ipv4Addr::LOCALHOST).into(), IpAddr::from(Ipv6Addr::LOCALHOST).into()]);
+    }
+
+    let app_state = builder.build_state();
+    let app = sando::router(app_state.clone());
+    let listen_address = if use_pingora {
+        let control_port = std::env::var("SANDO_CONTROL_PORT").ok().and_then(|value| value.parse().ok()).unwrap_or(0);
+        format!("127.0.0.1:{}", control_port)
+    } else {
+        format!("0.0.0.0:{}", port)
+    };
 
     // Create TCP listener
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", app_state.port)).await.unwrap();
+    let listener = tokio::net::TcpListener::bind(listen_address).await.unwrap();
+    #[cfg(feature = "pingora")]
+    if use_pingora {
+        let control_plane = listener.local_addr().unwrap();
+        let public_address = format!("0.0.0.0:{}", port);
+        println!("🛰️ Pingora data plane on {}, control plane on {}", public_address, control_plane);
+        std::thread::spawn(move || sando::services::data_plane::run_data_plane(app_state, &public_address, control_plane));
+    }
     
-    println!("🚀 Server running on http://{}:{}", app_state.host, app_state.port);
+    println!("🚀 Server running on http://{}:{}", host, port);
     println!("📦 Database: connections.db");
     println!("🔄 Reverse proxy available at:");
-    println!("   • Subdomain:  {{connection-string}}.{}:{}/{{path}}", app_state.host, app_state.port);
+    println!("   • Subdomain:  {{connection-string}}.{}:{}/{{path}}", host, port);
+    for additional_host in &additional_hosts {
+        println!("   • Subdomain:  {{connection-string}}.{}:{}/{{path}}", additional_host, port);
+    }
     
     // Run the server
-    axum::serve(listener, app.into_make_service()).await.unwrap();
-}
\ No newline at end of file
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+}
+// M2.2 Command Line (Import/Export)
+// `sando export FILE [--owner OWNER]` and `sando import FILE [--dry-run]` work
+// on connections.db directly, e.g. to restore a backup before starting the
+// server. Files ending in .csv are CSV, anything else JSON; holesail keys are
+// encrypted/decrypted with SANDO_EXPORT_PASSPHRASE when it is set.
+const USAGE: &str = "usage: sando [export FILE [--owner OWNER] | import FILE [--dry-run]]";
+
+async fn run_command(app_state: &AppState, args: &[String]) -> Result<(), String> {
+    let passphrase = std::env::var("SANDO_EXPORT_PASSPHRASE").ok().filter(|passphrase| !passphrase.is_empty());
+    let (command, file) = match args {
+        [command, file, ..] if !file.starts_with("--") => (command.as_str(), file.as_str()),
+        _ => return Err(USAGE.to_string()),
+    };
+    let mut owner = None;
+    let mut dry_run = false;
+    let mut flags = args[2..].iter();
+    while let Some(flag) = flags.next() {
+        match (command, flag.as_str()) {
+            ("export", "--owner") => owner = Some(flags.next().ok_or(USAGE)?.clone()),
+            ("import", "--dry-run") => dry_run = true,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let is_csv = file.to_ascii_lowercase().ends_with(".csv");
+
+    match command {
+        "export" => {
+            let document = backup::export_connections(app_state.pool.as_ref(), owner.as_deref(), passphrase.as_deref())
+                .await
+                .map_err(|e| format!("Export failed: {}", e))?;
+            let contents = if is_csv {
+                backup::to_csv(&document)
+            } else {
+                serde_json::to_string_pretty(&document).map_err(|e| e.to_string())?
+            };
+            std::fs::write(file, contents).map_err(|e| format!("Failed to write {}: {}", file, e))?;
+            AuditEvent::new(AuditActor::Cli, AuditAction::Export)
+                .owner(owner.as_deref())
+                .detail("connections", document.connections.len())
+                .detail("with_keys", passphrase.is_some())
+                .record(app_state.pool.as_ref())
+                .await;
+            if passphrase.is_none() {
+                println!("ℹ️  Holesail keys left out; set SANDO_EXPORT_PASSPHRASE to include them encrypted");
+            }
+            println!("📦 Exported {} connections to {}", document.connections.len(), file);
+        }
+        "import" => {
+            let contents = std::fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file, e))?;
+            let document: ExportDocument = if is_csv {
+                backup::from_csv(&contents)?
+            } else {
+                serde_json::from_str(&contents).map_err(|e| format!("Invalid export document: {}", e))?
+            };
+            let report = backup::import_connections(
+                app_state.pool.as_ref(),
+                &app_state.port_allocator,
+                &app_state.hosts,
+                document,
+                passphrase.as_deref(),
+                dry_run,
+                AuditActor::Cli,
+            )
+            .await
+            .map_err(|(_, message)| message)?;
+            let verb = if dry_run { "Would import" } else { "Imported" };
+            println!("📥 {}: {} created, {} updated", verb, report.created.len(), report.updated.len());
+            if !report.replaced_tunnels.is_empty() {
+                println!("ℹ️  {} keys changed; restart a running server to drop the old tunnels", report.replaced_tunnels.len());
+            }
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+
+    Ok(())
+}
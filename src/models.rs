@@ -5,27 +5,906 @@
  * Defines the primary data structures used throughout the application.
  * This file is tagged for machine-readability.
  *
- * Tags: T1.1, T1.2, T1.3, T1.4, T1.5
+ * Tags: T1.1, T1.2, T1.3, T1.4, T1.5, T1.6, T1.7, T1.8, T1.9, T1.10, T1.11, T1.12, T1.13
  */
 // T1.1 Dependencies
-use serde::Deserialize;
+use serde::{de::IntoDeserializer, Deserialize, Deserializer, Serialize};
+use ipnet::IpNet;
 use sqlx::FromRow;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use utoipa::{IntoParams, ToSchema};
 
 // T1.2 ConnectionForm
 // Represents the data submitted from the connection input form.
-#[derive(Deserialize)]
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct ConnectionForm {
-    pub connection: String,
+    pub connection: String, // Holesail key: 64 hex characters or an hs:// URL
     pub subdomain: Option<String>, // Optional custom subdomain
+    #[serde(default)]
+    pub owner: Option<String>, // Owner contact; decides which namespaces the subdomain may use
+    #[serde(default)]
+    pub pow: Option<String>, // Hashcash stamp, when the server asks for proof of work (also `X-Sando-PoW`)
 }
 
 // T1.3 Connection
 // Represents a single connection record retrieved from the database.
-#[derive(FromRow)]
+#[derive(FromRow, Default)]
 pub struct Connection {
     pub id: i64,
     pub connection_string: String,
     pub port: i32,
     pub subdomain: Option<String>, // Optional custom subdomain
     pub created_at: String,
-}
\ No newline at end of file
+    pub label: Option<String>,
+    pub description: Option<String>,
+    pub owner: Option<String>, // Contact for the owning team or person
+    #[sqlx(try_from = "String")]
+    pub tags: Tags,
+    pub response_rewrite: ResponseRewrite,
+    pub tier: Option<String>, // Operator-assigned limits tier; the default limits apply without one
+    #[sqlx(try_from = "String")]
+    pub limits: TunnelLimits, // The owner's own limits, applied on top of the tier's
+    pub link_secret: Option<String>, // Key for signed links; exported only encrypted
+    #[sqlx(try_from = "String")]
+    pub ip_rules: IpRules, // Client addresses allowed or denied, on top of the global blocklist
+}
+
+// Column list matching `Connection`, for `SELECT {} FROM connections`
+pub const CONNECTION_COLUMNS: &str =
+    "id, connection_string, port, subdomain, created_at, label, description, owner, tags, response_rewrite, tier, limits, \
+     link_secret, ip_rules";
+
+// How much of a tunnel's responses is rewritten so URLs the app builds for
+// `localhost` work on its public hostname (see `services::rewrite`)
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum ResponseRewrite {
+    // Responses are passed on unchanged
+    Off,
+    // `Location`, `Content-Location`, `Refresh` and `Set-Cookie`
+    #[default]
+    Headers,
+    // The headers plus absolute URLs in HTML, CSS and JavaScript bodies
+    Content,
+}
+
+impl ResponseRewrite {
+    pub const ALL: [ResponseRewrite; 3] = [Self::Off, Self::Headers, Self::Content];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Headers => "headers",
+            Self::Content => "content",
+        }
+    }
+}
+
+// T1.4 ConnectionUpdateForm
+// Represents the fields that can be changed on an existing connection.
+// Missing fields are left unchanged. An empty key or subdomain is ignored,
+// while empty metadata clears the field.
+#[derive(Deserialize, Debug, Default, ToSchema)]
+pub struct ConnectionUpdateForm {
+    pub connection: Option<String>, // New connection string (key rotation)
+    pub subdomain: Option<String>,
+    pub label: Option<String>,
+    pub description: Option<String>,
+    pub owner: Option<String>,
+    // Replaces all tags. The edit form sends them as `key=value` lines.
+    #[schema(value_type = Option<BTreeMap<String, String>>)]
+    pub tags: Option<Tags>,
+    pub response_rewrite: Option<ResponseRewrite>,
+    // Operators only (admin token); an empty tier goes back to the default limits
+    pub tier: Option<String>,
+    // Replaces the connection's own limits. The edit form sends `key=value` lines.
+    pub limits: Option<TunnelLimits>,
+    // Replaces the allow and deny lists. The edit form sends `allow=`/`deny=` lines.
+    pub ip_rules: Option<IpRules>,
+}
+
+// T1.5 ConnectionResource
+// JSON representation of a connection returned by the REST API.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ConnectionResource {
+    pub id: i64,
+    // The holesail key; only returned with the admin token, since anyone
+    // holding it can reach the tunnel directly
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_string: Option<String>,
+    pub port: i32,
+    pub subdomain: Option<String>,
+    pub url: String,
+    pub created_at: String,
+    pub label: Option<String>,
+    pub description: Option<String>,
+    pub owner: Option<String>,
+    #[schema(value_type = BTreeMap<String, String>)]
+    pub tags: Tags,
+    pub response_rewrite: ResponseRewrite,
+    pub tier: Option<String>,
+    pub limits: TunnelLimits,
+    pub ip_rules: IpRules,
+}
+
+impl ConnectionResource {
+    pub fn from_connection(connection: Connection, host: &str, show_key: bool) -> Self {
+        // Rows from before subdomains only answer on the base host
+        let url = match &connection.subdomain {
+            Some(subdomain) => format!("https://{}.{}", subdomain, host),
+            None => format!("https://{}", host),
+        };
+
+        Self {
+            id: connection.id,
+            connection_string: show_key.then_some(connection.connection_string),
+            port: connection.port,
+            subdomain: connection.subdomain,
+            url,
+            created_at: connection.created_at,
+            label: connection.label,
+            description: connection.description,
+            owner: connection.owner,
+            tags: connection.tags,
+            response_rewrite: connection.response_rewrite,
+            tier: connection.tier,
+            limits: connection.limits,
+            ip_rules: connection.ip_rules,
+        }
+    }
+}
+
+// T1.6 ConnectionQuery
+// Search, filter, sort and pagination parameters for connection listings,
+// shared by the HTML list and the JSON API. Empty values (as sent by the
+// HTML filter form) count as unset.
+#[derive(Deserialize, Debug, Default, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ConnectionQuery {
+    // Case-insensitive text search on subdomain, label, description and
+    // owner; a whole connection string also matches
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub q: Option<String>,
+    // Tunnel status: online, starting, stopped, error or idle (never started)
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub status: Option<TunnelStatus>,
+    // Owner contact, matched exactly but case-insensitively
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub owner: Option<String>,
+    // Tag filter: `key` (tag present) or `key=value`
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub tag: Option<String>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub sort: Option<ConnectionSort>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub order: Option<SortOrder>,
+    // Opaque cursor from a previous page's `next_cursor`
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub cursor: Option<String>,
+    // Page size (default 50, max 200)
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub limit: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelStatus {
+    Online,
+    Starting,
+    Stopped,
+    Error,
+    Idle,
+}
+
+impl TunnelStatus {
+    pub const ALL: [TunnelStatus; 5] = [Self::Online, Self::Starting, Self::Stopped, Self::Error, Self::Idle];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Online => "online",
+            Self::Starting => "starting",
+            Self::Stopped => "stopped",
+            Self::Error => "error",
+            Self::Idle => "idle",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionSort {
+    #[default]
+    CreatedAt,
+    Subdomain,
+    Port,
+}
+
+impl ConnectionSort {
+    pub const ALL: [ConnectionSort; 3] = [Self::CreatedAt, Self::Subdomain, Self::Port];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::CreatedAt => "created_at",
+            Self::Subdomain => "subdomain",
+            Self::Port => "port",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Asc => "asc",
+            Self::Desc => "desc",
+        }
+    }
+}
+
+// T1.7 Tags
+// Free-form key/value tags on a connection, stored as a JSON object. Keys are
+// lowercase `[a-z0-9_.-]`; JSON clients send an object, the HTML form sends
+// `key=value` lines.
+pub const MAX_TAGS: usize = 32;
+const MAX_TAG_KEY_LENGTH: usize = 64;
+const MAX_TAG_VALUE_LENGTH: usize = 256;
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Tags(pub BTreeMap<String, String>);
+
+impl Tags {
+    // Parses `key=value` lines (or `key: value`); blank lines are skipped.
+    pub fn parse_lines(text: &str) -> Result<Self, String> {
+        let mut tags = BTreeMap::new();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .or_else(|| line.split_once(':'))
+                .ok_or_else(|| format!("Tag '{}' must look like key=value", line))?;
+            tags.insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+        Ok(Self(tags))
+    }
+
+    pub fn to_lines(&self) -> String {
+        self.0.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join("\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.0).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.0.len() > MAX_TAGS {
+            errors.push(format!("At most {} tags are allowed", MAX_TAGS));
+        }
+        for (key, value) in &self.0 {
+            if let Err(e) = validate_tag_key(key) {
+                errors.push(e);
+            }
+            if value.len() > MAX_TAG_VALUE_LENGTH {
+                errors.push(format!("Tag '{}' value must be at most {} characters", key, MAX_TAG_VALUE_LENGTH));
+            }
+        }
+        errors
+    }
+}
+
+pub fn validate_tag_key(key: &str) -> Result<(), String> {
+    let valid_chars = key
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '.' | '-'));
+    if key.is_empty() || key.len() > MAX_TAG_KEY_LENGTH || !valid_chars {
+        return Err(format!(
+            "Tag key '{}' must be 1-{} characters of a-z, 0-9, '_', '.' or '-'",
+            key, MAX_TAG_KEY_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+impl TryFrom<String> for Tags {
+    type Error = serde_json::Error;
+
+    fn try_from(json: String) -> Result<Self, Self::Error> {
+        serde_json::from_str(&json).map(Self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Tags {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum TagsInput {
+            Map(BTreeMap<String, String>),
+            Lines(String),
+        }
+
+        match TagsInput::deserialize(deserializer)? {
+            TagsInput::Map(map) => Ok(Self(
+                map.into_iter()
+                    .map(|(key, value)| (key.trim().to_ascii_lowercase(), value.trim().to_string()))
+                    .collect(),
+            )),
+            TagsInput::Lines(text) => Self::parse_lines(&text).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+// T1.8 ConnectionHostname
+// A subdomain a connection answers on. The canonical one is mirrored in
+// `connections.subdomain`; aliases either serve the tunnel too or redirect
+// to the canonical hostname.
+#[derive(FromRow, Serialize, Debug, Clone, ToSchema)]
+pub struct ConnectionHostname {
+    pub id: i64,
+    pub connection_id: i64,
+    pub hostname: String,
+    pub is_canonical: bool,
+    pub redirect: bool,
+    pub created_at: String,
+}
+
+// Adds an alias. `canonical` makes it the connection's main hostname right away.
+#[derive(Deserialize, Debug, Default, ToSchema)]
+pub struct HostnameForm {
+    pub hostname: String,
+    #[serde(default)]
+    pub redirect: bool,
+    #[serde(default)]
+    pub canonical: bool,
+}
+
+// Changes an alias. Missing fields are left unchanged; `canonical: true`
+// promotes it, turning the previous canonical hostname into a redirecting alias.
+#[derive(Deserialize, Debug, Default, ToSchema)]
+pub struct HostnameUpdateForm {
+    pub redirect: Option<bool>,
+    pub canonical: Option<bool>,
+}
+
+// T1.9 CustomDomain
+// An owner's own domain attached to a connection. It only routes to the
+// tunnel once `verified_at` is set.
+#[derive(FromRow, Debug, Clone)]
+pub struct CustomDomain {
+    pub id: i64,
+    pub connection_id: i64,
+    pub domain: String,
+    pub method: VerificationMethod,
+    pub token: String,
+    pub verified_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum VerificationMethod {
+    // TXT record `sando-verification={token}` at `_sando-challenge.{domain}`
+    #[default]
+    Dns,
+    // `http://{domain}/.well-known/sando-challenge/{token}` answering the token,
+    // served by the domain's own web server; Sando doesn't answer it
+    Http,
+}
+
+pub const DNS_CHALLENGE_PREFIX: &str = "_sando-challenge";
+pub const HTTP_CHALLENGE_PATH: &str = "/.well-known/sando-challenge";
+
+impl CustomDomain {
+    pub fn dns_record_name(&self) -> String {
+        format!("{}.{}", DNS_CHALLENGE_PREFIX, self.domain)
+    }
+
+    pub fn dns_record_value(&self) -> String {
+        format!("sando-verification={}", self.token)
+    }
+
+    pub fn http_challenge_url(&self) -> String {
+        format!("http://{}{}/{}", self.domain, HTTP_CHALLENGE_PATH, self.token)
+    }
+}
+
+#[derive(Deserialize, Debug, Default, ToSchema)]
+pub struct CustomDomainForm {
+    pub domain: String,
+    #[serde(default)]
+    pub method: VerificationMethod,
+}
+
+// JSON representation of a custom domain, with what to publish to verify it
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CustomDomainResource {
+    pub id: i64,
+    pub connection_id: i64,
+    pub domain: String,
+    pub method: VerificationMethod,
+    pub verified: bool,
+    pub verified_at: Option<String>,
+    pub created_at: String,
+    pub challenge: DomainChallenge,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DomainChallenge {
+    pub dns_record_name: String,
+    pub dns_record_value: String,
+    pub http_url: String,
+    pub http_body: String,
+}
+
+impl From<CustomDomain> for CustomDomainResource {
+    fn from(domain: CustomDomain) -> Self {
+        let challenge = DomainChallenge {
+            dns_record_name: domain.dns_record_name(),
+            dns_record_value: domain.dns_record_value(),
+            http_url: domain.http_challenge_url(),
+            http_body: domain.token.clone(),
+        };
+
+        Self {
+            id: domain.id,
+            connection_id: domain.connection_id,
+            domain: domain.domain,
+            method: domain.method,
+            verified: domain.verified_at.is_some(),
+            verified_at: domain.verified_at,
+            created_at: domain.created_at,
+            challenge,
+        }
+    }
+}
+
+// T1.10 AuditEntry
+// One row of the `audit_log` table. `details` is a JSON object describing
+// the change; holesail keys are never written to it.
+#[derive(Serialize, Debug, Clone, FromRow, ToSchema)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: String,
+    pub actor: AuditActor,
+    pub action: AuditAction,
+    pub connection_id: Option<i64>,
+    pub subdomain: Option<String>,
+    pub owner: Option<String>,
+    #[sqlx(try_from = "String")]
+    #[schema(value_type = Object)]
+    pub details: AuditDetails,
+}
+
+// Who made a change. Sando has no user accounts, so this is the channel the
+// request came through; the connection's owner contact is recorded next to it.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum AuditActor {
+    // The HTML pages
+    Web,
+    // The JSON API
+    Api,
+    // Operator endpoints, authenticated with the admin token
+    Admin,
+    // The `sando` command line
+    Cli,
+    // Background tasks
+    System,
+}
+
+impl AuditActor {
+    pub const ALL: [AuditActor; 5] = [Self::Web, Self::Api, Self::Admin, Self::Cli, Self::System];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Web => "web",
+            Self::Api => "api",
+            Self::Admin => "admin",
+            Self::Cli => "cli",
+            Self::System => "system",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    // Subdomain, key, metadata, alias or custom domain changes
+    Update,
+    Delete,
+    BatchDelete,
+    PaymentAccepted,
+    Import,
+    Export,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 7] = [
+        Self::Create,
+        Self::Update,
+        Self::Delete,
+        Self::BatchDelete,
+        Self::PaymentAccepted,
+        Self::Import,
+        Self::Export,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Create => "create",
+            Self::Update => "update",
+            Self::Delete => "delete",
+            Self::BatchDelete => "batch_delete",
+            Self::PaymentAccepted => "payment_accepted",
+            Self::Import => "import",
+            Self::Export => "export",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct AuditDetails(pub serde_json::Map<String, serde_json::Value>);
+
+impl TryFrom<String> for AuditDetails {
+    type Error = serde_json::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        serde_json::from_str(&value).map(AuditDetails)
+    }
+}
+
+// Filters for the audit log, shared by the admin page and the JSON API.
+// Newest entries come first.
+#[derive(Deserialize, Debug, Default, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub action: Option<AuditAction>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub actor: Option<AuditActor>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub connection_id: Option<i64>,
+    // Case-insensitive text search on subdomain, owner and details
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub q: Option<String>,
+    // Only entries at or after / before this UTC time, `YYYY-MM-DD[ HH:MM:SS]`
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub since: Option<String>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub until: Option<String>,
+    // `next_cursor` of the previous page
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub cursor: Option<i64>,
+    // Page size (default 100, max 500)
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub limit: Option<u32>,
+}
+
+// T1.11 TunnelLimits
+// Request limits for a tunnel: a token bucket for all its traffic, one per
+// client address, and a cap on requests in flight. Unset fields are
+// unlimited. Stored as a JSON object; like tags, the edit form sends
+// `key=value` lines (`rate=20`, `client_burst=10`, ...).
+#[derive(Serialize, Debug, Clone, Default, PartialEq, ToSchema)]
+pub struct TunnelLimits {
+    // Requests per second across all clients, and how many may come at once
+    pub rate: Option<f64>,
+    pub burst: Option<u32>,
+    // The same for each client address
+    pub client_rate: Option<f64>,
+    pub client_burst: Option<u32>,
+    // Requests being proxied at the same time
+    pub max_in_flight: Option<u32>,
+}
+
+impl TunnelLimits {
+    // Parses `key=value` pairs separated by commas or newlines
+    pub fn parse_lines(text: &str) -> Result<Self, String> {
+        let mut limits = Self::default();
+        for pair in text.split([',', '\n']).map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or_else(|| format!("Limit '{}' must look like key=value", pair))?;
+            let (key, value) = (key.trim().to_ascii_lowercase(), value.trim());
+            let invalid = || format!("Limit '{}' has an invalid value '{}'", key, value);
+            match key.as_str() {
+                "rate" => limits.rate = Some(value.parse().map_err(|_| invalid())?),
+                "burst" => limits.burst = Some(value.parse().map_err(|_| invalid())?),
+                "client_rate" => limits.client_rate = Some(value.parse().map_err(|_| invalid())?),
+                "client_burst" => limits.client_burst = Some(value.parse().map_err(|_| invalid())?),
+                "max_in_flight" => limits.max_in_flight = Some(value.parse().map_err(|_| invalid())?),
+                _ => return Err(format!("Unknown limit '{}'; use rate, burst, client_rate, client_burst or max_in_flight", key)),
+            }
+        }
+        Ok(limits)
+    }
+
+    pub fn to_lines(&self) -> String {
+        let fields = [
+            ("rate", self.rate.map(|rate| rate.to_string())),
+            ("burst", self.burst.map(|burst| burst.to_string())),
+            ("client_rate", self.client_rate.map(|rate| rate.to_string())),
+            ("client_burst", self.client_burst.map(|burst| burst.to_string())),
+            ("max_in_flight", self.max_in_flight.map(|max| max.to_string())),
+        ];
+        fields
+            .into_iter()
+            .filter_map(|(key, value)| Some(format!("{}={}", key, value?)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (name, rate) in [("rate", self.rate), ("client_rate", self.client_rate)] {
+            if rate.is_some_and(|rate| !rate.is_finite() || rate <= 0.0) {
+                errors.push(format!("Limit '{}' must be a positive number of requests per second", name));
+            }
+        }
+        for (name, count) in [("burst", self.burst), ("client_burst", self.client_burst), ("max_in_flight", self.max_in_flight)] {
+            if count == Some(0) {
+                errors.push(format!("Limit '{}' must be at least 1", name));
+            }
+        }
+        errors
+    }
+
+    // The stricter of two sets of limits, field by field
+    pub fn tighten(&self, other: &TunnelLimits) -> TunnelLimits {
+        fn min<T: PartialOrd + Copy>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(if b < a { b } else { a }),
+                (a, b) => a.or(b),
+            }
+        }
+        TunnelLimits {
+            rate: min(self.rate, other.rate),
+            burst: min(self.burst, other.burst),
+            client_rate: min(self.client_rate, other.client_rate),
+            client_burst: min(self.client_burst, other.client_burst),
+            max_in_flight: min(self.max_in_flight, other.max_in_flight),
+        }
+    }
+}
+
+impl TryFrom<String> for TunnelLimits {
+    type Error = serde_json::Error;
+
+    fn try_from(json: String) -> Result<Self, Self::Error> {
+        serde_json::from_str(&json)
+    }
+}
+
+impl<'de> Deserialize<'de> for TunnelLimits {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Fields {
+            rate: Option<f64>,
+            burst: Option<u32>,
+            client_rate: Option<f64>,
+            client_burst: Option<u32>,
+            max_in_flight: Option<u32>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum LimitsInput {
+            Fields(Fields),
+            Lines(String),
+        }
+
+        match LimitsInput::deserialize(deserializer)? {
+            LimitsInput::Fields(fields) => Ok(Self {
+                rate: fields.rate,
+                burst: fields.burst,
+                client_rate: fields.client_rate,
+                client_burst: fields.client_burst,
+                max_in_flight: fields.max_in_flight,
+            }),
+            LimitsInput::Lines(text) => Self::parse_lines(&text).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+// T1.12 Access Protection
+// A credential that opens a protected tunnel. Secrets are only stored
+// hashed; a token is returned once, when it is created.
+#[derive(FromRow, Serialize, Debug, Clone, ToSchema)]
+pub struct ConnectionCredential {
+    pub id: i64,
+    pub connection_id: i64,
+    pub kind: CredentialKind,
+    pub name: String, // The Basic user name, or the token's label
+    pub created_at: String,
+    // Only set in the answer to creating a token; it can't be shown again
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum CredentialKind {
+    // HTTP Basic auth with a user name and password
+    #[default]
+    Basic,
+    // `Authorization: Bearer {token}` with a token generated by the server
+    Token,
+}
+
+impl CredentialKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Basic => "basic",
+            Self::Token => "token",
+        }
+    }
+}
+
+// Adds a credential. Basic users need a password; tokens are generated.
+#[derive(Deserialize, Debug, Default, ToSchema)]
+pub struct CredentialForm {
+    #[serde(default)]
+    pub kind: CredentialKind,
+    pub name: String,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+// Creates a signed link. `expires_in` is in seconds (default one day, at
+// most 30 days); `path` is where the link lands (default `/`).
+#[derive(Deserialize, Debug, Default, ToSchema)]
+pub struct SignedLinkForm {
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+// A shareable link into a protected tunnel. Opening it sets a cookie that
+// keeps the browser signed in until the link expires.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct SignedLink {
+    pub url: String,
+    pub expires_at: String,
+}
+
+// T1.13 IpRules
+// Which client addresses may reach a tunnel. Addresses in `deny` never may;
+// when `allow` isn't empty, only addresses in it may. Entries are CIDR
+// ranges or single addresses. Stored as a JSON object; the edit form sends
+// `allow=10.0.0.0/8` and `deny=203.0.113.7` lines.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq, ToSchema)]
+pub struct IpRules {
+    #[schema(value_type = Vec<String>)]
+    pub allow: Vec<IpNet>,
+    #[schema(value_type = Vec<String>)]
+    pub deny: Vec<IpNet>,
+}
+
+pub const MAX_IP_RULES: usize = 256;
+
+impl IpRules {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    // Parses `allow=...` and `deny=...` pairs separated by commas or newlines
+    pub fn parse_lines(text: &str) -> Result<Self, String> {
+        let mut rules = Self::default();
+        for pair in text.split([',', '\n']).map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or_else(|| format!("Rule '{}' must look like allow=<range> or deny=<range>", pair))?;
+            let net = parse_ip_net(value.trim())?;
+            match key.trim().to_ascii_lowercase().as_str() {
+                "allow" => rules.allow.push(net),
+                "deny" => rules.deny.push(net),
+                key => return Err(format!("Unknown rule '{}'; use allow or deny", key)),
+            }
+        }
+        Ok(rules)
+    }
+
+    pub fn to_lines(&self) -> String {
+        let allow = self.allow.iter().map(|net| format!("allow={}", net));
+        let deny = self.deny.iter().map(|net| format!("deny={}", net));
+        allow.chain(deny).collect::<Vec<_>>().join("\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (name, nets) in [("allow", &self.allow), ("deny", &self.deny)] {
+            if nets.len() > MAX_IP_RULES {
+                errors.push(format!("At most {} '{}' rules", MAX_IP_RULES, name));
+            }
+        }
+        errors
+    }
+}
+
+// A CIDR range, or a single address as a range of one
+pub fn parse_ip_net(value: &str) -> Result<IpNet, String> {
+    value
+        .parse::<IpNet>()
+        .map(|net| net.trunc())
+        .or_else(|_| value.parse::<IpAddr>().map(|ip| IpNet::from(ip.to_canonical())))
+        .map_err(|_| format!("'{}' is not an address or CIDR range", value))
+}
+
+impl TryFrom<String> for IpRules {
+    type Error = serde_json::Error;
+
+    fn try_from(json: String) -> Result<Self, Self::Error> {
+        serde_json::from_str(&json)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpRules {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Fields {
+            #[serde(default)]
+            allow: Vec<String>,
+            #[serde(default)]
+            deny: Vec<String>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RulesInput {
+            Fields(Fields),
+            Lines(String),
+        }
+
+        let parse = |entries: Vec<String>| -> Result<Vec<IpNet>, D::Error> {
+            entries.iter().map(|entry| parse_ip_net(entry.trim()).map_err(serde::de::Error::custom)).collect()
+        };
+        match RulesInput::deserialize(deserializer)? {
+            RulesInput::Fields(fields) => Ok(Self { allow: parse(fields.allow)?, deny: parse(fields.deny)? }),
+            RulesInput::Lines(text) => Self::parse_lines(&text).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    // Query strings carry everything as text, so numeric fields need a retry
+    let parsed: Result<T, serde::de::value::Error> = T::deserialize(value.into_deserializer())
+        .or_else(|e| match value.parse::<u64>() {
+            Ok(number) => T::deserialize(number.into_deserializer()),
+            Err(_) => Err(e),
+        });
+    parsed.map(Some).map_err(serde::de::Error::custom)
+}
@@ -0,0 +1,392 @@
+/**
+ * R11.0 Tunnel Access Route
+ * =========================
+ *
+ * Lets operators protect a connection with HTTP Basic users, bearer tokens
+ * and signed links, which `services::access` checks on every proxied request.
+ * Every route here needs the admin token (see `routes::admin`); otherwise
+ * anyone could hand themselves access to any tunnel.
+ * Passwords and tokens are hashed before they are stored; a token is shown
+ * once, when it is created, like signed links. Revoking links clears the
+ * connection's link secret, which invalidates every link handed out so far.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: R11.1, R11.2, R11.3, R11.4, R11.5, R11.6, R11.7, R11.8
+ */
+// R11.1 Dependencies
+use crate::models::{
+    AuditAction, AuditActor, ConnectionCredential, CredentialForm, CredentialKind, SignedLink, SignedLinkForm,
+};
+use crate::routes::admin::admin_challenge;
+use crate::routes::connections::{fetch_connection, render_edit_page};
+use crate::services::access::{generate_secret, hash_password, hash_token, sign_link, LINK_PARAM};
+use crate::services::audit::AuditEvent;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use sqlx::sqlite::SqlitePool;
+
+const MAX_NAME_LENGTH: usize = 100;
+const MIN_PASSWORD_LENGTH: usize = 8;
+const DEFAULT_LINK_LIFETIME: u64 = 24 * 60 * 60;
+const MAX_LINK_LIFETIME: u64 = 30 * 24 * 60 * 60;
+
+// R11.2 Add Credential Handler
+// POST /connections/:id/credentials from the edit page. A new token is shown
+// on the page right away, since it can't be looked up later.
+#[tracing::instrument(name = "add_credential", skip(app_state, headers, form))]
+pub async fn add_credential_handler(
+    State(app_state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    Form(form): Form<CredentialForm>,
+) -> Result<Response, (StatusCode, String)> {
+    if let Some(response) = admin_challenge(&app_state, &headers) {
+        return Ok(response);
+    }
+    let credential = add_credential(app_state.pool.as_ref(), id, form, AuditActor::Admin).await?;
+    match credential.token {
+        Some(token) => Ok(render_edit_page(&app_state, id, true, Some(&token))
+            .await
+            .map_err(|status| (status, "Failed to render the edit page".to_string()))?
+            .into_response()),
+        None => Ok(Redirect::to(&format!("/connections/{}/edit", id)).into_response()),
+    }
+}
+
+// R11.3 Remove Credential Handler
+// DELETE /connections/:id/credentials/:credential_id, sent from script
+#[tracing::instrument(name = "remove_credential", skip(app_state, headers))]
+pub async fn remove_credential_handler(
+    State(app_state): State<AppState>,
+    Path((id, credential_id)): Path<(i64, i64)>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    if let Some(response) = admin_challenge(&app_state, &headers) {
+        return Ok(response);
+    }
+    remove_credential(app_state.pool.as_ref(), id, credential_id, AuditActor::Admin).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// R11.4 Signed Link Handlers
+// POST /connections/:id/links from the edit page shows the new link there;
+// DELETE /connections/:id/links, sent from script, revokes them all
+#[tracing::instrument(name = "create_link", skip(app_state, headers, form))]
+pub async fn create_link_handler(
+    State(app_state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    Form(form): Form<SignedLinkForm>,
+) -> Result<Response, (StatusCode, String)> {
+    if let Some(response) = admin_challenge(&app_state, &headers) {
+        return Ok(response);
+    }
+    let link = create_link(&app_state, id, form, AuditActor::Admin).await?;
+    Ok(render_edit_page(&app_state, id, true, Some(&link.url))
+        .await
+        .map_err(|status| (status, "Failed to render the edit page".to_string()))?
+        .into_response())
+}
+
+#[tracing::instrument(name = "revoke_links", skip(app_state, headers))]
+pub async fn revoke_links_handler(
+    State(app_state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    if let Some(response) = admin_challenge(&app_state, &headers) {
+        return Ok(response);
+    }
+    revoke_links(app_state.pool.as_ref(), id, AuditActor::Admin).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// R11.5 Credential Logic
+// Shared by the HTML handlers above and the JSON API. Changes are recorded
+// in the audit log as updates by `actor`; secrets never are.
+pub async fn list_credentials(pool: &SqlitePool, connection_id: i64) -> Result<Vec<ConnectionCredential>, sqlx::Error> {
+    sqlx::query_as::<_, ConnectionCredential>(
+        "SELECT id, connection_id, kind, name, created_at FROM connection_credentials \
+         WHERE connection_id = ? ORDER BY kind, name",
+    )
+    .bind(connection_id)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn add_credential(
+    pool: &SqlitePool,
+    connection_id: i64,
+    form: CredentialForm,
+    actor: AuditActor,
+) -> Result<ConnectionCredential, (StatusCode, String)> {
+    let name = form.name.trim().to_string();
+    // The edit form always sends the password field
+    let password = form.password.filter(|password| !password.is_empty());
+    let errors = validate_credential(&name, form.kind, password.as_deref());
+    if !errors.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, errors.join(". ")));
+    }
+    fetch_connection(pool, connection_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, format!("Connection {} not found", connection_id)))?;
+
+    let (secret_hash, token) = match (form.kind, password) {
+        (CredentialKind::Basic, Some(password)) => (hash_password(&password), None),
+        _ => {
+            let token = generate_secret();
+            (hash_token(&token), Some(token))
+        }
+    };
+    let credential_id =
+        sqlx::query("INSERT INTO connection_credentials (connection_id, kind, name, secret_hash) VALUES (?, ?, ?, ?)")
+            .bind(connection_id)
+            .bind(form.kind)
+            .bind(&name)
+            .bind(&secret_hash)
+            .execute(pool)
+            .await
+            .map_err(|e| match &e {
+                sqlx::Error::Database(db) if db.is_unique_violation() => (
+                    StatusCode::CONFLICT,
+                    format!("This connection already has a {} credential named '{}'", form.kind.as_str(), name),
+                ),
+                _ => internal_error(e),
+            })?
+            .last_insert_rowid();
+
+    tracing::info!("Added {} credential '{}' to connection {}", form.kind.as_str(), name, connection_id);
+    AuditEvent::new(actor, AuditAction::Update)
+        .connection_id(connection_id)
+        .detail("credential_added", name.as_str())
+        .detail("kind", form.kind.as_str())
+        .record(pool)
+        .await;
+
+    let mut credential = fetch_credential(pool, connection_id, credential_id).await?;
+    credential.token = token;
+    Ok(credential)
+}
+
+pub async fn remove_credential(
+    pool: &SqlitePool,
+    connection_id: i64,
+    credential_id: i64,
+    actor: AuditActor,
+) -> Result<(), (StatusCode, String)> {
+    let credential = fetch_credential(pool, connection_id, credential_id).await?;
+    sqlx::query("DELETE FROM connection_credentials WHERE id = ?")
+        .bind(credential_id)
+        .execute(pool)
+        .await
+        .map_err(internal_error)?;
+
+    tracing::info!("Removed {} credential '{}' from connection {}", credential.kind.as_str(), credential.name, connection_id);
+    AuditEvent::new(actor, AuditAction::Update)
+        .connection_id(connection_id)
+        .detail("credential_removed", credential.name.as_str())
+        .detail("kind", credential.kind.as_str())
+        .record(pool)
+        .await;
+    Ok(())
+}
+
+// R11.6 Signed Link Logic
+// The first link creates the connection's link secret, which also makes the
+// tunnel protected from then on
+pub async fn create_link(
+    app_state: &AppState,
+    connection_id: i64,
+    form: SignedLinkForm,
+    actor: AuditActor,
+) -> Result<SignedLink, (StatusCode, String)> {
+    let pool = app_state.pool.as_ref();
+    let lifetime = form.expires_in.unwrap_or(DEFAULT_LINK_LIFETIME);
+    if lifetime == 0 || lifetime > MAX_LINK_LIFETIME {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Links expire after 1 to {} seconds", MAX_LINK_LIFETIME),
+        ));
+    }
+    let path = form.path.as_deref().map(str::trim).filter(|path| !path.is_empty()).unwrap_or("/");
+    if !path.starts_with('/') || path.starts_with("//") || path.contains(char::is_whitespace) {
+        return Err((StatusCode::BAD_REQUEST, "The path must start with a single '/'".to_string()));
+    }
+
+    let connection = fetch_connection(pool, connection_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, format!("Connection {} not found", connection_id)))?;
+    // A link on the key's own hostname would hand out the key
+    let subdomain = connection
+        .subdomain
+        .clone()
+        .ok_or((StatusCode::BAD_REQUEST, "Give the connection a subdomain before sharing links".to_string()))?;
+    let secret = match connection.link_secret {
+        Some(secret) => secret,
+        None => {
+            // Another request may have created a secret meanwhile; keep theirs
+            sqlx::query("UPDATE connections SET link_secret = ? WHERE id = ? AND link_secret IS NULL")
+                .bind(generate_secret())
+                .bind(connection_id)
+                .execute(pool)
+                .await
+                .map_err(internal_error)?;
+            let (secret,): (Option<String>,) = sqlx::query_as("SELECT link_secret FROM connections WHERE id = ?")
+                .bind(connection_id)
+                .fetch_one(pool)
+                .await
+                .map_err(internal_error)?;
+            secret.ok_or_else(|| internal_error(sqlx::Error::RowNotFound))?
+        }
+    };
+
+    let expires = chrono::Utc::now().timestamp() + lifetime as i64;
+    let separator = if path.contains('?') { '&' } else { '?' };
+    let url = format!(
+        "https://{}.{}{}{}{}={}",
+        subdomain,
+        app_state.host,
+        path,
+        separator,
+        LINK_PARAM,
+        sign_link(&secret, expires)
+    );
+    let expires_at = chrono::DateTime::from_timestamp(expires, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+
+    tracing::info!("Created a signed link for connection {}, expiring {}", connection_id, expires_at);
+    AuditEvent::new(actor, AuditAction::Update)
+        .connection_id(connection_id)
+        .detail("link_created", expires_at.as_str())
+        .record(pool)
+        .await;
+    Ok(SignedLink { url, expires_at })
+}
+
+// Links are only protection together with the secret, so a tunnel without
+// credentials is public again afterwards
+pub async fn revoke_links(pool: &SqlitePool, connection_id: i64, actor: AuditActor) -> Result<(), (StatusCode, String)> {
+    let result = sqlx::query("UPDATE connections SET link_secret = NULL WHERE id = ?")
+        .bind(connection_id)
+        .execute(pool)
+        .await
+        .map_err(internal_error)?;
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, format!("Connection {} not found", connection_id)));
+    }
+
+    tracing::info!("Revoked the signed links of connection {}", connection_id);
+    AuditEvent::new(actor, AuditAction::Update)
+        .connection_id(connection_id)
+        .detail("links_revoked", true)
+        .record(pool)
+        .await;
+    Ok(())
+}
+
+// R11.7 Helper Functions
+fn validate_credential(name: &str, kind: CredentialKind, password: Option<&str>) -> Vec<String> {
+    let mut errors = Vec::new();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        errors.push(format!("The name must be 1 to {} characters", MAX_NAME_LENGTH));
+    }
+    match kind {
+        // Basic auth separates the user name from the password with a colon
+        CredentialKind::Basic if name.contains(':') => errors.push("User names can't contain ':'".to_string()),
+        CredentialKind::Basic if password.map_or(0, |password| password.chars().count()) < MIN_PASSWORD_LENGTH => {
+            errors.push(format!("Passwords need at least {} characters", MIN_PASSWORD_LENGTH));
+        }
+        CredentialKind::Token if password.is_some() => {
+            errors.push("Tokens are generated by the server; leave the password empty".to_string());
+        }
+        _ => {}
+    }
+    errors
+}
+
+async fn fetch_credential(
+    pool: &SqlitePool,
+    connection_id: i64,
+    credential_id: i64,
+) -> Result<ConnectionCredential, (StatusCode, String)> {
+    sqlx::query_as::<_, ConnectionCredential>(
+        "SELECT id, connection_id, kind, name, created_at FROM connection_credentials WHERE id = ? AND connection_id = ?",
+    )
+    .bind(credential_id)
+    .bind(connection_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(internal_error)?
+    .ok_or((StatusCode::NOT_FOUND, format!("Credential {} not found on connection {}", credential_id, connection_id)))
+}
+
+fn internal_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
+}
+
+// R11.8 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn test_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::migrate(&pool).await.unwrap();
+
+        sqlx::query("INSERT INTO connections (id, connection_string, port, subdomain) VALUES (1, 'key1', 4001, 'staging')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    fn basic(name: &str, password: &str) -> CredentialForm {
+        CredentialForm { kind: CredentialKind::Basic, name: name.to_string(), password: Some(password.to_string()) }
+    }
+
+    #[tokio::test]
+    async fn test_credentials_are_stored_hashed() {
+        let pool = test_pool().await;
+
+        let user = add_credential(&pool, 1, basic("alice", "hunter22"), AuditActor::Admin).await.unwrap();
+        assert_eq!((user.kind, user.name.as_str(), user.token), (CredentialKind::Basic, "alice", None));
+        let token_form = CredentialForm { kind: CredentialKind::Token, name: "ci".to_string(), password: None };
+        let token = add_credential(&pool, 1, token_form, AuditActor::Admin).await.unwrap();
+        let secret = token.token.clone().unwrap();
+
+        let hashes: Vec<(String,)> = sqlx::query_as("SELECT secret_hash FROM connection_credentials ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert!(hashes[0].0.starts_with("pbkdf2-sha256$") && !hashes[0].0.contains("hunter22"));
+        assert_eq!(hashes[1].0, hash_token(&secret));
+        assert_eq!(list_credentials(&pool, 1).await.unwrap().len(), 2);
+
+        let (status, _) = add_credential(&pool, 1, basic("alice", "another-one"), AuditActor::Admin).await.unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = add_credential(&pool, 1, basic("bob", "short"), AuditActor::Admin).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = add_credential(&pool, 1, basic("a:b", "long enough"), AuditActor::Admin).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = add_credential(&pool, 9, basic("carol", "long enough"), AuditActor::Admin).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        remove_credential(&pool, 1, user.id, AuditActor::Admin).await.unwrap();
+        sqlx::query("DELETE FROM connections WHERE id = 1").execute(&pool).await.unwrap();
+        assert!(list_credentials(&pool, 1).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+/**
+ * R10.0 Admin Routes
+ * ==================
+ *
+ * Operator-only pages. They are disabled unless an admin token is configured
+ * (SANDO_ADMIN_TOKEN) and accept it either as a Bearer token or as the
+ * password of HTTP Basic auth, so a browser can sign in with its own prompt.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: R10.1, R10.2, R10.3
+ */
+// R10.1 Dependencies
+use crate::components::audit_log::audit_log;
+use crate::models::AuditQuery;
+use crate::services::audit::search_audit_log;
+use crate::AppState;
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+
+// R10.2 Admin Authentication
+// FORBIDDEN when no admin token is configured, UNAUTHORIZED when the request
+// doesn't carry it. Digests are compared so the check doesn't leak how much
+// of the token matched.
+pub fn check_admin(app_state: &AppState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let Some(admin_token) = &app_state.admin_token else {
+        return Err(StatusCode::FORBIDDEN);
+    };
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    let presented = if let Some(token) = authorization.strip_prefix("Bearer ") {
+        token.to_string()
+    } else if let Some(credentials) = authorization.strip_prefix("Basic ") {
+        // Any user name; the password is the token
+        STANDARD
+            .decode(credentials.trim())
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| decoded.split_once(':').map(|(_, password)| password.to_string()))
+            .unwrap_or_default()
+    } else {
+        String::new()
+    };
+
+    if Sha256::digest(presented.as_bytes()) != Sha256::digest(admin_token.as_bytes()) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(())
+}
+
+// `check_admin` for pages and forms: the response for a request without the
+// admin token, a Basic challenge so the browser prompts for it, or `None`
+// when the request has it
+pub fn admin_challenge(app_state: &AppState, headers: &HeaderMap) -> Option<Response> {
+    match check_admin(app_state, headers) {
+        Ok(()) => None,
+        Err(StatusCode::UNAUTHORIZED) => Some(
+            (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Basic realm=\"Sando admin\"")],
+                "Sign in with the admin token as the password",
+            )
+                .into_response(),
+        ),
+        Err(status) => Some((status, "Set SANDO_ADMIN_TOKEN to enable the admin pages").into_response()),
+    }
+}
+
+// R10.3 Audit Log Page
+// GET /admin/audit with the same filters as GET /api/v1/audit.
+#[tracing::instrument(name = "audit_log_page", skip(app_state, headers))]
+pub async fn audit_log_page(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<AuditQuery>,
+) -> Response {
+    if let Some(response) = admin_challenge(&app_state, &headers) {
+        return response;
+    }
+
+    match search_audit_log(app_state.pool.as_ref(), &query).await {
+        Ok(page) => Html(audit_log(&page.entries, &query, page.next_cursor).into_string()).into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
@@ -0,0 +1,979 @@
+/**
+ * R6.0 Connections API Route
+ * ==========================
+ *
+ * Versioned JSON API under `/api/v1/connections` for managing tunnels without
+ * scraping HTML. Mirrors the HTML routes: list, get, create (402-aware),
+ * update and delete, plus the hostnames (aliases), custom domains and access
+ * credentials of each connection, and operator-only import/export and audit log.
+ * Every error is returned as
+ * `{"error": {"code": "...", "message": "..."}}`.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: R6.1, R6.2, R6.3, R6.4, R6.5, R6.6, R6.7, R6.8, R6.9, R6.10, R6.11, R6.12, R6.13
+ */
+// R6.1 Dependencies
+use crate::models::{
+    AuditAction, AuditActor, AuditEntry, AuditQuery, ConnectionCredential, ConnectionForm, ConnectionHostname, ConnectionQuery,
+    ConnectionResource, ConnectionUpdateForm, CredentialForm, CustomDomainForm, CustomDomainResource, HostnameForm,
+    HostnameUpdateForm, SignedLink, SignedLinkForm,
+};
+use crate::routes::connections::{
+    apply_connection_update, check_owner, delete_connections, fetch_connection, search_connections, Authority,
+    MAX_OWNER_LENGTH,
+};
+use crate::routes::admin::check_admin;
+use crate::routes::{access, domains, hostnames};
+use crate::routes::submit::{
+    check_connection_string, check_custom_subdomain, create_payment_request, default_subdomain, record_payment,
+    store_connection, validate_cashu_token,
+};
+use crate::services::antispam::{require_proof_of_work, POW_HEADER};
+use crate::services::access::protected_connections;
+use crate::services::audit::{self, AuditEvent};
+use crate::services::backup::{self, ExportDocument, ExportFormat, ImportReport};
+use crate::{AppState, Connection};
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        Path, Query, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+// R6.2 Error Body
+// The single error shape used by every API endpoint.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self { status, code, message: message.into() }
+    }
+
+    pub fn not_found(id: i64) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", format!("Connection {} not found", id))
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", message)
+    }
+}
+
+// Maps the (status, message) pairs returned by the shared route helpers.
+impl From<(StatusCode, String)> for ApiError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        let code = match status {
+            StatusCode::BAD_REQUEST => "invalid_request",
+            StatusCode::NOT_FOUND => "not_found",
+            StatusCode::CONFLICT => "conflict",
+            StatusCode::PAYMENT_REQUIRED => "payment_required",
+            StatusCode::UNAUTHORIZED => "unauthorized",
+            StatusCode::FORBIDDEN => "forbidden",
+            StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+            StatusCode::UNPROCESSABLE_ENTITY => "verification_failed",
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => "upstream_unavailable",
+            _ => "internal_error",
+        };
+        Self::new(status, code, message)
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        tracing::error!("Database error: {}", e);
+        Self::internal("Database error")
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::new(rejection.status(), "invalid_request", rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request", rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request", rejection.body_text())
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ErrorBody {
+    pub error: ErrorDetail,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ErrorDetail {
+    pub code: String,
+    pub message: String,
+}
+
+// Body of the 402 answer to an unpaid create. `payment_request` repeats the
+// encoded NUT-18 request from the `X-Cashu` header.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PaymentRequiredBody {
+    pub error: ErrorDetail,
+    pub payment_request: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: ErrorDetail { code: self.code.to_string(), message: self.message },
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ConnectionList {
+    pub connections: Vec<ConnectionResource>,
+    // Pass as `cursor` to fetch the next page; null on the last page
+    pub next_cursor: Option<String>,
+}
+
+// R6.3 List Connections
+// GET /api/v1/connections
+#[utoipa::path(
+    get,
+    path = "/api/v1/connections",
+    tag = "connections",
+    params(ConnectionQuery),
+    responses(
+        (status = 200, description = "One page of matching connections", body = ConnectionList),
+        (status = 400, description = "Invalid query or cursor", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_list_connections", skip(app_state, headers, query))]
+pub async fn list_connections(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    query: Result<Query<ConnectionQuery>, QueryRejection>,
+) -> ApiResult<Json<ConnectionList>> {
+    let Query(query) = query?;
+    let page = search_connections(&app_state, &query).await?;
+
+    Ok(Json(ConnectionList {
+        connections: connection_resources(&app_state, &headers, page.connections).await?,
+        next_cursor: page.next_cursor,
+    }))
+}
+
+// R6.4 Get Connection
+// GET /api/v1/connections/:id
+#[utoipa::path(
+    get,
+    path = "/api/v1/connections/{id}",
+    tag = "connections",
+    params(("id" = i64, Path, description = "Connection id")),
+    responses(
+        (status = 200, description = "The connection", body = ConnectionResource),
+        (status = 404, description = "No such connection", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_get_connection", skip(app_state, headers, id))]
+pub async fn get_connection(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    id: Result<Path<i64>, PathRejection>,
+) -> ApiResult<Json<ConnectionResource>> {
+    let Path(id) = id?;
+    let connection = fetch_connection(app_state.pool.as_ref(), id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(id))?;
+
+    Ok(Json(connection_resource(&app_state, &headers, connection).await?))
+}
+
+// R6.5 Create Connection
+// POST /api/v1/connections
+// Without an `X-Cashu` token this answers 402 with a NUT-18 payment request in
+// the `X-Cashu` header (and in the body), exactly like `/submit`. When the
+// server asks for proof of work, that needs a hashcash stamp in `X-Sando-PoW`
+// (or `pow`); without one the answer is 403 with the challenge in that header.
+#[utoipa::path(
+    post,
+    path = "/api/v1/connections",
+    tag = "connections",
+    request_body = ConnectionForm,
+    params(
+        ("X-Cashu" = Option<String>, Header, description = "Cashu token paying for the connection"),
+        ("X-Sando-PoW" = Option<String>, Header, description = "Hashcash stamp 1:bits:date:resource:ext:rand:counter, when proof of work is required"),
+    ),
+    responses(
+        (status = 201, description = "Connection created", body = ConnectionResource),
+        (status = 400, description = "Invalid request, holesail key or payment token", body = ErrorBody),
+        (status = 401, description = "Setting the owner needs the admin token", body = ErrorBody),
+        (status = 402, description = "Payment required; the NUT-18 request is in the X-Cashu header", body = PaymentRequiredBody),
+        (status = 403, description = "Proof of work required; the challenge (1:bits:resource) is in the X-Sando-PoW header, or an owner was set without an admin token configured", body = ErrorBody),
+        (status = 409, description = "Subdomain already taken", body = ErrorBody),
+        (status = 429, description = "Too many payment requests from this address; see Retry-After", body = ErrorBody),
+        (status = 502, description = "Connect probe enabled and no peer answered for the key", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_create_connection", skip(app_state, headers, form))]
+pub async fn create_connection(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    form: Result<Json<ConnectionForm>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(form) = form?;
+    let owner = form.owner.as_deref().map(str::trim).filter(|owner| !owner.is_empty());
+    // Owners share namespaces, so only operators assign them
+    if owner.is_some() {
+        require_admin(&app_state, &headers)?;
+    }
+    if owner.is_some_and(|owner| owner.chars().count() > MAX_OWNER_LENGTH) {
+        let message = format!("Owner must be at most {} characters", MAX_OWNER_LENGTH);
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_request", message));
+    }
+    let subdomain = match check_custom_subdomain(&app_state, form.subdomain.as_deref(), owner).await? {
+        Some(subdomain) => subdomain,
+        None => default_subdomain(&app_state).await?,
+    };
+    let connection_string = check_connection_string(&app_state, &form.connection).await?.to_string();
+
+    let Some(token) = headers.get("X-Cashu") else {
+        if let Err(message) = require_proof_of_work(&app_state, &headers, form.pow.as_deref()) {
+            let challenge = app_state.proof_of_work.as_ref().map(|pow| pow.challenge(&app_state.host)).unwrap_or_default();
+            let body = ErrorBody { error: ErrorDetail { code: "proof_of_work_required".to_string(), message } };
+            return Ok((StatusCode::FORBIDDEN, [(POW_HEADER, challenge)], Json(body)).into_response());
+        }
+        let payment_request = create_payment_request().to_string();
+        let body = PaymentRequiredBody {
+            error: ErrorDetail {
+                code: "payment_required".to_string(),
+                message: "Payment required to create a connection".to_string(),
+            },
+            payment_request: payment_request.clone(),
+        };
+        return Ok((
+            StatusCode::PAYMENT_REQUIRED,
+            [("X-Cashu", payment_request)],
+            Json(body),
+        ).into_response());
+    };
+
+    let token = token
+        .to_str()
+        .map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, "invalid_payment", "Invalid X-Cashu header format"))?;
+    match validate_cashu_token(token) {
+        Ok(true) => {}
+        Ok(false) => return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_payment", "Invalid payment token provided.")),
+        Err(e) => return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_payment", e)),
+    }
+
+    record_payment(&app_state, AuditActor::Api, &subdomain, owner).await;
+
+    let id = store_connection(&app_state, &connection_string, &subdomain, owner, AuditActor::Api)
+        .await
+        .map_err(ApiError::internal)?;
+    let connection = fetch_connection(app_state.pool.as_ref(), id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(id))?;
+
+    tracing::info!("Connection {} created via API", id);
+    let resource = connection_resource(&app_state, &headers, connection).await?;
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, format!("/api/v1/connections/{}", id))],
+        Json(resource),
+    ).into_response())
+}
+
+// R6.6 Update Connection
+// PATCH /api/v1/connections/:id. Changes need the admin token or the current
+// key in `X-Sando-Key`; `tier` and `ip_rules` need the admin token. Values
+// sent back unchanged don't count, so the whole resource can be returned.
+#[utoipa::path(
+    patch,
+    path = "/api/v1/connections/{id}",
+    tag = "connections",
+    params(
+        ("id" = i64, Path, description = "Connection id"),
+        ("X-Sando-Key" = Option<String>, Header, description = "The connection's current holesail key, proving it is yours"),
+    ),
+    request_body = ConnectionUpdateForm,
+    responses(
+        (status = 200, description = "Updated connection", body = ConnectionResource),
+        (status = 400, description = "Invalid subdomain, holesail key, limits or tier", body = ErrorBody),
+        (status = 401, description = "Changes need the admin token or the current key; the tier and IP rules need the admin token", body = ErrorBody),
+        (status = 403, description = "No admin token configured for a tier or IP rule change", body = ErrorBody),
+        (status = 404, description = "No such connection", body = ErrorBody),
+        (status = 409, description = "Subdomain already taken", body = ErrorBody),
+        (status = 502, description = "The new key could not be confirmed online", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_update_connection", skip(app_state, headers, id, form))]
+pub async fn update_connection(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    id: Result<Path<i64>, PathRejection>,
+    form: Result<Json<ConnectionUpdateForm>, JsonRejection>,
+) -> ApiResult<Json<ConnectionResource>> {
+    let Path(id) = id?;
+    let Json(form) = form?;
+    let authority = Authority::from_headers(&app_state, &headers);
+    let connection = apply_connection_update(&app_state, id, form, authority.actor(AuditActor::Api), &authority).await?;
+
+    Ok(Json(connection_resource(&app_state, &headers, connection).await?))
+}
+
+// R6.7 Delete Connection
+// DELETE /api/v1/connections/:id
+#[utoipa::path(
+    delete,
+    path = "/api/v1/connections/{id}",
+    tag = "connections",
+    params(("id" = i64, Path, description = "Connection id")),
+    responses(
+        (status = 204, description = "Connection deleted"),
+        (status = 404, description = "No such connection", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_delete_connection", skip(app_state, id))]
+pub async fn delete_connection(
+    State(app_state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+) -> ApiResult<StatusCode> {
+    let Path(id) = id?;
+    match delete_connections(&app_state, &[id], AuditActor::Api, AuditAction::Delete).await? {
+        0 => Err(ApiError::not_found(id)),
+        _ => Ok(StatusCode::NO_CONTENT),
+    }
+}
+
+// R6.8 Hostnames
+// GET/POST /api/v1/connections/:id/hostnames and
+// PATCH/DELETE /api/v1/connections/:id/hostnames/:hostname_id
+#[derive(Serialize, Debug, ToSchema)]
+pub struct HostnameList {
+    pub hostnames: Vec<ConnectionHostname>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/connections/{id}/hostnames",
+    tag = "hostnames",
+    params(("id" = i64, Path, description = "Connection id")),
+    responses(
+        (status = 200, description = "Canonical hostname first, then aliases", body = HostnameList),
+        (status = 404, description = "No such connection", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_list_hostnames", skip(app_state, id))]
+pub async fn list_hostnames(
+    State(app_state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+) -> ApiResult<Json<HostnameList>> {
+    let Path(id) = id?;
+    let hostnames = hostnames::list_hostnames(app_state.pool.as_ref(), id).await?;
+    if hostnames.is_empty() && fetch_connection(app_state.pool.as_ref(), id).await?.is_none() {
+        return Err(ApiError::not_found(id));
+    }
+
+    Ok(Json(HostnameList { hostnames }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/connections/{id}/hostnames",
+    tag = "hostnames",
+    params(
+        ("id" = i64, Path, description = "Connection id"),
+        ("X-Sando-Key" = Option<String>, Header, description = "The connection's current holesail key, proving it is yours"),
+    ),
+    request_body = HostnameForm,
+    responses(
+        (status = 201, description = "Alias added", body = ConnectionHostname),
+        (status = 400, description = "Invalid hostname", body = ErrorBody),
+        (status = 401, description = "Needs the admin token or the connection's key in X-Sando-Key", body = ErrorBody),
+        (status = 404, description = "No such connection", body = ErrorBody),
+        (status = 409, description = "Hostname already taken", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_add_hostname", skip(app_state, headers, id, form))]
+pub async fn add_hostname(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    id: Result<Path<i64>, PathRejection>,
+    form: Result<Json<HostnameForm>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<ConnectionHostname>)> {
+    let Path(id) = id?;
+    let Json(form) = form?;
+    let authority = Authority::from_headers(&app_state, &headers);
+    check_owner(&app_state, id, &authority, "Adding hostnames").await?;
+    let actor = authority.actor(AuditActor::Api);
+    let hostname = hostnames::add_hostname(app_state.pool.as_ref(), &app_state.hosts, id, form, actor).await?;
+
+    Ok((StatusCode::CREATED, Json(hostname)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/connections/{id}/hostnames/{hostname_id}",
+    tag = "hostnames",
+    params(
+        ("id" = i64, Path, description = "Connection id"),
+        ("hostname_id" = i64, Path, description = "Hostname id"),
+        ("X-Sando-Key" = Option<String>, Header, description = "The connection's current holesail key, proving it is yours"),
+    ),
+    request_body = HostnameUpdateForm,
+    responses(
+        (status = 200, description = "Updated hostname", body = ConnectionHostname),
+        (status = 401, description = "Needs the admin token or the connection's key in X-Sando-Key", body = ErrorBody),
+        (status = 404, description = "No such hostname", body = ErrorBody),
+        (status = 409, description = "The canonical hostname can't be demoted", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_update_hostname", skip(app_state, headers, ids, form))]
+pub async fn update_hostname(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    ids: Result<Path<(i64, i64)>, PathRejection>,
+    form: Result<Json<HostnameUpdateForm>, JsonRejection>,
+) -> ApiResult<Json<ConnectionHostname>> {
+    let Path((id, hostname_id)) = ids?;
+    let Json(form) = form?;
+    let authority = Authority::from_headers(&app_state, &headers);
+    check_owner(&app_state, id, &authority, "Changing hostnames").await?;
+    let actor = authority.actor(AuditActor::Api);
+    let hostname = hostnames::update_hostname(app_state.pool.as_ref(), id, hostname_id, form, actor).await?;
+
+    Ok(Json(hostname))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/connections/{id}/hostnames/{hostname_id}",
+    tag = "hostnames",
+    params(
+        ("id" = i64, Path, description = "Connection id"),
+        ("hostname_id" = i64, Path, description = "Hostname id"),
+        ("X-Sando-Key" = Option<String>, Header, description = "The connection's current holesail key, proving it is yours"),
+    ),
+    responses(
+        (status = 204, description = "Alias removed"),
+        (status = 401, description = "Needs the admin token or the connection's key in X-Sando-Key", body = ErrorBody),
+        (status = 404, description = "No such hostname", body = ErrorBody),
+        (status = 409, description = "The canonical hostname can't be removed", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_remove_hostname", skip(app_state, headers, ids))]
+pub async fn remove_hostname(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    ids: Result<Path<(i64, i64)>, PathRejection>,
+) -> ApiResult<StatusCode> {
+    let Path((id, hostname_id)) = ids?;
+    let authority = Authority::from_headers(&app_state, &headers);
+    check_owner(&app_state, id, &authority, "Removing hostnames").await?;
+    hostnames::remove_hostname(app_state.pool.as_ref(), id, hostname_id, authority.actor(AuditActor::Api)).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// R6.9 Custom Domains
+// GET/POST /api/v1/connections/:id/domains,
+// DELETE /api/v1/connections/:id/domains/:domain_id and
+// POST /api/v1/connections/:id/domains/:domain_id/verify
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DomainList {
+    pub domains: Vec<CustomDomainResource>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/connections/{id}/domains",
+    tag = "domains",
+    params(("id" = i64, Path, description = "Connection id")),
+    responses(
+        (status = 200, description = "Custom domains, verified or pending", body = DomainList),
+        (status = 404, description = "No such connection", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_list_domains", skip(app_state, id))]
+pub async fn list_domains(
+    State(app_state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+) -> ApiResult<Json<DomainList>> {
+    let Path(id) = id?;
+    fetch_connection(app_state.pool.as_ref(), id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(id))?;
+    let domains = domains::list_domains(app_state.pool.as_ref(), id).await?;
+
+    Ok(Json(DomainList { domains: domains.into_iter().map(Into::into).collect() }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/connections/{id}/domains",
+    tag = "domains",
+    params(("id" = i64, Path, description = "Connection id")),
+    request_body = CustomDomainForm,
+    responses(
+        (status = 201, description = "Domain added; publish the challenge, then verify", body = CustomDomainResource),
+        (status = 400, description = "Invalid domain", body = ErrorBody),
+        (status = 404, description = "No such connection", body = ErrorBody),
+        (status = 409, description = "Domain already in use", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_add_domain", skip(app_state, id, form))]
+pub async fn add_domain(
+    State(app_state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+    form: Result<Json<CustomDomainForm>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<CustomDomainResource>)> {
+    let Path(id) = id?;
+    let Json(form) = form?;
+    let domain = domains::add_domain(&app_state, id, form, AuditActor::Api).await?;
+
+    Ok((StatusCode::CREATED, Json(domain.into())))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/connections/{id}/domains/{domain_id}/verify",
+    tag = "domains",
+    params(
+        ("id" = i64, Path, description = "Connection id"),
+        ("domain_id" = i64, Path, description = "Domain id"),
+    ),
+    responses(
+        (status = 200, description = "Domain verified and routed", body = CustomDomainResource),
+        (status = 404, description = "No such domain", body = ErrorBody),
+        (status = 409, description = "Domain verified by another connection", body = ErrorBody),
+        (status = 422, description = "Challenge not found", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_verify_domain", skip(app_state, ids))]
+pub async fn verify_domain(
+    State(app_state): State<AppState>,
+    ids: Result<Path<(i64, i64)>, PathRejection>,
+) -> ApiResult<Json<CustomDomainResource>> {
+    let Path((id, domain_id)) = ids?;
+    let domain = domains::verify_domain(&app_state, id, domain_id, AuditActor::Api).await?;
+
+    Ok(Json(domain.into()))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/connections/{id}/domains/{domain_id}",
+    tag = "domains",
+    params(
+        ("id" = i64, Path, description = "Connection id"),
+        ("domain_id" = i64, Path, description = "Domain id"),
+    ),
+    responses(
+        (status = 204, description = "Domain removed"),
+        (status = 404, description = "No such domain", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_remove_domain", skip(app_state, ids))]
+pub async fn remove_domain(
+    State(app_state): State<AppState>,
+    ids: Result<Path<(i64, i64)>, PathRejection>,
+) -> ApiResult<StatusCode> {
+    let Path((id, domain_id)) = ids?;
+    domains::remove_domain(app_state.pool.as_ref(), id, domain_id, AuditActor::Api).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// R6.10 Access
+// GET/POST /api/v1/connections/:id/credentials,
+// DELETE /api/v1/connections/:id/credentials/:credential_id and
+// POST/DELETE /api/v1/connections/:id/links. All of them need the admin
+// token, like the operator endpoints below.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CredentialList {
+    pub credentials: Vec<ConnectionCredential>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/connections/{id}/credentials",
+    tag = "access",
+    params(("id" = i64, Path, description = "Connection id")),
+    responses(
+        (status = 200, description = "Basic users and tokens, without their secrets", body = CredentialList),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+        (status = 403, description = "No admin token configured", body = ErrorBody),
+        (status = 404, description = "No such connection", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_list_credentials", skip(app_state, headers, id))]
+pub async fn list_credentials(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    id: Result<Path<i64>, PathRejection>,
+) -> ApiResult<Json<CredentialList>> {
+    require_admin(&app_state, &headers)?;
+    let Path(id) = id?;
+    fetch_connection(app_state.pool.as_ref(), id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(id))?;
+    let credentials = access::list_credentials(app_state.pool.as_ref(), id).await?;
+
+    Ok(Json(CredentialList { credentials }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/connections/{id}/credentials",
+    tag = "access",
+    params(("id" = i64, Path, description = "Connection id")),
+    request_body = CredentialForm,
+    responses(
+        (status = 201, description = "Credential added; a new token is only returned here", body = ConnectionCredential),
+        (status = 400, description = "Invalid name or password", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+        (status = 403, description = "No admin token configured", body = ErrorBody),
+        (status = 404, description = "No such connection", body = ErrorBody),
+        (status = 409, description = "Name already in use", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_add_credential", skip(app_state, headers, id, form))]
+pub async fn add_credential(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    id: Result<Path<i64>, PathRejection>,
+    form: Result<Json<CredentialForm>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<ConnectionCredential>)> {
+    require_admin(&app_state, &headers)?;
+    let Path(id) = id?;
+    let Json(form) = form?;
+    let credential = access::add_credential(app_state.pool.as_ref(), id, form, AuditActor::Admin).await?;
+
+    Ok((StatusCode::CREATED, Json(credential)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/connections/{id}/credentials/{credential_id}",
+    tag = "access",
+    params(
+        ("id" = i64, Path, description = "Connection id"),
+        ("credential_id" = i64, Path, description = "Credential id"),
+    ),
+    responses(
+        (status = 204, description = "Credential removed"),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+        (status = 403, description = "No admin token configured", body = ErrorBody),
+        (status = 404, description = "No such credential", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_remove_credential", skip(app_state, headers, ids))]
+pub async fn remove_credential(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    ids: Result<Path<(i64, i64)>, PathRejection>,
+) -> ApiResult<StatusCode> {
+    require_admin(&app_state, &headers)?;
+    let Path((id, credential_id)) = ids?;
+    access::remove_credential(app_state.pool.as_ref(), id, credential_id, AuditActor::Admin).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/connections/{id}/links",
+    tag = "access",
+    params(("id" = i64, Path, description = "Connection id")),
+    request_body = SignedLinkForm,
+    responses(
+        (status = 201, description = "Signed link; the tunnel is protected from now on", body = SignedLink),
+        (status = 400, description = "Invalid lifetime or path", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+        (status = 403, description = "No admin token configured", body = ErrorBody),
+        (status = 404, description = "No such connection", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_create_link", skip(app_state, headers, id, form))]
+pub async fn create_link(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    id: Result<Path<i64>, PathRejection>,
+    form: Result<Json<SignedLinkForm>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<SignedLink>)> {
+    require_admin(&app_state, &headers)?;
+    let Path(id) = id?;
+    let Json(form) = form?;
+    let link = access::create_link(&app_state, id, form, AuditActor::Admin).await?;
+
+    Ok((StatusCode::CREATED, Json(link)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/connections/{id}/links",
+    tag = "access",
+    params(("id" = i64, Path, description = "Connection id")),
+    responses(
+        (status = 204, description = "Every signed link revoked"),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+        (status = 403, description = "No admin token configured", body = ErrorBody),
+        (status = 404, description = "No such connection", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_revoke_links", skip(app_state, headers, id))]
+pub async fn revoke_links(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    id: Result<Path<i64>, PathRejection>,
+) -> ApiResult<StatusCode> {
+    require_admin(&app_state, &headers)?;
+    let Path(id) = id?;
+    access::revoke_links(app_state.pool.as_ref(), id, AuditActor::Admin).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// R6.11 Import and Export
+// GET /api/v1/export and POST /api/v1/import, for moving connections between
+// instances. Both need `Authorization: Bearer <admin token>`; holesail keys
+// travel encrypted with the `X-Sando-Passphrase` header.
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    // `json` (default, lossless) or `csv`
+    pub format: Option<ExportFormat>,
+    // Only export connections with this owner contact (case-insensitive)
+    pub owner: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    // Format of the request body: `json` (default) or `csv`
+    pub format: Option<ExportFormat>,
+    // Validate and report without writing anything
+    pub dry_run: Option<bool>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/export",
+    tag = "backup",
+    params(
+        ExportQuery,
+        ("X-Sando-Passphrase" = Option<String>, Header, description = "Include holesail keys, encrypted with this passphrase"),
+    ),
+    responses(
+        (status = 200, description = "Export document (or CSV with format=csv)", body = ExportDocument),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+        (status = 403, description = "No admin token configured", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_export_connections", skip(app_state, headers, query))]
+pub async fn export_connections(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    query: Result<Query<ExportQuery>, QueryRejection>,
+) -> ApiResult<Response> {
+    require_admin(&app_state, &headers)?;
+    let Query(query) = query?;
+    let owner = query.owner.as_deref().map(str::trim).filter(|owner| !owner.is_empty());
+    let passphrase = passphrase(&headers)?;
+    let document = backup::export_connections(app_state.pool.as_ref(), owner, passphrase).await?;
+
+    let format = query.format.unwrap_or_default();
+    let (body, extension) = match format {
+        ExportFormat::Json => (serde_json::to_string_pretty(&document).map_err(|e| ApiError::internal(e.to_string()))?, "json"),
+        ExportFormat::Csv => (backup::to_csv(&document), "csv"),
+    };
+    tracing::info!("Exported {} connections", document.connections.len());
+    AuditEvent::new(AuditActor::Admin, AuditAction::Export)
+        .owner(owner)
+        .detail("connections", document.connections.len())
+        .detail("with_keys", passphrase.is_some())
+        .record(app_state.pool.as_ref())
+        .await;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"sando-export.{}\"", extension)),
+        ],
+        body,
+    ).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/import",
+    tag = "backup",
+    params(
+        ImportQuery,
+        ("X-Sando-Passphrase" = Option<String>, Header, description = "Passphrase the exported keys were encrypted with"),
+    ),
+    request_body(content = ExportDocument, description = "Export document (or CSV with format=csv)"),
+    responses(
+        (status = 200, description = "Connections created and updated", body = ImportReport),
+        (status = 400, description = "Invalid document; nothing was imported", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+        (status = 403, description = "No admin token configured", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_import_connections", skip(app_state, headers, query, body))]
+pub async fn import_connections(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    query: Result<Query<ImportQuery>, QueryRejection>,
+    body: String,
+) -> ApiResult<Json<ImportReport>> {
+    require_admin(&app_state, &headers)?;
+    let Query(query) = query?;
+    let document: ExportDocument = match query.format.unwrap_or_default() {
+        ExportFormat::Json => serde_json::from_str(&body)
+            .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, "invalid_request", format!("Invalid export document: {}", e)))?,
+        ExportFormat::Csv => backup::from_csv(&body).map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, "invalid_request", e))?,
+    };
+
+    let report = backup::import_connections(
+        app_state.pool.as_ref(),
+        &app_state.port_allocator,
+        &app_state.hosts,
+        document,
+        passphrase(&headers)?,
+        query.dry_run.unwrap_or(false),
+        AuditActor::Admin,
+    )
+    .await?;
+    // Tunnels still running on a replaced key would keep serving it
+    for (key, port) in &report.replaced_tunnels {
+        app_state.tunnels.release(key, *port).await;
+    }
+
+    tracing::info!("Imported connections: {} created, {} updated", report.created.len(), report.updated.len());
+    Ok(Json(report))
+}
+
+// Operator endpoints are off unless an admin token is configured (see
+// `routes::admin::check_admin`).
+// Keys are only shown with the admin token, and never for protected
+// connections: the key reaches the tunnel without its credentials
+async fn connection_resources(
+    app_state: &AppState,
+    headers: &HeaderMap,
+    connections: Vec<Connection>,
+) -> ApiResult<Vec<ConnectionResource>> {
+    let hidden = match check_admin(app_state, headers) {
+        Ok(()) => protected_connections(app_state.pool.as_ref(), &connections).await?,
+        Err(_) => connections.iter().map(|connection| connection.id).collect(),
+    };
+    Ok(connections
+        .into_iter()
+        .map(|connection| {
+            let show_key = !hidden.contains(&connection.id);
+            ConnectionResource::from_connection(connection, &app_state.host, show_key)
+        })
+        .collect())
+}
+
+async fn connection_resource(app_state: &AppState, headers: &HeaderMap, connection: Connection) -> ApiResult<ConnectionResource> {
+    let mut resources = connection_resources(app_state, headers, vec![connection]).await?;
+    Ok(resources.remove(0))
+}
+
+fn require_admin(app_state: &AppState, headers: &HeaderMap) -> ApiResult<()> {
+    check_admin(app_state, headers).map_err(|status| match status {
+        StatusCode::FORBIDDEN => ApiError::new(status, "forbidden", "Set SANDO_ADMIN_TOKEN to enable this endpoint"),
+        _ => ApiError::new(status, "unauthorized", "A valid admin token is required"),
+    })
+}
+
+fn passphrase(headers: &HeaderMap) -> ApiResult<Option<&str>> {
+    match headers.get("X-Sando-Passphrase") {
+        Some(value) => value
+            .to_str()
+            .map(Some)
+            .map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, "invalid_request", "Invalid X-Sando-Passphrase header")),
+        None => Ok(None),
+    }
+}
+
+// R6.12 Audit Log
+// GET /api/v1/audit, newest first. JSON is paginated like the connection
+// list; `format=csv` exports every matching entry. Needs the admin token.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct AuditLog {
+    pub entries: Vec<AuditEntry>,
+    // Pass as `cursor` to get the next (older) page; null on the last page
+    pub next_cursor: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditFormatQuery {
+    // `json` (default, one page) or `csv` (every matching entry)
+    pub format: Option<ExportFormat>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/audit",
+    tag = "audit",
+    params(AuditQuery, AuditFormatQuery),
+    responses(
+        (status = 200, description = "One page of audit entries (or CSV with format=csv)", body = AuditLog),
+        (status = 400, description = "Invalid filter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+        (status = 403, description = "No admin token configured", body = ErrorBody),
+    ),
+)]
+#[tracing::instrument(name = "api_audit_log", skip(app_state, headers, query, format))]
+pub async fn audit_log(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    query: Result<Query<AuditQuery>, QueryRejection>,
+    format: Result<Query<AuditFormatQuery>, QueryRejection>,
+) -> ApiResult<Response> {
+    require_admin(&app_state, &headers)?;
+    let Query(query) = query?;
+    let Query(format) = format?;
+
+    match format.format.unwrap_or_default() {
+        ExportFormat::Json => {
+            let page = audit::search_audit_log(app_state.pool.as_ref(), &query).await?;
+            Ok(Json(AuditLog { entries: page.entries, next_cursor: page.next_cursor }).into_response())
+        }
+        ExportFormat::Csv => {
+            let entries = audit::export_audit_log(app_state.pool.as_ref(), &query).await?;
+            Ok((
+                [
+                    (header::CONTENT_TYPE, ExportFormat::Csv.content_type().to_string()),
+                    (header::CONTENT_DISPOSITION, "attachment; filename=\"sando-audit.csv\"".to_string()),
+                ],
+                audit::to_csv(&entries),
+            ).into_response())
+        }
+    }
+}
+
+// R6.13 Fallback
+// Unknown paths under /api get a JSON 404 instead of the plain-text one.
+pub async fn not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "not_found", "No such API endpoint")
+}
@@ -5,27 +5,49 @@
  * Handles GET requests to the `/connections` path.
  * This file is tagged for machine-readability.
  *
- * Tags: R3.1, R3.2, R3.3, R3.4
+ * Tags: R3.1, R3.2, R3.3, R3.4, R3.5, R3.6, R3.7, R3.8, R3.9, R3.10
  */
 // R3.1 Dependencies
 use crate::components::connections_list::connections_list;
+use crate::components::edit_connection::edit_connection;
+use crate::models::{
+    validate_tag_key, AuditAction, AuditActor, ConnectionQuery, ConnectionSort, ConnectionUpdateForm, SortOrder, Tags,
+    CONNECTION_COLUMNS,
+};
+use crate::routes::access::list_credentials;
+use crate::routes::admin::{admin_challenge, check_admin};
+use crate::routes::domains::list_domains;
+use crate::routes::hostnames::{hostname_owner, list_hostnames, promote_hostname};
+use crate::routes::subdomains::{namespace_error, validate_subdomain};
+use crate::services::audit::AuditEvent;
+use crate::services::holesail::HolesailKey;
 use crate::{AppState, Connection};
-use axum::{extract::{Path, State}, http::StatusCode, response::{Html, Redirect}, Form};
-use serde::Deserialize;
+use axum::{extract::{Path, Query, State}, http::{HeaderMap, StatusCode}, response::{Html, IntoResponse, Redirect, Response}, Form};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::{Sqlite, SqlitePool};
+use sqlx::QueryBuilder;
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+const MAX_LABEL_LENGTH: usize = 100;
+const MAX_DESCRIPTION_LENGTH: usize = 1000;
+pub const MAX_OWNER_LENGTH: usize = 200;
 
 // R3.2 List Connections Handler
-// Fetches all connections from the database and renders the
-// `connections_list` component.
+// Fetches one page of connections matching the search/filter/sort query and
+// renders the `connections_list` component.
 #[tracing::instrument(name = "list_connections", skip(app_state))]
-pub async fn list_connections(State(app_state): State<AppState>) -> Html<String> {
-    let connections = sqlx::query_as::<_, Connection>(
-        "SELECT id, connection_string, port, subdomain, created_at FROM connections ORDER BY created_at DESC",
-    )
-    .fetch_all(app_state.pool.as_ref())
-    .await
-    .unwrap_or_else(|_| vec![]);
+pub async fn list_connections(
+    State(app_state): State<AppState>,
+    Query(query): Query<ConnectionQuery>,
+) -> Result<Html<String>, (StatusCode, String)> {
+    let page = search_connections(&app_state, &query).await?;
 
-    Html(connections_list(&connections, &app_state.host, app_state.port).into_string())
+    Ok(Html(
+        connections_list(&page.connections, &app_state.host, app_state.port, &query, page.next_cursor.as_deref())
+            .into_string(),
+    ))
 }
 
 // R3.3 Delete Single Connection Handler
@@ -35,9 +57,7 @@ pub async fn delete_connection(
     State(app_state): State<AppState>,
     Path(id): Path<i64>,
 ) -> Result<Redirect, StatusCode> {
-    sqlx::query("DELETE FROM connections WHERE id = ?")
-        .bind(id)
-        .execute(app_state.pool.as_ref())
+    delete_connections(&app_state, &[id], AuditActor::Web, AuditAction::Delete)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
@@ -70,19 +90,844 @@ pub async fn batch_delete_connections(
         return Ok(Redirect::to("/connections"));
     }
 
+    delete_connections(&app_state, &connection_ids, AuditActor::Web, AuditAction::BatchDelete)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Redirect::to("/connections"))
+}
+
+// R3.6 Edit Connection Page Handler
+// Renders the edit form for a single connection
+#[tracing::instrument(name = "edit_connection_page", skip(app_state, headers))]
+pub async fn edit_connection_page(
+    State(app_state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<Html<String>, StatusCode> {
+    render_edit_page(&app_state, id, check_admin(&app_state, &headers).is_ok(), None).await
+}
+
+// Credentials are only listed for operators (`admin`). `fresh_secret` is a
+// token or signed link that was just created; the page is the only place it
+// is ever shown.
+pub async fn render_edit_page(
+    app_state: &AppState,
+    id: i64,
+    admin: bool,
+    fresh_secret: Option<&str>,
+) -> Result<Html<String>, StatusCode> {
+    let connection = fetch_connection(app_state.pool.as_ref(), id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let hostnames = list_hostnames(app_state.pool.as_ref(), id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let domains = list_domains(app_state.pool.as_ref(), id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let credentials = match admin {
+        true => Some(list_credentials(app_state.pool.as_ref(), id).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?),
+        false => None,
+    };
+
+    Ok(Html(
+        edit_connection(&connection, &app_state.host, &hostnames, &domains, credentials.as_deref(), fresh_secret)
+            .into_string(),
+    ))
+}
+
+// R3.7 Update Connection Handler
+// Applies a PATCH from the edit form and redirects back to the connections list.
+// Without the current key in `X-Sando-Key` it asks for the admin token like
+// the access section does.
+#[tracing::instrument(name = "update_connection", skip(app_state, headers, form))]
+pub async fn update_connection(
+    State(app_state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    Form(form): Form<ConnectionUpdateForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let authority = Authority::from_headers(&app_state, &headers);
+    match apply_connection_update(&app_state, id, form, authority.actor(AuditActor::Web), &authority).await {
+        Ok(_) => Ok(Redirect::to("/connections").into_response()),
+        Err(error) => challenge_unauthorized(&app_state, &headers, error),
+    }
+}
+
+// Web handlers answer a refused change with the admin sign-in prompt, so
+// operators get in from a browser; other errors pass through
+pub fn challenge_unauthorized(
+    app_state: &AppState,
+    headers: &HeaderMap,
+    error: (StatusCode, String),
+) -> Result<Response, (StatusCode, String)> {
+    match error {
+        (StatusCode::UNAUTHORIZED, message) => match app_state.admin_token {
+            Some(_) => admin_challenge(app_state, headers).ok_or((StatusCode::UNAUTHORIZED, message)),
+            None => Err((StatusCode::UNAUTHORIZED, message)),
+        },
+        error => Err(error),
+    }
+}
+
+// R3.8 Connection Update Logic
+// Who is asking for a change. Operators present the admin token; an owner
+// proves a connection is theirs with its current holesail key in
+// `X-Sando-Key`, which only they and the operators know.
+pub const KEY_HEADER: &str = "X-Sando-Key";
+
+#[derive(Debug, Clone, Default)]
+pub struct Authority {
+    pub operator: bool,
+    key: Option<String>,
+}
+
+impl Authority {
+    pub fn from_headers(app_state: &AppState, headers: &HeaderMap) -> Self {
+        Self {
+            operator: check_admin(app_state, headers).is_ok(),
+            key: headers.get(KEY_HEADER).and_then(|value| value.to_str().ok()).map(str::to_string),
+        }
+    }
+
+    pub fn operator() -> Self {
+        Self { operator: true, key: None }
+    }
+
+    // How a change is audited: `Admin` for operators, else the channel it came in on
+    pub fn actor(&self, channel: AuditActor) -> AuditActor {
+        if self.operator {
+            AuditActor::Admin
+        } else {
+            channel
+        }
+    }
+
+    // Operators own every connection
+    pub fn owns(&self, connection: &Connection) -> bool {
+        let normalize = |key: &str| HolesailKey::parse(key).map(|key| key.key).unwrap_or_else(|_| key.trim().to_string());
+        self.operator
+            || self.key.as_deref().is_some_and(|key| {
+                Sha256::digest(normalize(key).as_bytes()) == Sha256::digest(normalize(&connection.connection_string).as_bytes())
+            })
+    }
+}
+
+// The 401 (or 403 without a configured token) for changes only operators make
+pub fn operators_only(app_state: &AppState, what: &str) -> (StatusCode, String) {
+    match app_state.admin_token {
+        Some(_) => (StatusCode::UNAUTHORIZED, format!("{} needs the admin token", what)),
+        None => (StatusCode::FORBIDDEN, format!("{} needs the admin token; set SANDO_ADMIN_TOKEN", what)),
+    }
+}
+
+// The 401 for changes owners may make too
+pub fn owners_only(what: &str) -> (StatusCode, String) {
+    (StatusCode::UNAUTHORIZED, format!("{} needs the admin token or the connection's current key in {}", what, KEY_HEADER))
+}
+
+// Loads connection `id` for `what`, a change only its owner or an operator
+// may make
+pub async fn check_owner(
+    app_state: &AppState,
+    id: i64,
+    authority: &Authority,
+    what: &str,
+) -> Result<Connection, (StatusCode, String)> {
+    let connection = fetch_connection(app_state.pool.as_ref(), id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)))?
+        .ok_or((StatusCode::NOT_FOUND, format!("Connection {} not found", id)))?;
+    if !authority.owns(&connection) {
+        return Err(owners_only(what));
+    }
+    Ok(connection)
+}
+
+// Changes the subdomain, metadata and/or rotates the holesail key of a connection.
+// A new key is started on a fresh port alongside the old one and only written
+// to the database once it is confirmed online, so the subdomain keeps serving
+// the old key until the switch and never goes dark. The old tunnel is stopped
+// afterwards. Only values that differ count as changes: owners (see
+// `Authority`) may make most of them; the owner contact, tier and IP rules
+// are for operators.
+// The change is recorded in the audit log as done by `actor`.
+pub async fn apply_connection_update(
+    app_state: &AppState,
+    id: i64,
+    form: ConnectionUpdateForm,
+    actor: AuditActor,
+    authority: &Authority,
+) -> Result<Connection, (StatusCode, String)> {
+    let pool = app_state.pool.as_ref();
+    let internal_error = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e));
+
+    let connection = fetch_connection(pool, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, format!("Connection {} not found", id)))?;
+
+    let new_subdomain = non_empty(form.subdomain)
+        .map(|s| s.to_ascii_lowercase())
+        .filter(|s| Some(s) != connection.subdomain.as_ref());
+    let new_key = match non_empty(form.connection) {
+        Some(key) => Some(HolesailKey::parse(&key).map_err(|message| (StatusCode::BAD_REQUEST, message))?.to_string()),
+        None => None,
+    }
+    .filter(|key| *key != connection.connection_string);
+
+    // Metadata: a missing field keeps its value, an empty one clears it
+    let label = form.label.map_or(connection.label.clone(), |label| non_empty(Some(label)));
+    let description = form.description.map_or(connection.description.clone(), |description| non_empty(Some(description)));
+    let owner = form.owner.map_or(connection.owner.clone(), |owner| non_empty(Some(owner)));
+    let tags = form.tags.unwrap_or_else(|| connection.tags.clone());
+    let owner_changed = owner != connection.owner;
+    let metadata_changed = label != connection.label || description != connection.description || owner_changed || tags != connection.tags;
+    let new_rewrite = form.response_rewrite.filter(|rewrite| *rewrite != connection.response_rewrite);
+    let new_limits = form.limits.filter(|limits| *limits != connection.limits);
+    let new_ip_rules = form.ip_rules.filter(|rules| *rules != connection.ip_rules);
+    // Tiers are assigned by operators; an empty one goes back to the default limits
+    let new_tier = form
+        .tier
+        .map(|tier| non_empty(Some(tier)).map(|tier| tier.to_ascii_lowercase()))
+        .filter(|tier| *tier != connection.tier);
+
+    if new_ip_rules.is_some() && !authority.operator {
+        return Err(operators_only(app_state, "Changing IP rules"));
+    }
+    if new_tier.is_some() && !authority.operator {
+        return Err(operators_only(app_state, "Changing the tier"));
+    }
+    // The owner decides which namespaces a connection may use
+    if owner_changed && !authority.operator {
+        return Err(operators_only(app_state, "Changing the owner"));
+    }
+    let owner_change =
+        new_subdomain.is_some() || new_key.is_some() || metadata_changed || new_rewrite.is_some() || new_limits.is_some();
+    if owner_change && !authority.owns(&connection) {
+        return Err(owners_only("Changing a connection"));
+    }
+
+    let errors = validate_metadata(&label, &description, &owner, &tags);
+    if !errors.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, errors.join(". ")));
+    }
+    if let Some(limits) = &new_limits {
+        let errors = limits.validate();
+        if !errors.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, errors.join(". ")));
+        }
+    }
+    if let Some(rules) = &new_ip_rules {
+        let errors = rules.validate();
+        if !errors.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, errors.join(". ")));
+        }
+    }
+    if let Some(tier) = &new_tier {
+        if let Some(tier) = tier.as_ref().filter(|tier| !app_state.tiers.contains_key(*tier)) {
+            return Err((StatusCode::BAD_REQUEST, format!("Unknown tier '{}'", tier)));
+        }
+    }
+
+    if let Some(subdomain) = &new_subdomain {
+        let errors = validate_subdomain(subdomain, &app_state.hosts);
+        if !errors.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, errors.join(". ")));
+        }
+        // Moving onto one of the connection's own aliases is a promotion
+        if hostname_owner(pool, subdomain).await.map_err(internal_error)?.is_some_and(|owner| owner != id) {
+            return Err((StatusCode::CONFLICT, format!("Subdomain '{}' is already taken", subdomain)));
+        }
+        if let Some(error) = namespace_error(pool, subdomain, Some(id), owner.as_deref())
+            .await
+            .map_err(internal_error)?
+        {
+            return Err((StatusCode::CONFLICT, error));
+        }
+    }
+
+    // The reservation is held until the row points at the new port
+    let reservation = match &new_key {
+        Some(key) => {
+            let reservation = app_state.port_allocator.reserve(pool)
+                .await
+                .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+
+            tracing::info!("Rotating key for connection {} onto port {}", id, reservation.port);
+            if let Err(status) = app_state.tunnels.bring_online(key, reservation.port).await {
+                app_state.tunnels.release(key, reservation.port).await;
+                return Err((status, "The new key could not be confirmed online; the current key is still live".to_string()));
+            }
+            Some(reservation)
+        }
+        None => None,
+    };
+
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    if let (Some(key), Some(reservation)) = (&new_key, &reservation) {
+        sqlx::query("UPDATE connections SET connection_string = ?, port = ? WHERE id = ?")
+            .bind(key)
+            .bind(reservation.port)
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(internal_error)?;
+    }
+    // A rename keeps the old subdomain as an alias redirecting to the new one
+    if let Some(subdomain) = &new_subdomain {
+        promote_hostname(&mut tx, id, subdomain).await.map_err(internal_error)?;
+    }
+    tx.commit().await.map_err(internal_error)?;
+    drop(reservation);
+
+    if new_key.is_some() {
+        app_state.tunnels.release(&connection.connection_string, connection.port as u16).await;
+    }
+
+    if metadata_changed {
+        sqlx::query("UPDATE connections SET label = ?, description = ?, owner = ?, tags = ? WHERE id = ?")
+            .bind(&label)
+            .bind(&description)
+            .bind(&owner)
+            .bind(tags.to_json())
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(internal_error)?;
+    }
+    if let Some(rewrite) = new_rewrite {
+        sqlx::query("UPDATE connections SET response_rewrite = ? WHERE id = ?")
+            .bind(rewrite)
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(internal_error)?;
+    }
+
+    if let Some(limits) = &new_limits {
+        sqlx::query("UPDATE connections SET limits = ? WHERE id = ?")
+            .bind(limits.to_json())
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(internal_error)?;
+    }
+    if let Some(rules) = &new_ip_rules {
+        sqlx::query("UPDATE connections SET ip_rules = ? WHERE id = ?")
+            .bind(rules.to_json())
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(internal_error)?;
+    }
+    if let Some(tier) = &new_tier {
+        sqlx::query("UPDATE connections SET tier = ? WHERE id = ?")
+            .bind(tier)
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(internal_error)?;
+    }
+
+    let updated = fetch_connection(pool, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, format!("Connection {} not found", id)))?;
+
+    // Keys are secrets, so a rotation is noted but the key never logged
+    let mut event = AuditEvent::new(actor, AuditAction::Update);
+    let mut changed = Vec::new();
+    if new_subdomain.is_some() {
+        changed.push("subdomain");
+        event = event.detail("previous_subdomain", connection.subdomain.clone());
+    }
+    if new_key.is_some() {
+        changed.push("connection");
+    }
+    if metadata_changed {
+        changed.push("metadata");
+    }
+    if let Some(rewrite) = new_rewrite {
+        changed.push("response_rewrite");
+        event = event.detail("response_rewrite", rewrite.as_str());
+    }
+    if let Some(limits) = &new_limits {
+        changed.push("limits");
+        event = event.detail("limits", serde_json::to_value(limits).unwrap_or_default());
+    }
+    if let Some(rules) = &new_ip_rules {
+        changed.push("ip_rules");
+        event = event.detail("ip_rules", serde_json::to_value(rules).unwrap_or_default());
+    }
+    if let Some(tier) = &new_tier {
+        changed.push("tier");
+        event = event.detail("tier", tier.clone());
+    }
+    if !changed.is_empty() {
+        event.connection(&updated).detail("changed", changed).record(pool).await;
+    }
+
+    Ok(updated)
+}
+
+// Deletes the given connections and stops their tunnels so the local ports
+// are free again. Each deleted connection gets an audit entry for `action`
+// (a single or a batch delete) by `actor`. Returns how many rows were deleted.
+pub async fn delete_connections(
+    app_state: &AppState,
+    connection_ids: &[i64],
+    actor: AuditActor,
+    action: AuditAction,
+) -> Result<usize, sqlx::Error> {
+    if connection_ids.is_empty() {
+        return Ok(0);
+    }
+
     // Create placeholders for the IN clause
     let placeholders = vec!["?"; connection_ids.len()].join(",");
-    let query_str = format!("DELETE FROM connections WHERE id IN ({})", placeholders);
+    let query_str = format!(
+        "DELETE FROM connections WHERE id IN ({}) RETURNING id, connection_string, port, subdomain, owner",
+        placeholders
+    );
     
-    let mut query = sqlx::query(&query_str);
+    let mut query = sqlx::query_as::<_, (i64, String, i64, Option<String>, Option<String>)>(&query_str);
     for id in connection_ids {
         query = query.bind(id);
     }
     
-    query
-        .execute(app_state.pool.as_ref())
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let deleted = query.fetch_all(app_state.pool.as_ref()).await?;
 
-    Ok(Redirect::to("/connections"))
-} 
\ No newline at end of file
+    // Free the tunnels' local ports
+    for (id, connection_string, port, subdomain, owner) in &deleted {
+        app_state.tunnels.release(connection_string, *port as u16).await;
+
+        let mut event = AuditEvent::new(actor, action).connection_id(*id).owner(owner.as_deref()).detail("port", *port);
+        if let Some(subdomain) = subdomain {
+            event = event.subdomain(subdomain.as_str());
+        }
+        if action == AuditAction::BatchDelete {
+            event = event.detail("batch_size", deleted.len());
+        }
+        event.record(app_state.pool.as_ref()).await;
+    }
+
+    Ok(deleted.len())
+}
+
+pub async fn fetch_connection(pool: &SqlitePool, id: i64) -> Result<Option<Connection>, sqlx::Error> {
+    sqlx::query_as::<_, Connection>(&format!("SELECT {} FROM connections WHERE id = ?", CONNECTION_COLUMNS))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+// R3.9 Connection Search
+// Keyset pagination over (sort key, id), so pages stay stable while
+// connections are added or removed. The cursor encodes the last row's sort key.
+pub struct ConnectionPage {
+    pub connections: Vec<Connection>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+enum SortKey {
+    Int(i64),
+    Text(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Cursor {
+    sort: ConnectionSort,
+    key: SortKey,
+    id: i64,
+}
+
+impl Cursor {
+    fn after(connection: &Connection, sort: ConnectionSort) -> Self {
+        let key = match sort {
+            ConnectionSort::CreatedAt => SortKey::Text(connection.created_at.clone()),
+            ConnectionSort::Subdomain => {
+                SortKey::Text(connection.subdomain.clone().unwrap_or_else(|| connection.connection_string.clone()))
+            }
+            ConnectionSort::Port => SortKey::Int(connection.port as i64),
+        };
+        Self { sort, key, id: connection.id }
+    }
+
+    fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(cursor: &str, sort: ConnectionSort) -> Result<Self, (StatusCode, String)> {
+        let invalid = || (StatusCode::BAD_REQUEST, "Invalid cursor".to_string());
+        let bytes = hex::decode(cursor).map_err(|_| invalid())?;
+        let cursor: Self = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+        // A cursor is only meaningful for the sort it was issued under
+        if cursor.sort != sort {
+            return Err(invalid());
+        }
+        Ok(cursor)
+    }
+}
+
+fn sort_expression(sort: ConnectionSort) -> &'static str {
+    match sort {
+        ConnectionSort::CreatedAt => "created_at",
+        ConnectionSort::Subdomain => "COALESCE(subdomain, connection_string)",
+        ConnectionSort::Port => "port",
+    }
+}
+
+// Escapes LIKE wildcards so the search text matches literally.
+pub fn like_pattern(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+pub async fn search_connections(
+    app_state: &AppState,
+    query: &ConnectionQuery,
+) -> Result<ConnectionPage, (StatusCode, String)> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize;
+    let sort = query.sort.unwrap_or_default();
+    let order = query.order.unwrap_or_default();
+    let filters = SearchFilters::from_query(query)?;
+    let mut after = query.cursor.as_deref().map(|cursor| Cursor::decode(cursor, sort)).transpose()?;
+
+    let mut connections = Vec::with_capacity(limit);
+    loop {
+        let batch = fetch_connection_batch(app_state.pool.as_ref(), &filters, sort, order, after.as_ref(), limit + 1)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)))?;
+        let exhausted = batch.len() <= limit;
+
+        for connection in batch {
+            if connections.len() == limit {
+                // There is at least one more row, so hand out a cursor
+                let next_cursor = connections.last().map(|last| Cursor::after(last, sort).encode());
+                return Ok(ConnectionPage { connections, next_cursor });
+            }
+
+            after = Some(Cursor::after(&connection, sort));
+            // Tunnel status lives in the backend, not the database
+            let matches_status = query.status.is_none_or(|status| {
+                app_state.tunnels.tunnel_status(&connection.connection_string, connection.port as u16) == status
+            });
+            if matches_status {
+                connections.push(connection);
+            }
+        }
+
+        if exhausted {
+            return Ok(ConnectionPage { connections, next_cursor: None });
+        }
+    }
+}
+
+// The parts of a `ConnectionQuery` that are filtered in SQL
+struct SearchFilters {
+    text: Option<String>,
+    owner: Option<String>,
+    // JSON path of the tag key, plus the value it must have (if any)
+    tag: Option<(String, Option<String>)>,
+}
+
+impl SearchFilters {
+    fn from_query(query: &ConnectionQuery) -> Result<Self, (StatusCode, String)> {
+        let tag = match non_empty(query.tag.clone()) {
+            Some(tag) => {
+                let (key, value) = match tag.split_once('=') {
+                    Some((key, value)) => (key.trim().to_ascii_lowercase(), Some(value.trim().to_string())),
+                    None => (tag.to_ascii_lowercase(), None),
+                };
+                // Validated keys can't break out of the quoted JSON path
+                validate_tag_key(&key).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+                Some((format!("$.\"{}\"", key), value))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            text: non_empty(query.q.clone()),
+            owner: non_empty(query.owner.clone()),
+            tag,
+        })
+    }
+}
+
+async fn fetch_connection_batch(
+    pool: &SqlitePool,
+    filters: &SearchFilters,
+    sort: ConnectionSort,
+    order: SortOrder,
+    after: Option<&Cursor>,
+    batch_size: usize,
+) -> Result<Vec<Connection>, sqlx::Error> {
+    let expression = sort_expression(sort);
+    let (direction, comparison) = match order {
+        SortOrder::Asc => ("ASC", ">"),
+        SortOrder::Desc => ("DESC", "<"),
+    };
+
+    let mut builder = QueryBuilder::<Sqlite>::new(format!("SELECT {} FROM connections WHERE 1 = 1", CONNECTION_COLUMNS));
+
+    if let Some(text) = &filters.text {
+        let pattern = like_pattern(text);
+        builder.push(" AND (");
+        for (i, column) in ["subdomain", "label", "description", "owner"].iter().enumerate() {
+            if i > 0 {
+                builder.push(" OR ");
+            }
+            builder.push(format!("{} LIKE ", column)).push_bind(pattern.clone()).push(" ESCAPE '\\'");
+        }
+        // Keys are secret: only a whole one matches, so searches can't spell it out
+        builder.push(" OR connection_string = ").push_bind(text.clone());
+        builder.push(")");
+    }
+
+    if let Some(owner) = &filters.owner {
+        builder.push(" AND owner = ").push_bind(owner.clone()).push(" COLLATE NOCASE");
+    }
+
+    match &filters.tag {
+        Some((path, Some(value))) => {
+            builder.push(" AND json_extract(tags, ").push_bind(path.clone()).push(") = ").push_bind(value.clone());
+        }
+        Some((path, None)) => {
+            builder.push(" AND json_type(tags, ").push_bind(path.clone()).push(") IS NOT NULL");
+        }
+        None => {}
+    }
+
+    if let Some(cursor) = after {
+        builder.push(format!(" AND ({} {} ", expression, comparison));
+        push_sort_key(&mut builder, &cursor.key);
+        builder.push(format!(" OR ({} = ", expression));
+        push_sort_key(&mut builder, &cursor.key);
+        builder.push(format!(" AND id {} ", comparison)).push_bind(cursor.id).push("))");
+    }
+
+    builder
+        .push(format!(" ORDER BY {} {}, id {} LIMIT ", expression, direction, direction))
+        .push_bind(batch_size as i64);
+
+    builder.build_query_as::<Connection>().fetch_all(pool).await
+}
+
+fn push_sort_key(builder: &mut QueryBuilder<'_, Sqlite>, key: &SortKey) {
+    match key {
+        SortKey::Int(value) => builder.push_bind(*value),
+        SortKey::Text(value) => builder.push_bind(value.clone()),
+    };
+}
+
+// Length limits for the free-text metadata plus the tag rules; an empty list
+// means the metadata can be stored.
+pub fn validate_metadata(
+    label: &Option<String>,
+    description: &Option<String>,
+    owner: &Option<String>,
+    tags: &Tags,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (field, value, max) in [
+        ("Label", label, MAX_LABEL_LENGTH),
+        ("Description", description, MAX_DESCRIPTION_LENGTH),
+        ("Owner", owner, MAX_OWNER_LENGTH),
+    ] {
+        if value.as_ref().is_some_and(|v| v.chars().count() > max) {
+            errors.push(format!("{} must be at most {} characters", field, max));
+        }
+    }
+    errors.extend(tags.validate());
+    errors
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+// R3.10 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Tags, TunnelStatus};
+    use axum::http::HeaderName;
+    use crate::SandoBuilder;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn test_state() -> AppState {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::migrate(&pool).await.unwrap();
+
+        for (i, subdomain) in ["alpha", "bravo", "charlie", "delta", "echo_5"].iter().enumerate() {
+            sqlx::query("INSERT INTO connections (connection_string, port, subdomain, created_at) VALUES (?, ?, ?, ?)")
+                .bind(format!("key{}", i))
+                .bind(4000 + i as i64)
+                .bind(subdomain)
+                .bind(format!("2024-01-0{} 00:00:00", i + 1))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        SandoBuilder::new(pool).build_state()
+    }
+
+    fn subdomains(page: &ConnectionPage) -> Vec<&str> {
+        page.connections.iter().filter_map(|c| c.subdomain.as_deref()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_search_paginates_with_cursor() {
+        let state = test_state().await;
+        let mut query = ConnectionQuery {
+            sort: Some(ConnectionSort::Subdomain),
+            order: Some(SortOrder::Asc),
+            limit: Some(2),
+            ..Default::default()
+        };
+
+        let mut seen = Vec::new();
+        loop {
+            let page = search_connections(&state, &query).await.unwrap();
+            seen.extend(subdomains(&page).into_iter().map(str::to_string));
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(seen, vec!["alpha", "bravo", "charlie", "delta", "echo_5"]);
+
+        // Newest first by default
+        let page = search_connections(&state, &ConnectionQuery { limit: Some(2), ..Default::default() }).await.unwrap();
+        assert_eq!(subdomains(&page), vec!["echo_5", "delta"]);
+    }
+
+    #[tokio::test]
+    async fn test_search_text_and_status_filters() {
+        let state = test_state().await;
+
+        let query = ConnectionQuery { q: Some("LT".to_string()), ..Default::default() };
+        assert_eq!(subdomains(&search_connections(&state, &query).await.unwrap()), vec!["delta"]);
+
+        // Keys only match whole
+        let query = ConnectionQuery { q: Some("key".to_string()), ..Default::default() };
+        assert!(search_connections(&state, &query).await.unwrap().connections.is_empty());
+        let query = ConnectionQuery { q: Some("key3".to_string()), ..Default::default() };
+        assert_eq!(subdomains(&search_connections(&state, &query).await.unwrap()), vec!["delta"]);
+
+        // LIKE wildcards are matched literally
+        let query = ConnectionQuery { q: Some("_".to_string()), ..Default::default() };
+        assert_eq!(subdomains(&search_connections(&state, &query).await.unwrap()), vec!["echo_5"]);
+
+        // Nothing has been started, so every tunnel is idle
+        let query = ConnectionQuery { status: Some(TunnelStatus::Online), limit: Some(1), ..Default::default() };
+        let page = search_connections(&state, &query).await.unwrap();
+        assert!(page.connections.is_empty() && page.next_cursor.is_none());
+
+        let query = ConnectionQuery { status: Some(TunnelStatus::Idle), ..Default::default() };
+        assert_eq!(search_connections(&state, &query).await.unwrap().connections.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_update_and_filters() {
+        let state = test_state().await;
+        let form = ConnectionUpdateForm {
+            label: Some("Checkout API".to_string()),
+            owner: Some("Payments@example.com".to_string()),
+            tags: Some(Tags::parse_lines("Team=payments\nenv: staging").unwrap()),
+            ..Default::default()
+        };
+        let connection = apply_connection_update(&state, 2, form, AuditActor::Admin, &Authority::operator()).await.unwrap();
+        assert_eq!(connection.label.as_deref(), Some("Checkout API"));
+        assert_eq!(connection.tags.to_lines(), "env=staging\nteam=payments");
+
+        let search = |query: ConnectionQuery| {
+            let state = state.clone();
+            async move { search_connections(&state, &query).await.unwrap().connections.len() }
+        };
+        assert_eq!(search(ConnectionQuery { q: Some("checkout".to_string()), ..Default::default() }).await, 1);
+        assert_eq!(search(ConnectionQuery { owner: Some("payments@EXAMPLE.com".to_string()), ..Default::default() }).await, 1);
+        assert_eq!(search(ConnectionQuery { tag: Some("team=payments".to_string()), ..Default::default() }).await, 1);
+        assert_eq!(search(ConnectionQuery { tag: Some("env".to_string()), ..Default::default() }).await, 1);
+        assert_eq!(search(ConnectionQuery { tag: Some("team=billing".to_string()), ..Default::default() }).await, 0);
+
+        // Empty values clear, missing ones are kept
+        let form = ConnectionUpdateForm { label: Some(String::new()), ..Default::default() };
+        let connection = apply_connection_update(&state, 2, form, AuditActor::Admin, &Authority::operator()).await.unwrap();
+        assert!(connection.label.is_none());
+        assert_eq!(connection.owner.as_deref(), Some("Payments@example.com"));
+
+        let form = ConnectionUpdateForm { tags: Some(Tags::parse_lines("Bad Key=x").unwrap()), ..Default::default() };
+        let result = apply_connection_update(&state, 2, form, AuditActor::Admin, &Authority::operator()).await;
+        assert!(matches!(result, Err((StatusCode::BAD_REQUEST, _))));
+    }
+
+    #[tokio::test]
+    async fn test_changes_need_the_owner_or_an_operator() {
+        let state = test_state().await;
+        let relabel = || ConnectionUpdateForm { label: Some("Hijacked".to_string()), ..Default::default() };
+        let with_key = |key: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(HeaderName::from_bytes(KEY_HEADER.as_bytes()).unwrap(), key.parse().unwrap());
+            Authority::from_headers(&state, &headers)
+        };
+
+        for authority in [Authority::default(), with_key("key0")] {
+            let result = apply_connection_update(&state, 2, relabel(), AuditActor::Api, &authority).await;
+            assert!(matches!(result, Err((StatusCode::UNAUTHORIZED, _))));
+        }
+        // Values sent back unchanged are not changes
+        let same = ConnectionUpdateForm { subdomain: Some("bravo".to_string()), ..Default::default() };
+        assert!(apply_connection_update(&state, 2, same, AuditActor::Api, &Authority::default()).await.is_ok());
+
+        let connection = apply_connection_update(&state, 2, relabel(), AuditActor::Api, &with_key("key1")).await.unwrap();
+        assert_eq!(connection.label.as_deref(), Some("Hijacked"));
+        // Tiers stay with the operators, and this server has no admin token
+        let tier = ConnectionUpdateForm { tier: Some("pro".to_string()), ..Default::default() };
+        let result = apply_connection_update(&state, 2, tier, AuditActor::Api, &with_key("key1")).await;
+        assert!(matches!(result, Err((StatusCode::FORBIDDEN, _))));
+    }
+
+    #[tokio::test]
+    async fn test_only_operators_change_the_owner() {
+        let state = test_state().await;
+        let claim = || ConnectionUpdateForm { owner: Some("alice@example.com".to_string()), ..Default::default() };
+        let mut headers = HeaderMap::new();
+        headers.insert(HeaderName::from_bytes(KEY_HEADER.as_bytes()).unwrap(), "key1".parse().unwrap());
+        let key_holder = Authority::from_headers(&state, &headers);
+
+        // Not even the key holder: namespaces are shared by owner
+        for authority in [Authority::default(), key_holder] {
+            let result = apply_connection_update(&state, 2, claim(), AuditActor::Api, &authority).await;
+            assert!(matches!(result, Err((StatusCode::FORBIDDEN, _))));
+        }
+        assert!(fetch_connection(state.pool.as_ref(), 2).await.unwrap().unwrap().owner.is_none());
+
+        let connection = apply_connection_update(&state, 2, claim(), AuditActor::Admin, &Authority::operator()).await.unwrap();
+        assert_eq!(connection.owner.as_deref(), Some("alice@example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_cursor_must_match_sort() {
+        let state = test_state().await;
+        let page = search_connections(&state, &ConnectionQuery { limit: Some(1), ..Default::default() }).await.unwrap();
+
+        let query = ConnectionQuery {
+            sort: Some(ConnectionSort::Port),
+            cursor: page.next_cursor,
+            ..Default::default()
+        };
+        let result = search_connections(&state, &query).await;
+        assert!(matches!(result, Err((StatusCode::BAD_REQUEST, _))));
+    }
+}
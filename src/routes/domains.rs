@@ -0,0 +1,373 @@
+/**
+ * R9.0 Custom Domains Route
+ * =========================
+ *
+ * Lets owners attach their own domain (e.g. `app.example.com`) to a
+ * connection. A domain is only routed once ownership is proven, either with
+ * a TXT record at `_sando-challenge.{domain}` (looked up through the
+ * configured `DnsResolver`) or with a challenge file served at
+ * `http://{domain}/.well-known/sando-challenge/{token}`. Sando never answers
+ * the HTTP challenge itself: anyone could claim a domain pointed at it. The
+ * file has to come from the domain's own web server, before it moves here.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: R9.1, R9.2, R9.3, R9.4, R9.5, R9.6, R9.7
+ */
+// R9.1 Dependencies
+use crate::models::{AuditAction, AuditActor, CustomDomain, CustomDomainForm, VerificationMethod};
+use crate::routes::connections::fetch_connection;
+use crate::services::audit::AuditEvent;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Redirect,
+    Form,
+};
+use sqlx::sqlite::SqlitePool;
+use std::time::Duration;
+
+const MAX_DOMAIN_LENGTH: usize = 253;
+const MAX_LABEL_LENGTH: usize = 63;
+const HTTP_CHALLENGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// R9.2 Add Domain Handler
+// POST /connections/:id/domains from the edit page
+#[tracing::instrument(name = "add_domain", skip(app_state, form))]
+pub async fn add_domain_handler(
+    State(app_state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(form): Form<CustomDomainForm>,
+) -> Result<Redirect, (StatusCode, String)> {
+    add_domain(&app_state, id, form, AuditActor::Web).await?;
+    Ok(Redirect::to(&format!("/connections/{}/edit", id)))
+}
+
+// R9.3 Verify Domain Handler
+// POST /connections/:id/domains/:domain_id/verify from the edit page
+#[tracing::instrument(name = "verify_domain", skip(app_state))]
+pub async fn verify_domain_handler(
+    State(app_state): State<AppState>,
+    Path((id, domain_id)): Path<(i64, i64)>,
+) -> Result<Redirect, (StatusCode, String)> {
+    verify_domain(&app_state, id, domain_id, AuditActor::Web).await?;
+    Ok(Redirect::to(&format!("/connections/{}/edit", id)))
+}
+
+// R9.4 Remove Domain Handler
+// DELETE /connections/:id/domains/:domain_id, sent from script
+#[tracing::instrument(name = "remove_domain", skip(app_state))]
+pub async fn remove_domain_handler(
+    State(app_state): State<AppState>,
+    Path((id, domain_id)): Path<(i64, i64)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    remove_domain(app_state.pool.as_ref(), id, domain_id, AuditActor::Web).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// R9.5 Domain Logic
+// Shared by the HTML handlers above and the JSON API.
+pub async fn list_domains(pool: &SqlitePool, connection_id: i64) -> Result<Vec<CustomDomain>, sqlx::Error> {
+    sqlx::query_as::<_, CustomDomain>(
+        "SELECT id, connection_id, domain, method, token, verified_at, created_at FROM custom_domains \
+         WHERE connection_id = ? ORDER BY domain",
+    )
+    .bind(connection_id)
+    .fetch_all(pool)
+    .await
+}
+
+// The connection a verified domain routes to, if any
+pub async fn verified_domain_connection(pool: &SqlitePool, domain: &str) -> Result<Option<i64>, sqlx::Error> {
+    let connection: Option<(i64,)> = sqlx::query_as(
+        "SELECT connection_id FROM custom_domains WHERE domain = ? AND verified_at IS NOT NULL",
+    )
+    .bind(normalize_domain(domain))
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(connection.map(|(connection_id,)| connection_id))
+}
+
+// Changes are recorded in the audit log as updates by `actor`.
+pub async fn add_domain(
+    app_state: &AppState,
+    connection_id: i64,
+    form: CustomDomainForm,
+    actor: AuditActor,
+) -> Result<CustomDomain, (StatusCode, String)> {
+    let pool = app_state.pool.as_ref();
+    let domain = normalize_domain(&form.domain);
+    let errors = validate_domain(&domain, &app_state.hosts);
+    if !errors.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, errors.join(". ")));
+    }
+
+    fetch_connection(pool, connection_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, format!("Connection {} not found", connection_id)))?;
+    if verified_domain_connection(pool, &domain).await.map_err(internal_error)?.is_some() {
+        return Err((StatusCode::CONFLICT, format!("Domain '{}' is already in use", domain)));
+    }
+
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    let domain_id = sqlx::query("INSERT INTO custom_domains (connection_id, domain, method, token) VALUES (?, ?, ?, ?)")
+        .bind(connection_id)
+        .bind(&domain)
+        .bind(form.method)
+        .bind(&token)
+        .execute(pool)
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                (StatusCode::CONFLICT, format!("Domain '{}' is already attached to this connection", domain))
+            }
+            _ => internal_error(e),
+        })?
+        .last_insert_rowid();
+
+    tracing::info!("Domain '{}' added to connection {}, pending verification", domain, connection_id);
+    AuditEvent::new(actor, AuditAction::Update)
+        .connection_id(connection_id)
+        .detail("domain_added", domain.as_str())
+        .detail("method", serde_json::to_value(form.method).unwrap_or_default())
+        .record(pool)
+        .await;
+    fetch_domain(pool, connection_id, domain_id).await
+}
+
+// Checks the challenge and, if it passes, starts routing the domain. Other
+// connections' pending claims on the same domain are dropped.
+pub async fn verify_domain(
+    app_state: &AppState,
+    connection_id: i64,
+    domain_id: i64,
+    actor: AuditActor,
+) -> Result<CustomDomain, (StatusCode, String)> {
+    let pool = app_state.pool.as_ref();
+    let domain = fetch_domain(pool, connection_id, domain_id).await?;
+    if domain.verified_at.is_some() {
+        return Ok(domain);
+    }
+
+    let proven = match domain.method {
+        VerificationMethod::Dns => {
+            let records = app_state
+                .dns
+                .txt_records(&domain.dns_record_name())
+                .await
+                .map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+            records.iter().any(|record| record.trim() == domain.dns_record_value())
+        }
+        VerificationMethod::Http => fetch_http_challenge(&app_state.upstream_client, &domain.http_challenge_url())
+            .await
+            .is_ok_and(|body| body.trim() == domain.token),
+    };
+
+    if !proven {
+        let expected = match domain.method {
+            VerificationMethod::Dns => format!(
+                "TXT record '{}' with value '{}' not found",
+                domain.dns_record_name(),
+                domain.dns_record_value()
+            ),
+            VerificationMethod::Http => format!("{} did not answer with the token", domain.http_challenge_url()),
+        };
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, expected));
+    }
+
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    sqlx::query("UPDATE custom_domains SET verified_at = CURRENT_TIMESTAMP WHERE id = ?")
+        .bind(domain_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                (StatusCode::CONFLICT, format!("Domain '{}' is already in use", domain.domain))
+            }
+            _ => internal_error(e),
+        })?;
+    sqlx::query("DELETE FROM custom_domains WHERE domain = ? AND id != ? AND verified_at IS NULL")
+        .bind(&domain.domain)
+        .bind(domain_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+
+    tracing::info!("Domain '{}' verified for connection {}", domain.domain, connection_id);
+    AuditEvent::new(actor, AuditAction::Update)
+        .connection_id(connection_id)
+        .detail("domain_verified", domain.domain.as_str())
+        .record(pool)
+        .await;
+    fetch_domain(pool, connection_id, domain_id).await
+}
+
+pub async fn remove_domain(
+    pool: &SqlitePool,
+    connection_id: i64,
+    domain_id: i64,
+    actor: AuditActor,
+) -> Result<(), (StatusCode, String)> {
+    let domain = fetch_domain(pool, connection_id, domain_id).await?;
+    sqlx::query("DELETE FROM custom_domains WHERE id = ?")
+        .bind(domain_id)
+        .execute(pool)
+        .await
+        .map_err(internal_error)?;
+
+    tracing::info!("Domain '{}' removed from connection {}", domain.domain, connection_id);
+    AuditEvent::new(actor, AuditAction::Update)
+        .connection_id(connection_id)
+        .detail("domain_removed", domain.domain.as_str())
+        .record(pool)
+        .await;
+    Ok(())
+}
+
+// R9.6 Helper Functions
+pub fn normalize_domain(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+// Plain DNS names only; subdomains of the base hosts are managed as hostnames.
+pub fn validate_domain(domain: &str, base_hosts: &[String]) -> Vec<String> {
+    let mut errors = Vec::new();
+    if domain.is_empty() || domain.len() > MAX_DOMAIN_LENGTH {
+        errors.push(format!("Domain must be 1-{} characters", MAX_DOMAIN_LENGTH));
+        return errors;
+    }
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 {
+        errors.push("Domain needs at least two labels, e.g. app.example.com".to_string());
+    }
+    let valid_labels = labels.iter().all(|label| {
+        !label.is_empty()
+            && label.len() <= MAX_LABEL_LENGTH
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    });
+    if !valid_labels {
+        errors.push("Each label must be 1-63 letters, numbers or inner hyphens".to_string());
+    }
+    if labels.last().is_some_and(|tld| tld.chars().all(|c| c.is_ascii_digit())) {
+        errors.push("IP addresses can't be used as custom domains".to_string());
+    }
+    for base_host in base_hosts {
+        if domain == base_host || domain.ends_with(&format!(".{}", base_host)) {
+            errors.push(format!("Subdomains of {} are managed as hostnames, not custom domains", base_host));
+        }
+    }
+
+    errors
+}
+
+// Uses the shared client, which doesn't follow redirects: the token has to
+// come from the domain itself, not from wherever it points to
+async fn fetch_http_challenge(client: &reqwest::Client, url: &str) -> Result<String, reqwest::Error> {
+    client.get(url).timeout(HTTP_CHALLENGE_TIMEOUT).send().await?.error_for_status()?.text().await
+}
+
+async fn fetch_domain(
+    pool: &SqlitePool,
+    connection_id: i64,
+    domain_id: i64,
+) -> Result<CustomDomain, (StatusCode, String)> {
+    sqlx::query_as::<_, CustomDomain>(
+        "SELECT id, connection_id, domain, method, token, verified_at, created_at FROM custom_domains \
+         WHERE id = ? AND connection_id = ?",
+    )
+    .bind(domain_id)
+    .bind(connection_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(internal_error)?
+    .ok_or((StatusCode::NOT_FOUND, format!("Domain {} not found on connection {}", domain_id, connection_id)))
+}
+
+fn internal_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
+}
+
+// R9.7 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::dns::StaticResolver;
+    use crate::SandoBuilder;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use std::sync::Arc;
+
+    async fn test_state(resolver: Arc<StaticResolver>) -> AppState {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::migrate(&pool).await.unwrap();
+        for id in [1, 2] {
+            sqlx::query("INSERT INTO connections (id, connection_string, port, subdomain) VALUES (?, ?, ?, ?)")
+                .bind(id)
+                .bind(format!("key{}", id))
+                .bind(4000 + id)
+                .bind(format!("app{}", id))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        SandoBuilder::new(pool).host("sando.blue").dns_resolver(resolver).build_state()
+    }
+
+    fn form(domain: &str) -> CustomDomainForm {
+        CustomDomainForm { domain: domain.to_string(), method: VerificationMethod::Dns }
+    }
+
+    #[test]
+    fn test_validate_domain() {
+        let hosts = ["sando.blue".to_string(), "sando.internal".to_string()];
+        assert!(validate_domain("app.example.com", &hosts).is_empty());
+        assert!(validate_domain("xn--bcher-kva.example", &hosts).is_empty());
+        assert!(!validate_domain("localhost", &hosts).is_empty());
+        assert!(!validate_domain("10.0.0.1", &hosts).is_empty());
+        assert!(!validate_domain("-bad.example.com", &hosts).is_empty());
+        assert!(!validate_domain("a..example.com", &hosts).is_empty());
+        assert!(!validate_domain("my-app.sando.blue", &hosts).is_empty());
+        assert!(!validate_domain("my-app.sando.internal", &hosts).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dns_verification_enables_routing() {
+        let resolver = Arc::new(StaticResolver::default());
+        let state = test_state(resolver.clone()).await;
+        let pool = state.pool.as_ref();
+
+        let mine = add_domain(&state, 1, form("App.Example.com."), AuditActor::Web).await.unwrap();
+        assert_eq!(mine.domain, "app.example.com");
+        let squatter = add_domain(&state, 2, form("app.example.com"), AuditActor::Web).await.unwrap();
+
+        // Not routed and not verifiable until the record exists
+        assert_eq!(verified_domain_connection(pool, "app.example.com").await.unwrap(), None);
+        let (status, _) = verify_domain(&state, 1, mine.id, AuditActor::Web).await.unwrap_err();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        resolver.insert_txt(&mine.dns_record_name(), &mine.dns_record_value());
+        let verified = verify_domain(&state, 1, mine.id, AuditActor::Web).await.unwrap();
+        assert!(verified.verified_at.is_some());
+        assert_eq!(verified_domain_connection(pool, "APP.example.com").await.unwrap(), Some(1));
+
+        // The competing pending claim is gone and new claims are refused
+        assert!(list_domains(pool, 2).await.unwrap().is_empty());
+        let (status, _) = verify_domain(&state, 2, squatter.id, AuditActor::Web).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = add_domain(&state, 2, form("app.example.com"), AuditActor::Web).await.unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        remove_domain(pool, 1, mine.id, AuditActor::Web).await.unwrap();
+        assert_eq!(verified_domain_connection(pool, "app.example.com").await.unwrap(), None);
+    }
+}
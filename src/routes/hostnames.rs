@@ -0,0 +1,388 @@
+/**
+ * R8.0 Hostnames Route
+ * ====================
+ *
+ * Lets a connection answer on several subdomains. Each connection has one
+ * canonical hostname (mirrored in `connections.subdomain`) plus any number
+ * of aliases, which either serve the tunnel too or redirect to the canonical
+ * hostname so renamed services keep their old links working.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: R8.1, R8.2, R8.3, R8.4, R8.5, R8.6, R8.7
+ */
+// R8.1 Dependencies
+use crate::models::{AuditAction, AuditActor, ConnectionHostname, HostnameForm, HostnameUpdateForm};
+use crate::routes::connections::{challenge_unauthorized, check_owner, Authority};
+use crate::routes::subdomains::{namespace_error, validate_subdomain};
+use crate::services::audit::AuditEvent;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use sqlx::sqlite::{Sqlite, SqlitePool};
+use sqlx::Transaction;
+
+// R8.2 Add Alias Handler
+// POST /connections/:id/hostnames from the edit page. Hostname changes need
+// the connection's current key or the admin token, like other edits.
+#[tracing::instrument(name = "add_hostname", skip(app_state, headers, form))]
+pub async fn add_hostname_handler(
+    State(app_state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    Form(form): Form<HostnameForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let authority = Authority::from_headers(&app_state, &headers);
+    if let Err(error) = check_owner(&app_state, id, &authority, "Adding hostnames").await {
+        return challenge_unauthorized(&app_state, &headers, error);
+    }
+    add_hostname(app_state.pool.as_ref(), &app_state.hosts, id, form, authority.actor(AuditActor::Web)).await?;
+    Ok(Redirect::to(&format!("/connections/{}/edit", id)).into_response())
+}
+
+// R8.3 Update Alias Handler
+// PATCH /connections/:id/hostnames/:hostname_id, sent from script
+#[tracing::instrument(name = "update_hostname", skip(app_state, headers, form))]
+pub async fn update_hostname_handler(
+    State(app_state): State<AppState>,
+    Path((id, hostname_id)): Path<(i64, i64)>,
+    headers: HeaderMap,
+    Form(form): Form<HostnameUpdateForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let authority = Authority::from_headers(&app_state, &headers);
+    if let Err(error) = check_owner(&app_state, id, &authority, "Changing hostnames").await {
+        return challenge_unauthorized(&app_state, &headers, error);
+    }
+    update_hostname(app_state.pool.as_ref(), id, hostname_id, form, authority.actor(AuditActor::Web)).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// R8.4 Remove Alias Handler
+// DELETE /connections/:id/hostnames/:hostname_id, sent from script
+#[tracing::instrument(name = "remove_hostname", skip(app_state, headers))]
+pub async fn remove_hostname_handler(
+    State(app_state): State<AppState>,
+    Path((id, hostname_id)): Path<(i64, i64)>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let authority = Authority::from_headers(&app_state, &headers);
+    if let Err(error) = check_owner(&app_state, id, &authority, "Removing hostnames").await {
+        return challenge_unauthorized(&app_state, &headers, error);
+    }
+    remove_hostname(app_state.pool.as_ref(), id, hostname_id, authority.actor(AuditActor::Web)).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// R8.5 Hostname Logic
+// Shared by the HTML handlers above and the JSON API.
+pub async fn list_hostnames(pool: &SqlitePool, connection_id: i64) -> Result<Vec<ConnectionHostname>, sqlx::Error> {
+    sqlx::query_as::<_, ConnectionHostname>(
+        "SELECT id, connection_id, hostname, is_canonical, redirect, created_at FROM connection_hostnames \
+         WHERE connection_id = ? ORDER BY is_canonical DESC, hostname",
+    )
+    .bind(connection_id)
+    .fetch_all(pool)
+    .await
+}
+
+// The connection a hostname belongs to, if any
+pub async fn hostname_owner(pool: &SqlitePool, hostname: &str) -> Result<Option<i64>, sqlx::Error> {
+    let owner: Option<(i64,)> = sqlx::query_as("SELECT connection_id FROM connection_hostnames WHERE hostname = ?")
+        .bind(hostname)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(owner.map(|(connection_id,)| connection_id))
+}
+
+// `base_hosts` are the configured base hosts (see `validate_subdomain`).
+// Changes are recorded in the audit log as updates by `actor`.
+pub async fn add_hostname(
+    pool: &SqlitePool,
+    base_hosts: &[String],
+    connection_id: i64,
+    form: HostnameForm,
+    actor: AuditActor,
+) -> Result<ConnectionHostname, (StatusCode, String)> {
+    let hostname = form.hostname.trim().to_ascii_lowercase();
+    let errors = validate_subdomain(&hostname, base_hosts);
+    if !errors.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, errors.join(". ")));
+    }
+    let owner = fetch_connection_owner(pool, connection_id).await?;
+    if hostname_owner(pool, &hostname).await.map_err(internal_error)?.is_some() {
+        return Err((StatusCode::CONFLICT, format!("Subdomain '{}' is already taken", hostname)));
+    }
+    if let Some(error) = namespace_error(pool, &hostname, Some(connection_id), owner.as_deref())
+        .await
+        .map_err(internal_error)?
+    {
+        return Err((StatusCode::CONFLICT, error));
+    }
+
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let hostname_id = sqlx::query("INSERT INTO connection_hostnames (connection_id, hostname, redirect) VALUES (?, ?, ?)")
+        .bind(connection_id)
+        .bind(&hostname)
+        .bind(form.redirect && !form.canonical)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_error)?
+        .last_insert_rowid();
+    if form.canonical {
+        promote_hostname(&mut tx, connection_id, &hostname).await.map_err(internal_error)?;
+    }
+    tx.commit().await.map_err(internal_error)?;
+
+    tracing::info!("Added hostname '{}' to connection {}", hostname, connection_id);
+    AuditEvent::new(actor, AuditAction::Update)
+        .connection_id(connection_id)
+        .detail("hostname_added", hostname.as_str())
+        .detail("redirect", form.redirect && !form.canonical)
+        .detail("canonical", form.canonical)
+        .record(pool)
+        .await;
+    fetch_hostname(pool, connection_id, hostname_id).await
+}
+
+pub async fn update_hostname(
+    pool: &SqlitePool,
+    connection_id: i64,
+    hostname_id: i64,
+    form: HostnameUpdateForm,
+    actor: AuditActor,
+) -> Result<ConnectionHostname, (StatusCode, String)> {
+    let hostname = fetch_hostname(pool, connection_id, hostname_id).await?;
+
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    if form.canonical == Some(true) && !hostname.is_canonical {
+        promote_hostname(&mut tx, connection_id, &hostname.hostname).await.map_err(internal_error)?;
+    } else if form.canonical == Some(false) && hostname.is_canonical {
+        return Err((
+            StatusCode::CONFLICT,
+            "A connection needs a canonical hostname; promote another one instead".to_string(),
+        ));
+    }
+
+    // The canonical hostname always serves the tunnel itself
+    if let Some(redirect) = form.redirect {
+        sqlx::query("UPDATE connection_hostnames SET redirect = ? WHERE id = ? AND NOT is_canonical")
+            .bind(redirect)
+            .bind(hostname_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(internal_error)?;
+    }
+    tx.commit().await.map_err(internal_error)?;
+
+    let mut event = AuditEvent::new(actor, AuditAction::Update)
+        .connection_id(connection_id)
+        .detail("hostname_updated", hostname.hostname.as_str());
+    if let Some(redirect) = form.redirect {
+        event = event.detail("redirect", redirect);
+    }
+    if let Some(canonical) = form.canonical {
+        event = event.detail("canonical", canonical);
+    }
+    event.record(pool).await;
+
+    fetch_hostname(pool, connection_id, hostname_id).await
+}
+
+pub async fn remove_hostname(
+    pool: &SqlitePool,
+    connection_id: i64,
+    hostname_id: i64,
+    actor: AuditActor,
+) -> Result<(), (StatusCode, String)> {
+    let hostname = fetch_hostname(pool, connection_id, hostname_id).await?;
+    if hostname.is_canonical {
+        return Err((
+            StatusCode::CONFLICT,
+            "The canonical hostname can't be removed; promote another one first".to_string(),
+        ));
+    }
+
+    sqlx::query("DELETE FROM connection_hostnames WHERE id = ?")
+        .bind(hostname_id)
+        .execute(pool)
+        .await
+        .map_err(internal_error)?;
+
+    tracing::info!("Removed hostname '{}' from connection {}", hostname.hostname, connection_id);
+    AuditEvent::new(actor, AuditAction::Update)
+        .connection_id(connection_id)
+        .detail("hostname_removed", hostname.hostname.as_str())
+        .record(pool)
+        .await;
+    Ok(())
+}
+
+// R8.6 Canonical Hostname
+// Makes `hostname` the canonical name of the connection, adding it if it is
+// new. The previous canonical hostname stays as an alias that redirects, so
+// links to the old name keep working after a rename.
+pub async fn promote_hostname(
+    tx: &mut Transaction<'_, Sqlite>,
+    connection_id: i64,
+    hostname: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE connection_hostnames SET is_canonical = 0, redirect = 1 WHERE connection_id = ? AND is_canonical")
+        .bind(connection_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query(
+        "INSERT INTO connection_hostnames (connection_id, hostname, is_canonical) VALUES (?, ?, 1) \
+         ON CONFLICT (hostname) DO UPDATE SET is_canonical = 1, redirect = 0 WHERE connection_id = excluded.connection_id",
+    )
+    .bind(connection_id)
+    .bind(hostname)
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query("UPDATE connections SET subdomain = ? WHERE id = ?")
+        .bind(hostname)
+        .bind(connection_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+async fn fetch_hostname(
+    pool: &SqlitePool,
+    connection_id: i64,
+    hostname_id: i64,
+) -> Result<ConnectionHostname, (StatusCode, String)> {
+    sqlx::query_as::<_, ConnectionHostname>(
+        "SELECT id, connection_id, hostname, is_canonical, redirect, created_at FROM connection_hostnames \
+         WHERE id = ? AND connection_id = ?",
+    )
+    .bind(hostname_id)
+    .bind(connection_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(internal_error)?
+    .ok_or((StatusCode::NOT_FOUND, format!("Hostname {} not found on connection {}", hostname_id, connection_id)))
+}
+
+// The connection's owner contact, which decides the namespaces it may use
+async fn fetch_connection_owner(pool: &SqlitePool, connection_id: i64) -> Result<Option<String>, (StatusCode, String)> {
+    let connection: Option<(Option<String>,)> = sqlx::query_as("SELECT owner FROM connections WHERE id = ?")
+        .bind(connection_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(internal_error)?;
+
+    connection
+        .map(|(owner,)| owner)
+        .ok_or((StatusCode::NOT_FOUND, format!("Connection {} not found", connection_id)))
+}
+
+fn internal_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
+}
+
+// R8.7 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn test_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::migrate(&pool).await.unwrap();
+
+        for (id, subdomain) in [(1, "shop"), (2, "blog")] {
+            sqlx::query("INSERT INTO connections (id, connection_string, port, subdomain) VALUES (?, ?, ?, ?)")
+                .bind(id)
+                .bind(format!("key{}", id))
+                .bind(4000 + id)
+                .bind(subdomain)
+                .execute(&pool)
+                .await
+                .unwrap();
+            let mut tx = pool.begin().await.unwrap();
+            promote_hostname(&mut tx, id, subdomain).await.unwrap();
+            tx.commit().await.unwrap();
+        }
+        pool
+    }
+
+    fn hosts() -> Vec<String> {
+        vec!["localhost".to_string()]
+    }
+
+    fn form(hostname: &str) -> HostnameForm {
+        HostnameForm { hostname: hostname.to_string(), ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn test_aliases_are_unique_across_connections() {
+        let pool = test_pool().await;
+
+        let alias = add_hostname(&pool, &hosts(), 1, form("Store"), AuditActor::Web).await.unwrap();
+        assert_eq!(alias.hostname, "store");
+        assert!(!alias.is_canonical);
+
+        let (status, _) = add_hostname(&pool, &hosts(), 2, form("store"), AuditActor::Web).await.unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = add_hostname(&pool, &hosts(), 2, form("shop"), AuditActor::Web).await.unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = add_hostname(&pool, &hosts(), 2, form("not valid"), AuditActor::Web).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Nested names stay inside their namespace holder's connections
+        let nested = add_hostname(&pool, &hosts(), 1, form("v2.shop"), AuditActor::Web).await.unwrap();
+        assert_eq!(nested.hostname, "v2.shop");
+        let (status, _) = add_hostname(&pool, &hosts(), 2, form("admin.shop"), AuditActor::Web).await.unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = add_hostname(&pool, &hosts(), 99, form("orphan"), AuditActor::Web).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_promoting_an_alias_keeps_old_name_redirecting() {
+        let pool = test_pool().await;
+        let alias = add_hostname(&pool, &hosts(), 1, form("store"), AuditActor::Web).await.unwrap();
+        let original = list_hostnames(&pool, 1).await.unwrap().remove(0);
+        assert_eq!(original.hostname, "shop");
+
+        let update = HostnameUpdateForm { canonical: Some(true), ..Default::default() };
+        let promoted = update_hostname(&pool, 1, alias.id, update, AuditActor::Web).await.unwrap();
+        assert!(promoted.is_canonical && !promoted.redirect);
+
+        let hostnames = list_hostnames(&pool, 1).await.unwrap();
+        assert_eq!(hostnames[0].hostname, "store");
+        assert_eq!((hostnames[1].hostname.as_str(), hostnames[1].redirect), ("shop", true));
+        let (subdomain,): (String,) = sqlx::query_as("SELECT subdomain FROM connections WHERE id = 1")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(subdomain, "store");
+
+        // The canonical hostname can't be removed, aliases can
+        let (status, _) = remove_hostname(&pool, 1, promoted.id, AuditActor::Web).await.unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        remove_hostname(&pool, 1, original.id, AuditActor::Web).await.unwrap();
+        assert_eq!(list_hostnames(&pool, 1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_hostnames_are_deleted_with_their_connection() {
+        let pool = test_pool().await;
+        add_hostname(&pool, &hosts(), 1, form("store"), AuditActor::Web).await.unwrap();
+
+        sqlx::query("DELETE FROM connections WHERE id = 1").execute(&pool).await.unwrap();
+        assert_eq!(hostname_owner(&pool, "store").await.unwrap(), None);
+        assert_eq!(hostname_owner(&pool, "blog").await.unwrap(), Some(2));
+    }
+}
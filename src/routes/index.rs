@@ -9,11 +9,14 @@
  */
 // R1.1 Dependencies
 use crate::components::home_page::home_page;
-use axum::response::Html;
+use crate::AppState;
+use axum::{extract::State, response::Html};
 
 // R1.2 Index Handler
-// Serves the main page by rendering the `home_page` component.
-#[tracing::instrument(name = "index")]
-pub async fn index() -> Html<String> {
-    Html(home_page().into_string())
+// Serves the main page by rendering the `home_page` component, with the
+// proof-of-work challenge its form has to solve, if any.
+#[tracing::instrument(name = "index", skip(app_state))]
+pub async fn index(State(app_state): State<AppState>) -> Html<String> {
+    let proof_of_work = app_state.proof_of_work.as_ref().map(|pow| (pow.bits(), app_state.host.as_str()));
+    Html(home_page(proof_of_work).into_string())
 } 
\ No newline at end of file
@@ -8,4 +8,11 @@
 pub mod index;
 pub mod submit;
 pub mod connections;
-pub mod proxy; 
+pub mod proxy;
+pub mod subdomains;
+pub mod api;
+pub mod openapi;
+pub mod hostnames;
+pub mod domains;
+pub mod admin;
+pub mod access;
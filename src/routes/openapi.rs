@@ -0,0 +1,146 @@
+/**
+ * R7.0 OpenAPI Route
+ * ==================
+ *
+ * Serves the OpenAPI 3 description of the JSON API at `/api/openapi.json`.
+ * The document is generated from the `#[utoipa::path]` annotations on the
+ * handlers and the `ToSchema` models, so it can't drift from the code.
+ * A copy is checked in next to the `sando-client` crate; the test below fails
+ * when it is stale (regenerate with `SANDO_UPDATE_OPENAPI=1 cargo test`).
+ * This file is tagged for machine-readability.
+ *
+ * Tags: R7.1, R7.2, R7.3, R7.4
+ */
+// R7.1 Dependencies
+use crate::models::{
+    AuditAction, AuditActor, AuditEntry, ConnectionCredential, ConnectionForm, ConnectionHostname, ConnectionResource,
+    ConnectionSort, ConnectionUpdateForm, CredentialForm, CredentialKind, CustomDomainForm, CustomDomainResource,
+    DomainChallenge, HostnameForm, HostnameUpdateForm, IpRules, ResponseRewrite, SignedLink, SignedLinkForm, SortOrder,
+    TunnelLimits, TunnelStatus, VerificationMethod,
+};
+use crate::routes::api::{
+    AuditLog, ConnectionList, CredentialList, DomainList, ErrorBody, ErrorDetail, HostnameList, PaymentRequiredBody,
+};
+use crate::routes::subdomains::{Price, SubdomainAvailability};
+use crate::services::backup::{
+    ExportDocument, ExportFormat, ExportedConnection, ExportedCredential, ExportedDomain, ExportedHostname, ImportReport,
+};
+use crate::routes::{api, subdomains};
+use axum::Json;
+use utoipa::OpenApi;
+
+// R7.2 API Document
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Sando API",
+        description = "Manage Sando tunnels: connections paid for with Cashu (NUT-24) and subdomain availability.",
+    ),
+    paths(
+        api::list_connections,
+        api::get_connection,
+        api::create_connection,
+        api::update_connection,
+        api::delete_connection,
+        api::list_hostnames,
+        api::add_hostname,
+        api::update_hostname,
+        api::remove_hostname,
+        api::list_domains,
+        api::add_domain,
+        api::verify_domain,
+        api::remove_domain,
+        api::list_credentials,
+        api::add_credential,
+        api::remove_credential,
+        api::create_link,
+        api::revoke_links,
+        api::export_connections,
+        api::import_connections,
+        api::audit_log,
+        subdomains::check_subdomain,
+    ),
+    components(schemas(
+        ConnectionForm,
+        ConnectionUpdateForm,
+        ConnectionResource,
+        ConnectionList,
+        TunnelStatus,
+        ConnectionSort,
+        SortOrder,
+        ResponseRewrite,
+        TunnelLimits,
+        IpRules,
+        ConnectionHostname,
+        HostnameForm,
+        HostnameUpdateForm,
+        HostnameList,
+        CustomDomainForm,
+        CustomDomainResource,
+        DomainChallenge,
+        DomainList,
+        VerificationMethod,
+        ConnectionCredential,
+        CredentialKind,
+        CredentialForm,
+        CredentialList,
+        SignedLinkForm,
+        SignedLink,
+        ExportDocument,
+        ExportedConnection,
+        ExportedHostname,
+        ExportedDomain,
+        ExportedCredential,
+        ExportFormat,
+        ImportReport,
+        AuditEntry,
+        AuditActor,
+        AuditAction,
+        AuditLog,
+        ErrorBody,
+        ErrorDetail,
+        PaymentRequiredBody,
+        SubdomainAvailability,
+        Price,
+    )),
+    tags(
+        (name = "connections", description = "Connection CRUD"),
+        (name = "hostnames", description = "Canonical hostname and aliases of a connection"),
+        (name = "domains", description = "Custom domains with ownership verification"),
+        (name = "access", description = "Basic users, bearer tokens and signed links protecting a tunnel"),
+        (name = "backup", description = "Operator import and export of connections"),
+        (name = "audit", description = "Operator view of who changed what, and when"),
+        (name = "subdomains", description = "Subdomain availability"),
+    ),
+)]
+pub struct ApiDoc;
+
+// R7.3 OpenAPI Handler
+#[tracing::instrument(name = "openapi_json")]
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// R7.4 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECKED_IN_SPEC: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/crates/sando-client/openapi.json");
+
+    #[test]
+    fn test_checked_in_spec_is_current() {
+        let generated = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+
+        if std::env::var("SANDO_UPDATE_OPENAPI").is_ok() {
+            std::fs::write(CHECKED_IN_SPEC, &generated).unwrap();
+        }
+
+        let checked_in = std::fs::read_to_string(CHECKED_IN_SPEC).unwrap_or_default();
+        assert!(
+            checked_in == generated,
+            "{} is out of date; regenerate it with SANDO_UPDATE_OPENAPI=1 cargo test",
+            CHECKED_IN_SPEC
+        );
+    }
+}
@@ -7,26 +7,63 @@
  * Establishes holesail connections using background mode for persistent connections
  * This file is tagged for machine-readability.
  *
- * Tags: R4.1, R4.2, R4.3, R4.4, R4.5, R4.6, R4.7, R4.8, R4.9
+ * Tags: R4.1, R4.2, R4.3, R4.4, R4.5, R4.6, R4.7, R4.8, R4.9, R4.10, R4.11, R4.12
  */
 // R4.1 Dependencies
+use crate::models::{TunnelStatus, CONNECTION_COLUMNS};
+use crate::routes::admin::check_admin;
+use crate::routes::domains::verified_domain_connection;
+use crate::services::access::{access_denied, check_access, link_redirect, strip_access_credentials, Access};
+use crate::services::forwarding::{add_forwarding_headers, client_ip, via_header};
+use crate::services::ip_filter::forbidden;
+use crate::services::limits::{effective_limits, too_many_requests, InFlightPermit};
+use crate::services::ownership::is_port_owned_by;
+use crate::services::rewrite::{rewrite_headers, BodyRewriter, Rewriter};
 use crate::{AppState, Connection};
 use axum::{
-    body::Body,
+    body::{Body, Bytes, HttpBody},
     extract::{Host, OriginalUri, State},
-    http::{HeaderMap, Method, StatusCode, Uri},
+    http::{header, HeaderMap, Method, StatusCode, Uri},
     response::Response,
 };
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
+use futures_util::{Stream, StreamExt};
 use lazy_static::lazy_static;
-use reqwest::Client;
-use serde::Deserialize;
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
 use tokio::time::sleep;
 use tokio::process::Command as TokioCommand;
 
+// How long proxied requests may wait on the tunnel. There is no deadline for
+// the whole exchange, so event streams, long polls and slow downloads run as
+// long as data keeps flowing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpstreamTimeouts {
+    // Opening the connection to the tunnel's port
+    pub connect: Duration,
+    // From sending the request to receiving the response headers
+    pub first_byte: Duration,
+    // Between two chunks of a request or response body
+    pub idle: Duration,
+}
+
+impl Default for UpstreamTimeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(10),
+            first_byte: Duration::from_secs(30),
+            idle: Duration::from_secs(300),
+        }
+    }
+}
+
 // R4.2 Background Connection Manager
 // Tracks holesail background connections and their states
 lazy_static! {
@@ -40,6 +77,7 @@ struct BackgroundConnection {
     port: u16,
     name: String,
     status: ConnectionStatus,
+    pid: Option<u32>, // PID of the holesail process serving this port
     #[serde(skip)] // Skip serializing SystemTime as it's not easily serializable
     last_used: std::time::SystemTime,
 }
@@ -52,68 +90,310 @@ enum ConnectionStatus {
     Error,
 }
 
-#[derive(Deserialize, Debug)]
+// One block of `holesail --list` output
+#[derive(Debug, Clone, Default, PartialEq)]
 struct HolesailListEntry {
-    #[serde(rename = "ID")]
-    id: u32,
-    #[serde(rename = "Name")]
     name: String,
-    #[serde(rename = "Status")]
     status: String,
-    #[serde(rename = "PID")]
-    pid: u32,
-    #[serde(rename = "Uptime")]
-    uptime: String,
+    pid: Option<u32>,
 }
 
 // R4.3 Subdomain-based Proxy Handler  
 // Routes incoming requests from `{connection_string}.localhost:3000/*` to the appropriate backend service
-#[tracing::instrument(name = "proxy_handler_subdomain", skip(app_state, body, headers))]
+// `peer` is the address the request came from, for forwarding headers.
+#[tracing::instrument(name = "proxy_handler_subdomain", skip(app_state, body, headers, on_upgrade))]
+#[allow(clippy::too_many_arguments)]
 pub async fn proxy_handler_subdomain(
     State(app_state): State<AppState>,
     Host(host): Host,
     OriginalUri(original_uri): OriginalUri,
+    peer: Option<IpAddr>,
     method: Method,
     headers: HeaderMap,
+    on_upgrade: Option<OnUpgrade>,
     body: Body,
 ) -> Result<Response, StatusCode> {
     // Only handle subdomain requests, return 404 for everything else
-    let connection_string = match extract_subdomain(&host, &app_state.host) {
-        Ok(cs) => cs,
+    let (connection_string, base_host) = match extract_subdomain(&host, &app_state.hosts) {
+        Ok(matched) => matched,
         Err(_) => return Err(StatusCode::NOT_FOUND), // Not a subdomain request
     };
     
+    let request_host = headers.get(header::HOST).and_then(|value| value.to_str().ok());
+    let connection = match subdomain_target(&app_state, &connection_string, base_host, request_host, &original_uri).await? {
+        ProxyTarget::Tunnel(connection) => *connection,
+        // Redirecting aliases send clients to the canonical hostname instead
+        ProxyTarget::Redirect { connection, location } => {
+            let client = client_ip(&headers, peer, &app_state.trusted_proxies);
+            if let Some(refusal) = admit_redirect(&app_state, &connection, &connection_string, &headers, client)? {
+                return Ok(refusal);
+            }
+            return Response::builder()
+                .status(StatusCode::PERMANENT_REDIRECT)
+                .header(header::LOCATION, location)
+                .body(Body::empty())
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
     // Use the full path for subdomain-based proxying
     let proxy_path = original_uri.path();
     
-    proxy_request(app_state, &connection_string, proxy_path, original_uri.clone(), method, headers, body).await
+    proxy_request(
+        app_state,
+        connection,
+        &connection_string,
+        proxy_path,
+        original_uri.clone(),
+        peer,
+        method,
+        headers,
+        on_upgrade,
+        body,
+    )
+    .await
 }
 
-// R4.4 Core Proxy Logic
-// Shared logic for both path-based and subdomain-based proxying
-#[tracing::instrument(name = "proxy_request", skip(app_state, body, headers))]
-async fn proxy_request(
-    app_state: AppState,
-    connection_string: &str,
-    target_path: &str,
-    original_uri: Uri,
+// R4.4 Custom Domain Proxy Handler
+// Routes requests for a verified custom domain (see `routes::domains`) to the
+// connection it is attached to. The root handler has already resolved it.
+#[tracing::instrument(name = "proxy_handler_custom_domain", skip(app_state, body, headers, on_upgrade))]
+#[allow(clippy::too_many_arguments)]
+pub async fn proxy_handler_custom_domain(
+    State(app_state): State<AppState>,
+    connection_id: i64,
+    OriginalUri(original_uri): OriginalUri,
+    peer: Option<IpAddr>,
     method: Method,
     headers: HeaderMap,
+    on_upgrade: Option<OnUpgrade>,
     body: Body,
 ) -> Result<Response, StatusCode> {
-    // Look up the connection in the database by subdomain
-    let connection = sqlx::query_as::<_, Connection>(
-        "SELECT id, connection_string, port, subdomain, created_at FROM connections WHERE subdomain = ?",
+    let connection = fetch_proxied_connection(&app_state, connection_id).await?;
+    let source = headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    proxy_request(
+        app_state,
+        connection,
+        &source,
+        original_uri.path(),
+        original_uri.clone(),
+        peer,
+        method,
+        headers,
+        on_upgrade,
+        body,
+    )
+    .await
+}
+
+async fn fetch_proxied_connection(app_state: &AppState, connection_id: i64) -> Result<Connection, StatusCode> {
+    sqlx::query_as::<_, Connection>(
+        &format!("SELECT {} FROM connections WHERE id = ?", CONNECTION_COLUMNS),
     )
-    .bind(connection_string)
+    .bind(connection_id)
+    .fetch_optional(app_state.pool.as_ref())
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)
+}
+
+// Where a request for a tunnel hostname goes
+pub enum ProxyTarget {
+    Tunnel(Box<Connection>),
+    // A redirecting alias of `connection`; `location` is the canonical hostname
+    Redirect { connection: Box<Connection>, location: String },
+}
+
+// Resolves a request's Host the same way the root handler does, for data
+// planes other than this module (see `services::data_plane`). `None` means
+// the request is for the app itself: a base host, or a domain that isn't a
+// verified custom domain.
+pub async fn resolve_proxy_target(app_state: &AppState, host: &str, uri: &Uri) -> Result<Option<ProxyTarget>, StatusCode> {
+    let host_without_port = host.split(':').next().unwrap_or(host).trim_end_matches('.').to_ascii_lowercase();
+    if app_state.hosts.contains(&host_without_port) {
+        return Ok(None);
+    }
+    if let Ok((name, base_host)) = extract_subdomain(host, &app_state.hosts) {
+        return subdomain_target(app_state, &name, base_host, Some(host), uri).await.map(Some);
+    }
+
+    let connection_id = verified_domain_connection(app_state.pool.as_ref(), &host_without_port)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match connection_id {
+        Some(connection_id) => Ok(Some(ProxyTarget::Tunnel(Box::new(fetch_proxied_connection(app_state, connection_id).await?)))),
+        None => Ok(None),
+    }
+}
+
+// Looks the connection up by any of its hostnames
+async fn subdomain_target(
+    app_state: &AppState,
+    name: &str,
+    base_host: &str,
+    request_host: Option<&str>,
+    uri: &Uri,
+) -> Result<ProxyTarget, StatusCode> {
+    let hostname: Option<(i64, bool)> = sqlx::query_as(
+        "SELECT connection_id, redirect FROM connection_hostnames WHERE hostname = ?",
+    )
+    .bind(name)
     .fetch_optional(app_state.pool.as_ref())
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (connection_id, redirect) = hostname.ok_or(StatusCode::NOT_FOUND)?;
+    let connection = fetch_proxied_connection(app_state, connection_id).await?;
+
+    if redirect {
+        let canonical = connection.subdomain.as_deref().ok_or(StatusCode::NOT_FOUND)?;
+        let location = canonical_location(request_host, canonical, base_host, uri);
+        return Ok(ProxyTarget::Redirect { connection: Box::new(connection), location });
+    }
+    Ok(ProxyTarget::Tunnel(Box::new(connection)))
+}
+
+// R4.5 Core Proxy Logic
+// Whether a request may reach its tunnel, decided the same way by every data
+// plane (see `admit_tunnel_request`)
+pub enum Admission {
+    // Proxy it, holding `permit` until the response body has been sent or the
+    // WebSocket has closed. Drop the Authorization header first when
+    // `strip_authorization` is set.
+    Proxy { permit: InFlightPermit, strip_authorization: bool },
+    // Answer with this response instead
+    Refuse(Response),
+}
+
+// Checks a request for `connection` from `client` before it is proxied:
+// loop detection via `Via`, the global blocklist and the connection's allow
+// and deny ranges (see `services::ip_filter`), rate and in-flight limits
+// (`services::limits`) and credentials or signed links for protected
+// tunnels (`services::access`). Then makes sure the tunnel is online.
+// `headers` are the client's, before Sando adds its forwarding headers;
+// `source` is the hostname the request came in on, for logging.
+pub async fn admit_tunnel_request(
+    app_state: &AppState,
+    connection: &Connection,
+    source: &str,
+    headers: &HeaderMap,
+    uri: &Uri,
+    client: Option<IpAddr>,
+) -> Result<Admission, StatusCode> {
+    let permit = match screen_request(app_state, connection, source, headers, client)? {
+        Ok(permit) => permit,
+        Err(refusal) => return Ok(Admission::Refuse(refusal)),
+    };
 
-    let connection = connection.ok_or(StatusCode::NOT_FOUND)?;
+    let access = check_access(app_state.pool.as_ref(), &app_state.rate_limiter, connection, headers, uri, client)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let strip_authorization = match access {
+        Access::Granted { strip_authorization } => strip_authorization,
+        Access::Redirect { location, cookie } => return Ok(Admission::Refuse(link_redirect(&location, &cookie))),
+        Access::Denied { challenge } => {
+            tracing::info!("Denied {}{} without valid credentials", source, uri.path());
+            return Ok(Admission::Refuse(access_denied(challenge.as_deref())));
+        }
+        Access::Throttled(limited) => {
+            tracing::info!("Too many failed sign-ins to {} from {}", source, client.map_or_else(|| "unknown client".to_string(), |ip| ip.to_string()));
+            return Ok(Admission::Refuse(too_many_requests(limited)));
+        }
+    };
 
     // Establish or ensure holesail background connection is running
-    ensure_background_connection(&connection.connection_string, connection.port as u16).await?;
+    app_state.tunnels.bring_online(&connection.connection_string, connection.port as u16).await?;
+    Ok(Admission::Proxy { permit, strip_authorization })
+}
+
+// The checks of `admit_tunnel_request` that apply to a redirecting alias of
+// `connection`, which never reaches the tunnel: loop detection, IP filters
+// and limits. Returns the response to send instead of the redirect, if any.
+pub fn admit_redirect(
+    app_state: &AppState,
+    connection: &Connection,
+    source: &str,
+    headers: &HeaderMap,
+    client: Option<IpAddr>,
+) -> Result<Option<Response>, StatusCode> {
+    Ok(screen_request(app_state, connection, source, headers, client)?.err())
+}
+
+// Loop detection, IP filters and limits; the in-flight permit, or the
+// response refusing the request
+fn screen_request(
+    app_state: &AppState,
+    connection: &Connection,
+    source: &str,
+    headers: &HeaderMap,
+    client: Option<IpAddr>,
+) -> Result<Result<InFlightPermit, Response>, StatusCode> {
+    via_header(headers, &app_state.host)?;
+
+    let ip_filter = &app_state.ip_filter;
+    if ip_filter.check_blocklist(client).is_err() || ip_filter.check_connection(connection, client).is_err() {
+        return Ok(Err(forbidden()));
+    }
+
+    let limits = effective_limits(app_state, connection);
+    match app_state.rate_limiter.acquire(connection.id, client, &limits) {
+        Ok(permit) => Ok(Ok(permit)),
+        Err(limited) => {
+            tracing::info!("Limiting {} for {}", source, client.map_or_else(|| "unknown client".to_string(), |ip| ip.to_string()));
+            Ok(Err(too_many_requests(limited)))
+        }
+    }
+}
+
+// Shared logic for both subdomain and custom domain proxying. `source` is the
+// hostname the request came in on, for logging.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "proxy_request", skip(app_state, connection, body, headers, on_upgrade))]
+async fn proxy_request(
+    app_state: AppState,
+    connection: Connection,
+    source: &str,
+    target_path: &str,
+    original_uri: Uri,
+    peer: Option<IpAddr>,
+    method: Method,
+    mut headers: HeaderMap,
+    on_upgrade: Option<OnUpgrade>,
+    body: Body,
+) -> Result<Response, StatusCode> {
+    let client = client_ip(&headers, peer, &app_state.trusted_proxies);
+    let (permit, strip_authorization) =
+        match admit_tunnel_request(&app_state, &connection, source, &headers, &original_uri, client).await? {
+            Admission::Proxy { permit, strip_authorization } => (permit, strip_authorization),
+            Admission::Refuse(response) => return Ok(response),
+        };
+    add_forwarding_headers(&mut headers, peer, &app_state.trusted_proxies, &app_state.host)?;
+    strip_access_credentials(&mut headers, strip_authorization);
+
+    // WebSocket handshakes are relayed over a raw connection (see R4.11).
+    // Without `on_upgrade` (e.g. HTTP/2) the request is proxied as plain HTTP.
+    if let Some(on_upgrade) = on_upgrade.filter(|_| is_websocket_upgrade(&headers)) {
+        let path_and_query = original_uri
+            .query()
+            .map(|query| format!("{}?{}", target_path, query))
+            .unwrap_or_else(|| target_path.to_string());
+        tracing::debug!("Proxying WebSocket {} {}{}", method, source, path_and_query);
+        return proxy_websocket(
+            connection.port as u16,
+            &method,
+            &path_and_query,
+            &headers,
+            on_upgrade,
+            app_state.upstream_timeouts,
+            app_state.websocket_idle_timeout,
+            permit,
+        )
+        .await;
+    }
 
     // Create the target URL with the correct path and query string
     let target_url = format!("http://localhost:{}{}", connection.port, target_path);
@@ -125,28 +405,25 @@ async fn proxy_request(
         target_url
     };
 
-    println!("🔄 Proxying {} {} -> {}", method, connection_string, final_url);
-
-    // Create HTTP client with timeout
-    let client = Client::builder()
-        .timeout(Duration::from_secs(30))
-        .build()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    // Convert axum body to bytes
-    let body_bytes = axum::body::to_bytes(body, usize::MAX)
-        .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    tracing::debug!("Proxying {} {} -> {}", method, source, final_url);
 
-    // Build the proxy request
-    let mut request_builder = client.request(
+    // Build the proxy request on the shared, pooled client
+    let timeouts = app_state.upstream_timeouts;
+    let mut request_builder = app_state.upstream_client.request(
         method.as_str().parse().map_err(|_| StatusCode::BAD_REQUEST)?,
         &final_url,
     );
 
-    // Forward relevant headers (excluding hop-by-hop headers)
+    // Forward relevant headers (excluding hop-by-hop headers). Content-Length
+    // is kept, so streamed bodies of known size aren't sent chunked. Bodies
+    // that get rewritten must arrive uncompressed.
+    let rewriter = Rewriter::for_request(connection.response_rewrite, &headers);
+    let identity_only = rewriter.as_ref().is_some_and(Rewriter::needs_identity_encoding);
     for (name, value) in headers.iter() {
         let name_str = name.as_str();
+        if identity_only && *name == header::ACCEPT_ENCODING {
+            continue;
+        }
         if !is_hop_by_hop_header(name_str) && !name_str.starts_with("x-original-") {
             if let Ok(value_str) = value.to_str() {
                 request_builder = request_builder.header(name_str, value_str);
@@ -154,19 +431,24 @@ async fn proxy_request(
         }
     }
 
-    // Add the body if present
-    if !body_bytes.is_empty() {
-        request_builder = request_builder.body(body_bytes);
+    // Stream the body, if any, as it arrives from the client
+    if !body.is_end_stream() {
+        let upload = with_idle_timeout(body.into_data_stream(), timeouts.idle);
+        request_builder = request_builder.body(reqwest::Body::wrap_stream(upload));
     }
 
-    // Send the request
-    let response = request_builder
-        .send()
-        .await
-        .map_err(|err| {
+    // Send the request and wait for the response headers
+    let response = match tokio::time::timeout(timeouts.first_byte, request_builder.send()).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(err)) => {
             println!("❌ Proxy request failed: {}", err);
-            StatusCode::BAD_GATEWAY
-        })?;
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+        Err(_) => {
+            tracing::warn!("Proxy request to {} timed out", final_url);
+            return Err(StatusCode::GATEWAY_TIMEOUT);
+        }
+    };
 
     // Build the response
     let status = StatusCode::from_u16(response.status().as_u16())
@@ -174,7 +456,19 @@ async fn proxy_request(
     
     let mut response_builder = Response::builder().status(status);
     
+    // Event streams must reach the client event by event; this also tells a
+    // reverse proxy in front of Sando (e.g. nginx) not to buffer them
+    let is_event_stream = response
+        .headers()
+        .get(header::CONTENT_TYPE.as_str())
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim_start().to_ascii_lowercase().starts_with("text/event-stream"));
+    if is_event_stream {
+        response_builder = response_builder.header("x-accel-buffering", "no");
+    }
+
     // Forward response headers (excluding hop-by-hop headers)
+    let mut response_headers = HeaderMap::new();
     for (name, value) in response.headers() {
         let name_str = name.as_str();
         if !is_hop_by_hop_header(name_str) {
@@ -182,24 +476,109 @@ async fn proxy_request(
                 axum::http::HeaderName::from_bytes(name.as_str().as_bytes()),
                 axum::http::HeaderValue::from_bytes(value.as_bytes())
             ) {
-                response_builder = response_builder.header(header_name, header_value);
+                response_headers.append(header_name, header_value);
             }
         }
     }
 
-    let body_bytes = response
-        .bytes()
-        .await
-        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+    // Point localhost URLs at the public hostname (see `services::rewrite`)
+    let mut body_rewriter = None;
+    if let Some(rewriter) = &rewriter {
+        rewrite_headers(rewriter, &mut response_headers);
+        let content_type = response_headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok());
+        let content_encoding = response_headers.get(header::CONTENT_ENCODING).and_then(|value| value.to_str().ok());
+        if rewriter.rewrites_body(content_type, content_encoding) {
+            response_headers.remove(header::CONTENT_LENGTH);
+            body_rewriter = Some(rewriter.body_rewriter());
+        }
+    }
+    if let Some(headers) = response_builder.headers_mut() {
+        headers.extend(response_headers);
+    }
 
-    response_builder
-        .body(Body::from(body_bytes))
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    // Stream the body back; each chunk is only read from the tunnel once the
+    // client has taken the previous one, so memory stays bounded. Chunks are
+    // passed on as they arrive, never collected.
+    let body = hold_permit(with_idle_timeout(response.bytes_stream(), timeouts.idle), permit);
+    let body = match body_rewriter {
+        Some(body_rewriter) => Body::from_stream(rewrite_body(body, body_rewriter)),
+        None => Body::from_stream(body),
+    };
+    response_builder.body(body).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// Keeps the request counted as in flight until its body is done or dropped
+fn hold_permit<S>(stream: S, permit: InFlightPermit) -> impl Stream<Item = std::io::Result<Bytes>> + Send
+where
+    S: Stream<Item = std::io::Result<Bytes>> + Send + 'static,
+{
+    stream.map(move |chunk| {
+        let _ = &permit;
+        chunk
+    })
+}
+
+// Passes a body through a `BodyRewriter`, flushing what it held back at the end
+fn rewrite_body<S>(stream: S, rewriter: BodyRewriter) -> impl Stream<Item = std::io::Result<Bytes>> + Send
+where
+    S: Stream<Item = std::io::Result<Bytes>> + Send + 'static,
+{
+    futures_util::stream::unfold(Some((Box::pin(stream), rewriter)), |state| async move {
+        let (mut stream, mut rewriter) = state?;
+        match stream.next().await {
+            Some(Ok(chunk)) => Some((Ok(rewriter.push(&chunk)), Some((stream, rewriter)))),
+            Some(Err(err)) => Some((Err(err), None)),
+            None => Some((Ok(rewriter.finish()), None)),
+        }
+    })
 }
 
-// R4.5 Background Connection Management
-// Enhanced connection management using holesail's background features
-async fn ensure_background_connection(connection_string: &str, port: u16) -> Result<(), StatusCode> {
+// Ends a body stream with an error when no chunk arrives within `idle`, so
+// a stalled tunnel or client doesn't hold the connection open forever.
+fn with_idle_timeout<S, E>(stream: S, idle: Duration) -> impl Stream<Item = std::io::Result<Bytes>> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    futures_util::stream::unfold(Some(Box::pin(stream)), move |state| async move {
+        let mut stream = state?;
+        match tokio::time::timeout(idle, stream.next()).await {
+            Ok(Some(Ok(chunk))) => Some((Ok(chunk), Some(stream))),
+            Ok(Some(Err(err))) => Some((Err(std::io::Error::other(err)), None)),
+            Ok(None) => None,
+            Err(_) => {
+                let err = std::io::Error::new(std::io::ErrorKind::TimedOut, format!("no data for {:?}", idle));
+                Some((Err(err), None))
+            }
+        }
+    })
+}
+
+// R4.6 Background Connection Management
+// Makes sure holesail is serving `port` for `connection_string` and that the
+// port is really owned by that holesail process; otherwise whatever took the
+// port over would go public. Also used to confirm a rotated key is live.
+pub async fn bring_connection_online(connection_string: &str, port: u16) -> Result<(), StatusCode> {
+    let holesail_pid = ensure_background_connection(connection_string, port).await?;
+
+    if !is_port_owned_by(holesail_pid, port) {
+        tracing::error!(
+            "❌ Port {} is not owned by holesail PID {}; refusing to proxy",
+            port, holesail_pid
+        );
+        let connection_key = format!("{}:{}", connection_string, port);
+        if let Some(conn) = BACKGROUND_CONNECTIONS.lock().unwrap().get_mut(&connection_key) {
+            conn.status = ConnectionStatus::Error;
+        }
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+
+    Ok(())
+}
+
+// Enhanced connection management using holesail's background features.
+// Returns the PID of the holesail process serving the port.
+async fn ensure_background_connection(connection_string: &str, port: u16) -> Result<u32, StatusCode> {
     let connection_name = generate_connection_name(connection_string, port);
     let connection_key = format!("{}:{}", connection_string, port);
 
@@ -212,17 +591,19 @@ async fn ensure_background_connection(connection_string: &str, port: u16) -> Res
     }
 
     // Check if connection exists and is running
-    if is_background_connection_running(&connection_name).await? {
+    if let Some(entry) = find_background_connection(&connection_name).await? {
         tracing::debug!("Background connection {} already running", connection_name);
+        let pid = entry.pid.ok_or(StatusCode::BAD_GATEWAY)?;
         
         // Update our tracking
         {
             let mut connections = BACKGROUND_CONNECTIONS.lock().unwrap();
             if let Some(conn) = connections.get_mut(&connection_key) {
                 conn.status = ConnectionStatus::Online;
+                conn.pid = Some(pid);
             }
         }
-        return Ok(());
+        return Ok(pid);
     }
 
     // Check holesail availability
@@ -251,7 +632,7 @@ async fn start_background_connection(
     port: u16,
     connection_name: &str,
     connection_key: &str,
-) -> Result<(), StatusCode> {
+) -> Result<u32, StatusCode> {
     tracing::info!("Starting background holesail connection: {}", connection_name);
 
     // Update tracking before starting
@@ -262,6 +643,7 @@ async fn start_background_connection(
             port,
             name: connection_name.to_string(),
             status: ConnectionStatus::Starting,
+            pid: None,
             last_used: std::time::SystemTime::now(),
         });
     }
@@ -309,15 +691,16 @@ async fn start_background_connection(
     sleep(Duration::from_millis(2000)).await;
 
     // Verify connection is running
-    if is_background_connection_running(connection_name).await? {
+    if let Some(pid) = find_background_connection(connection_name).await?.and_then(|entry| entry.pid) {
         {
             let mut connections = BACKGROUND_CONNECTIONS.lock().unwrap();
             if let Some(conn) = connections.get_mut(connection_key) {
                 conn.status = ConnectionStatus::Online;
+                conn.pid = Some(pid);
             }
         }
-        tracing::info!("✅ Background holesail connection established: {}", connection_name);
-        Ok(())
+        tracing::info!("✅ Background holesail connection established: {} (PID {})", connection_name, pid);
+        Ok(pid)
     } else {
         {
             let mut connections = BACKGROUND_CONNECTIONS.lock().unwrap();
@@ -331,6 +714,11 @@ async fn start_background_connection(
 }
 
 async fn is_background_connection_running(connection_name: &str) -> Result<bool, StatusCode> {
+    Ok(find_background_connection(connection_name).await?.is_some())
+}
+
+// Looks up our connection in `holesail --list`, returning it only if online
+async fn find_background_connection(connection_name: &str) -> Result<Option<HolesailListEntry>, StatusCode> {
     let output = TokioCommand::new("holesail")
         .arg("--list")
         .output()
@@ -338,54 +726,71 @@ async fn is_background_connection_running(connection_name: &str) -> Result<bool,
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     if !output.status.success() {
-        return Ok(false);
+        return Ok(None);
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
-    
-    // Parse the specific format that holesail --list uses
-    // Looking for lines that contain "Name:" followed by our connection name
-    let mut current_connection_name = None;
-    let mut current_status = None;
-    
+
+    // Holesail adds "holesail-" prefix to our names
+    // So "sando-d7925c15-5854" becomes "holesail-sando-d7925c15-5854"
+    let holesail_name = format!("holesail-{}", connection_name);
+    let entry = parse_holesail_list(&stdout)
+        .into_iter()
+        .find(|entry| entry.name == holesail_name && entry.status == "online");
+
+    match &entry {
+        Some(entry) => tracing::info!("✅ Found matching background connection: {} (status: {}, PID: {:?})", entry.name, entry.status, entry.pid),
+        None => tracing::debug!("No matching online connection found for: {}", connection_name),
+    }
+
+    Ok(entry)
+}
+
+// Parse the specific format that holesail --list uses: blocks of
+// "Key: value" lines separated by "---", e.g.
+//   Name: holesail-sando-d7925c15-5854
+//   Status: online
+//   PID: 12345
+fn parse_holesail_list(stdout: &str) -> Vec<HolesailListEntry> {
+    let mut entries = Vec::new();
+    let mut current = HolesailListEntry::default();
+
     for line in stdout.lines() {
         let line = line.trim();
-        if line.is_empty() || line.starts_with("---") || line.starts_with("ID") {
+        if line.starts_with("---") {
+            if !current.name.is_empty() {
+                entries.push(std::mem::take(&mut current));
+            }
             continue;
         }
-        
-        if line.starts_with("Name:") {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                current_connection_name = Some(parts[1].to_string());
-            }
-        } else if line.starts_with("Status:") {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                current_status = Some(parts[1].to_string());
+
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+
+        match key.trim() {
+            "Name" => {
+                // A new name without a separator starts the next block
+                if !current.name.is_empty() {
+                    entries.push(std::mem::take(&mut current));
+                }
+                current.name = value.to_string();
             }
-        }
-        
-        // If we have both name and status, check if it matches
-        if let (Some(ref name), Some(ref status)) = (&current_connection_name, &current_status) {
-            // Holesail adds "holesail-" prefix to our names
-            // So "sando-d7925c15-5854" becomes "holesail-sando-d7925c15-5854"
-            if name == &format!("holesail-{}", connection_name) && status == "online" {
-                tracing::info!("✅ Found matching background connection: {} (status: {})", name, status);
-                return Ok(true);
-            }
-            
-            // Reset for next connection block
-            current_connection_name = None;
-            current_status = None;
+            "Status" => current.status = value.to_string(),
+            "PID" => current.pid = value.parse().ok(),
+            _ => {}
         }
     }
-    
-    tracing::debug!("No matching online connection found for: {}", connection_name);
-    Ok(false)
+
+    if !current.name.is_empty() {
+        entries.push(current);
+    }
+
+    entries
 }
 
-// R4.6 Holesail Availability Check
+// R4.7 Holesail Availability Check
 // Checks if holesail command is available in PATH
 fn check_holesail_available() -> bool {
     match Command::new("holesail").arg("--help").output() {
@@ -400,7 +805,7 @@ fn check_holesail_available() -> bool {
     }
 }
 
-// R4.7 Connection Cleanup and Management
+// R4.8 Connection Cleanup and Management
 // Periodically clean up unused connections to prevent resource leaks
 pub async fn cleanup_unused_connections() {
     let cleanup_threshold = Duration::from_secs(300); // 5 minutes
@@ -458,7 +863,23 @@ async fn stop_background_connection(connection_name: &str) -> Result<(), StatusC
     }
 }
 
-// R4.8 Helper Functions
+// Stops the holesail process for a deleted connection and forgets it, so its
+// local port is actually free again when the allocator hands it out next.
+pub async fn release_background_connection(connection_string: &str, port: u16) {
+    let connection_name = generate_connection_name(connection_string, port);
+    let connection_key = format!("{}:{}", connection_string, port);
+
+    BACKGROUND_CONNECTIONS.lock().unwrap().remove(&connection_key);
+
+    if is_background_connection_running(&connection_name).await.unwrap_or(false) {
+        tracing::info!("🧹 Stopping background connection for deleted tunnel: {}", connection_name);
+        if let Err(e) = stop_background_connection(&connection_name).await {
+            tracing::error!("Failed to stop background connection {}: {:?}", connection_name, e);
+        }
+    }
+}
+
+// R4.9 Helper Functions
 
 // Generate a unique, safe name for background connections
 fn generate_connection_name(connection_string: &str, port: u16) -> String {
@@ -472,22 +893,36 @@ fn generate_connection_name(connection_string: &str, port: u16) -> String {
     format!("sando-{}-{}", short_id, port)
 }
 
-// Extract connection string from subdomain (e.g., "my-service.localhost:3000" -> "my-service")
-fn extract_subdomain(host: &str, configured_host: &str) -> Result<String, StatusCode> {
-    // Remove port if present
-    let host_without_port = host.split(':').next().unwrap_or(host);
-    
-    // Check if it's a subdomain of the configured host
-    let suffix = format!(".{}", configured_host);
-    if let Some(subdomain) = host_without_port.strip_suffix(&suffix) {
-        if !subdomain.is_empty() {
-            Ok(subdomain.to_string())
-        } else {
-            Err(StatusCode::BAD_REQUEST)
-        }
-    } else {
-        Err(StatusCode::BAD_REQUEST)
-    }
+// Extract the hostname below a base host, with the base host it was found
+// under (e.g., "svc.alice.localhost:3000" -> ("svc.alice", "localhost")).
+// When base hosts nest, the longest one wins, so with "sando.blue" and
+// "internal.sando.blue" configured, "app.internal.sando.blue" is "app".
+pub fn extract_subdomain<'a>(host: &str, base_hosts: &'a [String]) -> Result<(String, &'a str), StatusCode> {
+    // Remove port and trailing dot if present
+    let host_without_port = host.split(':').next().unwrap_or(host).trim_end_matches('.').to_ascii_lowercase();
+
+    base_hosts
+        .iter()
+        .filter_map(|base_host| {
+            let subdomain = host_without_port.strip_suffix(&format!(".{}", base_host))?;
+            Some((subdomain, base_host.as_str()))
+        })
+        .max_by_key(|(_, base_host)| base_host.len())
+        .filter(|(subdomain, _)| !subdomain.is_empty() && !subdomain.split('.').any(str::is_empty))
+        .map(|(subdomain, base_host)| (subdomain.to_string(), base_host))
+        .ok_or(StatusCode::BAD_REQUEST)
+}
+
+// Scheme-relative URL of the same path on the canonical hostname, keeping the
+// port the client used (e.g. "//new-name.localhost:3000/path?q=1")
+fn canonical_location(request_host: Option<&str>, canonical: &str, base_host: &str, uri: &Uri) -> String {
+    let port = request_host
+        .and_then(|host| host.rsplit_once(':'))
+        .map(|(_, port)| format!(":{}", port))
+        .unwrap_or_default();
+    let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+
+    format!("//{}.{}{}{}", canonical, base_host, port, path_and_query)
 }
 
 // Helper function to identify hop-by-hop headers that shouldn't be forwarded
@@ -499,11 +934,10 @@ fn is_hop_by_hop_header(name: &str) -> bool {
     )
 }
 
-// R4.9 Connection Status API
+// R4.10 Connection Status API
 // Provides endpoints to check and manage background connections
-pub async fn get_connection_status() -> Result<Response, StatusCode> {
-    let connections = BACKGROUND_CONNECTIONS.lock().unwrap();
-    let status_json = serde_json::to_string(&*connections)
+pub async fn get_connection_status(State(app_state): State<AppState>) -> Result<Response, StatusCode> {
+    let status_json = serde_json::to_string(&app_state.tunnels.status())
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
     Response::builder()
@@ -513,7 +947,302 @@ pub async fn get_connection_status() -> Result<Response, StatusCode> {
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-// R4.10 Tests
+// Snapshot of the tracked holesail connections keyed by "{connection_string}:{port}"
+pub fn background_connection_status() -> serde_json::Value {
+    let connections = BACKGROUND_CONNECTIONS.lock().unwrap();
+    serde_json::to_value(&*connections).unwrap_or_default()
+}
+
+// State of a single tracked connection; untracked ones have never been
+// started (or were cleaned up) and count as idle.
+pub fn background_tunnel_status(connection_string: &str, port: u16) -> TunnelStatus {
+    let connection_key = format!("{}:{}", connection_string, port);
+    match BACKGROUND_CONNECTIONS.lock().unwrap().get(&connection_key).map(|conn| &conn.status) {
+        Some(ConnectionStatus::Starting) => TunnelStatus::Starting,
+        Some(ConnectionStatus::Online) => TunnelStatus::Online,
+        Some(ConnectionStatus::Stopped) => TunnelStatus::Stopped,
+        Some(ConnectionStatus::Error) => TunnelStatus::Error,
+        None => TunnelStatus::Idle,
+    }
+}
+
+// R4.11 WebSocket Upgrades
+// reqwest can't hand over an upgraded connection, so the handshake is written
+// to the tunnel's port by hand. Once the upstream answers 101 the client gets
+// the same answer, and the two connections are spliced: bytes (the WebSocket
+// frames) are copied both ways until either side closes, or neither has sent
+// anything for the idle timeout.
+pub const DEFAULT_WEBSOCKET_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const MAX_RESPONSE_HEAD: usize = 16 * 1024;
+const MAX_RESPONSE_HEADERS: usize = 64;
+// Refused handshakes are passed on with at most this much of their body
+const MAX_REFUSAL_BODY: usize = 64 * 1024;
+const WEBSOCKET_BUFFER_SIZE: usize = 16 * 1024;
+
+lazy_static! {
+    static ref WEBSOCKET_STATS: WebSocketStats = WebSocketStats::default();
+}
+
+// Counters since startup, served to the admin token at /status/websockets
+#[derive(Default)]
+struct WebSocketStats {
+    open: AtomicU64,
+    total: AtomicU64,
+    refused: AtomicU64,
+    idle_closed: AtomicU64,
+    bytes_to_upstream: AtomicU64,
+    bytes_to_client: AtomicU64,
+}
+
+pub async fn get_websocket_status(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    check_admin(&app_state, &headers)?;
+    let stats = &*WEBSOCKET_STATS;
+    let status = serde_json::json!({
+        "open": stats.open.load(Ordering::Relaxed),
+        "total": stats.total.load(Ordering::Relaxed),
+        "refused": stats.refused.load(Ordering::Relaxed),
+        "idle_closed": stats.idle_closed.load(Ordering::Relaxed),
+        "bytes_to_upstream": stats.bytes_to_upstream.load(Ordering::Relaxed),
+        "bytes_to_client": stats.bytes_to_client.load(Ordering::Relaxed),
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .body(Body::from(status.to_string()))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// Blocklist hits and per-connection rule violations since startup, for the admin token
+pub async fn get_ip_filter_status(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    check_admin(&app_state, &headers)?;
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .body(Body::from(app_state.ip_filter.status().to_string()))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// `Connection: upgrade` (possibly among other tokens) and `Upgrade: websocket`
+fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    let connection_upgrade = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+    let websocket = headers
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case("websocket"));
+
+    connection_upgrade && websocket
+}
+
+async fn proxy_websocket(
+    port: u16,
+    method: &Method,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    on_upgrade: OnUpgrade,
+    timeouts: UpstreamTimeouts,
+    idle_timeout: Duration,
+    permit: InFlightPermit,
+) -> Result<Response, StatusCode> {
+    let mut upstream = match tokio::time::timeout(timeouts.connect, TcpStream::connect(("localhost", port))).await {
+        Ok(Ok(upstream)) => upstream,
+        Ok(Err(err)) => {
+            tracing::warn!("WebSocket connection to port {} failed: {}", port, err);
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+        Err(_) => return Err(StatusCode::GATEWAY_TIMEOUT),
+    };
+
+    upstream
+        .write_all(&handshake_request(method, path_and_query, headers, port))
+        .await
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+    let (status, upstream_headers, mut buffered) =
+        match tokio::time::timeout(timeouts.first_byte, read_response_head(&mut upstream)).await {
+            Ok(Ok(head)) => head,
+            Ok(Err(status)) => return Err(status),
+            Err(_) => return Err(StatusCode::GATEWAY_TIMEOUT),
+        };
+
+    // The upstream refused the upgrade; pass its answer on as a plain response
+    if status != StatusCode::SWITCHING_PROTOCOLS {
+        WEBSOCKET_STATS.refused.fetch_add(1, Ordering::Relaxed);
+        let length = upstream_headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(0)
+            .min(MAX_REFUSAL_BODY);
+        if buffered.len() < length {
+            let mut rest = vec![0; length - buffered.len()];
+            let read = tokio::time::timeout(timeouts.first_byte, upstream.read_exact(&mut rest)).await;
+            if matches!(read, Ok(Ok(_))) {
+                buffered.extend_from_slice(&rest);
+            }
+        }
+        buffered.truncate(length);
+
+        let mut response_builder = Response::builder().status(status);
+        for (name, value) in upstream_headers.iter() {
+            if !is_hop_by_hop_header(name.as_str()) && name != header::CONTENT_LENGTH {
+                response_builder = response_builder.header(name, value);
+            }
+        }
+        return response_builder
+            .body(Body::from(buffered))
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    // Accepted: answer the client with the upstream's handshake headers
+    // (Sec-WebSocket-Accept, the chosen protocol and extensions, ...)
+    let mut response_builder = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "upgrade");
+    for (name, value) in upstream_headers.iter() {
+        if name == header::UPGRADE || !is_hop_by_hop_header(name.as_str()) {
+            response_builder = response_builder.header(name, value);
+        }
+    }
+    let response = response_builder
+        .body(Body::empty())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // The client's connection is only handed over after the 101 is sent. The
+    // socket counts as in flight until it closes.
+    tokio::spawn(async move {
+        let _permit = permit;
+        let client = match on_upgrade.await {
+            Ok(upgraded) => TokioIo::new(upgraded),
+            Err(err) => {
+                tracing::warn!("WebSocket upgrade failed: {}", err);
+                return;
+            }
+        };
+
+        WEBSOCKET_STATS.open.fetch_add(1, Ordering::Relaxed);
+        WEBSOCKET_STATS.total.fetch_add(1, Ordering::Relaxed);
+        match splice_websocket(client, upstream, &buffered, idle_timeout).await {
+            Ok(true) => {
+                WEBSOCKET_STATS.idle_closed.fetch_add(1, Ordering::Relaxed);
+                tracing::info!("Closed WebSocket on port {} after {:?} idle", port, idle_timeout);
+            }
+            Ok(false) => {}
+            Err(err) => tracing::warn!("WebSocket on port {} failed: {}", port, err),
+        }
+        WEBSOCKET_STATS.open.fetch_sub(1, Ordering::Relaxed);
+    });
+
+    Ok(response)
+}
+
+// The client's handshake as an HTTP/1.1 request head for the tunnel's port
+fn handshake_request(method: &Method, path_and_query: &str, headers: &HeaderMap, port: u16) -> Vec<u8> {
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nhost: localhost:{}\r\nconnection: upgrade\r\n",
+        method, path_and_query, port
+    )
+    .into_bytes();
+    for (name, value) in headers.iter() {
+        let name_str = name.as_str();
+        if name == header::UPGRADE || (!is_hop_by_hop_header(name_str) && !name_str.starts_with("x-original-")) {
+            request.extend_from_slice(name_str.as_bytes());
+            request.extend_from_slice(b": ");
+            request.extend_from_slice(value.as_bytes());
+            request.extend_from_slice(b"\r\n");
+        }
+    }
+    request.extend_from_slice(b"\r\n");
+    request
+}
+
+// Reads the upstream's response head; also returns whatever arrived after
+// it, which already belongs to the body (or the first WebSocket frames).
+async fn read_response_head(upstream: &mut TcpStream) -> Result<(StatusCode, HeaderMap, Vec<u8>), StatusCode> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let read = upstream.read(&mut chunk).await.map_err(|_| StatusCode::BAD_GATEWAY)?;
+        if read == 0 {
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+
+        let mut parsed_headers = [httparse::EMPTY_HEADER; MAX_RESPONSE_HEADERS];
+        let mut response = httparse::Response::new(&mut parsed_headers);
+        match response.parse(&buffer) {
+            Ok(httparse::Status::Complete(head_length)) => {
+                let status = response
+                    .code
+                    .and_then(|code| StatusCode::from_u16(code).ok())
+                    .ok_or(StatusCode::BAD_GATEWAY)?;
+                let mut headers = HeaderMap::new();
+                for parsed in response.headers.iter() {
+                    if let (Ok(name), Ok(value)) = (
+                        axum::http::HeaderName::from_bytes(parsed.name.as_bytes()),
+                        axum::http::HeaderValue::from_bytes(parsed.value),
+                    ) {
+                        headers.append(name, value);
+                    }
+                }
+                return Ok((status, headers, buffer.split_off(head_length)));
+            }
+            Ok(httparse::Status::Partial) if buffer.len() < MAX_RESPONSE_HEAD => continue,
+            _ => return Err(StatusCode::BAD_GATEWAY),
+        }
+    }
+}
+
+// Copies bytes both ways until either side closes. Returns true when the
+// connection was closed for being idle.
+async fn splice_websocket<C, U>(mut client: C, mut upstream: U, buffered: &[u8], idle_timeout: Duration) -> std::io::Result<bool>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+{
+    client.write_all(buffered).await?;
+    WEBSOCKET_STATS.bytes_to_client.fetch_add(buffered.len() as u64, Ordering::Relaxed);
+
+    let mut from_client = vec![0u8; WEBSOCKET_BUFFER_SIZE];
+    let mut from_upstream = vec![0u8; WEBSOCKET_BUFFER_SIZE];
+    loop {
+        let transfer = async {
+            tokio::select! {
+                read = client.read(&mut from_client) => {
+                    let read = read?;
+                    upstream.write_all(&from_client[..read]).await?;
+                    WEBSOCKET_STATS.bytes_to_upstream.fetch_add(read as u64, Ordering::Relaxed);
+                    Ok::<usize, std::io::Error>(read)
+                }
+                read = upstream.read(&mut from_upstream) => {
+                    let read = read?;
+                    client.write_all(&from_upstream[..read]).await?;
+                    WEBSOCKET_STATS.bytes_to_client.fetch_add(read as u64, Ordering::Relaxed);
+                    Ok(read)
+                }
+            }
+        };
+
+        match tokio::time::timeout(idle_timeout, transfer).await {
+            Ok(Ok(0)) => return Ok(false),
+            Ok(Ok(_)) => continue,
+            Ok(Err(err)) => return Err(err),
+            Err(_) => return Ok(true),
+        }
+    }
+}
+
+// R4.12 Tests
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -530,16 +1259,44 @@ mod tests {
 
     #[test]
     async fn test_extract_subdomain() {
+        let hosts = ["localhost".to_string()];
+
         // Test valid subdomains
-        assert_eq!(extract_subdomain("api.localhost", "localhost"), Ok("api".to_string()));
-        assert_eq!(extract_subdomain("my-service.localhost", "localhost"), Ok("my-service".to_string()));
-        assert_eq!(extract_subdomain("test-api.localhost:3000", "localhost"), Ok("test-api".to_string()));
+        assert_eq!(extract_subdomain("api.localhost", &hosts), Ok(("api".to_string(), "localhost")));
+        assert_eq!(extract_subdomain("my-service.localhost", &hosts), Ok(("my-service".to_string(), "localhost")));
+        assert_eq!(extract_subdomain("test-api.localhost:3000", &hosts), Ok(("test-api".to_string(), "localhost")));
+        assert_eq!(extract_subdomain("Svc.Alice.localhost.", &hosts), Ok(("svc.alice".to_string(), "localhost")));
         
         // Test invalid cases
-        assert!(extract_subdomain("localhost", "localhost").is_err());
-        assert!(extract_subdomain(".localhost", "localhost").is_err());
-        assert!(extract_subdomain("example.com", "localhost").is_err());
-        assert!(extract_subdomain("", "localhost").is_err());
+        assert!(extract_subdomain("localhost", &hosts).is_err());
+        assert!(extract_subdomain(".localhost", &hosts).is_err());
+        assert!(extract_subdomain("a..localhost", &hosts).is_err());
+        assert!(extract_subdomain("example.com", &hosts).is_err());
+        assert!(extract_subdomain("", &hosts).is_err());
+    }
+
+    #[test]
+    async fn test_extract_subdomain_with_several_base_hosts() {
+        let hosts = ["sando.blue".to_string(), "internal.sando.blue".to_string(), "tunnels.test".to_string()];
+
+        assert_eq!(extract_subdomain("app.sando.blue", &hosts), Ok(("app".to_string(), "sando.blue")));
+        assert_eq!(extract_subdomain("app.tunnels.test", &hosts), Ok(("app".to_string(), "tunnels.test")));
+        // The more specific base host wins
+        assert_eq!(extract_subdomain("app.internal.sando.blue", &hosts), Ok(("app".to_string(), "internal.sando.blue")));
+        assert_eq!(extract_subdomain("internal.sando.blue", &hosts), Ok(("internal".to_string(), "sando.blue")));
+    }
+
+    #[test]
+    async fn test_canonical_location() {
+        let uri: Uri = "/docs?page=2".parse().unwrap();
+        assert_eq!(
+            canonical_location(Some("old-name.localhost:3000"), "new-name", "localhost", &uri),
+            "//new-name.localhost:3000/docs?page=2"
+        );
+        assert_eq!(
+            canonical_location(Some("old.sando.blue"), "new", "sando.blue", &"/".parse().unwrap()),
+            "//new.sando.blue/"
+        );
     }
 
     #[test]
@@ -558,6 +1315,53 @@ mod tests {
         assert!(!is_hop_by_hop_header("accept"));
     }
 
+    #[test]
+    async fn test_websocket_handshake_request() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "chat.localhost:3000".parse().unwrap());
+        headers.insert(header::CONNECTION, "keep-alive, Upgrade".parse().unwrap());
+        headers.insert(header::UPGRADE, "websocket".parse().unwrap());
+        headers.insert("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==".parse().unwrap());
+        assert!(is_websocket_upgrade(&headers));
+
+        let request = String::from_utf8(handshake_request(&Method::GET, "/socket?room=1", &headers, 4100)).unwrap();
+        assert!(request.starts_with("GET /socket?room=1 HTTP/1.1\r\nhost: localhost:4100\r\nconnection: upgrade\r\n"));
+        assert!(request.contains("upgrade: websocket\r\n"));
+        assert!(request.contains("sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n"));
+        assert!(!request.contains("chat.localhost"));
+        assert!(request.ends_with("\r\n\r\n"));
+
+        headers.insert(header::UPGRADE, "h2c".parse().unwrap());
+        assert!(!is_websocket_upgrade(&headers));
+        headers.remove(header::CONNECTION);
+        headers.insert(header::UPGRADE, "websocket".parse().unwrap());
+        assert!(!is_websocket_upgrade(&headers));
+    }
+
+    #[test]
+    async fn test_parse_holesail_list() {
+        let stdout = "ID: 0
+Name: holesail-sando-abcdef12-3001
+Status: online
+PID: 4242
+Uptime: 5m
+---
+ID: 1
+Name: holesail-sando-12345678-3002
+Status: stopped
+PID: 0
+";
+        let entries = parse_holesail_list(stdout);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], HolesailListEntry {
+            name: "holesail-sando-abcdef12-3001".to_string(),
+            status: "online".to_string(),
+            pid: Some(4242),
+        });
+        assert_eq!(entries[1].status, "stopped");
+        assert!(parse_holesail_list("").is_empty());
+    }
+
     #[test]
     async fn test_background_connection_tracking() {
         // Clear any existing connections for this test
@@ -579,6 +1383,7 @@ mod tests {
                 port,
                 name: name.clone(),
                 status: ConnectionStatus::Starting,
+                pid: None,
                 last_used: std::time::SystemTime::now(),
             });
         }
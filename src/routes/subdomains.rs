@@ -0,0 +1,349 @@
+/**
+ * R5.0 Subdomains Route
+ * =====================
+ *
+ * Handles GET requests to `/api/subdomains/:name` so the home page can tell
+ * users whether a subdomain is valid and free before they reach the payment page.
+ * Also holds the naming rules shared by everything that hands out hostnames.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: R5.1, R5.2, R5.3, R5.4, R5.5, R5.6, R5.7
+ */
+// R5.1 Dependencies
+use crate::routes::submit::PAYMENT_AMOUNT;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rand::Rng;
+use serde::Serialize;
+use sqlx::sqlite::SqlitePool;
+use utoipa::ToSchema;
+
+// R5.2 Subdomain Rules
+// A subdomain is one to three DNS labels below a base host. The last label is
+// its namespace: `svc.alice` lives in the `alice` namespace, which belongs to
+// whoever holds `alice` itself (see `namespace_error`). Hosts map to exactly
+// one hostname; `x.svc.alice` never falls back to `svc.alice`. A few names
+// are reserved for the app itself and can't be used as namespaces either.
+const MAX_SUBDOMAIN_LENGTH: usize = 63;
+const MAX_SUBDOMAIN_LABELS: usize = 3;
+const RESERVED_SUBDOMAINS: &[&str] = &["www", "api", "admin", "static", "mail", "status"];
+const MAX_SUGGESTIONS: usize = 3;
+
+// R5.3 Availability Response
+#[derive(Serialize, Debug, ToSchema)]
+pub struct SubdomainAvailability {
+    pub name: String,
+    pub valid: bool,
+    pub available: bool,
+    pub errors: Vec<String>,
+    pub price: Price,
+    pub suggestions: Vec<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Price {
+    pub amount: u64,
+    pub unit: String,
+}
+
+// R5.4 Availability Handler
+// Validates the requested name, checks whether it is already taken and, if it
+// can't be used, suggests a few alternatives that can.
+#[utoipa::path(
+    get,
+    path = "/api/subdomains/{name}",
+    tag = "subdomains",
+    params(("name" = String, Path, description = "Requested subdomain")),
+    responses((status = 200, description = "Availability of the subdomain", body = SubdomainAvailability)),
+)]
+#[tracing::instrument(name = "check_subdomain", skip(app_state))]
+pub async fn check_subdomain(
+    State(app_state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<SubdomainAvailability>, StatusCode> {
+    let name = name.trim().to_ascii_lowercase();
+    let pool = app_state.pool.as_ref();
+
+    let mut errors = validate_subdomain(&name, &app_state.hosts);
+    let valid = errors.is_empty();
+    let mut available = valid
+        && !is_subdomain_taken(pool, &name)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Anonymous requests can only use nested names in unclaimed namespaces
+    if available {
+        if let Some(error) = namespace_error(pool, &name, None, None)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        {
+            errors.push(error);
+            available = false;
+        }
+    }
+
+    let suggestions = if available {
+        vec![]
+    } else {
+        suggest_subdomains(pool, &name, &app_state.hosts)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    };
+
+    Ok(Json(SubdomainAvailability {
+        name,
+        valid,
+        available,
+        errors,
+        price: Price {
+            amount: PAYMENT_AMOUNT,
+            unit: "sat".to_string(),
+        },
+        suggestions,
+    }))
+}
+
+// R5.5 Validation Helpers
+// Returns every rule the name breaks; an empty list means the name is valid.
+// `base_hosts` are the configured base hosts: a name that would turn into one
+// of them, or into a host below one of the others, could never be reached.
+pub fn validate_subdomain(name: &str, base_hosts: &[String]) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if name.is_empty() {
+        errors.push("Subdomain cannot be empty".to_string());
+        return errors;
+    }
+    let labels: Vec<&str> = name.split('.').collect();
+    if labels.len() > MAX_SUBDOMAIN_LABELS {
+        errors.push(format!("Subdomain can have at most {} dot-separated parts", MAX_SUBDOMAIN_LABELS));
+    }
+    if labels.iter().any(|label| label.is_empty()) {
+        errors.push("Subdomain parts cannot be empty".to_string());
+    }
+    if labels.iter().any(|label| label.len() > MAX_SUBDOMAIN_LENGTH) {
+        errors.push(format!("Each part of a subdomain must be at most {} characters", MAX_SUBDOMAIN_LENGTH));
+    }
+    if !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.') {
+        errors.push("Only lowercase letters, numbers, hyphens and dots are allowed".to_string());
+    }
+    if labels.iter().any(|label| label.starts_with('-') || label.ends_with('-')) {
+        errors.push("Subdomain parts cannot start or end with a hyphen".to_string());
+    }
+    if let Some(namespace) = labels.last().filter(|namespace| RESERVED_SUBDOMAINS.contains(namespace)) {
+        errors.push(format!("'{}' is reserved", namespace));
+    }
+    for base_host in base_hosts {
+        let host = format!("{}.{}", name, base_host);
+        let shadowed = base_hosts.iter().any(|other| {
+            other.ends_with(&format!(".{}", base_host)) && (host == *other || host.ends_with(&format!(".{}", other)))
+        });
+        if shadowed {
+            errors.push(format!("'{}' is claimed by another base host", host));
+        }
+    }
+
+    errors
+}
+
+// The namespace a subdomain lives in: its last label
+pub fn subdomain_namespace(name: &str) -> &str {
+    name.rsplit('.').next().unwrap_or(name)
+}
+
+pub async fn is_subdomain_taken(pool: &SqlitePool, name: &str) -> Result<bool, sqlx::Error> {
+    // Aliases count too: every hostname routes to exactly one connection
+    let existing: Option<(i64,)> = sqlx::query_as("SELECT id FROM connection_hostnames WHERE hostname = ? LIMIT 1")
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(existing.is_some())
+}
+
+// Checks that `name` may be used in its namespace by `connection_id` (None
+// for a connection that doesn't exist yet) with the given owner contact.
+// Nested names need the namespace itself to be registered, and every
+// connection holding a name in the namespace must be the requester or share
+// its owner. Returns the reason when the name can't be used.
+pub async fn namespace_error(
+    pool: &SqlitePool,
+    name: &str,
+    connection_id: Option<i64>,
+    owner: Option<&str>,
+) -> Result<Option<String>, sqlx::Error> {
+    let namespace = subdomain_namespace(name);
+    // Labels are restricted to [a-z0-9-], so the namespace needs no LIKE escaping
+    let holders: Vec<(i64, Option<String>, bool)> = sqlx::query_as(
+        "SELECT c.id, c.owner, h.hostname = ? FROM connection_hostnames h \
+         JOIN connections c ON c.id = h.connection_id \
+         WHERE h.hostname = ? OR h.hostname LIKE ?",
+    )
+    .bind(namespace)
+    .bind(namespace)
+    .bind(format!("%.{}", namespace))
+    .fetch_all(pool)
+    .await?;
+
+    if name != namespace && !holders.iter().any(|(_, _, is_root)| *is_root) {
+        return Ok(Some(format!("Register '{}' before using names in its namespace", namespace)));
+    }
+    let same_holder = |(id, holder_owner, _): &(i64, Option<String>, bool)| {
+        Some(*id) == connection_id
+            || matches!((owner, holder_owner), (Some(owner), Some(holder)) if owner.eq_ignore_ascii_case(holder))
+    };
+    if !holders.iter().all(same_holder) {
+        return Ok(Some(format!("The '{}' namespace belongs to another owner", namespace)));
+    }
+
+    Ok(None)
+}
+
+// Turns arbitrary input into something that passes `validate_subdomain`
+// (reserved names aside), e.g. "My App!" -> "my-app".
+fn sanitize_subdomain(name: &str) -> String {
+    let mut sanitized = String::with_capacity(name.len());
+    for c in name.chars() {
+        let c = c.to_ascii_lowercase();
+        if c.is_ascii_lowercase() || c.is_ascii_digit() {
+            sanitized.push(c);
+        } else if !sanitized.ends_with('-') {
+            sanitized.push('-');
+        }
+    }
+
+    let mut sanitized = sanitized.trim_matches('-').to_string();
+    // Leave room for a "-xxxx" suffix
+    sanitized.truncate(MAX_SUBDOMAIN_LENGTH - 5);
+    sanitized.trim_end_matches('-').to_string()
+}
+
+// R5.6 Suggestions
+// Offers numbered variants first, then a couple of random ones.
+async fn suggest_subdomains(pool: &SqlitePool, name: &str, base_hosts: &[String]) -> Result<Vec<String>, sqlx::Error> {
+    let base = match sanitize_subdomain(name) {
+        base if base.is_empty() => "tunnel".to_string(),
+        base => base,
+    };
+
+    let mut candidates = vec![base.clone()];
+    candidates.extend((1..=3).map(|n| format!("{}-{}", base, n)));
+    candidates.extend((0..3).map(|_| format!("{}-{:04x}", base, rand::thread_rng().gen::<u16>())));
+
+    let mut suggestions = Vec::new();
+    for candidate in candidates {
+        if suggestions.len() >= MAX_SUGGESTIONS {
+            break;
+        }
+        if candidate == name || suggestions.contains(&candidate) || !validate_subdomain(&candidate, base_hosts).is_empty() {
+            continue;
+        }
+        if !is_subdomain_taken(pool, &candidate).await? {
+            suggestions.push(candidate);
+        }
+    }
+
+    Ok(suggestions)
+}
+
+// Default for connections created without a subdomain: short, a valid DNS
+// label, and unrelated to the key. `default_subdomain` checks it is free.
+pub fn random_subdomain() -> String {
+    format!("tunnel-{:08x}", rand::thread_rng().gen::<u32>())
+}
+
+// R5.7 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_subdomain() {
+        let hosts = ["localhost".to_string()];
+        assert!(validate_subdomain("my-app", &hosts).is_empty());
+        assert!(validate_subdomain("app2", &hosts).is_empty());
+        assert!(validate_subdomain("svc.alice", &hosts).is_empty());
+        assert!(validate_subdomain("www.svc.alice", &hosts).is_empty());
+
+        assert!(!validate_subdomain("", &hosts).is_empty());
+        assert!(!validate_subdomain("-app", &hosts).is_empty());
+        assert!(!validate_subdomain("app-", &hosts).is_empty());
+        assert!(!validate_subdomain("My_App", &hosts).is_empty());
+        assert!(!validate_subdomain("a..b", &hosts).is_empty());
+        assert!(!validate_subdomain("a.b.c.d", &hosts).is_empty());
+        assert!(!validate_subdomain("svc.-alice", &hosts).is_empty());
+        assert!(!validate_subdomain("www", &hosts).is_empty());
+        assert!(!validate_subdomain("svc.api", &hosts).is_empty());
+        assert!(!validate_subdomain(&"a".repeat(64), &hosts).is_empty());
+    }
+
+    #[test]
+    fn test_validate_subdomain_against_nested_base_hosts() {
+        let hosts = ["sando.blue".to_string(), "internal.sando.blue".to_string()];
+        assert!(validate_subdomain("app", &hosts).is_empty());
+        assert!(validate_subdomain("svc.internal-tools", &hosts).is_empty());
+
+        // app.internal.sando.blue belongs to the internal base host
+        assert!(!validate_subdomain("internal", &hosts).is_empty());
+        assert!(!validate_subdomain("app.internal", &hosts).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_namespace_rules() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::migrate(&pool).await.unwrap();
+        for (id, name, owner) in [(1, "alice", Some("alice@example.com")), (2, "bob", None)] {
+            sqlx::query("INSERT INTO connections (id, connection_string, port, subdomain, owner) VALUES (?, ?, ?, ?, ?)")
+                .bind(id)
+                .bind(format!("key{}", id))
+                .bind(4000 + id)
+                .bind(name)
+                .bind(owner)
+                .execute(&pool)
+                .await
+                .unwrap();
+            sqlx::query("INSERT INTO connection_hostnames (connection_id, hostname, is_canonical) VALUES (?, ?, 1)")
+                .bind(id)
+                .bind(name)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        // Top-level names outside any namespace are always fine
+        assert_eq!(namespace_error(&pool, "carol", None, None).await.unwrap(), None);
+        // The namespace root must exist
+        assert!(namespace_error(&pool, "svc.carol", None, Some("carol@example.com")).await.unwrap().is_some());
+        // Alice's namespace is open to her own connections and her owner contact
+        assert_eq!(namespace_error(&pool, "svc.alice", Some(1), None).await.unwrap(), None);
+        assert_eq!(namespace_error(&pool, "svc.alice", None, Some("Alice@example.com")).await.unwrap(), None);
+        assert!(namespace_error(&pool, "svc.alice", Some(2), None).await.unwrap().is_some());
+        assert!(namespace_error(&pool, "svc.alice", None, Some("mallory@example.com")).await.unwrap().is_some());
+        // Without an owner, only the root connection itself can nest names
+        assert_eq!(namespace_error(&pool, "svc.bob", Some(2), None).await.unwrap(), None);
+        assert!(namespace_error(&pool, "svc.bob", None, None).await.unwrap().is_some());
+    }
+
+    #[test]
+    fn test_random_subdomain() {
+        let name = random_subdomain();
+        assert_eq!(name.len(), 15);
+        assert!(validate_subdomain(&name, &["localhost".to_string()]).is_empty());
+        assert_ne!(name, random_subdomain());
+    }
+
+    #[test]
+    fn test_sanitize_subdomain() {
+        assert_eq!(sanitize_subdomain("My App!"), "my-app");
+        assert_eq!(sanitize_subdomain("--a__b--"), "a-b");
+        assert_eq!(sanitize_subdomain("!!!"), "");
+        assert!(sanitize_subdomain(&"a".repeat(100)).len() <= MAX_SUBDOMAIN_LENGTH - 5);
+    }
+}
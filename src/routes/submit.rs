@@ -5,12 +5,18 @@
  * Handles POST requests to the `/submit` path.
  * This file is tagged for machine-readability.
  *
- * Tags: R2.1, R2.2, R2.3, R2.4, R2.5
+ * Tags: R2.1, R2.2, R2.3, R2.4, R2.5, R2.6
  */
 // R2.1 Dependencies
 use crate::components::status_page::status_page;
 use crate::components::payment_page::payment_page;
-use crate::{models::ConnectionForm, AppState};
+use crate::models::{AuditAction, AuditActor, ConnectionForm};
+use crate::routes::hostnames::promote_hostname;
+use crate::routes::subdomains::{is_subdomain_taken, namespace_error, random_subdomain, validate_subdomain};
+use crate::services::antispam::{require_proof_of_work, POW_HEADER};
+use crate::services::audit::AuditEvent;
+use crate::services::holesail::HolesailKey;
+use crate::AppState;
 use axum::{
     extract::{Form, State},
     http::{HeaderMap, StatusCode},
@@ -19,19 +25,21 @@ use axum::{
 use cdk::{nuts::{Token, PaymentRequest, CurrencyUnit}, mint_url::MintUrl, Amount};
 use std::str::FromStr;
 use uuid::Uuid;
-use rand::Rng;
 
 // R2.2 Payment Configuration
 // Configuration for the payment requirements
-const PAYMENT_AMOUNT: u64 = 100; // 100 sats
+pub const PAYMENT_AMOUNT: u64 = 100; // 100 sats
 const ACCEPTED_MINTS: &[&str] = &[
     "https://testnut.cashu.space",
     "https://mint.minibits.cash/Bitcoin",
 ];
 
+// Random subdomains tried before asking for a custom one
+const DEFAULT_SUBDOMAIN_ATTEMPTS: usize = 5;
+
 // R2.3 Payment Request Helper
 // Creates a NUT-18 payment request for HTTP 402 responses
-fn create_payment_request() -> PaymentRequest {
+pub fn create_payment_request() -> PaymentRequest {
     PaymentRequest {
         payment_id: Some(Uuid::new_v4().to_string()),
         amount: Some(Amount::from(PAYMENT_AMOUNT)),
@@ -48,7 +56,7 @@ fn create_payment_request() -> PaymentRequest {
 
 // R2.4 Payment Validation Helper
 // Validates the received Cashu token (simplified implementation)
-fn validate_cashu_token(token: &str) -> Result<bool, String> {
+pub fn validate_cashu_token(token: &str) -> Result<bool, String> {
     println!("token: {:?}", token);
     let token = Token::from_str(token).map_err(|e| e.to_string())?;
     let mint_url = token.mint_url().map_err(|e| e.to_string())?;
@@ -75,7 +83,33 @@ pub async fn submit_connection(
     let cashu_header = headers.get("X-Cashu");
 
     println!("cashu_header: {:?}", cashu_header);
-    
+
+    // Reject unusable custom subdomains and keys before asking for payment.
+    // Without a custom subdomain a free random one is picked; the payment
+    // page carries it through to the paid submission.
+    let subdomain = match check_custom_subdomain(&app_state, form.subdomain.as_deref(), None).await {
+        Ok(Some(subdomain)) => Ok(subdomain),
+        Ok(None) => default_subdomain(&app_state).await,
+        Err(rejection) => Err(rejection),
+    };
+    let checked = match subdomain {
+        Ok(subdomain) => check_connection_string(&app_state, &form.connection)
+            .await
+            .map(|key| (subdomain, key)),
+        Err(rejection) => Err(rejection),
+    };
+    let (subdomain, key) = match checked {
+        Ok(checked) => checked,
+        Err((status, message)) => {
+            let requested = form.subdomain.clone().unwrap_or_default();
+            return (
+                status,
+                Html(status_page(false, message, requested, "https".to_string(), app_state.host.clone()).into_string()),
+            ).into_response();
+        }
+    };
+    let form = ConnectionForm { connection: key.to_string(), ..form };
+
     match cashu_header {
         Some(header_value) => {
             // Payment token provided, validate it
@@ -85,19 +119,9 @@ pub async fn submit_connection(
                         Ok(true) => {
                             // Valid payment, proceed with connection storage
                             tracing::info!("Valid payment received for connection: {}", form.connection);
+                            record_payment(&app_state, AuditActor::Web, &subdomain, None).await;
                             
-                            // Generate random port in range 3001-8000
-                            let random_port = rand::thread_rng().gen_range(3001..=8000);
-                            
-                            // Use provided subdomain or default to connection_string
-                            let subdomain = form.subdomain.as_ref().unwrap_or(&form.connection).clone();
-                            
-                            let result = sqlx::query("INSERT INTO connections (connection_string, port, subdomain) VALUES (?, ?, ?)")
-                                .bind(&form.connection)
-                                .bind(random_port)
-                                .bind(&subdomain)
-                                .execute(app_state.pool.as_ref())
-                                .await;
+                            let result = store_connection(&app_state, &form.connection, &subdomain, None, AuditActor::Web).await;
 
                             let (success, message) = match result {
                                 Ok(_) => (
@@ -138,14 +162,24 @@ pub async fn submit_connection(
             }
         },
         None => {
+            // Payment requests cost the client some work when the server asks for it
+            if let Err(message) = require_proof_of_work(&app_state, &headers, form.pow.as_deref()) {
+                tracing::warn!("Proof of work missing or invalid: {}", message);
+                let mut response = (
+                    StatusCode::FORBIDDEN,
+                    Html(status_page(false, message, subdomain, "https".to_string(), app_state.host.clone()).into_string()),
+                ).into_response();
+                if let Some(challenge) = app_state.proof_of_work.as_ref().and_then(|pow| pow.challenge(&app_state.host).parse().ok()) {
+                    response.headers_mut().insert(POW_HEADER, challenge);
+                }
+                return response;
+            }
+
             // No payment provided, return HTTP 402 with payment page
             tracing::info!("Payment required for connection submission: {}", form.connection);
             
             let payment_request = create_payment_request();   
             
-            // Use provided subdomain or default to connection_string for display
-            let subdomain = form.subdomain.as_ref().unwrap_or(&form.connection).clone();
-            
             let response = Response::builder()
                 .status(StatusCode::PAYMENT_REQUIRED)
                 .header("X-Cashu", payment_request.to_string())
@@ -156,4 +190,132 @@ pub async fn submit_connection(
             response
         }
     }
-} 
\ No newline at end of file
+}
+
+// R2.6 Shared Submission Helpers
+// Used by both the HTML form handler and the JSON API.
+
+// Normalizes a requested custom subdomain and rejects it if it is invalid,
+// already taken or in a namespace `owner` can't use. Returns `None` when no
+// custom subdomain was requested.
+pub async fn check_custom_subdomain(
+    app_state: &AppState,
+    subdomain: Option<&str>,
+    owner: Option<&str>,
+) -> Result<Option<String>, (StatusCode, String)> {
+    let Some(subdomain) = subdomain.map(str::trim).filter(|s| !s.is_empty()) else {
+        return Ok(None);
+    };
+    let subdomain = subdomain.to_ascii_lowercase();
+    let pool = app_state.pool.as_ref();
+
+    let errors = validate_subdomain(&subdomain, &app_state.hosts);
+    let rejection = if !errors.is_empty() {
+        Some((StatusCode::BAD_REQUEST, errors.join(". ")))
+    } else {
+        match is_subdomain_taken(pool, &subdomain).await {
+            Ok(true) => Some((StatusCode::CONFLICT, format!("Subdomain '{}' is already taken", subdomain))),
+            Ok(false) => match namespace_error(pool, &subdomain, None, owner).await {
+                Ok(error) => error.map(|error| (StatusCode::CONFLICT, error)),
+                Err(e) => Some((StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))),
+            },
+            // Never treat an unreadable table as "free": the insert after payment would fail
+            Err(e) => Some((StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))),
+        }
+    };
+
+    match rejection {
+        Some((status, message)) => {
+            tracing::info!("Rejected subdomain '{}': {}", subdomain, message);
+            Err((status, message))
+        }
+        None => Ok(Some(subdomain)),
+    }
+}
+
+// Picks a free random subdomain for a connection submitted without one. The
+// key itself is never used: it is too long for a DNS label and would be
+// published in the hostname.
+pub async fn default_subdomain(app_state: &AppState) -> Result<String, (StatusCode, String)> {
+    for _ in 0..DEFAULT_SUBDOMAIN_ATTEMPTS {
+        match check_custom_subdomain(app_state, Some(&random_subdomain()), None).await {
+            Ok(Some(subdomain)) => return Ok(subdomain),
+            Ok(None) | Err((StatusCode::CONFLICT, _)) => continue,
+            Err(rejection) => return Err(rejection),
+        }
+    }
+    Err((StatusCode::CONFLICT, "No free subdomain found; choose a custom subdomain".to_string()))
+}
+
+// Parses the submitted holesail key and, when a connect probe is configured,
+// checks that its peer can be reached. Returns the normalized key.
+pub async fn check_connection_string(
+    app_state: &AppState,
+    connection: &str,
+) -> Result<HolesailKey, (StatusCode, String)> {
+    let key = HolesailKey::parse(connection).map_err(|message| {
+        tracing::info!("Rejected connection string: {}", message);
+        (StatusCode::BAD_REQUEST, message)
+    })?;
+
+    if let Some(timeout) = app_state.connect_probe {
+        if let Err(status) = app_state.tunnels.probe(&key.to_string(), timeout).await {
+            tracing::info!("Connect probe failed for {}: {}", key, status);
+            let message = match status {
+                StatusCode::BAD_GATEWAY => format!("No holesail peer answered for this key within {}s; is it running?", timeout.as_secs()),
+                _ => "The key could not be checked right now; try again later".to_string(),
+            };
+            return Err((status, message));
+        }
+    }
+
+    Ok(key)
+}
+
+// Notes an accepted Cashu payment in the audit log. It is recorded before the
+// connection is stored, so a payment whose connection then fails to save
+// still leaves a trace.
+pub async fn record_payment(app_state: &AppState, actor: AuditActor, subdomain: &str, owner: Option<&str>) {
+    AuditEvent::new(actor, AuditAction::PaymentAccepted)
+        .subdomain(subdomain)
+        .owner(owner)
+        .detail("amount", PAYMENT_AMOUNT)
+        .detail("unit", "sat")
+        .record(app_state.pool.as_ref())
+        .await;
+}
+
+// Reserves a free local port and stores the connection, returning its id.
+// The reservation is held until the row is written.
+pub async fn store_connection(
+    app_state: &AppState,
+    connection_string: &str,
+    subdomain: &str,
+    owner: Option<&str>,
+    actor: AuditActor,
+) -> Result<i64, String> {
+    let reservation = app_state.port_allocator
+        .reserve(app_state.pool.as_ref())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut tx = app_state.pool.begin().await.map_err(|e| e.to_string())?;
+    let id = sqlx::query("INSERT INTO connections (connection_string, port, subdomain, owner) VALUES (?, ?, ?, ?)")
+        .bind(connection_string)
+        .bind(reservation.port)
+        .bind(subdomain)
+        .bind(owner)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?
+        .last_insert_rowid();
+    promote_hostname(&mut tx, id, subdomain).await.map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    AuditEvent::new(actor, AuditAction::Create)
+        .connection_id(id)
+        .detail("port", reservation.port)
+        .record(app_state.pool.as_ref())
+        .await;
+    Ok(id)
+}
@@ -0,0 +1,387 @@
+/**
+ * S13.0 Tunnel Access
+ * ===================
+ *
+ * Lets owners keep a tunnel private, e.g. for a staging environment. A
+ * tunnel with credentials or a link secret only answers requests carrying
+ * HTTP Basic auth for one of its users, `Authorization: Bearer` with one of
+ * its tokens, or a signed link (`?sando_link=`) that hasn't expired. Opening
+ * a link sets a cookie so the rest of the visit works without it. Passwords
+ * are stored as PBKDF2 hashes and tokens as SHA-256 digests; links are an
+ * HMAC over their expiry with the connection's link secret, so clearing the
+ * secret revokes them all and a restored backup keeps them working. What
+ * Sando consumed is removed before the request reaches the tunnel. Failed
+ * sign-ins are limited per client, and answered with a 429 once used up.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S13.1, S13.2, S13.3, S13.4, S13.5, S13.6
+ */
+// S13.1 Dependencies
+use crate::models::CredentialKind;
+use crate::services::limits::{Limited, RateLimiter};
+use crate::Connection;
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::response::Response;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Query parameter carrying a signed link, and the cookie it is traded for
+pub const LINK_PARAM: &str = "sando_link";
+pub const ACCESS_COOKIE: &str = "sando_access";
+
+const PASSWORD_ROUNDS: u32 = 100_000;
+const PASSWORD_SCHEME: &str = "pbkdf2-sha256";
+
+// Checking a password costs a full PBKDF2 run, too slow for every request a
+// browser makes, so successful checks are remembered for a while
+const VERIFIED_TTL: Duration = Duration::from_secs(300);
+const MAX_VERIFIED: usize = 10_000;
+
+lazy_static! {
+    static ref VERIFIED_PASSWORDS: Mutex<HashMap<[u8; 32], Instant>> = Mutex::new(HashMap::new());
+}
+
+// S13.2 Secrets
+// 32 random bytes as hex, for tokens and link secrets
+pub fn generate_secret() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+// Tokens are random, so a plain digest is enough to look them up by
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// `pbkdf2-sha256${rounds}${salt}${hash}`
+pub fn hash_password(password: &str) -> String {
+    let salt: [u8; 16] = rand::thread_rng().gen();
+    let mut hash = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, PASSWORD_ROUNDS, &mut hash);
+    format!("{}${}${}${}", PASSWORD_SCHEME, PASSWORD_ROUNDS, hex::encode(salt), hex::encode(hash))
+}
+
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    let cache_key: [u8; 32] = Sha256::new()
+        .chain_update(stored.as_bytes())
+        .chain_update([0])
+        .chain_update(password.as_bytes())
+        .finalize()
+        .into();
+    let now = Instant::now();
+    if VERIFIED_PASSWORDS.lock().unwrap().get(&cache_key).is_some_and(|verified| now.duration_since(*verified) < VERIFIED_TTL) {
+        return true;
+    }
+
+    let mut parts = stored.split('$');
+    let (Some(PASSWORD_SCHEME), Some(rounds), Some(salt), Some(expected), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+    let (Ok(rounds), Ok(salt), Ok(expected)) = (rounds.parse::<u32>(), hex::decode(salt), hex::decode(expected)) else {
+        return false;
+    };
+    let mut hash = vec![0u8; expected.len()];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, rounds, &mut hash);
+    // Compare digests so the check doesn't leak how much of the hash matched
+    if Sha256::digest(&hash) != Sha256::digest(&expected) {
+        return false;
+    }
+
+    let mut verified = VERIFIED_PASSWORDS.lock().unwrap();
+    if verified.len() >= MAX_VERIFIED {
+        verified.retain(|_, at| now.duration_since(*at) < VERIFIED_TTL);
+        if verified.len() >= MAX_VERIFIED {
+            verified.clear();
+        }
+    }
+    verified.insert(cache_key, now);
+    true
+}
+
+// S13.3 Signed Links
+// `{expires}.{signature}`, where `expires` is a Unix timestamp
+pub fn sign_link(secret: &str, expires: i64) -> String {
+    format!("{}.{}", expires, hex::encode(link_mac(secret, expires).finalize().into_bytes()))
+}
+
+// The expiry of a valid, unexpired link value
+pub fn verify_link(secret: &str, value: &str, now: i64) -> Option<i64> {
+    let (expires, signature) = value.split_once('.')?;
+    let expires: i64 = expires.parse().ok()?;
+    let signature = hex::decode(signature).ok()?;
+    link_mac(secret, expires).verify_slice(&signature).ok()?;
+    (expires > now).then_some(expires)
+}
+
+// The secret is per connection, so the connection id isn't signed; a backup
+// restored under another id keeps its links
+fn link_mac(secret: &str, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(expires.to_string().as_bytes());
+    mac
+}
+
+// S13.4 Access Check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    // The tunnel is open or the request is signed in. `strip_authorization`
+    // when the Authorization header was meant for Sando, not the tunnel.
+    Granted { strip_authorization: bool },
+    // A valid signed link: send the browser on without it, with the cookie
+    Redirect { location: String, cookie: String },
+    // No valid credential; `challenge` goes in `WWW-Authenticate`
+    Denied { challenge: Option<String> },
+    // Too many failed sign-ins from this client; not checked this time
+    Throttled(Limited),
+}
+
+// The ids among `connections` that need a credential or signed link. Their
+// holesail keys are never shown: the key reaches the tunnel without either.
+pub async fn protected_connections(pool: &SqlitePool, connections: &[Connection]) -> Result<HashSet<i64>, sqlx::Error> {
+    let credentialed: HashSet<i64> = sqlx::query_scalar("SELECT DISTINCT connection_id FROM connection_credentials")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+    Ok(connections
+        .iter()
+        .filter(|connection| connection.link_secret.is_some() || credentialed.contains(&connection.id))
+        .map(|connection| connection.id)
+        .collect())
+}
+
+#[derive(sqlx::FromRow)]
+struct StoredCredential {
+    kind: CredentialKind,
+    name: String,
+    secret_hash: String,
+}
+
+// `client` is the request's address; failed sign-ins are limited per client
+// and tunnel through `limiter`.
+pub async fn check_access(
+    pool: &SqlitePool,
+    limiter: &RateLimiter,
+    connection: &Connection,
+    headers: &HeaderMap,
+    uri: &Uri,
+    client: Option<IpAddr>,
+) -> Result<Access, sqlx::Error> {
+    let credentials = sqlx::query_as::<_, StoredCredential>(
+        "SELECT kind, name, secret_hash FROM connection_credentials WHERE connection_id = ?",
+    )
+    .bind(connection.id)
+    .fetch_all(pool)
+    .await?;
+    if credentials.is_empty() && connection.link_secret.is_none() {
+        return Ok(Access::Granted { strip_authorization: false });
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    if let Some(secret) = &connection.link_secret {
+        let link = uri
+            .query()
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .find_map(|pair| pair.strip_prefix(LINK_PARAM)?.strip_prefix('='));
+        if let Some(link) = link {
+            if let Some(expires) = verify_link(secret, link, now) {
+                return Ok(Access::Redirect {
+                    location: location_without_link(uri),
+                    cookie: format!(
+                        "{}={}; Max-Age={}; Path=/; HttpOnly; SameSite=Lax",
+                        ACCESS_COOKIE,
+                        link,
+                        expires - now
+                    ),
+                });
+            }
+        }
+        let cookie_valid = cookie_values(headers, ACCESS_COOKIE).any(|value| verify_link(secret, value, now).is_some());
+        if cookie_valid {
+            return Ok(Access::Granted { strip_authorization: false });
+        }
+    }
+
+    let authorization = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    let signing_in = authorization.starts_with("Bearer ") || authorization.starts_with("Basic ");
+    if signing_in {
+        if let Err(limited) = limiter.check_sign_in(connection.id, client) {
+            return Ok(Access::Throttled(limited));
+        }
+    }
+    if let Some(token) = authorization.strip_prefix("Bearer ") {
+        let digest = hash_token(token.trim());
+        if credentials.iter().any(|credential| credential.kind == CredentialKind::Token && credential.secret_hash == digest) {
+            return Ok(Access::Granted { strip_authorization: true });
+        }
+    } else if let Some(encoded) = authorization.strip_prefix("Basic ") {
+        let decoded = STANDARD.decode(encoded.trim()).ok().and_then(|decoded| String::from_utf8(decoded).ok());
+        if let Some((user, password)) = decoded.as_deref().and_then(|decoded| decoded.split_once(':')) {
+            let hashes: Vec<String> = credentials
+                .iter()
+                .filter(|credential| credential.kind == CredentialKind::Basic && credential.name == user)
+                .map(|credential| credential.secret_hash.clone())
+                .collect();
+            let password = password.to_string();
+            // PBKDF2 is slow on purpose; keep it off the async workers
+            let matched = tokio::task::spawn_blocking(move || hashes.iter().any(|hash| verify_password(&password, hash)))
+                .await
+                .unwrap_or(false);
+            if matched {
+                return Ok(Access::Granted { strip_authorization: true });
+            }
+        }
+    }
+    if signing_in {
+        limiter.record_failed_sign_in(connection.id, client);
+    }
+
+    // Browsers only prompt for Basic auth; token-only tunnels name the scheme
+    let realm = connection.subdomain.as_deref().unwrap_or("Sando tunnel");
+    let challenge = if credentials.iter().any(|credential| credential.kind == CredentialKind::Basic) {
+        Some(format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm))
+    } else if credentials.iter().any(|credential| credential.kind == CredentialKind::Token) {
+        Some(format!("Bearer realm=\"{}\"", realm))
+    } else {
+        None
+    };
+    Ok(Access::Denied { challenge })
+}
+
+// The request's path and query, minus the link parameter
+fn location_without_link(uri: &Uri) -> String {
+    let query: Vec<&str> = uri
+        .query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .filter(|pair| *pair != LINK_PARAM && !pair.starts_with(&format!("{}=", LINK_PARAM)))
+        .collect();
+    if query.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), query.join("&"))
+    }
+}
+
+fn cookie_values<'a>(headers: &'a HeaderMap, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(move |pair| pair.trim().strip_prefix(name)?.strip_prefix('='))
+}
+
+// A Cookie header without Sando's access cookie; `None` when nothing is left
+pub fn without_access_cookie(cookie: &str) -> Option<String> {
+    let pairs: Vec<&str> = cookie
+        .split(';')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some(ACCESS_COOKIE))
+        .collect();
+    (!pairs.is_empty()).then(|| pairs.join("; "))
+}
+
+// Removes what Sando consumed from a request before it is proxied
+pub fn strip_access_credentials(headers: &mut HeaderMap, strip_authorization: bool) {
+    if strip_authorization {
+        headers.remove(header::AUTHORIZATION);
+    }
+    let cookies: Vec<String> = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(without_access_cookie)
+        .collect();
+    headers.remove(header::COOKIE);
+    for cookie in cookies {
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            headers.append(header::COOKIE, value);
+        }
+    }
+}
+
+// S13.5 Responses
+pub fn access_denied(challenge: Option<&str>) -> Response {
+    let mut response = Response::builder().status(StatusCode::UNAUTHORIZED);
+    if let Some(challenge) = challenge {
+        response = response.header(header::WWW_AUTHENTICATE, challenge);
+    }
+    response
+        .body(Body::from("This tunnel is protected; sign in or open a link shared by its owner"))
+        .unwrap()
+}
+
+pub fn link_redirect(location: &str, cookie: &str) -> Response {
+    Response::builder()
+        .status(StatusCode::FOUND)
+        .header(header::LOCATION, location)
+        .header(header::SET_COOKIE, cookie)
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::empty())
+        .unwrap()
+}
+
+// S13.6 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passwords_and_tokens_are_hashed() {
+        let stored = hash_password("correct horse");
+        assert!(stored.starts_with("pbkdf2-sha256$100000$"));
+        assert!(!stored.contains("correct horse"));
+        assert_ne!(stored, hash_password("correct horse"));
+        assert!(verify_password("correct horse", &stored));
+        // Cached the second time round
+        assert!(verify_password("correct horse", &stored));
+        assert!(!verify_password("wrong horse", &stored));
+        assert!(!verify_password("correct horse", "plaintext"));
+
+        let token = generate_secret();
+        assert_eq!(token.len(), 64);
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+    }
+
+    #[test]
+    fn test_signed_links_expire_and_are_bound_to_their_secret() {
+        let secret = generate_secret();
+        let link = sign_link(&secret, 2_000);
+        assert_eq!(verify_link(&secret, &link, 1_000), Some(2_000));
+        assert_eq!(verify_link(&secret, &link, 2_000), None);
+        assert_eq!(verify_link(&generate_secret(), &link, 1_000), None);
+        // The expiry can't be pushed out without a new signature
+        let extended = link.replacen("2000", "9000", 1);
+        assert_eq!(verify_link(&secret, &extended, 1_000), None);
+        assert_eq!(verify_link(&secret, "garbage", 1_000), None);
+    }
+
+    #[test]
+    fn test_consumed_credentials_are_stripped() {
+        let uri: Uri = "/docs?page=2&sando_link=123.abc&q=x".parse().unwrap();
+        assert_eq!(location_without_link(&uri), "/docs?page=2&q=x");
+        assert_eq!(location_without_link(&"/?sando_link=1.2".parse().unwrap()), "/");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Basic dXNlcjpwYXNz"));
+        headers.append(header::COOKIE, HeaderValue::from_static("session=1; sando_access=123.abc; theme=dark"));
+        headers.append(header::COOKIE, HeaderValue::from_static("sando_access=123.abc"));
+        strip_access_credentials(&mut headers, true);
+        assert!(headers.get(header::AUTHORIZATION).is_none());
+        let cookies: Vec<_> = headers.get_all(header::COOKIE).iter().collect();
+        assert_eq!(cookies, vec!["session=1; theme=dark"]);
+    }
+}
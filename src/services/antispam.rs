@@ -0,0 +1,297 @@
+/**
+ * S12.0 Control-Plane Anti-Spam
+ * =============================
+ *
+ * Per-client token buckets for the app's own routes (pages, the JSON API,
+ * `/submit`), so bots can't hammer page rendering or hand out payment
+ * requests without end. Routes fall into three classes: payment requests
+ * (unpaid `/submit` and `POST /api/v1/connections`, which answer 402), other
+ * writes and reads. Requests with the admin token are not limited.
+ * Payment requests can additionally require a hashcash stamp
+ * (`X-Sando-PoW`), so each 402 costs the client some CPU.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S12.1, S12.2, S12.3, S12.4, S12.5
+ */
+// S12.1 Dependencies
+use crate::routes::admin::check_admin;
+use crate::routes::api::{ErrorBody, ErrorDetail};
+use crate::services::forwarding::client_ip;
+use crate::services::limits::{retry_after_seconds, Limited};
+use crate::AppState;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Header carrying a hashcash stamp on requests, and the challenge on responses
+pub const POW_HEADER: &str = "X-Sando-PoW";
+
+// S12.2 Control-Plane Limits
+// A token bucket: `rate` requests per second with bursts of `burst`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+// Limits per client address for each class of route; `None` is unlimited
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ControlPlaneLimits {
+    pub payment_requests: Option<RateLimit>,
+    pub writes: Option<RateLimit>,
+    pub reads: Option<RateLimit>,
+}
+
+impl Default for ControlPlaneLimits {
+    // A few payment requests a minute, generous for everything else
+    fn default() -> Self {
+        Self {
+            payment_requests: Some(RateLimit { rate: 0.1, burst: 10 }),
+            writes: Some(RateLimit { rate: 2.0, burst: 30 }),
+            reads: Some(RateLimit { rate: 10.0, burst: 100 }),
+        }
+    }
+}
+
+impl ControlPlaneLimits {
+    pub const CLASSES: [&'static str; 3] = ["payment_requests", "writes", "reads"];
+
+    pub const UNLIMITED: Self = Self { payment_requests: None, writes: None, reads: None };
+
+    // Parses `class=rate/burst` pairs separated by commas, e.g.
+    // "payment_requests=0.05/5, reads=off". Classes left out keep their
+    // default limits.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut limits = Self::default();
+        for pair in text.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (class, value) = pair.split_once('=').ok_or_else(|| format!("Limit '{}' must look like class=rate/burst", pair))?;
+            let value = value.trim();
+            let limit = if value.eq_ignore_ascii_case("off") {
+                None
+            } else {
+                let invalid = || format!("Limit '{}' must be rate/burst (e.g. 2/30) or off", pair);
+                let (rate, burst) = value.split_once('/').ok_or_else(invalid)?;
+                let rate: f64 = rate.trim().parse().map_err(|_| invalid())?;
+                let burst: u32 = burst.trim().parse().map_err(|_| invalid())?;
+                if !rate.is_finite() || rate <= 0.0 || burst == 0 {
+                    return Err(invalid());
+                }
+                Some(RateLimit { rate, burst })
+            };
+            match class.trim() {
+                "payment_requests" => limits.payment_requests = limit,
+                "writes" => limits.writes = limit,
+                "reads" => limits.reads = limit,
+                class => {
+                    return Err(format!("Unknown route class '{}'; use {}", class, Self::CLASSES.join(", ")));
+                }
+            }
+        }
+        Ok(limits)
+    }
+
+    fn get(&self, class: &str) -> Option<RateLimit> {
+        match class {
+            "payment_requests" => self.payment_requests,
+            "writes" => self.writes,
+            _ => self.reads,
+        }
+    }
+}
+
+// Unpaid connection submissions hand out a new payment request each time
+fn route_class(method: &Method, path: &str, headers: &HeaderMap) -> &'static str {
+    let creates_connection = path == "/submit" || path.trim_end_matches('/') == "/api/v1/connections";
+    if *method == Method::POST && creates_connection && !headers.contains_key("X-Cashu") {
+        "payment_requests"
+    } else if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        "reads"
+    } else {
+        "writes"
+    }
+}
+
+// S12.3 Middleware
+// Route layer for the app router. Requests whose client address is unknown
+// (no `ConnectInfo`) are let through.
+pub async fn limit_control_plane(State(app_state): State<AppState>, request: Request, next: Next) -> Response {
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(address)| address.ip());
+    let client = client_ip(request.headers(), peer, &app_state.trusted_proxies);
+    let class = route_class(request.method(), request.uri().path(), request.headers());
+
+    if let (Some(client), Some(limit)) = (client, app_state.control_plane_limits.get(class)) {
+        if check_admin(&app_state, request.headers()).is_err() {
+            if let Err(limited) = app_state.rate_limiter.throttle(class, client, limit.rate, limit.burst) {
+                tracing::warn!("Limiting {} requests from {} ({} {})", class, client, request.method(), request.uri().path());
+                return rate_limited(request.uri().path(), limited);
+            }
+        }
+    }
+    next.run(request).await
+}
+
+// JSON API routes answer in the API's error shape
+fn rate_limited(path: &str, limited: Limited) -> Response {
+    let retry_after = [(header::RETRY_AFTER, retry_after_seconds(limited).to_string())];
+    let message = "Too many requests; try again later";
+    if path.starts_with("/api/") {
+        let body = ErrorBody { error: ErrorDetail { code: "rate_limited".to_string(), message: message.to_string() } };
+        (StatusCode::TOO_MANY_REQUESTS, retry_after, Json(body)).into_response()
+    } else {
+        (StatusCode::TOO_MANY_REQUESTS, retry_after, Body::from(message)).into_response()
+    }
+}
+
+// S12.4 Proof of Work
+// Hashcash stamps `1:bits:date:resource:ext:rand:counter`, where `date` is
+// the UTC time as YYMMDDhhmm[ss], `resource` is Sando's primary host and the
+// SHA-256 of the whole stamp starts with at least `bits` zero bits. Each
+// stamp is accepted once while it is fresh.
+const STAMP_LIFETIME: Duration = Duration::from_secs(10 * 60);
+const CLOCK_SKEW: Duration = Duration::from_secs(2 * 60);
+
+pub struct ProofOfWork {
+    bits: u8,
+    spent: Mutex<HashMap<String, Instant>>,
+}
+
+impl ProofOfWork {
+    pub fn new(bits: u8) -> Self {
+        Self { bits, spent: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    // What a client needs to know to mint a stamp, sent in `X-Sando-PoW`
+    pub fn challenge(&self, resource: &str) -> String {
+        format!("1:{}:{}", self.bits, resource)
+    }
+
+    pub fn check(&self, stamp: Option<&str>, resource: &str) -> Result<(), String> {
+        let stamp = stamp.map(str::trim).filter(|stamp| !stamp.is_empty()).ok_or_else(|| {
+            format!("A proof-of-work stamp with {} bits for {} is required", self.bits, resource)
+        })?;
+        let fields: Vec<&str> = stamp.split(':').collect();
+        let [version, bits, date, stamp_resource, _ext, _rand, _counter] = fields[..] else {
+            return Err("The proof-of-work stamp must look like 1:bits:date:resource:ext:rand:counter".to_string());
+        };
+        if version != "1" {
+            return Err(format!("Unsupported proof-of-work stamp version '{}'", version));
+        }
+        if bits.parse::<u8>().map_or(true, |bits| bits < self.bits) {
+            return Err(format!("The proof-of-work stamp must have at least {} bits", self.bits));
+        }
+        if !stamp_resource.eq_ignore_ascii_case(resource) {
+            return Err(format!("The proof-of-work stamp must be for {}", resource));
+        }
+        let minted = ["%y%m%d%H%M%S", "%y%m%d%H%M"]
+            .iter()
+            .find_map(|format| chrono::NaiveDateTime::parse_from_str(date, format).ok())
+            .ok_or("The proof-of-work stamp's date must be YYMMDDhhmm[ss] in UTC")?;
+        let age = chrono::Utc::now().naive_utc() - minted;
+        let lifetime = chrono::Duration::from_std(STAMP_LIFETIME).unwrap_or_default();
+        let skew = chrono::Duration::from_std(CLOCK_SKEW).unwrap_or_default();
+        if age > lifetime || age < -skew {
+            return Err("The proof-of-work stamp has expired; mint a new one".to_string());
+        }
+        if leading_zero_bits(&Sha256::digest(stamp.as_bytes())) < u32::from(self.bits) {
+            return Err("The proof-of-work stamp does not have enough work".to_string());
+        }
+
+        let now = Instant::now();
+        let mut spent = self.spent.lock().unwrap();
+        spent.retain(|_, used| now.duration_since(*used) < STAMP_LIFETIME + CLOCK_SKEW);
+        if spent.insert(stamp.to_string(), now).is_some() {
+            return Err("The proof-of-work stamp was already used".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Checks the stamp in `X-Sando-PoW` or, from the HTML form, in `form_stamp`,
+// when the server asks for proof of work
+pub fn require_proof_of_work(app_state: &AppState, headers: &HeaderMap, form_stamp: Option<&str>) -> Result<(), String> {
+    let Some(proof_of_work) = &app_state.proof_of_work else {
+        return Ok(());
+    };
+    let stamp = headers.get(POW_HEADER).and_then(|value| value.to_str().ok()).or(form_stamp);
+    proof_of_work.check(stamp, &app_state.host)
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+// S12.5 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mints a stamp like the home page script does
+    fn mint(bits: u8, resource: &str, date: &str) -> String {
+        (0u64..)
+            .map(|counter| format!("1:{}:{}:{}::c2FuZG8:{:x}", bits, date, resource, counter))
+            .find(|stamp| leading_zero_bits(&Sha256::digest(stamp.as_bytes())) >= u32::from(bits))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_hashcash_stamps() {
+        let pow = ProofOfWork::new(8);
+        let now = chrono::Utc::now().format("%y%m%d%H%M%S").to_string();
+        let stamp = mint(8, "sando.blue", &now);
+
+        assert!(pow.check(None, "sando.blue").is_err());
+        assert!(pow.check(Some(&stamp), "other.example").is_err());
+        assert!(pow.check(Some(&stamp), "sando.blue").is_ok());
+        assert!(pow.check(Some(&stamp), "sando.blue").unwrap_err().contains("already used"));
+
+        // Too little work, claimed or actual, and stale stamps are refused
+        assert!(pow.check(Some(&mint(4, "sando.blue", &now)), "sando.blue").is_err());
+        let lazy = (0u64..)
+            .map(|counter| format!("1:8:{}:sando.blue::bGF6eQ:{:x}", now, counter))
+            .find(|stamp| leading_zero_bits(&Sha256::digest(stamp.as_bytes())) < 8)
+            .unwrap();
+        assert!(pow.check(Some(&lazy), "sando.blue").unwrap_err().contains("enough work"));
+        assert!(pow.check(Some(&mint(8, "sando.blue", "2001010000")), "sando.blue").unwrap_err().contains("expired"));
+        assert_eq!(leading_zero_bits(&[0, 0x1f, 0xff]), 11);
+    }
+
+    #[test]
+    fn test_route_classes_and_limits() {
+        let mut paid = HeaderMap::new();
+        paid.insert("X-Cashu", "cashuA...".parse().unwrap());
+        assert_eq!(route_class(&Method::POST, "/submit", &HeaderMap::new()), "payment_requests");
+        assert_eq!(route_class(&Method::POST, "/api/v1/connections", &HeaderMap::new()), "payment_requests");
+        assert_eq!(route_class(&Method::POST, "/submit", &paid), "writes");
+        assert_eq!(route_class(&Method::DELETE, "/connections/1", &HeaderMap::new()), "writes");
+        assert_eq!(route_class(&Method::GET, "/connections", &HeaderMap::new()), "reads");
+
+        let limits = ControlPlaneLimits::parse("payment_requests=0.05/5, reads=off").unwrap();
+        assert_eq!(limits.payment_requests, Some(RateLimit { rate: 0.05, burst: 5 }));
+        assert_eq!(limits.writes, ControlPlaneLimits::default().writes);
+        assert_eq!(limits.reads, None);
+        assert!(ControlPlaneLimits::parse("pages=1/1").is_err());
+        assert!(ControlPlaneLimits::parse("writes=1").is_err());
+        assert!(ControlPlaneLimits::parse("writes=0/5").is_err());
+    }
+}
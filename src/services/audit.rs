@@ -0,0 +1,285 @@
+/**
+ * S7.0 Audit Log
+ * ==============
+ *
+ * Records who did what to which connection, and when, in the `audit_log`
+ * table, and reads it back for the admin page, the JSON API and CSV exports.
+ * Recording is best effort: a failed insert is logged but never undoes or
+ * fails the change it describes.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S7.1, S7.2, S7.3, S7.4, S7.5
+ */
+// S7.1 Dependencies
+use crate::models::{AuditAction, AuditActor, AuditEntry, AuditQuery};
+use crate::routes::connections::like_pattern;
+use crate::services::backup::csv_row;
+use crate::Connection;
+use axum::http::StatusCode;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde_json::{Map, Value};
+use sqlx::sqlite::{Sqlite, SqlitePool};
+use sqlx::QueryBuilder;
+
+const DEFAULT_PAGE_SIZE: u32 = 100;
+const MAX_PAGE_SIZE: u32 = 500;
+const ENTRY_COLUMNS: &str = "id, created_at, actor, action, connection_id, subdomain, owner, details";
+
+// S7.2 Recording
+// Built up at the call site and written with `record`, e.g.
+//
+//     AuditEvent::new(actor, AuditAction::Delete).connection(&connection).record(pool).await;
+//
+// Without an explicit subdomain and owner they are copied from the
+// connection row, if it still exists.
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    actor: AuditActor,
+    action: AuditAction,
+    connection_id: Option<i64>,
+    subdomain: Option<String>,
+    owner: Option<String>,
+    details: Map<String, Value>,
+}
+
+impl AuditEvent {
+    pub fn new(actor: AuditActor, action: AuditAction) -> Self {
+        Self {
+            actor,
+            action,
+            connection_id: None,
+            subdomain: None,
+            owner: None,
+            details: Map::new(),
+        }
+    }
+
+    pub fn connection(mut self, connection: &Connection) -> Self {
+        self.connection_id = Some(connection.id);
+        self.subdomain = connection.subdomain.clone();
+        self.owner = connection.owner.clone();
+        self
+    }
+
+    pub fn connection_id(mut self, connection_id: i64) -> Self {
+        self.connection_id = Some(connection_id);
+        self
+    }
+
+    pub fn subdomain(mut self, subdomain: impl Into<String>) -> Self {
+        self.subdomain = Some(subdomain.into());
+        self
+    }
+
+    pub fn owner(mut self, owner: Option<&str>) -> Self {
+        self.owner = owner.map(str::to_string);
+        self
+    }
+
+    pub fn detail(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.details.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub async fn record(self, pool: &SqlitePool) {
+        let result = sqlx::query(
+            "INSERT INTO audit_log (actor, action, connection_id, subdomain, owner, details) VALUES \
+             (?1, ?2, ?3, COALESCE(?4, (SELECT subdomain FROM connections WHERE id = ?3)), \
+             COALESCE(?5, (SELECT owner FROM connections WHERE id = ?3)), ?6)",
+        )
+        .bind(self.actor)
+        .bind(self.action)
+        .bind(self.connection_id)
+        .bind(&self.subdomain)
+        .bind(&self.owner)
+        .bind(Value::Object(self.details).to_string())
+        .execute(pool)
+        .await;
+
+        if let Err(e) = result {
+            tracing::error!(
+                "❌ Failed to record {} by {} for connection {:?}: {}",
+                self.action.as_str(),
+                self.actor.as_str(),
+                self.connection_id,
+                e
+            );
+        }
+    }
+}
+
+// S7.3 Search
+// Newest first, paginated by id: `next_cursor` is the id to continue below.
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub next_cursor: Option<i64>,
+}
+
+pub async fn search_audit_log(pool: &SqlitePool, query: &AuditQuery) -> Result<AuditPage, (StatusCode, String)> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let mut entries = fetch_entries(pool, query, Some(limit + 1)).await?;
+
+    let next_cursor = if entries.len() > limit as usize {
+        entries.truncate(limit as usize);
+        entries.last().map(|entry| entry.id)
+    } else {
+        None
+    };
+    Ok(AuditPage { entries, next_cursor })
+}
+
+// Every entry matching the filters, for exports.
+pub async fn export_audit_log(pool: &SqlitePool, query: &AuditQuery) -> Result<Vec<AuditEntry>, (StatusCode, String)> {
+    fetch_entries(pool, query, None).await
+}
+
+async fn fetch_entries(
+    pool: &SqlitePool,
+    query: &AuditQuery,
+    limit: Option<u32>,
+) -> Result<Vec<AuditEntry>, (StatusCode, String)> {
+    let since = query.since.as_deref().map(parse_timestamp).transpose()?;
+    let until = query.until.as_deref().map(parse_timestamp).transpose()?;
+
+    let mut builder: QueryBuilder<Sqlite> =
+        QueryBuilder::new(format!("SELECT {} FROM audit_log WHERE 1 = 1", ENTRY_COLUMNS));
+    if let Some(action) = query.action {
+        builder.push(" AND action = ").push_bind(action);
+    }
+    if let Some(actor) = query.actor {
+        builder.push(" AND actor = ").push_bind(actor);
+    }
+    if let Some(connection_id) = query.connection_id {
+        builder.push(" AND connection_id = ").push_bind(connection_id);
+    }
+    if let Some(text) = &query.q {
+        let pattern = like_pattern(text);
+        builder.push(" AND (");
+        for (i, column) in ["subdomain", "owner", "details"].iter().enumerate() {
+            if i > 0 {
+                builder.push(" OR ");
+            }
+            builder.push(format!("{} LIKE ", column)).push_bind(pattern.clone()).push(" ESCAPE '\\'");
+        }
+        builder.push(")");
+    }
+    if let Some(since) = since {
+        builder.push(" AND created_at >= ").push_bind(since);
+    }
+    if let Some(until) = until {
+        builder.push(" AND created_at < ").push_bind(until);
+    }
+    if let Some(cursor) = query.cursor {
+        builder.push(" AND id < ").push_bind(cursor);
+    }
+    builder.push(" ORDER BY id DESC");
+    if let Some(limit) = limit {
+        builder.push(" LIMIT ").push_bind(limit);
+    }
+
+    builder
+        .build_query_as::<AuditEntry>()
+        .fetch_all(pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)))
+}
+
+// Accepts a date or a date and time (UTC) and returns it in SQLite's
+// CURRENT_TIMESTAMP format, so it compares correctly with `created_at`.
+fn parse_timestamp(value: &str) -> Result<String, (StatusCode, String)> {
+    const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+    let value = value.trim();
+    let parsed = NaiveDateTime::parse_from_str(value, FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default()));
+
+    parsed.map(|timestamp| timestamp.format(FORMAT).to_string()).map_err(|_| {
+        (StatusCode::BAD_REQUEST, format!("Invalid time '{}'; use YYYY-MM-DD or YYYY-MM-DD HH:MM:SS", value))
+    })
+}
+
+// S7.4 CSV Export
+pub fn to_csv(entries: &[AuditEntry]) -> String {
+    let mut csv = csv_row(
+        ["id", "created_at", "actor", "action", "connection_id", "subdomain", "owner", "details"].map(String::from),
+    );
+    for entry in entries {
+        csv.push_str(&csv_row([
+            entry.id.to_string(),
+            entry.created_at.clone(),
+            entry.actor.as_str().to_string(),
+            entry.action.as_str().to_string(),
+            entry.connection_id.map(|id| id.to_string()).unwrap_or_default(),
+            entry.subdomain.clone().unwrap_or_default(),
+            entry.owner.clone().unwrap_or_default(),
+            serde_json::to_string(&entry.details).unwrap_or_default(),
+        ]));
+    }
+    csv
+}
+
+// S7.5 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn test_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_record_copies_connection_and_filters() {
+        let pool = test_pool().await;
+        let id = sqlx::query("INSERT INTO connections (connection_string, port, subdomain, owner) VALUES ('key', 4100, 'my-app', 'alice')")
+            .execute(&pool)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+
+        AuditEvent::new(AuditActor::Api, AuditAction::Create).connection_id(id).record(&pool).await;
+        AuditEvent::new(AuditActor::Web, AuditAction::Update)
+            .connection_id(id)
+            .detail("changed", vec!["label"])
+            .record(&pool)
+            .await;
+        AuditEvent::new(AuditActor::Web, AuditAction::BatchDelete)
+            .connection_id(99)
+            .subdomain("gone")
+            .record(&pool)
+            .await;
+
+        let page = search_audit_log(&pool, &AuditQuery::default()).await.unwrap();
+        let actions: Vec<AuditAction> = page.entries.iter().map(|entry| entry.action).collect();
+        assert_eq!(actions, vec![AuditAction::BatchDelete, AuditAction::Update, AuditAction::Create]);
+        assert_eq!(page.entries[1].subdomain.as_deref(), Some("my-app"));
+        assert_eq!(page.entries[1].owner.as_deref(), Some("alice"));
+        assert_eq!(page.entries[1].details.0["changed"], serde_json::json!(["label"]));
+        assert_eq!(page.entries[0].owner, None);
+
+        let query = AuditQuery { actor: Some(AuditActor::Web), limit: Some(1), ..Default::default() };
+        let page = search_audit_log(&pool, &query).await.unwrap();
+        assert_eq!(page.entries[0].action, AuditAction::BatchDelete);
+        let query = AuditQuery { cursor: page.next_cursor, ..query };
+        let page = search_audit_log(&pool, &query).await.unwrap();
+        assert_eq!(page.entries[0].action, AuditAction::Update);
+        assert_eq!(page.next_cursor, None);
+
+        let query = AuditQuery { q: Some("ALICE".to_string()), ..Default::default() };
+        assert_eq!(export_audit_log(&pool, &query).await.unwrap().len(), 2);
+        let query = AuditQuery { since: Some("2999-01-01".to_string()), ..Default::default() };
+        assert!(export_audit_log(&pool, &query).await.unwrap().is_empty());
+        let query = AuditQuery { until: Some("yesterday".to_string()), ..Default::default() };
+        assert!(export_audit_log(&pool, &query).await.is_err());
+
+        let csv = to_csv(&export_audit_log(&pool, &AuditQuery::default()).await.unwrap());
+        assert!(csv.starts_with("id,created_at,actor,action,connection_id,subdomain,owner,details\r\n"));
+        assert!(csv.contains(",web,update,1,my-app,alice,\"{\"\"changed\"\":[\"\"label\"\"]}\"\r\n"));
+    }
+}
@@ -0,0 +1,1037 @@
+/**
+ * S5.0 Backup
+ * ===========
+ *
+ * Export and import of connections with their metadata, hostnames and custom
+ * domains, for moving to another Sando instance or restoring after disk
+ * loss. Documents are JSON (lossless) or CSV (one row per connection).
+ * Holesail keys and link secrets are only exported when a passphrase is
+ * given, and then encrypted with it; credentials travel as their hashes.
+ * Imports upsert by canonical subdomain, and refuse to restore a tunnel
+ * with signed links without its link secret rather than open it up.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S5.1, S5.2, S5.3, S5.4, S5.5, S5.6, S5.7
+ */
+// S5.1 Dependencies
+use crate::models::{
+    AuditAction, AuditActor, Connection, CredentialKind, CustomDomain, ResponseRewrite, Tags, TunnelLimits, IpRules,
+    VerificationMethod, CONNECTION_COLUMNS,
+};
+use crate::routes::connections::validate_metadata;
+use crate::routes::domains::{list_domains, normalize_domain, validate_domain, verified_domain_connection};
+use crate::routes::hostnames::{hostname_owner, list_hostnames, promote_hostname};
+use crate::routes::subdomains::validate_subdomain;
+use crate::services::audit::AuditEvent;
+use crate::services::ports::PortAllocator;
+use axum::http::StatusCode;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::sqlite::{Sqlite, SqlitePool};
+use sqlx::Transaction;
+use std::collections::{HashMap, HashSet};
+use utoipa::ToSchema;
+
+// S5.2 Document Types
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+pub struct ExportDocument {
+    pub version: u32,
+    pub exported_at: String,
+    pub connections: Vec<ExportedConnection>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+pub struct ExportedConnection {
+    // Canonical hostname; imports match existing connections on it
+    pub subdomain: String,
+    // Holesail key, `sando-enc1:...` when encrypted. Left out of exports
+    // without a passphrase; plain keys are accepted on import.
+    #[serde(default)]
+    pub connection_string: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    #[schema(value_type = std::collections::BTreeMap<String, String>)]
+    pub tags: Tags,
+    #[serde(default)]
+    pub response_rewrite: ResponseRewrite,
+    #[serde(default)]
+    pub tier: Option<String>,
+    #[serde(default)]
+    pub limits: TunnelLimits,
+    #[serde(default)]
+    pub ip_rules: IpRules,
+    // Aliases only; the canonical hostname is `subdomain`
+    #[serde(default)]
+    pub hostnames: Vec<ExportedHostname>,
+    #[serde(default)]
+    pub domains: Vec<ExportedDomain>,
+    // Basic users and tokens, as their hashes
+    #[serde(default)]
+    pub credentials: Vec<ExportedCredential>,
+    // Key of the connection's signed links, `sando-enc1:...` when encrypted.
+    // Left out of exports without a passphrase; `signed_links` still tells
+    // the import the connection had links, so it is refused instead.
+    #[serde(default)]
+    pub link_secret: Option<String>,
+    #[serde(default)]
+    pub signed_links: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema)]
+pub struct ExportedHostname {
+    pub hostname: String,
+    #[serde(default)]
+    pub redirect: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema)]
+pub struct ExportedDomain {
+    pub domain: String,
+    #[serde(default)]
+    pub method: VerificationMethod,
+    pub token: String,
+    #[serde(default)]
+    pub verified_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema, sqlx::FromRow)]
+pub struct ExportedCredential {
+    pub kind: CredentialKind,
+    pub name: String,
+    // PBKDF2 for passwords, SHA-256 for tokens, as stored
+    pub secret_hash: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, ToSchema)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    // Canonical subdomains of the connections created and updated
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    // (key, port) of tunnels whose key was replaced; the caller stops them
+    #[serde(skip)]
+    pub replaced_tunnels: Vec<(String, u16)>,
+}
+
+// S5.3 Secret Encryption
+// Keys are sealed with ChaCha20-Poly1305 under a key derived from the
+// passphrase with PBKDF2-HMAC-SHA256. Each value carries its salt and nonce
+// (`sando-enc1:{salt}:{nonce}:{ciphertext}`, hex), so a CSV cell or a single
+// JSON field decrypts on its own.
+const SECRET_PREFIX: &str = "sando-enc1";
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+struct SecretBox {
+    salt: [u8; 16],
+    key: [u8; 32],
+}
+
+impl SecretBox {
+    fn new(passphrase: &str) -> Self {
+        let salt: [u8; 16] = rand::thread_rng().gen();
+        Self { key: derive_key(passphrase, &salt), salt }
+    }
+
+    fn seal(&self, secret: &str) -> String {
+        let nonce: [u8; 12] = rand::thread_rng().gen();
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&self.key))
+            .encrypt(Nonce::from_slice(&nonce), secret.as_bytes())
+            .expect("encrypting in memory can't fail");
+        format!("{}:{}:{}:{}", SECRET_PREFIX, hex::encode(self.salt), hex::encode(nonce), hex::encode(ciphertext))
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    key
+}
+
+// Opens a sealed key. Derived keys are cached by salt, since every value in
+// one export shares it. Values without the prefix are plain keys.
+fn open_secret(
+    value: &str,
+    passphrase: Option<&str>,
+    keys: &mut HashMap<Vec<u8>, [u8; 32]>,
+) -> Result<String, String> {
+    let Some(sealed) = value.strip_prefix(SECRET_PREFIX).and_then(|rest| rest.strip_prefix(':')) else {
+        return Ok(value.to_string());
+    };
+    let passphrase = passphrase.ok_or("the key is encrypted; a passphrase is required")?;
+    let parts: Vec<Vec<u8>> = sealed
+        .split(':')
+        .map(hex::decode)
+        .collect::<Result<_, _>>()
+        .map_err(|_| "the encrypted key is malformed")?;
+    let [salt, nonce, ciphertext] = parts.as_slice() else {
+        return Err("the encrypted key is malformed".to_string());
+    };
+    if nonce.len() != 12 {
+        return Err("the encrypted key is malformed".to_string());
+    }
+
+    let key = keys.entry(salt.clone()).or_insert_with(|| derive_key(passphrase, salt));
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext.as_slice())
+        .map_err(|_| "the key could not be decrypted; wrong passphrase?")?;
+
+    String::from_utf8(plaintext).map_err(|_| "the decrypted key is not valid UTF-8".to_string())
+}
+
+// S5.4 Export
+// Every connection, or only those of `owner` (case-insensitive). Keys and
+// link secrets are included, encrypted, only when a passphrase is given.
+pub async fn export_connections(
+    pool: &SqlitePool,
+    owner: Option<&str>,
+    passphrase: Option<&str>,
+) -> Result<ExportDocument, sqlx::Error> {
+    let connections = sqlx::query_as::<_, Connection>(&format!(
+        "SELECT {} FROM connections WHERE ?1 IS NULL OR owner = ?1 COLLATE NOCASE ORDER BY id",
+        CONNECTION_COLUMNS
+    ))
+    .bind(owner)
+    .fetch_all(pool)
+    .await?;
+
+    let secret_box = passphrase.map(SecretBox::new);
+    let mut exported = Vec::with_capacity(connections.len());
+    for connection in connections {
+        let hostnames = list_hostnames(pool, connection.id)
+            .await?
+            .into_iter()
+            .filter(|hostname| !hostname.is_canonical)
+            .map(|hostname| ExportedHostname { hostname: hostname.hostname, redirect: hostname.redirect })
+            .collect();
+        let domains = list_domains(pool, connection.id)
+            .await?
+            .into_iter()
+            .map(|domain: CustomDomain| ExportedDomain {
+                domain: domain.domain,
+                method: domain.method,
+                token: domain.token,
+                verified_at: domain.verified_at,
+            })
+            .collect();
+        let credentials = sqlx::query_as::<_, ExportedCredential>(
+            "SELECT kind, name, secret_hash FROM connection_credentials WHERE connection_id = ? ORDER BY kind, name",
+        )
+        .bind(connection.id)
+        .fetch_all(pool)
+        .await?;
+
+        exported.push(ExportedConnection {
+            subdomain: connection.subdomain.clone().unwrap_or_else(|| connection.connection_string.clone()),
+            connection_string: secret_box.as_ref().map(|secret_box| secret_box.seal(&connection.connection_string)),
+            created_at: Some(connection.created_at),
+            label: connection.label,
+            description: connection.description,
+            owner: connection.owner,
+            tags: connection.tags,
+            response_rewrite: connection.response_rewrite,
+            tier: connection.tier,
+            limits: connection.limits,
+            ip_rules: connection.ip_rules,
+            hostnames,
+            domains,
+            credentials,
+            link_secret: connection
+                .link_secret
+                .as_ref()
+                .and_then(|secret| secret_box.as_ref().map(|secret_box| secret_box.seal(secret))),
+            signed_links: connection.link_secret.is_some(),
+        });
+    }
+
+    Ok(ExportDocument {
+        version: EXPORT_VERSION,
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        connections: exported,
+    })
+}
+
+// S5.5 Import
+// Validates the whole document first and writes nothing if any entry is
+// invalid. Connections are matched on their canonical subdomain: existing
+// ones get the document's metadata (and key, if given), missing ones are
+// created on a freshly allocated local port. Aliases, custom domains and
+// credentials are added or updated, never removed; a link secret replaces
+// the current one.
+enum ImportTarget {
+    Create,
+    Update(i64, Box<Connection>),
+}
+
+struct PreparedImport {
+    entry: ExportedConnection,
+    key: Option<String>,
+    link_secret: Option<String>,
+    target: ImportTarget,
+}
+
+pub async fn import_connections(
+    pool: &SqlitePool,
+    ports: &PortAllocator,
+    base_hosts: &[String],
+    document: ExportDocument,
+    passphrase: Option<&str>,
+    dry_run: bool,
+    actor: AuditActor,
+) -> Result<ImportReport, (StatusCode, String)> {
+    if document.version != EXPORT_VERSION {
+        return Err((StatusCode::BAD_REQUEST, format!("Unsupported export version {}", document.version)));
+    }
+
+    let mut prepared = Vec::with_capacity(document.connections.len());
+    let mut errors = Vec::new();
+    let mut seen_hostnames = HashSet::new();
+    let mut seen_domains = HashSet::new();
+    let mut keys = HashMap::new();
+    for (index, entry) in document.connections.into_iter().enumerate() {
+        match prepare_entry(pool, base_hosts, entry, passphrase, &mut keys, &mut seen_hostnames, &mut seen_domains).await {
+            Ok(Ok(entry)) => prepared.push(entry),
+            Ok(Err(entry_errors)) => {
+                errors.extend(entry_errors.into_iter().map(|error| format!("connections[{}]: {}", index, error)))
+            }
+            Err(e) => return Err(internal_error(e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, errors.join(". ")));
+    }
+
+    let mut report = ImportReport { dry_run, ..Default::default() };
+    for PreparedImport { entry, key, link_secret, target } in prepared {
+        match target {
+            ImportTarget::Create => {
+                if !dry_run {
+                    let key = key.as_deref().unwrap_or_default();
+                    let reservation = ports.reserve(pool).await.map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+                    let mut tx = pool.begin().await.map_err(internal_error)?;
+                    let id = sqlx::query(
+                        "INSERT INTO connections (connection_string, port, subdomain, created_at, label, description, owner, tags, response_rewrite, tier, limits, ip_rules) \
+                         VALUES (?, ?, ?, COALESCE(?, CURRENT_TIMESTAMP), ?, ?, ?, ?, ?, ?, ?, ?)",
+                    )
+                    .bind(key)
+                    .bind(reservation.port)
+                    .bind(&entry.subdomain)
+                    .bind(&entry.created_at)
+                    .bind(&entry.label)
+                    .bind(&entry.description)
+                    .bind(&entry.owner)
+                    .bind(entry.tags.to_json())
+                    .bind(entry.response_rewrite)
+                    .bind(&entry.tier)
+                    .bind(entry.limits.to_json())
+                    .bind(entry.ip_rules.to_json())
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(internal_error)?
+                    .last_insert_rowid();
+                    promote_hostname(&mut tx, id, &entry.subdomain).await.map_err(internal_error)?;
+                    import_hostnames_and_domains(&mut tx, id, &entry).await.map_err(internal_error)?;
+                    import_access(&mut tx, id, &entry, link_secret.as_deref()).await.map_err(internal_error)?;
+                    tx.commit().await.map_err(internal_error)?;
+                    drop(reservation);
+                    tracing::info!("Imported connection '{}' as {}", entry.subdomain, id);
+                    AuditEvent::new(actor, AuditAction::Import)
+                        .connection_id(id)
+                        .detail("created", true)
+                        .record(pool)
+                        .await;
+                }
+                report.created.push(entry.subdomain);
+            }
+            ImportTarget::Update(id, connection) => {
+                if !dry_run {
+                    let mut tx = pool.begin().await.map_err(internal_error)?;
+                    sqlx::query(
+                        "UPDATE connections SET label = ?, description = ?, owner = ?, tags = ?, response_rewrite = ?, tier = ?, limits = ?, \
+                         ip_rules = ? WHERE id = ?",
+                    )
+                    .bind(&entry.label)
+                    .bind(&entry.description)
+                    .bind(&entry.owner)
+                    .bind(entry.tags.to_json())
+                    .bind(entry.response_rewrite)
+                    .bind(&entry.tier)
+                    .bind(entry.limits.to_json())
+                    .bind(entry.ip_rules.to_json())
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(internal_error)?;
+                    let key_replaced = key.as_ref().is_some_and(|key| *key != connection.connection_string);
+                    if let Some(key) = key.filter(|key| *key != connection.connection_string) {
+                        sqlx::query("UPDATE connections SET connection_string = ? WHERE id = ?")
+                            .bind(&key)
+                            .bind(id)
+                            .execute(&mut *tx)
+                            .await
+                            .map_err(internal_error)?;
+                        report.replaced_tunnels.push((connection.connection_string, connection.port as u16));
+                    }
+                    import_hostnames_and_domains(&mut tx, id, &entry).await.map_err(internal_error)?;
+                    import_access(&mut tx, id, &entry, link_secret.as_deref()).await.map_err(internal_error)?;
+                    tx.commit().await.map_err(internal_error)?;
+                    tracing::info!("Imported connection '{}' over {}", entry.subdomain, id);
+                    AuditEvent::new(actor, AuditAction::Import)
+                        .connection_id(id)
+                        .detail("created", false)
+                        .detail("key_replaced", key_replaced)
+                        .record(pool)
+                        .await;
+                }
+                report.updated.push(entry.subdomain);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+// Normalizes one entry and checks it against the database and the entries
+// before it. The inner result carries the entry's validation errors.
+#[allow(clippy::too_many_arguments)]
+async fn prepare_entry(
+    pool: &SqlitePool,
+    base_hosts: &[String],
+    mut entry: ExportedConnection,
+    passphrase: Option<&str>,
+    keys: &mut HashMap<Vec<u8>, [u8; 32]>,
+    seen_hostnames: &mut HashSet<String>,
+    seen_domains: &mut HashSet<String>,
+) -> Result<Result<PreparedImport, Vec<String>>, sqlx::Error> {
+    let mut errors = Vec::new();
+    entry.subdomain = entry.subdomain.trim().to_ascii_lowercase();
+    entry.label = non_empty(entry.label);
+    entry.description = non_empty(entry.description);
+    entry.owner = non_empty(entry.owner);
+    let name = entry.subdomain.clone();
+
+    errors.extend(validate_subdomain(&name, base_hosts).into_iter().map(|error| format!("{}: {}", name, error)));
+    errors.extend(validate_metadata(&entry.label, &entry.description, &entry.owner, &entry.tags));
+    errors.extend(entry.limits.validate().into_iter().map(|error| format!("{}: {}", name, error)));
+    errors.extend(entry.ip_rules.validate().into_iter().map(|error| format!("{}: {}", name, error)));
+
+    let key = match non_empty(entry.connection_string.take()).map(|value| open_secret(&value, passphrase, keys)) {
+        Some(Ok(key)) => Some(key),
+        Some(Err(error)) => {
+            errors.push(format!("{}: {}", name, error));
+            None
+        }
+        None => None,
+    };
+    let link_secret = match non_empty(entry.link_secret.take()).map(|value| open_secret(&value, passphrase, keys)) {
+        Some(Ok(secret)) => Some(secret),
+        Some(Err(error)) => {
+            errors.push(format!("{}: link_secret: {}", name, error));
+            None
+        }
+        None => {
+            if entry.signed_links {
+                errors.push(format!(
+                    "{}: has signed links but no link_secret; export with a passphrase to include it",
+                    name
+                ));
+            }
+            None
+        }
+    };
+    let mut seen_credentials = HashSet::new();
+    for credential in &mut entry.credentials {
+        credential.name = credential.name.trim().to_string();
+        let label = format!("{}: {} credential '{}'", name, credential.kind.as_str(), credential.name);
+        if credential.name.is_empty() || credential.name.len() > 100 || credential.name.contains(char::is_control) {
+            errors.push(format!("{}: the name must be 1 to 100 characters", label));
+        } else if credential.kind == CredentialKind::Basic && credential.name.contains(':') {
+            errors.push(format!("{}: user names can't contain ':'", label));
+        }
+        if credential.secret_hash.trim().is_empty() {
+            errors.push(format!("{}: a secret_hash is required", label));
+        }
+        if !seen_credentials.insert((credential.kind.as_str(), credential.name.clone())) {
+            errors.push(format!("{}: listed more than once", label));
+        }
+    }
+
+    let target = match hostname_owner(pool, &name).await? {
+        None => {
+            if key.is_none() && errors.is_empty() {
+                errors.push(format!("{}: a connection_string is required to create a connection", name));
+            }
+            ImportTarget::Create
+        }
+        Some(id) => {
+            let connection = sqlx::query_as::<_, Connection>(&format!(
+                "SELECT {} FROM connections WHERE id = ?",
+                CONNECTION_COLUMNS
+            ))
+            .bind(id)
+            .fetch_one(pool)
+            .await?;
+            if connection.subdomain.as_deref() != Some(name.as_str()) {
+                errors.push(format!("{}: already an alias of another connection", name));
+            }
+            ImportTarget::Update(id, Box::new(connection))
+        }
+    };
+    let target_id = match &target {
+        ImportTarget::Update(id, _) => Some(*id),
+        ImportTarget::Create => None,
+    };
+
+    if !seen_hostnames.insert(name.clone()) {
+        errors.push(format!("{}: listed more than once", name));
+    }
+    for hostname in &mut entry.hostnames {
+        hostname.hostname = hostname.hostname.trim().to_ascii_lowercase();
+        let alias = &hostname.hostname;
+        errors.extend(validate_subdomain(alias, base_hosts).into_iter().map(|error| format!("{}: {}", alias, error)));
+        if !seen_hostnames.insert(alias.clone()) {
+            errors.push(format!("{}: listed more than once", alias));
+        } else if hostname_owner(pool, alias).await?.is_some_and(|owner| Some(owner) != target_id) {
+            errors.push(format!("{}: already taken by another connection", alias));
+        }
+    }
+
+    for domain in &mut entry.domains {
+        domain.domain = normalize_domain(&domain.domain);
+        let name = &domain.domain;
+        errors.extend(validate_domain(name, base_hosts).into_iter().map(|error| format!("{}: {}", name, error)));
+        if domain.token.trim().is_empty() {
+            errors.push(format!("{}: a challenge token is required", name));
+        }
+        if domain.verified_at.is_some() {
+            if !seen_domains.insert(name.clone()) {
+                errors.push(format!("{}: verified for more than one connection", name));
+            } else if verified_domain_connection(pool, name).await?.is_some_and(|owner| Some(owner) != target_id) {
+                errors.push(format!("{}: already verified by another connection", name));
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Ok(Err(errors));
+    }
+    Ok(Ok(PreparedImport { entry, key, link_secret, target }))
+}
+
+async fn import_hostnames_and_domains(
+    tx: &mut Transaction<'_, Sqlite>,
+    connection_id: i64,
+    entry: &ExportedConnection,
+) -> Result<(), sqlx::Error> {
+    for hostname in &entry.hostnames {
+        sqlx::query(
+            "INSERT INTO connection_hostnames (connection_id, hostname, redirect) VALUES (?, ?, ?) \
+             ON CONFLICT (hostname) DO UPDATE SET redirect = excluded.redirect \
+             WHERE connection_id = excluded.connection_id AND NOT is_canonical",
+        )
+        .bind(connection_id)
+        .bind(&hostname.hostname)
+        .bind(hostname.redirect)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    for domain in &entry.domains {
+        // A verified domain settles competing claims, as in `verify_domain`
+        if domain.verified_at.is_some() {
+            sqlx::query("DELETE FROM custom_domains WHERE domain = ? AND connection_id != ? AND verified_at IS NULL")
+                .bind(&domain.domain)
+                .bind(connection_id)
+                .execute(&mut **tx)
+                .await?;
+        }
+        sqlx::query(
+            "INSERT INTO custom_domains (connection_id, domain, method, token, verified_at) VALUES (?, ?, ?, ?, ?) \
+             ON CONFLICT (connection_id, domain) DO UPDATE SET method = excluded.method, token = excluded.token, \
+             verified_at = COALESCE(excluded.verified_at, custom_domains.verified_at)",
+        )
+        .bind(connection_id)
+        .bind(&domain.domain)
+        .bind(domain.method)
+        .bind(&domain.token)
+        .bind(&domain.verified_at)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn import_access(
+    tx: &mut Transaction<'_, Sqlite>,
+    connection_id: i64,
+    entry: &ExportedConnection,
+    link_secret: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    for credential in &entry.credentials {
+        sqlx::query(
+            "INSERT INTO connection_credentials (connection_id, kind, name, secret_hash) VALUES (?, ?, ?, ?) \
+             ON CONFLICT (connection_id, kind, name) DO UPDATE SET secret_hash = excluded.secret_hash",
+        )
+        .bind(connection_id)
+        .bind(credential.kind)
+        .bind(&credential.name)
+        .bind(&credential.secret_hash)
+        .execute(&mut **tx)
+        .await?;
+    }
+    if let Some(secret) = link_secret {
+        sqlx::query("UPDATE connections SET link_secret = ? WHERE id = ?")
+            .bind(secret)
+            .bind(connection_id)
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(())
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn internal_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
+}
+
+// S5.6 CSV
+// One row per connection. Tags, limits and IP rules are `key=value` lines; aliases, redirecting
+// aliases and verified custom domains are space-separated lists. Pending
+// custom domains are left out, and imported domains get a fresh token.
+// Credentials are `kind:secret_hash:name` lines (hashes never contain ':').
+const CSV_COLUMNS: &[&str] = &[
+    "subdomain",
+    "connection_string",
+    "created_at",
+    "label",
+    "description",
+    "owner",
+    "tags",
+    "response_rewrite",
+    "tier",
+    "limits",
+    "ip_rules",
+    "aliases",
+    "redirects",
+    "domains",
+    "credentials",
+    "link_secret",
+    "signed_links",
+];
+
+pub fn to_csv(document: &ExportDocument) -> String {
+    let mut csv = csv_row(CSV_COLUMNS.iter().map(|column| column.to_string()));
+    for connection in &document.connections {
+        let aliases = |redirect: bool| {
+            connection
+                .hostnames
+                .iter()
+                .filter(|hostname| hostname.redirect == redirect)
+                .map(|hostname| hostname.hostname.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let domains = connection
+            .domains
+            .iter()
+            .filter(|domain| domain.verified_at.is_some())
+            .map(|domain| domain.domain.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        csv.push_str(&csv_row([
+            connection.subdomain.clone(),
+            connection.connection_string.clone().unwrap_or_default(),
+            connection.created_at.clone().unwrap_or_default(),
+            connection.label.clone().unwrap_or_default(),
+            connection.description.clone().unwrap_or_default(),
+            connection.owner.clone().unwrap_or_default(),
+            connection.tags.to_lines(),
+            connection.response_rewrite.as_str().to_string(),
+            connection.tier.clone().unwrap_or_default(),
+            connection.limits.to_lines(),
+            connection.ip_rules.to_lines(),
+            aliases(false),
+            aliases(true),
+            domains,
+            connection
+                .credentials
+                .iter()
+                .map(|credential| format!("{}:{}:{}", credential.kind.as_str(), credential.secret_hash, credential.name))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            connection.link_secret.clone().unwrap_or_default(),
+            if connection.signed_links { "true" } else { "" }.to_string(),
+        ]));
+    }
+    csv
+}
+
+pub fn from_csv(text: &str) -> Result<ExportDocument, String> {
+    let mut rows = parse_csv(text)?.into_iter();
+    let header = rows.next().ok_or("The CSV file is empty")?;
+    let column = |name: &str| header.iter().position(|column| column.trim() == name);
+    let subdomain_column = column("subdomain").ok_or("The CSV file needs a subdomain column")?;
+    let columns: HashMap<&str, usize> = CSV_COLUMNS.iter().filter_map(|name| Some((*name, column(name)?))).collect();
+
+    let mut connections = Vec::new();
+    for (index, row) in rows.enumerate() {
+        if row.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        let field = |name: &str| columns.get(name).and_then(|&i| row.get(i)).cloned().filter(|value| !value.trim().is_empty());
+        let list = |name: &str| field(name).map(|value| value.split_whitespace().map(str::to_string).collect::<Vec<_>>()).unwrap_or_default();
+
+        let tags = Tags::parse_lines(&field("tags").unwrap_or_default()).map_err(|e| format!("Row {}: {}", index + 2, e))?;
+        let response_rewrite = match field("response_rewrite") {
+            Some(value) => ResponseRewrite::ALL
+                .into_iter()
+                .find(|rewrite| rewrite.as_str() == value.trim())
+                .ok_or_else(|| format!("Row {}: response_rewrite must be off, headers or content", index + 2))?,
+            None => ResponseRewrite::default(),
+        };
+        let limits = TunnelLimits::parse_lines(&field("limits").unwrap_or_default()).map_err(|e| format!("Row {}: {}", index + 2, e))?;
+        let ip_rules = IpRules::parse_lines(&field("ip_rules").unwrap_or_default()).map_err(|e| format!("Row {}: {}", index + 2, e))?;
+        let mut hostnames: Vec<ExportedHostname> =
+            list("aliases").into_iter().map(|hostname| ExportedHostname { hostname, redirect: false }).collect();
+        hostnames.extend(list("redirects").into_iter().map(|hostname| ExportedHostname { hostname, redirect: true }));
+        let verified_at = Some(chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string());
+        let domains = list("domains")
+            .into_iter()
+            .map(|domain| ExportedDomain {
+                domain,
+                method: VerificationMethod::Dns,
+                token: uuid::Uuid::new_v4().simple().to_string(),
+                verified_at: verified_at.clone(),
+            })
+            .collect();
+
+        let credentials = field("credentials")
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let mut parts = line.splitn(3, ':');
+                let (Some(kind), Some(secret_hash), Some(name)) = (parts.next(), parts.next(), parts.next()) else {
+                    return Err(format!("Row {}: credentials are kind:secret_hash:name lines", index + 2));
+                };
+                let kind = match kind.trim() {
+                    "basic" => CredentialKind::Basic,
+                    "token" => CredentialKind::Token,
+                    _ => return Err(format!("Row {}: credential kinds are basic or token", index + 2)),
+                };
+                Ok(ExportedCredential { kind, name: name.to_string(), secret_hash: secret_hash.trim().to_string() })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let signed_links = match field("signed_links").as_deref().map(str::trim) {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(_) => return Err(format!("Row {}: signed_links must be true or false", index + 2)),
+        };
+
+        connections.push(ExportedConnection {
+            subdomain: row.get(subdomain_column).cloned().unwrap_or_default(),
+            connection_string: field("connection_string"),
+            created_at: field("created_at"),
+            label: field("label"),
+            description: field("description"),
+            owner: field("owner"),
+            tags,
+            response_rewrite,
+            tier: field("tier").map(|tier| tier.trim().to_ascii_lowercase()),
+            limits,
+            ip_rules,
+            hostnames,
+            domains,
+            credentials,
+            link_secret: field("link_secret"),
+            signed_links,
+        });
+    }
+
+    Ok(ExportDocument {
+        version: EXPORT_VERSION,
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        connections,
+    })
+}
+
+// RFC 4180: fields with commas, quotes or line breaks are quoted, quotes doubled
+pub fn csv_row(fields: impl IntoIterator<Item = String>) -> String {
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if field.is_empty() => in_quotes = true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) if chars.peek() == Some(&'\n') => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err("The CSV file has an unterminated quoted field".to_string());
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+// S5.7 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn test_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::migrate(&pool).await.unwrap();
+        pool
+    }
+
+    async fn seed(pool: &SqlitePool) {
+        sqlx::query(
+            "INSERT INTO connections (id, connection_string, port, subdomain, label, owner, tags) \
+             VALUES (1, 'secret-key', 4001, 'shop', 'Shop, \"main\"', 'ops@example.com', '{\"env\":\"prod\"}')",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        let mut tx = pool.begin().await.unwrap();
+        promote_hostname(&mut tx, 1, "shop").await.unwrap();
+        tx.commit().await.unwrap();
+        sqlx::query("INSERT INTO connection_hostnames (connection_id, hostname, redirect) VALUES (1, 'old-shop', 1)")
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO custom_domains (connection_id, domain, token, verified_at) \
+             VALUES (1, 'shop.example.com', 'tok', CURRENT_TIMESTAMP)",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO connection_credentials (connection_id, kind, name, secret_hash) VALUES (1, 'basic', 'alice', 'pbkdf2-sha256$1$00$00')")
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE connections SET link_secret = 'link-key' WHERE id = 1")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    fn hosts() -> Vec<String> {
+        vec!["localhost".to_string()]
+    }
+
+    #[test]
+    fn test_secrets_round_trip() {
+        let sealed = SecretBox::new("correct horse").seal("abcdef123456");
+        assert!(sealed.starts_with("sando-enc1:"));
+        assert!(!sealed.contains("abcdef123456"));
+
+        let mut keys = HashMap::new();
+        assert_eq!(open_secret(&sealed, Some("correct horse"), &mut keys).unwrap(), "abcdef123456");
+        assert!(open_secret(&sealed, Some("wrong"), &mut HashMap::new()).is_err());
+        assert!(open_secret(&sealed, None, &mut keys).is_err());
+        assert_eq!(open_secret("plain-key", None, &mut keys).unwrap(), "plain-key");
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let mut tags = Tags::default();
+        tags.0.insert("env".to_string(), "prod".to_string());
+        let document = ExportDocument {
+            version: EXPORT_VERSION,
+            exported_at: String::new(),
+            connections: vec![ExportedConnection {
+                subdomain: "shop".to_string(),
+                label: Some("Shop, \"main\"".to_string()),
+                description: Some("two\nlines".to_string()),
+                tags,
+                response_rewrite: ResponseRewrite::Content,
+                tier: Some("pro".to_string()),
+                limits: TunnelLimits { rate: Some(2.5), max_in_flight: Some(8), ..Default::default() },
+                ip_rules: IpRules::parse_lines("allow=10.0.0.0/8\ndeny=10.6.6.6").unwrap(),
+                credentials: vec![
+                    ExportedCredential {
+                        kind: CredentialKind::Basic,
+                        name: "alice".to_string(),
+                        secret_hash: "pbkdf2-sha256$1$00$00".to_string(),
+                    },
+                    ExportedCredential { kind: CredentialKind::Token, name: "ci: main".to_string(), secret_hash: "ab".repeat(32) },
+                ],
+                link_secret: Some("sando-enc1:00:00:00".to_string()),
+                signed_links: true,
+                hostnames: vec![
+                    ExportedHostname { hostname: "store".to_string(), redirect: false },
+                    ExportedHostname { hostname: "old-shop".to_string(), redirect: true },
+                ],
+                ..Default::default()
+            }],
+        };
+
+        let parsed = from_csv(&to_csv(&document)).unwrap();
+        let connection = &parsed.connections[0];
+        assert_eq!(connection.subdomain, "shop");
+        assert_eq!(connection.label, document.connections[0].label);
+        assert_eq!(connection.description, document.connections[0].description);
+        assert_eq!(connection.tags, document.connections[0].tags);
+        assert_eq!(connection.response_rewrite, ResponseRewrite::Content);
+        assert_eq!(connection.tier.as_deref(), Some("pro"));
+        assert_eq!(connection.limits, document.connections[0].limits);
+        assert_eq!(connection.ip_rules, document.connections[0].ip_rules);
+        assert_eq!(connection.hostnames, document.connections[0].hostnames);
+        assert_eq!(connection.credentials, document.connections[0].credentials);
+        assert_eq!(connection.link_secret, document.connections[0].link_secret);
+        assert!(connection.signed_links);
+        assert!(from_csv("subdomain\n\"open").is_err());
+        assert!(from_csv("label\nx").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_then_import_into_empty_instance() {
+        let source = test_pool().await;
+        seed(&source).await;
+
+        let without_secrets = export_connections(&source, None, None).await.unwrap();
+        assert_eq!(without_secrets.connections[0].connection_string, None);
+        assert_eq!(without_secrets.connections[0].link_secret, None);
+        assert!(without_secrets.connections[0].signed_links);
+        assert_eq!(without_secrets.connections[0].credentials[0].secret_hash, "pbkdf2-sha256$1$00$00");
+        assert!(export_connections(&source, Some("nobody"), None).await.unwrap().connections.is_empty());
+        let document = export_connections(&source, Some("OPS@example.com"), Some("pass")).await.unwrap();
+
+        let target = test_pool().await;
+        let ports = PortAllocator::new(4100..=4200);
+        // Keys can't be created without the passphrase, and nothing is written
+        let (status, _) = import_connections(&target, &ports, &hosts(), document.clone(), None, false, AuditActor::Cli).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let report = import_connections(&target, &ports, &hosts(), document.clone(), Some("pass"), true, AuditActor::Cli).await.unwrap();
+        assert_eq!(report.created, vec!["shop"]);
+        assert_eq!(hostname_owner(&target, "shop").await.unwrap(), None);
+
+        let report = import_connections(&target, &ports, &hosts(), document.clone(), Some("pass"), false, AuditActor::Cli).await.unwrap();
+        assert_eq!(report.created, vec!["shop"]);
+        let id = hostname_owner(&target, "shop").await.unwrap().unwrap();
+        assert_eq!(hostname_owner(&target, "old-shop").await.unwrap(), Some(id));
+        assert_eq!(verified_domain_connection(&target, "shop.example.com").await.unwrap(), Some(id));
+        let restored = export_connections(&target, None, None).await.unwrap();
+        assert_eq!(restored.connections[0].label.as_deref(), Some("Shop, \"main\""));
+        assert_eq!(restored.connections[0].tags, document.connections[0].tags);
+        assert_eq!(restored.connections[0].created_at, document.connections[0].created_at);
+        // The tunnel stays protected, and links handed out before still verify
+        assert_eq!(restored.connections[0].credentials, document.connections[0].credentials);
+        let (link_secret,): (Option<String>,) =
+            sqlx::query_as("SELECT link_secret FROM connections WHERE id = ?").bind(id).fetch_one(&target).await.unwrap();
+        assert_eq!(link_secret.as_deref(), Some("link-key"));
+
+        // Without the link secret the links can't be restored, so nothing is
+        let (status, message) =
+            import_connections(&target, &ports, &hosts(), without_secrets, None, false, AuditActor::Cli).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(message.contains("has signed links"), "{}", message);
+
+        // Importing again updates in place
+        let report = import_connections(&target, &ports, &hosts(), document, Some("pass"), false, AuditActor::Cli).await.unwrap();
+        assert_eq!(report.updated, vec!["shop"]);
+        assert!(report.replaced_tunnels.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_conflicts() {
+        let pool = test_pool().await;
+        seed(&pool).await;
+        let ports = PortAllocator::new(4100..=4200);
+        let document = |entry: ExportedConnection| ExportDocument {
+            version: EXPORT_VERSION,
+            connections: vec![entry],
+            ..Default::default()
+        };
+
+        let alias_taken = ExportedConnection {
+            subdomain: "blog".to_string(),
+            connection_string: Some("other-key".to_string()),
+            hostnames: vec![ExportedHostname { hostname: "old-shop".to_string(), redirect: false }],
+            ..Default::default()
+        };
+        let (status, message) =
+            import_connections(&pool, &ports, &hosts(), document(alias_taken), None, false, AuditActor::Cli).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(message.contains("old-shop: already taken"), "{}", message);
+
+        let over_alias = ExportedConnection {
+            subdomain: "old-shop".to_string(),
+            connection_string: Some("other-key".to_string()),
+            ..Default::default()
+        };
+        assert!(import_connections(&pool, &ports, &hosts(), document(over_alias), None, false, AuditActor::Cli).await.is_err());
+        assert_eq!(hostname_owner(&pool, "blog").await.unwrap(), None);
+    }
+}
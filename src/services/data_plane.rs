@@ -0,0 +1,357 @@
+/**
+ * S8.0 Pingora Data Plane
+ * =======================
+ *
+ * Serves tunnel traffic with Pingora instead of the axum proxy in
+ * `routes::proxy`. Requests for tunnel hostnames and verified custom domains
+ * go straight to the tunnel's local port over pooled keepalive connections;
+ * Pingora streams bodies and passes upgrades (WebSockets) through. Everything
+ * else (base hosts, pending custom domains) is handed to the axum control
+ * plane, which the binary serves on a loopback port. Both get forwarding
+ * headers for the client; tunnels also get `Via`, and their responses are
+ * rewritten per the connection's `ResponseRewrite` mode. Tunnel requests
+ * go through the same checks as in the axum proxy (`admit_tunnel_request`:
+ * loop detection, IP filter, limits and access), and alias redirects through
+ * the part that applies to them (`admit_redirect`); requests handed to the
+ * control plane are filtered there.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S8.1, S8.2, S8.3, S8.4
+ */
+// S8.1 Dependencies
+use crate::models::ResponseRewrite;
+use crate::routes::proxy::{
+    admit_redirect, admit_tunnel_request, resolve_proxy_target, Admission, ProxyTarget, UpstreamTimeouts,
+};
+use crate::services::access::{without_access_cookie, ACCESS_COOKIE};
+use crate::services::forwarding::{client_ip, forwarding_headers, via_header, FORWARDING_HEADERS};
+use crate::services::limits::InFlightPermit;
+use crate::services::rewrite::{BodyRewriter, Rewriter};
+use crate::AppState;
+use async_trait::async_trait;
+use axum::body::{to_bytes, Bytes};
+use axum::http::header;
+use axum::response::Response;
+use pingora_core::server::Server;
+use pingora_core::upstreams::peer::HttpPeer;
+use pingora_core::{Error, ErrorSource, ErrorType, Result};
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::{http_proxy_service, FailToProxy, ProxyHttp, Session};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+// How long an unused keepalive connection to a tunnel stays in the pool
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// S8.2 Tunnel Proxy
+pub struct TunnelProxy {
+    app_state: AppState,
+    control_plane: SocketAddr,
+}
+
+impl TunnelProxy {
+    pub fn new(app_state: AppState, control_plane: SocketAddr) -> Self {
+        Self { app_state, control_plane }
+    }
+}
+
+// Per request: the tunnel port it goes to (`None` sends it to the control
+// plane), how the response is rewritten, the tunnel's in-flight permit,
+// released when the request is done, and whether Sando consumed the
+// Authorization header
+#[derive(Default)]
+pub struct TunnelContext {
+    port: Option<u16>,
+    strip_authorization: bool,
+    rewrite: ResponseRewrite,
+    rewriter: Option<Rewriter>,
+    body_rewriter: Option<BodyRewriter>,
+    permit: Option<InFlightPermit>,
+}
+
+#[async_trait]
+impl ProxyHttp for TunnelProxy {
+    type CTX = TunnelContext;
+
+    fn new_ctx(&self) -> Self::CTX {
+        TunnelContext::default()
+    }
+
+    // Resolves the Host, answers alias redirects and admits tunnel requests
+    // (see `admit_tunnel_request`)
+    async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
+        let request = session.req_header();
+        let host = request
+            .headers
+            .get(header::HOST)
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| request.uri.authority().map(|authority| authority.as_str()))
+            .unwrap_or_default()
+            .to_string();
+        let uri = request.uri.clone();
+
+        match resolve_proxy_target(&self.app_state, &host, &uri).await {
+            Ok(None) => Ok(false),
+            Ok(Some(ProxyTarget::Redirect { connection, location })) => {
+                let peer = session.client_addr().and_then(|address| address.as_inet()).map(|address| address.ip());
+                let headers = &session.req_header().headers;
+                let client = client_ip(headers, peer, &self.app_state.trusted_proxies);
+                let refusal = admit_redirect(&self.app_state, &connection, &host, headers, client)
+                    .map_err(|status| Error::explain(ErrorType::HTTPStatus(status.as_u16()), "redirect not admitted"))?;
+                if let Some(response) = refusal {
+                    write_response(session, response).await?;
+                    return Ok(true);
+                }
+                let mut response = ResponseHeader::build(308, Some(2))?;
+                response.insert_header(header::LOCATION, location)?;
+                response.insert_header(header::CONTENT_LENGTH, "0")?;
+                session.write_response_header(Box::new(response), true).await?;
+                Ok(true)
+            }
+            Ok(Some(ProxyTarget::Tunnel(connection))) => {
+                let peer = session.client_addr().and_then(|address| address.as_inet()).map(|address| address.ip());
+                let headers = &session.req_header().headers;
+                let client = client_ip(headers, peer, &self.app_state.trusted_proxies);
+                let admission = admit_tunnel_request(&self.app_state, &connection, &host, headers, &uri, client)
+                    .await
+                    .map_err(|status| Error::explain(ErrorType::HTTPStatus(status.as_u16()), "tunnel request not admitted"))?;
+                match admission {
+                    Admission::Proxy { permit, strip_authorization } => {
+                        ctx.permit = Some(permit);
+                        ctx.strip_authorization = strip_authorization;
+                    }
+                    Admission::Refuse(response) => {
+                        write_response(session, response).await?;
+                        return Ok(true);
+                    }
+                }
+                ctx.port = Some(connection.port as u16);
+                ctx.rewrite = connection.response_rewrite;
+                Ok(false)
+            }
+            Err(status) => Err(Error::explain(ErrorType::HTTPStatus(status.as_u16()), "no tunnel for this host")),
+        }
+    }
+
+    async fn upstream_peer(&self, _session: &mut Session, ctx: &mut Self::CTX) -> Result<Box<HttpPeer>> {
+        let mut peer = match ctx.port {
+            Some(port) => tunnel_peer(port, self.app_state.upstream_timeouts),
+            None => HttpPeer::new(self.control_plane, false, String::new()),
+        };
+        peer.options.idle_timeout = Some(POOL_IDLE_TIMEOUT);
+        Ok(Box::new(peer))
+    }
+
+    // Tunnels see the same request head as with the axum proxy; the control
+    // plane needs the original Host to route the request.
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        upstream_request: &mut RequestHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        let peer = session.client_addr().and_then(|address| address.as_inet()).map(|address| address.ip());
+        let forwarding = forwarding_headers(&upstream_request.headers, peer, &self.app_state.trusted_proxies);
+        for name in FORWARDING_HEADERS {
+            upstream_request.remove_header(&name);
+        }
+        for (name, value) in forwarding {
+            upstream_request.insert_header(name, value)?;
+        }
+
+        let Some(port) = ctx.port else {
+            return Ok(());
+        };
+        let via = via_header(&upstream_request.headers, &self.app_state.host)
+            .map_err(|status| Error::explain(ErrorType::HTTPStatus(status.as_u16()), "request loops through this proxy"))?;
+        upstream_request.insert_header(header::VIA, via)?;
+        if ctx.strip_authorization {
+            upstream_request.remove_header(&header::AUTHORIZATION);
+        }
+        let cookies: Vec<String> = upstream_request
+            .headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(str::to_string)
+            .collect();
+        if cookies.iter().any(|cookie| cookie.contains(ACCESS_COOKIE)) {
+            upstream_request.remove_header(&header::COOKIE);
+            for cookie in cookies.iter().filter_map(|cookie| without_access_cookie(cookie)) {
+                upstream_request.append_header(header::COOKIE, cookie)?;
+            }
+        }
+        // Reads the public hostname, so before Host is replaced
+        ctx.rewriter = Rewriter::for_request(ctx.rewrite, &upstream_request.headers);
+        if ctx.rewriter.as_ref().is_some_and(Rewriter::needs_identity_encoding) {
+            upstream_request.remove_header(&header::ACCEPT_ENCODING);
+        }
+        upstream_request.insert_header(header::HOST, format!("localhost:{}", port))?;
+        let original: Vec<String> = upstream_request
+            .headers
+            .keys()
+            .map(|name| name.as_str())
+            .filter(|name| name.starts_with("x-original-"))
+            .map(str::to_string)
+            .collect();
+        for name in original {
+            upstream_request.remove_header(&name);
+        }
+        Ok(())
+    }
+
+    async fn response_filter(
+        &self,
+        _session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        // Same as the axum proxy: tell a reverse proxy in front not to buffer events
+        let is_event_stream = upstream_response
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.trim_start().to_ascii_lowercase().starts_with("text/event-stream"));
+        if ctx.port.is_some() && is_event_stream {
+            upstream_response.insert_header("x-accel-buffering", "no")?;
+        }
+
+        let Some(rewriter) = &ctx.rewriter else {
+            return Ok(());
+        };
+        let names: Vec<_> = upstream_response.headers.keys().cloned().collect();
+        for name in names {
+            let values: Vec<String> = upstream_response
+                .headers
+                .get_all(&name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .map(str::to_string)
+                .collect();
+            let rewritten: Vec<Option<String>> =
+                values.iter().map(|value| rewriter.rewrite_header(name.as_str(), value)).collect();
+            if rewritten.iter().any(Option::is_some) {
+                upstream_response.remove_header(&name);
+                for (value, rewritten) in values.into_iter().zip(rewritten) {
+                    upstream_response.append_header(name.clone(), rewritten.unwrap_or(value))?;
+                }
+            }
+        }
+        let content_type = upstream_response.headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok());
+        let content_encoding = upstream_response.headers.get(header::CONTENT_ENCODING).and_then(|value| value.to_str().ok());
+        if rewriter.rewrites_body(content_type, content_encoding) {
+            upstream_response.remove_header(&header::CONTENT_LENGTH);
+            upstream_response.insert_header(header::TRANSFER_ENCODING, "chunked")?;
+            ctx.body_rewriter = Some(rewriter.body_rewriter());
+        }
+        Ok(())
+    }
+
+    fn response_body_filter(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<Option<Duration>> {
+        let Some(rewriter) = ctx.body_rewriter.as_mut() else {
+            return Ok(None);
+        };
+        let mut output = body.as_deref().map(|chunk| rewriter.push(chunk).to_vec()).unwrap_or_default();
+        if end_of_stream {
+            output.extend_from_slice(&rewriter.finish());
+        }
+        *body = Some(Bytes::from(output));
+        Ok(None)
+    }
+
+    // Pingora's default error responses, except that a tunnel that doesn't
+    // answer in time gets 504 Gateway Timeout, as with the axum proxy
+    async fn fail_to_proxy(&self, session: &mut Session, e: &Error, ctx: &mut Self::CTX) -> FailToProxy {
+        let code = match (e.etype(), e.esource()) {
+            (ErrorType::HTTPStatus(code), _) => *code,
+            (ErrorType::ReadTimedout, ErrorSource::Upstream) if ctx.port.is_some() => {
+                tracing::warn!("Tunnel read timed out after {:?}", self.app_state.upstream_timeouts.first_byte);
+                504
+            }
+            (_, ErrorSource::Upstream) => 502,
+            // The client is gone; nothing to answer
+            (ErrorType::WriteError | ErrorType::ReadError | ErrorType::ConnectionClosed, ErrorSource::Downstream) => 0,
+            (_, ErrorSource::Downstream) => 400,
+            (_, ErrorSource::Internal | ErrorSource::Unset) => 500,
+        };
+        if code > 0 {
+            if let Err(e) = session.respond_error(code).await {
+                tracing::warn!("Failed to send error response: {}", e);
+            }
+        }
+        FailToProxy { error_code: code, can_reuse_downstream: false }
+    }
+}
+
+// Writes a response built for the axum proxy, e.g. a refusal from
+// `admit_tunnel_request`, to the client
+async fn write_response(session: &mut Session, response: Response) -> Result<()> {
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|_| Error::explain(ErrorType::HTTPStatus(500), "unreadable response body"))?;
+    let mut header = ResponseHeader::build(parts.status.as_u16(), Some(parts.headers.len() + 1))?;
+    for (name, value) in &parts.headers {
+        header.append_header(name.clone(), value.clone())?;
+    }
+    header.insert_header(header::CONTENT_LENGTH, body.len().to_string())?;
+    session.write_response_header(Box::new(header), body.is_empty()).await?;
+    if !body.is_empty() {
+        session.write_response_body(Some(body), true).await?;
+    }
+    Ok(())
+}
+
+// Pingora applies one read timeout to the response head and every body
+// chunk, and it can't be changed once the request is sent. Reads use the
+// first-byte timeout, so a tunnel that never answers gets a 504 in time and
+// a head that arrives in time is always served; a response body may then
+// only pause that long between chunks, too.
+fn tunnel_peer(port: u16, timeouts: UpstreamTimeouts) -> HttpPeer {
+    let mut peer = HttpPeer::new(("127.0.0.1", port), false, String::new());
+    peer.options.connection_timeout = Some(timeouts.connect);
+    peer.options.read_timeout = Some(timeouts.first_byte);
+    peer.options.write_timeout = Some(timeouts.idle);
+    peer
+}
+
+// S8.3 Server
+// Serves `listen` (e.g. "0.0.0.0:3000") with Pingora, passing control plane
+// requests to `control_plane`. Pingora runs its own threads and runtimes and
+// exits the process on shutdown, so call this from a dedicated thread, not
+// from inside a tokio runtime.
+pub fn run_data_plane(app_state: AppState, listen: &str, control_plane: SocketAddr) -> ! {
+    let mut server = Server::new(None).expect("Failed to configure the Pingora server");
+    server.bootstrap();
+
+    let mut service = http_proxy_service(&server.configuration, TunnelProxy::new(app_state, control_plane));
+    service.add_tcp(listen);
+    server.add_service(service);
+    server.run_forever()
+}
+
+// S8.4 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tunnel_peers_use_the_upstream_timeouts() {
+        let timeouts = UpstreamTimeouts {
+            connect: Duration::from_secs(1),
+            first_byte: Duration::from_secs(2),
+            idle: Duration::from_secs(3),
+        };
+        let peer = tunnel_peer(4100, timeouts);
+        assert_eq!(peer.options.connection_timeout, Some(timeouts.connect));
+        // The head is cut off at the first-byte timeout, not after the idle one
+        assert_eq!(peer.options.read_timeout, Some(timeouts.first_byte));
+        assert_eq!(peer.options.write_timeout, Some(timeouts.idle));
+    }
+}
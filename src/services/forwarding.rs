@@ -0,0 +1,246 @@
+/**
+ * S9.0 Forwarding Headers
+ * =======================
+ *
+ * Builds the X-Forwarded-For/-Proto/-Host, RFC 7239 `Forwarded` and `Via`
+ * headers sent to tunnels. Incoming forwarding headers are only kept when the
+ * peer is a trusted proxy (e.g. a load balancer in front of Sando); anyone
+ * else could use them to spoof their address. `Via` carries the primary base
+ * host, so a request that already passed through this Sando is a loop.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S9.1, S9.2, S9.3, S9.4, S9.5
+ */
+// S9.1 Dependencies
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+// S9.2 Trusted Proxies
+// Parses a comma separated list of addresses and CIDR ranges, e.g.
+// "10.0.0.0/8, 192.168.1.1"; a bare address trusts just that address.
+pub fn parse_trusted_proxies(value: &str) -> Result<Vec<IpNet>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("{} is not an address or CIDR range", entry))
+        })
+        .collect()
+}
+
+pub fn is_trusted(trusted_proxies: &[IpNet], ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+    trusted_proxies.iter().any(|net| net.contains(&ip))
+}
+
+// The address a request came from. Behind trusted proxies this walks
+// X-Forwarded-For from the right and takes the first untrusted hop.
+pub fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>, trusted_proxies: &[IpNet]) -> Option<IpAddr> {
+    let peer = peer?.to_canonical();
+    if !is_trusted(trusted_proxies, peer) {
+        return Some(peer);
+    }
+    forwarded_client_ip(headers, trusted_proxies).or(Some(peer))
+}
+
+// The client address in an X-Forwarded-For chain: the first untrusted hop
+// from the right. After `add_forwarding_headers` the chain ends with the
+// peer, so this gives the same answer as `client_ip`.
+pub fn forwarded_client_ip(headers: &HeaderMap, trusted_proxies: &[IpNet]) -> Option<IpAddr> {
+    let hops: Vec<IpAddr> = header_list(headers, &X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|hop| hop.parse::<IpAddr>().ok())
+        .map(|hop| hop.to_canonical())
+        .collect();
+    hops.iter()
+        .rev()
+        .find(|hop| !is_trusted(trusted_proxies, **hop))
+        .or(hops.first())
+        .copied()
+}
+
+// S9.3 Forwarded Headers
+// X-Forwarded-For, X-Forwarded-Proto, X-Forwarded-Host and Forwarded for a
+// request Sando received over plain HTTP from `peer`. Callers replace any
+// incoming headers of the same names with these.
+pub fn forwarding_headers(
+    headers: &HeaderMap,
+    peer: Option<IpAddr>,
+    trusted_proxies: &[IpNet],
+) -> Vec<(HeaderName, HeaderValue)> {
+    let peer = peer.map(|ip| ip.to_canonical());
+    let trusted = peer.is_some_and(|ip| is_trusted(trusted_proxies, ip));
+    let host = headers.get(header::HOST).and_then(|value| value.to_str().ok());
+    // What the client originally asked for, as reported by a trusted proxy
+    let reported = |name: &HeaderName| Some(header_list(headers, name)).filter(|_| trusted).and_then(|values| values.into_iter().next());
+
+    let mut forwarded_for = if trusted { header_list(headers, &X_FORWARDED_FOR) } else { Vec::new() };
+    forwarded_for.extend(peer.map(|ip| ip.to_string()));
+    let proto = reported(&X_FORWARDED_PROTO).unwrap_or_else(|| "http".to_string());
+    let forwarded_host = reported(&X_FORWARDED_HOST).or_else(|| host.map(str::to_string));
+
+    // RFC 7239: this hop's element describes the request as Sando received it
+    let mut forwarded = if trusted { header_list(headers, &header::FORWARDED) } else { Vec::new() };
+    let mut element = format!("for={}", forwarded_node(peer));
+    if let Some(host) = host {
+        element.push_str(&format!(";host=\"{}\"", host.replace(['"', '\\'], "")));
+    }
+    element.push_str(";proto=http");
+    forwarded.push(element);
+
+    let mut result = Vec::new();
+    if !forwarded_for.is_empty() {
+        result.push((X_FORWARDED_FOR, forwarded_for.join(", ")));
+    }
+    result.push((X_FORWARDED_PROTO, proto));
+    if let Some(forwarded_host) = forwarded_host {
+        result.push((X_FORWARDED_HOST, forwarded_host));
+    }
+    result.push((header::FORWARDED, forwarded.join(", ")));
+    result
+        .into_iter()
+        .filter_map(|(name, value)| HeaderValue::from_str(&value).ok().map(|value| (name, value)))
+        .collect()
+}
+
+// Every header name `forwarding_headers` may set, so callers can drop
+// incoming copies even when a value is left out
+pub const FORWARDING_HEADERS: [HeaderName; 4] = [X_FORWARDED_FOR, X_FORWARDED_PROTO, X_FORWARDED_HOST, header::FORWARDED];
+
+fn forwarded_node(peer: Option<IpAddr>) -> String {
+    match peer {
+        Some(IpAddr::V4(ip)) => ip.to_string(),
+        Some(IpAddr::V6(ip)) => format!("\"[{}]\"", ip),
+        None => "unknown".to_string(),
+    }
+}
+
+// S9.4 Via and Loop Detection
+// The Via header to send on, with this Sando (named by its primary base host)
+// appended. Fails with 508 Loop Detected if the request already went through
+// it, e.g. because a tunnel points back at Sando.
+pub fn via_header(headers: &HeaderMap, name: &str) -> Result<HeaderValue, StatusCode> {
+    let mut hops = header_list(headers, &header::VIA);
+    let looped = hops.iter().any(|hop| {
+        let received_by = hop.split_whitespace().nth(1).unwrap_or_default();
+        received_by.eq_ignore_ascii_case(name)
+    });
+    if looped {
+        tracing::warn!("Request already passed through {}; refusing to proxy it again", name);
+        return Err(StatusCode::LOOP_DETECTED);
+    }
+    hops.push(format!("1.1 {}", name));
+    HeaderValue::from_str(&hops.join(", ")).map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+// Replaces the forwarding and Via headers in `headers` before they are sent
+// to a tunnel
+pub fn add_forwarding_headers(
+    headers: &mut HeaderMap,
+    peer: Option<IpAddr>,
+    trusted_proxies: &[IpNet],
+    via_name: &str,
+) -> Result<(), StatusCode> {
+    let via = via_header(headers, via_name)?;
+    let forwarding = forwarding_headers(headers, peer, trusted_proxies);
+    for name in FORWARDING_HEADERS {
+        headers.remove(name);
+    }
+    for (name, value) in forwarding {
+        headers.insert(name, value);
+    }
+    headers.insert(header::VIA, via);
+    Ok(())
+}
+
+// Comma separated values across every copy of a header
+fn header_list(headers: &HeaderMap, name: &HeaderName) -> Vec<String> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// S9.5 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(HeaderName::from_bytes(name.as_bytes()).unwrap(), value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_untrusted_peers_cannot_spoof_forwarding_headers() {
+        let trusted = parse_trusted_proxies("10.0.0.0/8").unwrap();
+        let mut request = headers(&[
+            ("host", "app.localhost:3000"),
+            ("x-forwarded-for", "1.1.1.1"),
+            ("x-forwarded-proto", "https"),
+            ("forwarded", "for=1.1.1.1"),
+        ]);
+        let peer = Some("203.0.113.7".parse().unwrap());
+
+        assert_eq!(client_ip(&request, peer, &trusted), peer);
+        add_forwarding_headers(&mut request, peer, &trusted, "sando.blue").unwrap();
+        assert_eq!(request["x-forwarded-for"], "203.0.113.7");
+        assert_eq!(request["x-forwarded-proto"], "http");
+        assert_eq!(request["x-forwarded-host"], "app.localhost:3000");
+        assert_eq!(request["forwarded"], "for=203.0.113.7;host=\"app.localhost:3000\";proto=http");
+        assert_eq!(request["via"], "1.1 sando.blue");
+    }
+
+    #[test]
+    fn test_trusted_proxies_extend_the_chain() {
+        let trusted = parse_trusted_proxies("10.0.0.0/8, ::1").unwrap();
+        let mut request = headers(&[
+            ("host", "app.sando.blue"),
+            ("x-forwarded-for", "198.51.100.2, 10.0.0.3"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "app.sando.blue"),
+            ("forwarded", "for=198.51.100.2;proto=https"),
+            ("via", "1.1 lb"),
+        ]);
+        let peer = Some("::1".parse().unwrap());
+
+        assert_eq!(client_ip(&request, peer, &trusted), Some("198.51.100.2".parse().unwrap()));
+        add_forwarding_headers(&mut request, peer, &trusted, "sando.blue").unwrap();
+        assert_eq!(forwarded_client_ip(&request, &trusted), Some("198.51.100.2".parse().unwrap()));
+        assert_eq!(request["x-forwarded-for"], "198.51.100.2, 10.0.0.3, ::1");
+        assert_eq!(request["x-forwarded-proto"], "https");
+        assert_eq!(
+            request["forwarded"],
+            "for=198.51.100.2;proto=https, for=\"[::1]\";host=\"app.sando.blue\";proto=http"
+        );
+        assert_eq!(request["via"], "1.1 lb, 1.1 sando.blue");
+
+        // IPv4-mapped peers match IPv4 ranges
+        assert!(is_trusted(&trusted, "::ffff:10.1.2.3".parse().unwrap()));
+        assert!(parse_trusted_proxies("10.0.0.0/8, example.com").is_err());
+    }
+
+    #[test]
+    fn test_via_loop_detection() {
+        let request = headers(&[("via", "1.1 lb, 1.1 Sando.Blue")]);
+        assert_eq!(via_header(&request, "sando.blue"), Err(StatusCode::LOOP_DETECTED));
+        assert_eq!(via_header(&request, "other.example").unwrap(), "1.1 lb, 1.1 Sando.Blue, 1.1 other.example");
+    }
+}
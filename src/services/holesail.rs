@@ -0,0 +1,169 @@
+/**
+ * S6.0 Holesail Keys
+ * ==================
+ *
+ * Parses the connection strings users submit, so keys that can never work
+ * are refused before anyone is asked to pay, and optionally checks that the
+ * peer behind a key can be found at all.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S6.1, S6.2, S6.3, S6.4, S6.5
+ */
+// S6.1 Dependencies
+use axum::http::StatusCode;
+use std::fmt;
+use std::time::Duration;
+use tokio::process::Command;
+
+// S6.2 Key Types
+// Keys are 32 bytes, written as hex.
+pub const KEY_HEX_LENGTH: usize = 64;
+const URL_SCHEME: &str = "hs://";
+// `hs://` URLs carry four flag characters before the key; the first one
+// tells private (`s`, holesail's secure mode) from public (`0`) keys.
+const PRIVATE_FLAGS: &str = "s000";
+const PUBLIC_FLAGS: &str = "0000";
+// The same formats as an HTML `pattern`, for early feedback in forms
+pub const KEY_PATTERN: &str = r"\s*([0-9a-fA-F]{64}|[hH][sS]://[sS0]000[0-9a-fA-F]{64}/?)\s*";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyVisibility {
+    // The key is a secret seed; only people given it can connect
+    Private,
+    // The server's public key (`holesail --public`); anyone can look it up
+    Public,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HolesailKey {
+    // Lowercase hex
+    pub key: String,
+    pub visibility: KeyVisibility,
+    // Whether it was given as an `hs://` URL; kept so the holesail CLI gets
+    // the key back in the form the user copied from it
+    pub is_url: bool,
+}
+
+// S6.3 Parser
+// Accepts a raw 64-hex key (holesail's default, private mode) or an
+// `hs://s000<key>` / `hs://0000<key>` URL, ignoring case and surrounding
+// whitespace.
+impl HolesailKey {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim().to_ascii_lowercase();
+        if input.is_empty() {
+            return Err("A holesail key is required".to_string());
+        }
+
+        let (key, visibility, is_url) = match input.strip_prefix(URL_SCHEME) {
+            Some(rest) => {
+                let rest = rest.trim_end_matches('/');
+                let flags = rest.get(..PRIVATE_FLAGS.len()).unwrap_or(rest);
+                let key = &rest[flags.len()..];
+                let visibility = match flags {
+                    PRIVATE_FLAGS => KeyVisibility::Private,
+                    PUBLIC_FLAGS => KeyVisibility::Public,
+                    _ => return Err(format!("Unsupported holesail URL flags '{}'; expected hs://s000<key> or hs://0000<key>", flags)),
+                };
+                (key.to_string(), visibility, true)
+            }
+            None if input.contains("://") => {
+                return Err("Holesail URLs must start with hs://".to_string());
+            }
+            None => (input, KeyVisibility::Private, false),
+        };
+
+        if key.len() != KEY_HEX_LENGTH {
+            return Err(format!(
+                "A holesail key has {} hex characters, this one has {}",
+                KEY_HEX_LENGTH,
+                key.len()
+            ));
+        }
+        if !key.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("A holesail key may only contain hex characters (0-9, a-f)".to_string());
+        }
+
+        Ok(Self { key, visibility, is_url })
+    }
+}
+
+// The normalized connection string that is stored and passed to holesail.
+impl fmt::Display for HolesailKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.is_url {
+            return f.write_str(&self.key);
+        }
+        let flags = match self.visibility {
+            KeyVisibility::Private => PRIVATE_FLAGS,
+            KeyVisibility::Public => PUBLIC_FLAGS,
+        };
+        write!(f, "{}{}{}", URL_SCHEME, flags, self.key)
+    }
+}
+
+// S6.4 Reachability Probe
+// Asks holesail to look the key up on the DHT without opening a tunnel.
+// BAD_GATEWAY when no peer answers within `timeout`, SERVICE_UNAVAILABLE
+// when holesail can't be run at all.
+pub async fn lookup_key(connection_string: &str, timeout: Duration) -> Result<(), StatusCode> {
+    let lookup = Command::new("holesail")
+        .arg("--lookup")
+        .arg(connection_string)
+        .kill_on_drop(true)
+        .output();
+
+    match tokio::time::timeout(timeout, lookup).await {
+        Ok(Ok(output)) if output.status.success() => Ok(()),
+        Ok(Ok(output)) => {
+            tracing::info!("holesail lookup failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            Err(StatusCode::BAD_GATEWAY)
+        }
+        Ok(Err(e)) => {
+            tracing::error!("❌ Failed to run holesail lookup: {}", e);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+        Err(_) => Err(StatusCode::BAD_GATEWAY),
+    }
+}
+
+// S6.5 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "5f2a9c1e0b7d4e3f8a6b2c9d1e0f7a3b4c5d6e7f8091a2b3c4d5e6f708192a3b";
+
+    #[test]
+    fn test_parse_accepted_formats() {
+        let raw = HolesailKey::parse(&format!("  {}\n", KEY.to_uppercase())).unwrap();
+        assert_eq!(raw.visibility, KeyVisibility::Private);
+        assert_eq!(raw.to_string(), KEY);
+
+        let private = HolesailKey::parse(&format!("hs://s000{}", KEY)).unwrap();
+        assert_eq!((private.visibility, private.key.as_str()), (KeyVisibility::Private, KEY));
+        assert_eq!(private.to_string(), format!("hs://s000{}", KEY));
+
+        let public = HolesailKey::parse(&format!("HS://0000{}/", KEY)).unwrap();
+        assert_eq!(public.visibility, KeyVisibility::Public);
+        assert_eq!(public.to_string(), format!("hs://0000{}", KEY));
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_keys() {
+        for input in [
+            "",
+            "abcdef123456",
+            "my-app",
+            &KEY[1..],
+            &format!("{}0", KEY),
+            &format!("{}g", &KEY[1..]),
+            &format!("hs://x000{}", KEY),
+            &format!("hs://s000{}", &KEY[2..]),
+            &format!("https://{}", KEY),
+            "hs://",
+        ] {
+            assert!(HolesailKey::parse(input).is_err(), "{:?}", input);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+/**
+ * S14.0 IP Filter
+ * ===============
+ *
+ * Decides which client addresses may reach Sando and its tunnels. The
+ * server's global blocklist turns an address away from everything, control
+ * plane included; a connection's own `IpRules` deny listed ranges and, when
+ * they allow any, let only those through (e.g. office or VPN ranges). The
+ * client is the socket peer, or the forwarded address when the peer is a
+ * trusted proxy (see `services::forwarding`). A request with no known
+ * address passes the blocklist but not an allow list. Violations are logged
+ * and counted; the counts are served at /status/ip-filter.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S14.1, S14.2, S14.3, S14.4, S14.5
+ */
+// S14.1 Dependencies
+use crate::models::{parse_ip_net, IpRules};
+use crate::Connection;
+use axum::body::Body;
+use axum::http::StatusCode;
+use axum::response::Response;
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// S14.2 Rules
+// Why a client was turned away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    // The address is on the server's global blocklist
+    Blocklisted,
+    // The address is in one of the connection's deny ranges
+    Denied,
+    // The connection has allow ranges and the address (if known) isn't in any
+    NotAllowed,
+}
+
+impl Violation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Blocklisted => "blocklisted",
+            Self::Denied => "denied",
+            Self::NotAllowed => "not_allowed",
+        }
+    }
+}
+
+// A comma separated list of addresses and CIDR ranges, e.g. the
+// SANDO_IP_BLOCKLIST value "198.51.100.0/24, 203.0.113.7"
+pub fn parse_ip_list(value: &str) -> Result<Vec<IpNet>, String> {
+    value.split(',').map(str::trim).filter(|entry| !entry.is_empty()).map(parse_ip_net).collect()
+}
+
+fn contains(nets: &[IpNet], ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+    nets.iter().any(|net| net.contains(&ip))
+}
+
+// Deny ranges win over allow ranges
+pub fn check_rules(rules: &IpRules, client: Option<IpAddr>) -> Result<(), Violation> {
+    if client.is_some_and(|ip| contains(&rules.deny, ip)) {
+        return Err(Violation::Denied);
+    }
+    if !rules.allow.is_empty() && !client.is_some_and(|ip| contains(&rules.allow, ip)) {
+        return Err(Violation::NotAllowed);
+    }
+    Ok(())
+}
+
+// S14.3 Filter
+// The global blocklist and the violation counters since startup
+#[derive(Default)]
+pub struct IpFilter {
+    blocklist: Vec<IpNet>,
+    blocklisted: AtomicU64,
+    // Per connection: (denied, not allowed)
+    violations: Mutex<HashMap<i64, (u64, u64)>>,
+}
+
+impl IpFilter {
+    pub fn new(blocklist: Vec<IpNet>) -> Self {
+        Self { blocklist, ..Default::default() }
+    }
+
+    // Checks `client` against the global blocklist, logging and counting a hit
+    pub fn check_blocklist(&self, client: Option<IpAddr>) -> Result<(), Violation> {
+        match client.filter(|ip| contains(&self.blocklist, *ip)) {
+            Some(ip) => {
+                self.blocklisted.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!("Blocked request from {}: on the global blocklist", ip);
+                Err(Violation::Blocklisted)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Checks `client` against the connection's own rules, logging and
+    // counting a violation against the connection
+    pub fn check_connection(&self, connection: &Connection, client: Option<IpAddr>) -> Result<(), Violation> {
+        let violation = match check_rules(&connection.ip_rules, client) {
+            Ok(()) => return Ok(()),
+            Err(violation) => violation,
+        };
+        {
+            let mut violations = self.violations.lock().unwrap();
+            let counts = violations.entry(connection.id).or_default();
+            match violation {
+                Violation::NotAllowed => counts.1 += 1,
+                _ => counts.0 += 1,
+            }
+        }
+        let client = client.map_or_else(|| "unknown client".to_string(), |ip| ip.to_string());
+        let subdomain = connection.subdomain.as_deref().unwrap_or_default();
+        tracing::warn!("Blocked request from {} to connection {} ({}): {}", client, connection.id, subdomain, violation.as_str());
+        Err(violation)
+    }
+
+    // Counts for /status/ip-filter
+    pub fn status(&self) -> serde_json::Value {
+        let violations = self.violations.lock().unwrap();
+        let mut connections: Vec<_> = violations.iter().collect();
+        connections.sort_by_key(|(id, _)| **id);
+        serde_json::json!({
+            "blocklist_ranges": self.blocklist.len(),
+            "blocklisted": self.blocklisted.load(Ordering::Relaxed),
+            "denied": violations.values().map(|counts| counts.0).sum::<u64>(),
+            "not_allowed": violations.values().map(|counts| counts.1).sum::<u64>(),
+            "connections": connections
+                .into_iter()
+                .map(|(id, (denied, not_allowed))| serde_json::json!({
+                    "id": id,
+                    "denied": denied,
+                    "not_allowed": not_allowed,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+// S14.4 Responses
+pub fn forbidden() -> Response {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(Body::from("Your address is not allowed to reach this tunnel"))
+        .unwrap()
+}
+
+// S14.5 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn test_rules_deny_before_allow_and_fail_closed() {
+        let rules = IpRules::parse_lines("allow=10.0.0.0/8\nallow=2001:db8::/32\ndeny=10.6.6.0/24").unwrap();
+        assert_eq!(check_rules(&rules, ip("10.1.2.3")), Ok(()));
+        assert_eq!(check_rules(&rules, ip("2001:db8::1")), Ok(()));
+        assert_eq!(check_rules(&rules, ip("10.6.6.7")), Err(Violation::Denied));
+        assert_eq!(check_rules(&rules, ip("192.0.2.1")), Err(Violation::NotAllowed));
+        assert_eq!(check_rules(&rules, None), Err(Violation::NotAllowed));
+        // IPv4-mapped IPv6 peers match IPv4 ranges
+        assert_eq!(check_rules(&rules, ip("::ffff:10.1.2.3")), Ok(()));
+
+        let deny_only = IpRules::parse_lines("deny=203.0.113.7").unwrap();
+        assert_eq!(check_rules(&deny_only, ip("203.0.113.7")), Err(Violation::Denied));
+        assert_eq!(check_rules(&deny_only, ip("203.0.113.8")), Ok(()));
+        assert_eq!(check_rules(&deny_only, None), Ok(()));
+        assert_eq!(check_rules(&IpRules::default(), None), Ok(()));
+    }
+
+    #[test]
+    fn test_filter_counts_violations() {
+        let filter = IpFilter::new(vec!["198.51.100.0/24".parse().unwrap()]);
+        assert_eq!(filter.check_blocklist(ip("198.51.100.9")), Err(Violation::Blocklisted));
+        assert_eq!(filter.check_blocklist(ip("192.0.2.1")), Ok(()));
+        assert_eq!(filter.check_blocklist(None), Ok(()));
+
+        let connection = Connection {
+            id: 7,
+            ip_rules: IpRules::parse_lines("allow=10.0.0.0/8\ndeny=10.6.6.6").unwrap(),
+            ..Default::default()
+        };
+        assert!(filter.check_connection(&connection, ip("10.6.6.6")).is_err());
+        assert!(filter.check_connection(&connection, ip("192.0.2.1")).is_err());
+        assert!(filter.check_connection(&connection, None).is_err());
+        assert!(filter.check_connection(&connection, ip("10.0.0.1")).is_ok());
+
+        let status = filter.status();
+        assert_eq!(status["blocklisted"], 1);
+        assert_eq!(status["denied"], 1);
+        assert_eq!(status["not_allowed"], 2);
+        assert_eq!(status["connections"][0]["id"], 7);
+    }
+
+    #[test]
+    fn test_parse_ip_list() {
+        let nets = parse_ip_list("198.51.100.7/24, 203.0.113.7,, ::ffff:192.0.2.1").unwrap();
+        assert_eq!(
+            nets,
+            vec![
+                "198.51.100.0/24".parse::<IpNet>().unwrap(),
+                "203.0.113.7/32".parse().unwrap(),
+                "192.0.2.1/32".parse().unwrap(),
+            ]
+        );
+        assert_eq!(parse_ip_list(" ").unwrap(), vec![]);
+        assert_eq!(parse_ip_list("10.0.0.0/8, example.com").unwrap_err(), "'example.com' is not an address or CIDR range");
+    }
+
+    #[test]
+    fn test_parse_ip_rules() {
+        let rules = IpRules::parse_lines("allow=10.1.2.3/8, deny = 203.0.113.7\nallow=::1").unwrap();
+        assert_eq!(rules.allow, vec!["10.0.0.0/8".parse::<IpNet>().unwrap(), "::1/128".parse().unwrap()]);
+        assert_eq!(rules.deny, vec!["203.0.113.7/32".parse::<IpNet>().unwrap()]);
+        assert_eq!(IpRules::parse_lines(&rules.to_lines()).unwrap(), rules);
+        assert_eq!(serde_json::from_str::<IpRules>(&rules.to_json()).unwrap(), rules);
+        assert!(IpRules::parse_lines("allow=10.0.0.0/33").is_err());
+        assert!(IpRules::parse_lines("permit=10.0.0.0/8").is_err());
+        assert!(IpRules::parse_lines("10.0.0.0/8").is_err());
+        assert!(serde_json::from_str::<IpRules>(r#"{"allow": ["nope"]}"#).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+/**
+ * S11.0 Tunnel Limits
+ * ===================
+ *
+ * Keeps one visitor from saturating a holesail peer (and Sando's file
+ * descriptors): token buckets per tunnel and per client address, and a cap
+ * on requests in flight per tunnel. A connection's limits come from its tier
+ * (or the default limits) tightened by the owner's own `TunnelLimits`.
+ * Failed sign-ins to protected tunnels are limited per client the same way.
+ * Rejected requests get a 429 with `Retry-After`.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S11.1, S11.2, S11.3, S11.4, S11.5
+ */
+// S11.1 Dependencies
+use crate::models::TunnelLimits;
+use crate::{AppState, Connection};
+use axum::body::Body;
+use axum::http::{header, StatusCode};
+use axum::response::Response;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Idle buckets are dropped once there are this many
+const MAX_BUCKETS: usize = 10_000;
+
+// Failed sign-ins to a protected tunnel, per client: a burst of ten, then
+// one more every six seconds
+const SIGN_IN_RATE: f64 = 1.0 / 6.0;
+const SIGN_IN_BURST: f64 = 10.0;
+
+// S11.2 Effective Limits
+// The connection's tier, or the default limits when it has none (or names a
+// tier that is no longer configured), tightened by its own limits
+pub fn effective_limits(app_state: &AppState, connection: &Connection) -> TunnelLimits {
+    let base = match connection.tier.as_deref() {
+        Some(tier) => app_state.tiers.get(tier).unwrap_or_else(|| {
+            tracing::warn!("Connection {} has unknown tier '{}'; using the default limits", connection.id, tier);
+            &app_state.tunnel_limits
+        }),
+        None => &app_state.tunnel_limits,
+    };
+    base.tighten(&connection.limits)
+}
+
+// S11.3 Rate Limiter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum BucketKey {
+    Tunnel(i64),
+    Client(i64, IpAddr),
+    // A class of control-plane routes, per client (see `services::antispam`)
+    ControlPlane(&'static str, IpAddr),
+    // Failed sign-ins to a tunnel, per client (see `services::access`)
+    SignIn(i64, Option<IpAddr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    full_at: Instant, // When the bucket will have refilled completely
+}
+
+#[derive(Default)]
+struct LimiterState {
+    buckets: HashMap<BucketKey, Bucket>,
+    in_flight: HashMap<i64, u32>,
+}
+
+impl LimiterState {
+    // The tokens in a bucket at `now`; missing buckets are full
+    fn available(&self, now: Instant, key: BucketKey, rate: f64, burst: f64) -> f64 {
+        let bucket = self.buckets.get(&key).copied().unwrap_or(Bucket { tokens: burst, updated: now, full_at: now });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * rate).min(burst)
+    }
+
+    // Takes one token from each `(key, rate, burst)` bucket, or none if any
+    // of them is empty
+    fn take_tokens(&mut self, now: Instant, buckets: &[(BucketKey, f64, f64)]) -> Result<(), Limited> {
+        let mut refilled = Vec::with_capacity(buckets.len());
+        let mut wait = Duration::ZERO;
+        for &(key, rate, burst) in buckets {
+            let tokens = self.available(now, key, rate, burst);
+            if tokens < 1.0 {
+                wait = wait.max(Duration::from_secs_f64((1.0 - tokens) / rate));
+            }
+            refilled.push((key, rate, burst, tokens));
+        }
+        if !wait.is_zero() {
+            return Err(Limited::Rate(wait));
+        }
+
+        for (key, rate, burst, tokens) in refilled {
+            let tokens = tokens - 1.0;
+            let full_at = now + Duration::from_secs_f64((burst - tokens) / rate);
+            self.buckets.insert(key, Bucket { tokens, updated: now, full_at });
+        }
+        if self.buckets.len() > MAX_BUCKETS {
+            self.buckets.retain(|_, bucket| bucket.full_at > now);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    state: Mutex<LimiterState>,
+}
+
+// Why a request was turned away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limited {
+    // A token bucket is empty; a token is back after this long
+    Rate(Duration),
+    // The tunnel already has `max_in_flight` requests open
+    InFlight,
+}
+
+impl Limited {
+    pub fn retry_after(self) -> Duration {
+        match self {
+            Self::Rate(wait) => wait,
+            Self::InFlight => Duration::from_secs(1),
+        }
+    }
+}
+
+impl RateLimiter {
+    // Takes a token from the tunnel's and the client's bucket and counts the
+    // request as in flight until the permit is dropped. Nothing is taken when
+    // the request is rejected.
+    pub fn acquire(
+        self: &Arc<Self>,
+        connection_id: i64,
+        client: Option<IpAddr>,
+        limits: &TunnelLimits,
+    ) -> Result<InFlightPermit, Limited> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        let in_flight = state.in_flight.get(&connection_id).copied().unwrap_or(0);
+        if limits.max_in_flight.is_some_and(|max| in_flight >= max) {
+            return Err(Limited::InFlight);
+        }
+
+        let mut buckets = vec![(BucketKey::Tunnel(connection_id), limits.rate, limits.burst)];
+        if let Some(client) = client {
+            buckets.push((BucketKey::Client(connection_id, client), limits.client_rate, limits.client_burst));
+        }
+        let buckets: Vec<(BucketKey, f64, f64)> = buckets
+            .into_iter()
+            .filter_map(|(key, rate, burst)| {
+                let rate = rate?;
+                // Without a burst, allow about one second's worth
+                let burst = burst.map_or(rate.ceil().max(1.0), f64::from);
+                Some((key, rate, burst))
+            })
+            .collect();
+
+        state.take_tokens(now, &buckets)?;
+        *state.in_flight.entry(connection_id).or_insert(0) += 1;
+
+        Ok(InFlightPermit { limiter: self.clone(), connection_id })
+    }
+
+    // Takes a token from `client`'s bucket for a class of control-plane routes
+    pub fn throttle(&self, class: &'static str, client: IpAddr, rate: f64, burst: u32) -> Result<(), Limited> {
+        let mut state = self.state.lock().unwrap();
+        state.take_tokens(Instant::now(), &[(BucketKey::ControlPlane(class, client), rate, f64::from(burst.max(1)))])
+    }
+
+    // Whether `client` may try to sign in to tunnel `connection_id` again.
+    // Only failures use up attempts (see `record_failed_sign_in`).
+    pub fn check_sign_in(&self, connection_id: i64, client: Option<IpAddr>) -> Result<(), Limited> {
+        let state = self.state.lock().unwrap();
+        let tokens = state.available(Instant::now(), BucketKey::SignIn(connection_id, client), SIGN_IN_RATE, SIGN_IN_BURST);
+        if tokens < 1.0 {
+            return Err(Limited::Rate(Duration::from_secs_f64((1.0 - tokens) / SIGN_IN_RATE)));
+        }
+        Ok(())
+    }
+
+    pub fn record_failed_sign_in(&self, connection_id: i64, client: Option<IpAddr>) {
+        let mut state = self.state.lock().unwrap();
+        // An empty bucket already refuses the next attempt
+        let _ = state.take_tokens(Instant::now(), &[(BucketKey::SignIn(connection_id, client), SIGN_IN_RATE, SIGN_IN_BURST)]);
+    }
+
+    pub fn in_flight(&self, connection_id: i64) -> u32 {
+        self.state.lock().unwrap().in_flight.get(&connection_id).copied().unwrap_or(0)
+    }
+}
+
+// Counts a request as in flight for as long as it is held; keep it alive
+// until the response body has been sent
+pub struct InFlightPermit {
+    limiter: Arc<RateLimiter>,
+    connection_id: i64,
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap();
+        if let Some(count) = state.in_flight.get_mut(&self.connection_id) {
+            *count -= 1;
+            if *count == 0 {
+                state.in_flight.remove(&self.connection_id);
+            }
+        }
+    }
+}
+
+// S11.4 Responses
+// Whole seconds for `Retry-After`, rounded up
+pub fn retry_after_seconds(limited: Limited) -> u64 {
+    limited.retry_after().as_secs_f64().ceil().max(1.0) as u64
+}
+
+pub fn too_many_requests(limited: Limited) -> Response {
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(header::RETRY_AFTER, retry_after_seconds(limited))
+        .body(Body::from("Too many requests for this tunnel; try again later"))
+        .unwrap()
+}
+
+// S11.5 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_buckets_and_in_flight_cap() {
+        let limiter = Arc::new(RateLimiter::default());
+        let limits = TunnelLimits { client_rate: Some(1.0), client_burst: Some(2), max_in_flight: Some(3), ..Default::default() };
+        let alice: IpAddr = "203.0.113.1".parse().unwrap();
+        let bob: IpAddr = "203.0.113.2".parse().unwrap();
+
+        // Alice gets her burst, then has to wait about a second
+        let first = limiter.acquire(1, Some(alice), &limits).unwrap();
+        drop(limiter.acquire(1, Some(alice), &limits).unwrap());
+        match limiter.acquire(1, Some(alice), &limits) {
+            Err(Limited::Rate(wait)) => assert!(wait > Duration::from_millis(500) && wait <= Duration::from_secs(1)),
+            _ => panic!("expected the client bucket to be empty"),
+        }
+        // Other clients and tunnels have their own buckets
+        let second = limiter.acquire(1, Some(bob), &limits).unwrap();
+        let third = limiter.acquire(1, Some(bob), &limits).unwrap();
+        assert_eq!(limiter.in_flight(1), 3);
+        assert!(matches!(limiter.acquire(1, None, &limits), Err(Limited::InFlight)));
+        drop(limiter.acquire(2, Some(alice), &limits).unwrap());
+
+        drop((first, second, third));
+        assert_eq!(limiter.in_flight(1), 0);
+        assert!(limiter.acquire(1, None, &limits).is_ok());
+    }
+
+    #[test]
+    fn test_failed_sign_ins_are_limited_per_client() {
+        let limiter = RateLimiter::default();
+        let mallory: IpAddr = "203.0.113.66".parse().unwrap();
+        for _ in 0..10 {
+            assert!(limiter.check_sign_in(1, Some(mallory)).is_ok());
+            limiter.record_failed_sign_in(1, Some(mallory));
+        }
+        match limiter.check_sign_in(1, Some(mallory)) {
+            Err(Limited::Rate(wait)) => assert!(wait > Duration::from_secs(5) && wait <= Duration::from_secs(6)),
+            _ => panic!("expected further sign-ins to wait"),
+        }
+        // Other clients and tunnels are unaffected
+        assert!(limiter.check_sign_in(1, Some("203.0.113.7".parse().unwrap())).is_ok());
+        assert!(limiter.check_sign_in(2, Some(mallory)).is_ok());
+    }
+
+    #[test]
+    fn test_limits_tighten_and_parse() {
+        let tier = TunnelLimits::parse_lines("rate=100, burst=200, max_in_flight=32").unwrap();
+        let own = TunnelLimits::parse_lines("rate=10\nclient_rate=0.5").unwrap();
+        let limits = tier.tighten(&own);
+        assert_eq!(limits.rate, Some(10.0));
+        assert_eq!(limits.burst, Some(200));
+        assert_eq!(limits.client_rate, Some(0.5));
+        assert_eq!(limits.max_in_flight, Some(32));
+        assert_eq!(TunnelLimits::parse_lines(&limits.to_lines()).unwrap(), limits);
+
+        assert!(TunnelLimits::parse_lines("speed=1").is_err());
+        assert!(TunnelLimits::parse_lines("rate=fast").is_err());
+        assert_eq!(TunnelLimits::parse_lines("rate=0,burst=0").unwrap().validate().len(), 2);
+        assert_eq!(retry_after_seconds(Limited::Rate(Duration::from_millis(1200))), 2);
+    }
+}
@@ -0,0 +1,25 @@
+/**
+ * Service Modules
+ * ===============
+ *
+ * Contains background services and shared helpers used by the routes.
+ * This file is tagged for machine-readability.
+ */
+pub mod ports;
+// Only Linux has the procfs tables the check reads
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub mod ownership;
+pub mod tunnel;
+pub mod dns;
+pub mod backup;
+pub mod holesail;
+pub mod audit;
+pub mod forwarding;
+pub mod rewrite;
+pub mod limits;
+pub mod antispam;
+pub mod access;
+pub mod ip_filter;
+// Needs cmake to build; see the `pingora` feature
+#[cfg(feature = "pingora")]
+pub mod data_plane;
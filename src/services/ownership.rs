@@ -0,0 +1,145 @@
+/**
+ * S2.0 Upstream Ownership Check
+ * =============================
+ *
+ * Confirms that the socket listening on a tunnel's local port belongs to the
+ * holesail process we started. If holesail died and another local service
+ * grabbed the port, the proxy must not expose that service on a subdomain.
+ * Ownership is read from `/proc/net/tcp{,6}` (listening socket inodes) and
+ * `/proc/<pid>/fd` (the sockets a process holds).
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S2.1, S2.2, S2.3, S2.4, S2.5, S2.6
+ */
+// S2.1 Dependencies
+use std::collections::HashSet;
+use std::fs;
+
+// TCP_LISTEN as reported in the `st` column of /proc/net/tcp
+const TCP_LISTEN: &str = "0A";
+
+// S2.2 Ownership Check
+// True when `pid` (or one of its descendants, since holesail may fork a
+// worker) holds a socket listening on `port`.
+#[cfg(target_os = "linux")]
+pub fn is_port_owned_by(pid: u32, port: u16) -> bool {
+    let listening = listening_socket_inodes(port);
+    if listening.is_empty() {
+        return false;
+    }
+
+    process_tree(pid)
+        .into_iter()
+        .any(|pid| process_socket_inodes(pid).iter().any(|inode| listening.contains(inode)))
+}
+
+// Without procfs there is nothing to check against; trust the supervisor.
+#[cfg(not(target_os = "linux"))]
+pub fn is_port_owned_by(_pid: u32, _port: u16) -> bool {
+    true
+}
+
+// S2.3 Listening Sockets
+// Inodes of every socket in LISTEN state on `port`, over IPv4 and IPv6.
+fn listening_socket_inodes(port: u16) -> HashSet<u64> {
+    ["/proc/net/tcp", "/proc/net/tcp6"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|contents| parse_listening_inodes(&contents, port))
+        .collect()
+}
+
+// Parses the procfs table format:
+//   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+//    0: 0100007F:0BB8 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 12345 ...
+fn parse_listening_inodes(contents: &str, port: u16) -> HashSet<u64> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 || fields[3] != TCP_LISTEN {
+                return None;
+            }
+            let local_port = fields[1].rsplit(':').next()?;
+            if u16::from_str_radix(local_port, 16).ok()? != port {
+                return None;
+            }
+            fields[9].parse().ok().filter(|inode| *inode != 0)
+        })
+        .collect()
+}
+
+// S2.4 Process Sockets
+// Socket inodes held open by `pid`, read from the `socket:[inode]` fd links.
+fn process_socket_inodes(pid: u32) -> HashSet<u64> {
+    let Ok(entries) = fs::read_dir(format!("/proc/{}/fd", pid)) else {
+        return HashSet::new();
+    };
+
+    entries
+        .filter_map(|entry| fs::read_link(entry.ok()?.path()).ok())
+        .filter_map(|target| {
+            target
+                .to_str()?
+                .strip_prefix("socket:[")?
+                .strip_suffix(']')?
+                .parse()
+                .ok()
+        })
+        .collect()
+}
+
+// S2.5 Process Tree
+// `pid` plus all of its descendants, via /proc/<pid>/task/<tid>/children.
+fn process_tree(pid: u32) -> Vec<u32> {
+    let mut pids = vec![pid];
+    let mut i = 0;
+
+    while i < pids.len() {
+        let current = pids[i];
+        if let Ok(tasks) = fs::read_dir(format!("/proc/{}/task", current)) {
+            for task in tasks.flatten() {
+                if let Ok(children) = fs::read_to_string(task.path().join("children")) {
+                    for child in children.split_whitespace().filter_map(|c| c.parse().ok()) {
+                        if !pids.contains(&child) {
+                            pids.push(child);
+                        }
+                    }
+                }
+            }
+        }
+        i += 1;
+    }
+
+    pids
+}
+
+// S2.6 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listening_inodes() {
+        let contents = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:0BB8 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 12345 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:0BB8 0100007F:D2F0 01 00000000:00000000 00:00000000 00000000  1000        0 23456 1 0000000000000000 20 4 30 10 -1
+   2: 00000000:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 34567 1 0000000000000000 100 0 0 10 0";
+
+        assert_eq!(parse_listening_inodes(contents, 3000), HashSet::from([12345]));
+        assert_eq!(parse_listening_inodes(contents, 8080), HashSet::from([34567]));
+        assert!(parse_listening_inodes(contents, 4000).is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_is_port_owned_by_current_process() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(is_port_owned_by(std::process::id(), port));
+        drop(listener);
+        assert!(!is_port_owned_by(std::process::id(), port));
+    }
+}
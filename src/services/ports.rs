@@ -0,0 +1,190 @@
+/**
+ * S1.0 Port Allocator
+ * ===================
+ *
+ * Hands out local ports for holesail tunnels. Ports already assigned to a
+ * connection (tracked in the `connections` table) are never reused, and a
+ * candidate is only handed out if it can actually be bound on this host.
+ * Deleting a connection frees its row and therefore its port.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S1.1, S1.2, S1.3, S1.4, S1.5, S1.6
+ */
+// S1.1 Dependencies
+use rand::Rng;
+use sqlx::sqlite::SqlitePool;
+use std::collections::HashSet;
+use std::net::TcpListener;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+// S1.2 Defaults
+pub const DEFAULT_PORT_RANGE: RangeInclusive<u16> = 3001..=8000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PortAllocationError {
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("no free ports left in range {0}-{1}")]
+    Exhausted(u16, u16),
+}
+
+// S1.3 Allocator
+// Allocation is serialized so two concurrent submissions can't pick the same
+// port between the availability check and the INSERT.
+pub struct PortAllocator {
+    range: RangeInclusive<u16>,
+    lock: Arc<Mutex<()>>,
+}
+
+// A port picked by the allocator. Other allocations wait until this is
+// dropped, so keep it alive until the connection row has been written.
+pub struct PortReservation {
+    pub port: u16,
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl PortAllocator {
+    pub fn new(range: RangeInclusive<u16>) -> Self {
+        Self {
+            range,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    // Picks a port that no connection owns and that nothing else on the host
+    // is listening on. Starts at a random offset so ports aren't handed out in
+    // an easily guessable order.
+    pub async fn reserve(&self, pool: &SqlitePool) -> Result<PortReservation, PortAllocationError> {
+        let guard = self.lock.clone().lock_owned().await;
+
+        let assigned: HashSet<u16> = sqlx::query_as::<_, (i64,)>("SELECT port FROM connections")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .filter_map(|(port,)| u16::try_from(port).ok())
+            .collect();
+
+        let (start, end) = (*self.range.start(), *self.range.end());
+        let size = u32::from(end - start) + 1;
+        let offset = rand::thread_rng().gen_range(0..size);
+
+        for i in 0..size {
+            let port = start + ((offset + i) % size) as u16;
+            if !assigned.contains(&port) && is_port_bindable(port) {
+                tracing::debug!("Allocated local port {}", port);
+                return Ok(PortReservation { port, _guard: guard });
+            }
+        }
+
+        tracing::error!("❌ Port range {}-{} exhausted", start, end);
+        Err(PortAllocationError::Exhausted(start, end))
+    }
+}
+
+// S1.4 Port Probe
+// The listener is dropped immediately; we only care that the bind succeeds.
+fn is_port_bindable(port: u16) -> bool {
+    TcpListener::bind(("127.0.0.1", port)).is_ok()
+}
+
+// S1.5 Range Parsing
+// Parses ranges like "3001-8000" (used for the SANDO_PORT_RANGE env var).
+pub fn parse_port_range(value: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = value
+        .split_once('-')
+        .ok_or_else(|| format!("invalid port range '{}', expected START-END", value))?;
+    let start: u16 = start.trim().parse().map_err(|_| format!("invalid start port '{}'", start))?;
+    let end: u16 = end.trim().parse().map_err(|_| format!("invalid end port '{}'", end))?;
+
+    if start == 0 || start > end {
+        return Err(format!("invalid port range '{}'", value));
+    }
+
+    Ok(start..=end)
+}
+
+// S1.6 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn test_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    #[test]
+    fn test_parse_port_range() {
+        assert_eq!(parse_port_range("3001-8000"), Ok(3001..=8000));
+        assert_eq!(parse_port_range("4000 - 4000"), Ok(4000..=4000));
+        assert!(parse_port_range("8000-3001").is_err());
+        assert!(parse_port_range("0-10").is_err());
+        assert!(parse_port_range("3001").is_err());
+        assert!(parse_port_range("a-b").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reserve_skips_assigned_ports() {
+        let pool = test_pool().await;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let allocator = PortAllocator::new(port..=port);
+        assert_eq!(allocator.reserve(&pool).await.unwrap().port, port);
+
+        sqlx::query("INSERT INTO connections (connection_string, port, subdomain) VALUES ('key', ?, 'sub')")
+            .bind(port)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            allocator.reserve(&pool).await,
+            Err(PortAllocationError::Exhausted(_, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_ports_move_into_the_default_range() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let mut before_unique_ports = sqlx::migrate!("./migrations");
+        before_unique_ports.migrations =
+            before_unique_ports.migrations.iter().filter(|migration| migration.version < 4).cloned().collect();
+        before_unique_ports.run(&pool).await.unwrap();
+        for port in [3001, 3001, 3002, 8080, 8080] {
+            sqlx::query("INSERT INTO connections (connection_string, port) VALUES ('key', ?)")
+                .bind(port)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        let ports: Vec<(i64, i64)> =
+            sqlx::query_as("SELECT id, port FROM connections ORDER BY id").fetch_all(&pool).await.unwrap();
+        assert_eq!(ports, vec![(1, 3001), (2, 3003), (3, 3002), (4, 8080), (5, 3004)]);
+    }
+
+    #[tokio::test]
+    async fn test_reserve_skips_ports_in_use() {
+        let pool = test_pool().await;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let allocator = PortAllocator::new(port..=port);
+        assert!(allocator.reserve(&pool).await.is_err());
+    }
+}
@@ -0,0 +1,367 @@
+/**
+ * S10.0 Response Rewriting
+ * ========================
+ *
+ * Apps behind a tunnel think they run on `localhost`, so they redirect to
+ * `http://localhost:3000/...` and scope cookies to `localhost`. Depending on
+ * the connection's `ResponseRewrite` mode, this points such URLs at the
+ * public hostname the request came in on, fixes `Set-Cookie` attributes, and
+ * optionally rewrites absolute URLs in HTML, CSS and JavaScript bodies as
+ * they stream through.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S10.1, S10.2, S10.3, S10.4, S10.5, S10.6
+ */
+// S10.1 Dependencies
+use crate::models::ResponseRewrite;
+use axum::body::Bytes;
+use axum::http::{header, HeaderMap};
+
+// Hostnames an app may use for itself, with any port
+const LOCAL_HOSTS: [&str; 4] = ["localhost", "127.0.0.1", "[::1]", "0.0.0.0"];
+
+// Longest local URL prefix the body rewriter matches ("https://[::1]:65535")
+// plus the byte after it; this much is held back between chunks
+const HOLD_BACK: usize = 32;
+
+// S10.2 Rewriter
+// The public origin of one proxied request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewriter {
+    mode: ResponseRewrite,
+    origin: String, // e.g. "https://app.sando.blue"
+    host: String,   // e.g. "app.sando.blue", with the port if the request had one
+    secure: bool,
+}
+
+impl Rewriter {
+    // Reads the public origin from the forwarding headers, so call it after
+    // `services::forwarding` has set them. `None` when the connection has
+    // rewriting turned off.
+    pub fn for_request(mode: ResponseRewrite, headers: &HeaderMap) -> Option<Self> {
+        if mode == ResponseRewrite::Off {
+            return None;
+        }
+        let first = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+        let host = first("x-forwarded-host").or_else(|| first(header::HOST.as_str()))?.to_ascii_lowercase();
+        let secure = first("x-forwarded-proto").is_some_and(|proto| proto.eq_ignore_ascii_case("https"));
+        let scheme = if secure { "https" } else { "http" };
+
+        Some(Self { mode, origin: format!("{}://{}", scheme, host), host, secure })
+    }
+
+    // The rewritten value of a response header, or `None` to keep it as is
+    pub fn rewrite_header(&self, name: &str, value: &str) -> Option<String> {
+        match name.to_ascii_lowercase().as_str() {
+            "location" | "content-location" => self.rewrite_url(value.trim()),
+            "refresh" => self.rewrite_refresh(value),
+            "set-cookie" => Some(self.rewrite_cookie(value)).filter(|cookie| cookie != value),
+            _ => None,
+        }
+    }
+
+    // Whether the body is rewritten too: only in content mode, for HTML, CSS
+    // and JavaScript that isn't compressed
+    pub fn rewrites_body(&self, content_type: Option<&str>, content_encoding: Option<&str>) -> bool {
+        let content_type = content_type.unwrap_or_default().trim().to_ascii_lowercase();
+        let is_text = ["text/html", "text/css", "text/javascript", "application/javascript", "application/x-javascript"]
+            .iter()
+            .any(|prefix| content_type.starts_with(prefix));
+        let is_plain = content_encoding.is_none_or(|encoding| encoding.trim().eq_ignore_ascii_case("identity"));
+        self.mode == ResponseRewrite::Content && is_text && is_plain
+    }
+
+    // Content mode needs bodies the rewriter can read
+    pub fn needs_identity_encoding(&self) -> bool {
+        self.mode == ResponseRewrite::Content
+    }
+
+    pub fn body_rewriter(&self) -> BodyRewriter {
+        BodyRewriter {
+            origin: self.origin.clone().into_bytes(),
+            host: format!("//{}", self.host).into_bytes(),
+            pending: Vec::new(),
+            previous: None,
+        }
+    }
+
+    // S10.3 Header Rules
+    // Absolute and protocol-relative URLs on a local host; relative URLs
+    // already work on the public hostname.
+    fn rewrite_url(&self, url: &str) -> Option<String> {
+        let (has_scheme, rest) = if let Some(rest) = strip_prefix_ignore_case(url, "http://") {
+            (true, rest)
+        } else if let Some(rest) = strip_prefix_ignore_case(url, "https://") {
+            (true, rest)
+        } else {
+            (false, url.strip_prefix("//")?)
+        };
+        let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        if !is_local_authority(&rest[..authority_end]) {
+            return None;
+        }
+        let origin = if has_scheme { self.origin.clone() } else { format!("//{}", self.host) };
+        Some(format!("{}{}", origin, &rest[authority_end..]))
+    }
+
+    // `Refresh: 5; url=http://localhost:3000/next`, optionally quoted
+    fn rewrite_refresh(&self, value: &str) -> Option<String> {
+        let start = value.to_ascii_lowercase().find("url=")? + "url=".len();
+        let quoted = value[start..].starts_with(['\'', '"']);
+        let url_start = if quoted { start + 1 } else { start };
+        let url_end = if quoted {
+            value[url_start..].find(['\'', '"']).map_or(value.len(), |end| url_start + end)
+        } else {
+            value.len()
+        };
+        let url = self.rewrite_url(value[url_start..url_end].trim())?;
+        Some(format!("{}{}{}", &value[..url_start], url, &value[url_end..]))
+    }
+
+    // Drops a Domain other than the public host, so the cookie is scoped to
+    // the tunnel's own hostname rather than `localhost` or every tunnel under
+    // the base host. Secure follows the public scheme: browsers ignore Secure
+    // cookies over plain HTTP, and over HTTPS there is no reason to leave it out.
+    fn rewrite_cookie(&self, value: &str) -> String {
+        let public_host = self.host.split(':').next().unwrap_or(&self.host);
+        let mut parts = value.split(';').map(str::trim);
+        let mut cookie = vec![parts.next().unwrap_or_default().to_string()];
+        let mut has_secure = false;
+        for attribute in parts.filter(|attribute| !attribute.is_empty()) {
+            let (name, attribute_value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let name = name.trim().to_ascii_lowercase();
+            let keep = match name.as_str() {
+                "domain" => attribute_value.trim().trim_start_matches('.').eq_ignore_ascii_case(public_host),
+                "secure" => self.secure,
+                // SameSite=None is only accepted together with Secure
+                "samesite" => self.secure || !attribute_value.trim().eq_ignore_ascii_case("none"),
+                _ => true,
+            };
+            has_secure |= name == "secure";
+            if keep {
+                cookie.push(attribute.to_string());
+            }
+        }
+        if self.secure && !has_secure {
+            cookie.push("Secure".to_string());
+        }
+        cookie.join("; ")
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(value: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = value.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix).then(|| &value[prefix.len()..])
+}
+
+// `localhost`, `127.0.0.1:3000`, `[::1]:8080` and the like
+fn is_local_authority(authority: &str) -> bool {
+    let authority = authority.to_ascii_lowercase();
+    LOCAL_HOSTS.iter().any(|host| match authority.strip_prefix(host) {
+        Some("") => true,
+        Some(port) => port.strip_prefix(':').is_some_and(|port| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit())),
+        None => false,
+    })
+}
+
+// S10.4 Body Rewriting
+// Rewrites local URLs in a body that arrives in chunks. The last few bytes
+// of each chunk are held back until the next one, so a URL split across
+// chunks is still found.
+pub struct BodyRewriter {
+    origin: Vec<u8>,
+    host: Vec<u8>, // "//" followed by the public host, for protocol-relative URLs
+    pending: Vec<u8>,
+    previous: Option<u8>, // Last byte already passed on
+}
+
+impl BodyRewriter {
+    pub fn push(&mut self, chunk: &[u8]) -> Bytes {
+        self.pending.extend_from_slice(chunk);
+        self.process(false)
+    }
+
+    // Flushes whatever was held back once the body has ended
+    pub fn finish(&mut self) -> Bytes {
+        self.process(true)
+    }
+
+    fn process(&mut self, end: bool) -> Bytes {
+        let limit = if end { self.pending.len() } else { self.pending.len().saturating_sub(HOLD_BACK) };
+        let mut output = Vec::with_capacity(limit);
+        let mut i = 0;
+        while i < limit {
+            let previous = if i == 0 { self.previous } else { Some(self.pending[i - 1]) };
+            match self.match_at(i, previous, end) {
+                Some((length, has_scheme)) => {
+                    output.extend_from_slice(if has_scheme { &self.origin } else { &self.host });
+                    i += length;
+                }
+                None => {
+                    output.push(self.pending[i]);
+                    i += 1;
+                }
+            }
+        }
+        if i > 0 {
+            self.previous = Some(self.pending[i - 1]);
+        }
+        self.pending.drain(..i);
+        Bytes::from(output)
+    }
+
+    // Length of a local URL prefix (scheme and authority) starting at `i`,
+    // and whether it has a scheme
+    fn match_at(&self, i: usize, previous: Option<u8>, end: bool) -> Option<(usize, bool)> {
+        let buffer = &self.pending[i..];
+        let starts_with = |prefix: &[u8]| buffer.len() >= prefix.len() && buffer[..prefix.len()].eq_ignore_ascii_case(prefix);
+        let (scheme_length, has_scheme) = if starts_with(b"http://") {
+            (7, true)
+        } else if starts_with(b"https://") {
+            (8, true)
+        } else if starts_with(b"//") && !matches!(previous, Some(b':' | b'/')) && !previous.is_some_and(is_host_byte) {
+            (2, false)
+        } else {
+            return None;
+        };
+        if has_scheme && previous.is_some_and(is_host_byte) {
+            return None;
+        }
+
+        let authority = &buffer[scheme_length..];
+        LOCAL_HOSTS.iter().find_map(|host| {
+            let host = host.as_bytes();
+            if authority.len() < host.len() || !authority[..host.len()].eq_ignore_ascii_case(host) {
+                return None;
+            }
+            let mut length = host.len();
+            if authority.get(length) == Some(&b':') {
+                let digits = authority[length + 1..].iter().take_while(|b| b.is_ascii_digit()).count();
+                if digits == 0 || digits > 5 {
+                    return None;
+                }
+                length += 1 + digits;
+            }
+            // The name must end here, e.g. not `localhost.example.com`
+            match authority.get(length) {
+                Some(&next) if is_host_byte(next) || next == b':' => None,
+                None if !end => None,
+                _ => Some((scheme_length + length, has_scheme)),
+            }
+        })
+    }
+}
+
+fn is_host_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_')
+}
+
+// S10.5 Header Rewriting Helpers
+// Applies `rewrite_header` to every header, keeping repeated ones such as
+// Set-Cookie in order
+pub fn rewrite_headers(rewriter: &Rewriter, headers: &mut HeaderMap) {
+    let names: Vec<_> = headers.keys().cloned().collect();
+    for name in names {
+        let values: Vec<_> = headers.get_all(&name).iter().cloned().collect();
+        let rewritten: Vec<_> = values
+            .iter()
+            .map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|text| rewriter.rewrite_header(name.as_str(), text))
+                    .and_then(|text| text.parse().ok())
+                    .unwrap_or_else(|| value.clone())
+            })
+            .collect();
+        if rewritten != values {
+            headers.remove(&name);
+            for value in rewritten {
+                headers.append(name.clone(), value);
+            }
+        }
+    }
+}
+
+// S10.6 Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewriter(mode: ResponseRewrite, proto: &str) -> Rewriter {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "app.sando.blue".parse().unwrap());
+        headers.insert("x-forwarded-proto", proto.parse().unwrap());
+        Rewriter::for_request(mode, &headers).unwrap()
+    }
+
+    #[test]
+    fn test_rewrites_local_urls_in_headers() {
+        let rewriter = rewriter(ResponseRewrite::Headers, "https");
+        let rewrite = |name: &str, value: &str| rewriter.rewrite_header(name, value);
+
+        assert_eq!(rewrite("Location", "http://localhost:3000/login?next=/").as_deref(), Some("https://app.sando.blue/login?next=/"));
+        assert_eq!(rewrite("location", "http://127.0.0.1").as_deref(), Some("https://app.sando.blue"));
+        assert_eq!(rewrite("content-location", "//[::1]:8080/a").as_deref(), Some("//app.sando.blue/a"));
+        assert_eq!(rewrite("location", "/relative"), None);
+        assert_eq!(rewrite("location", "http://localhost.example.com/"), None);
+        assert_eq!(rewrite("location", "https://example.com/"), None);
+        assert_eq!(rewrite("refresh", "5; URL='http://localhost:3000/next'").as_deref(), Some("5; URL='https://app.sando.blue/next'"));
+        assert_eq!(rewrite("x-custom", "http://localhost:3000/"), None);
+        assert!(Rewriter::for_request(ResponseRewrite::Off, &HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn test_rewrites_cookie_attributes() {
+        let https = rewriter(ResponseRewrite::Headers, "https");
+        assert_eq!(
+            https.rewrite_header("set-cookie", "sid=abc; Domain=localhost; Path=/; HttpOnly").as_deref(),
+            Some("sid=abc; Path=/; HttpOnly; Secure")
+        );
+        // Other tunnels under the base host must not see the cookie
+        assert_eq!(https.rewrite_header("set-cookie", "a=1; Domain=.sando.blue; Secure").as_deref(), Some("a=1; Secure"));
+        assert_eq!(https.rewrite_header("set-cookie", "a=1; Domain=app.sando.blue; Secure"), None);
+
+        let http = rewriter(ResponseRewrite::Headers, "http");
+        assert_eq!(
+            http.rewrite_header("set-cookie", "sid=abc; Secure; SameSite=None; Path=/").as_deref(),
+            Some("sid=abc; Path=/")
+        );
+        assert_eq!(http.rewrite_header("set-cookie", "sid=abc; SameSite=Lax"), None);
+    }
+
+    #[test]
+    fn test_rewrites_bodies_across_chunks() {
+        let rewriter = rewriter(ResponseRewrite::Content, "https");
+        assert!(rewriter.rewrites_body(Some("text/html; charset=utf-8"), None));
+        assert!(!rewriter.rewrites_body(Some("text/html"), Some("gzip")));
+        assert!(!rewriter.rewrites_body(Some("image/png"), None));
+        assert!(!self::rewriter(ResponseRewrite::Headers, "https").rewrites_body(Some("text/html"), None));
+
+        let html = "<a href=\"http://localhost:3000/a\">a</a><script src=\"//127.0.0.1:3000/app.js\"></script>\
+                    <img src=\"https://cdn.example.com//localhost/x.png\"><a href=\"http://localhost.example.com/\">";
+        let expected = "<a href=\"https://app.sando.blue/a\">a</a><script src=\"//app.sando.blue/app.js\"></script>\
+                        <img src=\"https://cdn.example.com//localhost/x.png\"><a href=\"http://localhost.example.com/\">";
+        for chunk_size in [1, 3, 7, 64, html.len()] {
+            let mut body = rewriter.body_rewriter();
+            let mut output = Vec::new();
+            for chunk in html.as_bytes().chunks(chunk_size) {
+                output.extend_from_slice(&body.push(chunk));
+            }
+            output.extend_from_slice(&body.finish());
+            assert_eq!(String::from_utf8(output).unwrap(), expected, "chunk size {}", chunk_size);
+        }
+
+        let mut body = rewriter.body_rewriter();
+        let mut output = body.push(b"fetch('http://localhost:3000").to_vec();
+        output.extend_from_slice(&body.finish());
+        assert_eq!(output, b"fetch('https://app.sando.blue");
+    }
+}
@@ -0,0 +1,74 @@
+/**
+ * S3.0 Tunnel Backend
+ * ===================
+ *
+ * The seam between the web app and whatever brings tunnels up on local
+ * ports. `HolesailBackend` drives the holesail CLI (see `routes::proxy`);
+ * embedders and tests can plug in their own implementation.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: S3.1, S3.2, S3.3
+ */
+// S3.1 Dependencies
+use crate::models::TunnelStatus;
+use crate::services::holesail::lookup_key;
+use crate::routes::proxy::{
+    background_connection_status, background_tunnel_status, bring_connection_online, release_background_connection,
+};
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use std::time::Duration;
+
+// S3.2 Tunnel Backend Trait
+#[async_trait]
+pub trait TunnelBackend: Send + Sync {
+    // Makes sure `connection_string` is being served on local `port`, starting
+    // it if needed. Only returns Ok once the tunnel is confirmed online.
+    async fn bring_online(&self, connection_string: &str, port: u16) -> Result<(), StatusCode>;
+
+    // Stops the tunnel so its port can be handed out again.
+    async fn release(&self, connection_string: &str, port: u16);
+
+    // Snapshot of the tunnels this backend manages, served at /status/connections.
+    fn status(&self) -> serde_json::Value;
+
+    // State of one tunnel, used to filter the connection list. Backends that
+    // don't track individual tunnels report everything as idle.
+    fn tunnel_status(&self, _connection_string: &str, _port: u16) -> TunnelStatus {
+        TunnelStatus::Idle
+    }
+
+    // Dry run: checks that the peer behind a key can be reached, without
+    // serving it. Backends that can't tell accept every key.
+    async fn probe(&self, _connection_string: &str, _timeout: Duration) -> Result<(), StatusCode> {
+        Ok(())
+    }
+}
+
+// S3.3 Holesail Backend
+// Runs tunnels as holesail background processes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HolesailBackend;
+
+#[async_trait]
+impl TunnelBackend for HolesailBackend {
+    async fn bring_online(&self, connection_string: &str, port: u16) -> Result<(), StatusCode> {
+        bring_connection_online(connection_string, port).await
+    }
+
+    async fn release(&self, connection_string: &str, port: u16) {
+        release_background_connection(connection_string, port).await
+    }
+
+    fn status(&self) -> serde_json::Value {
+        background_connection_status()
+    }
+
+    fn tunnel_status(&self, connection_string: &str, port: u16) -> TunnelStatus {
+        background_tunnel_status(connection_string, port)
+    }
+
+    async fn probe(&self, connection_string: &str, timeout: Duration) -> Result<(), StatusCode> {
+        lookup_key(connection_string, timeout).await
+    }
+}
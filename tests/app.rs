@@ -0,0 +1,1578 @@
+/**
+ * I1.0 Embedded App Tests
+ * =======================
+ *
+ * Drives the `Router` built by `SandoBuilder` directly, with an in-memory
+ * database and a stub tunnel backend instead of holesail.
+ * This file is tagged for machine-readability.
+ *
+ * Tags: I1.1, I1.2, I1.3
+ */
+// I1.1 Dependencies
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use axum::{
+    body::{to_bytes, Body, HttpBody},
+    extract::ConnectInfo,
+    http::{header, HeaderMap, Request, StatusCode},
+    routing::{get, post},
+    Router,
+};
+use sando::{ControlPlaneLimits, SandoBuilder, StaticResolver, TunnelBackend, TunnelLimits, UpstreamTimeouts};
+use sha2::{Digest, Sha256};
+use serde_json::Value;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tower::util::ServiceExt;
+
+// I1.2 Test Helpers
+#[derive(Clone, Default)]
+struct StubTunnels {
+    offline: bool,
+    released: Arc<Mutex<Vec<(String, u16)>>>,
+}
+
+#[async_trait]
+impl TunnelBackend for StubTunnels {
+    async fn bring_online(&self, _connection_string: &str, _port: u16) -> Result<(), StatusCode> {
+        if self.offline {
+            Err(StatusCode::BAD_GATEWAY)
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn release(&self, connection_string: &str, port: u16) {
+        self.released.lock().unwrap().push((connection_string.to_string(), port));
+    }
+
+    fn status(&self) -> Value {
+        Value::Null
+    }
+
+    async fn probe(&self, _connection_string: &str, _timeout: Duration) -> Result<(), StatusCode> {
+        if self.offline {
+            Err(StatusCode::BAD_GATEWAY)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+const KEY: &str = "5f2a9c1e0b7d4e3f8a6b2c9d1e0f7a3b4c5d6e7f8091a2b3c4d5e6f708192a3b";
+
+async fn test_pool() -> SqlitePool {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sando::migrate(&pool).await.unwrap();
+    pool
+}
+
+async fn insert_connection(pool: &SqlitePool, connection_string: &str, port: u16, subdomain: &str) -> i64 {
+    let id = sqlx::query("INSERT INTO connections (connection_string, port, subdomain) VALUES (?, ?, ?)")
+        .bind(connection_string)
+        .bind(port)
+        .bind(subdomain)
+        .execute(pool)
+        .await
+        .unwrap()
+        .last_insert_rowid();
+    sqlx::query("INSERT INTO connection_hostnames (connection_id, hostname, is_canonical) VALUES (?, ?, 1)")
+        .bind(id)
+        .bind(subdomain)
+        .execute(pool)
+        .await
+        .unwrap();
+    id
+}
+
+fn app(pool: SqlitePool, tunnels: StubTunnels) -> Router {
+    SandoBuilder::new(pool).host("localhost").tunnel_backend(tunnels).build()
+}
+
+fn request(method: &str, host: &str, uri: &str, body: Option<Value>) -> Request<Body> {
+    let builder = Request::builder().method(method).uri(uri).header(header::HOST, host);
+    match body {
+        Some(body) => builder
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap(),
+        None => builder.body(Body::empty()).unwrap(),
+    }
+}
+
+async fn json_body(response: axum::response::Response) -> Value {
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+// I1.3 Tests
+#[tokio::test]
+async fn test_api_errors_share_one_shape() {
+    let app = app(test_pool().await, StubTunnels::default());
+
+    let response = app.clone().oneshot(request("GET", "localhost", "/api/v1/connections/999", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(json_body(response).await["error"]["code"], "not_found");
+
+    let response = app.clone().oneshot(request("GET", "localhost", "/api/v1/connections/abc", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(json_body(response).await["error"]["code"], "invalid_request");
+
+    let response = app.oneshot(request("GET", "localhost", "/api/v1/nope", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(json_body(response).await["error"]["code"], "not_found");
+}
+
+#[tokio::test]
+async fn test_create_without_payment_returns_402() {
+    let app = app(test_pool().await, StubTunnels::default());
+    let body = serde_json::json!({ "connection": format!("hs://s000{}", KEY), "subdomain": "my-app" });
+
+    let response = app.oneshot(request("POST", "localhost", "/api/v1/connections", Some(body))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+    let payment_request = response.headers().get("X-Cashu").unwrap().to_str().unwrap().to_string();
+    let body = json_body(response).await;
+    assert_eq!(body["error"]["code"], "payment_required");
+    assert_eq!(body["payment_request"], payment_request);
+}
+
+#[tokio::test]
+async fn test_default_subdomain_is_short_and_not_the_key() {
+    let app = app(test_pool().await, StubTunnels::default());
+    let submit = Request::builder()
+        .method("POST")
+        .uri("/submit")
+        .header(header::HOST, "localhost")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(format!("connection={}", KEY)))
+        .unwrap();
+
+    let response = app.oneshot(submit).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let page = String::from_utf8(bytes.to_vec()).unwrap();
+    // The payment page carries the picked name through to the paid submission
+    assert!(page.contains("name=\"subdomain\" value=\"tunnel-"), "{}", page);
+    assert!(page.contains("https://tunnel-"));
+    assert!(!page.contains(&format!("https://{}", KEY)));
+}
+
+#[tokio::test]
+async fn test_subdomain_lookup_errors_stop_before_payment() {
+    let pool = test_pool().await;
+    sqlx::query("DROP TABLE connection_hostnames").execute(&pool).await.unwrap();
+    let app = app(pool, StubTunnels::default());
+    let body = serde_json::json!({ "connection": format!("hs://s000{}", KEY), "subdomain": "my-app" });
+
+    // An unreadable table must not look like a free name
+    let response = app.oneshot(request("POST", "localhost", "/api/v1/connections", Some(body))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(response.headers().get("X-Cashu").is_none());
+    assert_eq!(json_body(response).await["error"]["code"], "internal_error");
+}
+
+#[tokio::test]
+async fn test_payment_requests_are_rate_limited_and_need_proof_of_work() {
+    let app = SandoBuilder::new(test_pool().await)
+        .host("localhost")
+        .admin_token("secret")
+        .tunnel_backend(StubTunnels::default())
+        .control_plane_limits(ControlPlaneLimits::parse("payment_requests=0.01/2").unwrap())
+        .proof_of_work(8)
+        .build();
+    let create = |stamp: Option<&str>| {
+        let body = serde_json::json!({ "connection": KEY, "pow": stamp });
+        let mut request = request("POST", "localhost", "/api/v1/connections", Some(body));
+        request.extensions_mut().insert(ConnectInfo("203.0.113.7:5000".parse::<SocketAddr>().unwrap()));
+        request
+    };
+    let mint = |rand: &str| {
+        let date = chrono::Utc::now().format("%y%m%d%H%M%S");
+        (0u64..)
+            .map(|counter| format!("1:8:{}:localhost::{}:{:x}", date, rand, counter))
+            .find(|stamp| {
+                let hash = Sha256::digest(stamp.as_bytes());
+                let zeros = hash.iter().position(|byte| *byte != 0).unwrap_or(hash.len());
+                zeros * 8 + hash.get(zeros).map_or(0, |byte| byte.leading_zeros() as usize) >= 8
+            })
+            .unwrap()
+    };
+
+    // No stamp: the challenge comes back instead of a payment request
+    let response = app.clone().oneshot(create(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(response.headers()["X-Sando-PoW"], "1:8:localhost");
+    assert_eq!(json_body(response).await["error"]["code"], "proof_of_work_required");
+
+    // A fresh stamp gets a payment request, until the client's burst is used up
+    let stamp = mint("b25l");
+    let response = app.clone().oneshot(create(Some(&stamp))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+    let response = app.clone().oneshot(create(Some(&mint("dHdv")))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+    assert_eq!(json_body(response).await["error"]["code"], "rate_limited");
+
+    // Admins aren't limited, and other routes have their own buckets
+    let mut admin = create(Some(&mint("dGhyZWU")));
+    admin.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    assert_eq!(app.clone().oneshot(admin).await.unwrap().status(), StatusCode::PAYMENT_REQUIRED);
+    let mut list = request("GET", "localhost", "/api/v1/connections", None);
+    list.extensions_mut().insert(ConnectInfo("203.0.113.7:5000".parse::<SocketAddr>().unwrap()));
+    assert_eq!(app.clone().oneshot(list).await.unwrap().status(), StatusCode::OK);
+
+    // Stamps are single use
+    let mut replay = create(Some(&stamp));
+    replay.extensions_mut().insert(ConnectInfo("198.51.100.2:5000".parse::<SocketAddr>().unwrap()));
+    let response = app.oneshot(replay).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_bad_keys_rejected_before_payment() {
+    let app = app(test_pool().await, StubTunnels::default());
+    let body = serde_json::json!({ "connection": "abcdef123456" });
+    let response = app.oneshot(request("POST", "localhost", "/api/v1/connections", Some(body))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(json_body(response).await["error"]["code"], "invalid_request");
+
+    // With the connect probe on, unreachable peers are refused too
+    let unreachable = StubTunnels { offline: true, ..Default::default() };
+    let app = SandoBuilder::new(test_pool().await)
+        .tunnel_backend(unreachable)
+        .connect_probe(Duration::from_secs(1))
+        .build();
+    let body = serde_json::json!({ "connection": KEY });
+    let response = app.oneshot(request("POST", "localhost", "/api/v1/connections", Some(body))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(json_body(response).await["error"]["code"], "upstream_unavailable");
+}
+
+#[tokio::test]
+async fn test_list_and_delete_connections() {
+    let pool = test_pool().await;
+    let tunnels = StubTunnels::default();
+    let app = app(pool.clone(), tunnels.clone());
+    let id = insert_connection(&pool, "abcdef123456", 4100, "my-app").await;
+
+    let response = app.clone().oneshot(request("GET", "localhost", "/api/v1/connections", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = json_body(response).await;
+    assert_eq!(body["connections"][0]["subdomain"], "my-app");
+    assert_eq!(body["connections"][0]["url"], "https://my-app.localhost");
+
+    let uri = format!("/api/v1/connections/{}", id);
+    let response = app.clone().oneshot(request("DELETE", "localhost", &uri, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(*tunnels.released.lock().unwrap(), vec![("abcdef123456".to_string(), 4100)]);
+
+    let response = app.oneshot(request("GET", "localhost", &uri, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_keys_need_the_admin_token() {
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, KEY, 4100, "my-app").await;
+    let app = SandoBuilder::new(pool).host("localhost").admin_token("secret").tunnel_backend(StubTunnels::default()).build();
+    let get = |uri: String, authorization: Option<&str>| {
+        let mut request = request("GET", "localhost", &uri, None);
+        if let Some(authorization) = authorization {
+            request.headers_mut().insert(header::AUTHORIZATION, authorization.parse().unwrap());
+        }
+        request
+    };
+
+    for authorization in [None, Some("Bearer wrong")] {
+        let list = json_body(app.clone().oneshot(get("/api/v1/connections".to_string(), authorization)).await.unwrap()).await;
+        assert_eq!(list["connections"][0]["subdomain"], "my-app");
+        assert!(list["connections"][0].get("connection_string").is_none());
+        let connection = json_body(app.clone().oneshot(get(format!("/api/v1/connections/{}", id), authorization)).await.unwrap()).await;
+        assert!(connection.get("connection_string").is_none());
+        // Searching can't spell the key out either
+        let search = json_body(app.clone().oneshot(get(format!("/api/v1/connections?q={}", &KEY[..8]), authorization)).await.unwrap()).await;
+        assert_eq!(search["connections"], serde_json::json!([]));
+    }
+
+    let connection = json_body(app.clone().oneshot(get(format!("/api/v1/connections/{}", id), Some("Bearer secret"))).await.unwrap()).await;
+    assert_eq!(connection["connection_string"], KEY);
+    let search = json_body(app.clone().oneshot(get(format!("/api/v1/connections?q={}", KEY), None)).await.unwrap()).await;
+    assert_eq!(search["connections"][0]["id"], id);
+}
+
+#[tokio::test]
+async fn test_connections_change_only_for_their_owner() {
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, KEY, 4100, "paid-app").await;
+    let app = SandoBuilder::new(pool).host("localhost").admin_token("secret").tunnel_backend(StubTunnels::default()).build();
+    let uri = format!("/api/v1/connections/{}", id);
+    let patch = |body: Value, header: Option<(&'static str, &str)>| {
+        let mut request = request("PATCH", "localhost", &uri, Some(body));
+        if let Some((name, value)) = header {
+            request.headers_mut().insert(name, value.parse().unwrap());
+        }
+        request
+    };
+
+    // Taking the subdomain over by pointing it at another key
+    let other_key = KEY.replace('5', '6');
+    let takeover = serde_json::json!({ "connection": other_key });
+    for header in [None, Some(("x-sando-key", other_key.as_str())), Some(("authorization", "Bearer wrong"))] {
+        let response = app.clone().oneshot(patch(takeover.clone(), header)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(json_body(response).await["error"]["code"], "unauthorized");
+    }
+    let rename = serde_json::json!({ "subdomain": "stolen", "label": "Mine now" });
+    assert_eq!(app.clone().oneshot(patch(rename, None)).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+    // Sending the resource back unchanged is fine
+    let resource = json_body(app.clone().oneshot(request("GET", "localhost", &uri, None)).await.unwrap()).await;
+    let response = app.clone().oneshot(patch(resource, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The owner proves it with the key, in either form holesail prints it
+    let label = serde_json::json!({ "label": "Checkout" });
+    let response = app.clone().oneshot(patch(label, Some(("x-sando-key", &format!("hs://s000{}", KEY.to_uppercase()))))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let rename = serde_json::json!({ "subdomain": "checkout" });
+    let response = app.clone().oneshot(patch(rename, Some(("authorization", "Bearer secret")))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let connection = json_body(response).await;
+    assert_eq!(connection["subdomain"], "checkout");
+    assert_eq!(connection["label"], "Checkout");
+}
+
+#[tokio::test]
+async fn test_only_operators_assign_owners() {
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, KEY, 4100, "alice").await;
+    let app = SandoBuilder::new(pool).host("localhost").admin_token("secret").tunnel_backend(StubTunnels::default()).build();
+    let with = |method: &str, uri: &str, body: Value, header: (&'static str, &str)| {
+        let mut request = request(method, "localhost", uri, Some(body));
+        request.headers_mut().insert(header.0, header.1.parse().unwrap());
+        request
+    };
+    let uri = format!("/api/v1/connections/{}", id);
+    let response = app.clone().oneshot(with("PATCH", &uri, serde_json::json!({ "owner": "alice@example.com" }), ("authorization", "Bearer secret"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Claiming alice's namespace by writing her contact into another connection
+    let other_key = KEY.replace('5', '6');
+    let claim = serde_json::json!({ "connection": other_key, "subdomain": "svc.alice", "owner": "alice@example.com" });
+    let response = app.clone().oneshot(with("POST", "/api/v1/connections", claim, ("x-cashu", "cashuAbc"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(json_body(response).await["error"]["code"], "unauthorized");
+
+    // Or into her own connection, even holding its key
+    let takeover = serde_json::json!({ "owner": "mallory@example.com" });
+    let response = app.clone().oneshot(with("PATCH", &uri, takeover.clone(), ("x-sando-key", KEY))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let web = Request::builder()
+        .method("PATCH")
+        .uri(format!("/connections/{}", id))
+        .header(header::HOST, "localhost")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("x-sando-key", KEY)
+        .body(Body::from("owner=mallory%40example.com"))
+        .unwrap();
+    let response = app.clone().oneshot(web).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Basic realm=\"Sando admin\"");
+
+    let connection = json_body(app.clone().oneshot(request("GET", "localhost", &uri, None)).await.unwrap()).await;
+    assert_eq!(connection["owner"], "alice@example.com");
+}
+
+#[tokio::test]
+async fn test_subdomain_requests_are_proxied() {
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let upstream_app = Router::new().route("/hello", get(|| async { "hello from upstream" }));
+        axum::serve(upstream, upstream_app).await.unwrap();
+    });
+
+    let pool = test_pool().await;
+    insert_connection(&pool, "abcdef123456", upstream_port, "my-app").await;
+
+    let response = app(pool.clone(), StubTunnels::default())
+        .oneshot(request("GET", "my-app.localhost:3000", "/hello", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&bytes[..], b"hello from upstream");
+
+    let offline = StubTunnels { offline: true, ..Default::default() };
+    let response = app(pool.clone(), offline)
+        .oneshot(request("GET", "my-app.localhost:3000", "/hello", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+    let response = app(pool, StubTunnels::default())
+        .oneshot(request("GET", "unknown.localhost:3000", "/hello", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_forwarding_headers_reach_the_tunnel() {
+    // Reports the forwarding headers it was sent
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let upstream_app = Router::new().route(
+            "/",
+            get(|headers: HeaderMap| async move {
+                let names = ["x-forwarded-for", "x-forwarded-proto", "x-forwarded-host", "forwarded", "via"];
+                let lines: Vec<String> = names
+                    .iter()
+                    .map(|name| format!("{}: {}", name, headers.get(*name).map_or("", |value| value.to_str().unwrap())))
+                    .collect();
+                lines.join("\n")
+            }),
+        );
+        axum::serve(upstream, upstream_app).await.unwrap();
+    });
+
+    let pool = test_pool().await;
+    insert_connection(&pool, "abcdef123456", upstream_port, "my-app").await;
+    let app = SandoBuilder::new(pool)
+        .host("localhost")
+        .tunnel_backend(StubTunnels::default())
+        .trusted_proxies(["10.0.0.0/8".parse().unwrap()])
+        .build();
+    let from = |peer: &str, headers: &[(&'static str, &str)]| {
+        let mut request = request("GET", "my-app.localhost:3000", "/", None);
+        request.extensions_mut().insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        for (name, value) in headers {
+            request.headers_mut().insert(*name, value.parse().unwrap());
+        }
+        request
+    };
+
+    // A client can't claim another address
+    let spoofed = from("203.0.113.7:50000", &[("x-forwarded-for", "1.1.1.1"), ("x-forwarded-proto", "https")]);
+    let response = app.clone().oneshot(spoofed).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(
+        std::str::from_utf8(&bytes).unwrap(),
+        "x-forwarded-for: 203.0.113.7\n\
+         x-forwarded-proto: http\n\
+         x-forwarded-host: my-app.localhost:3000\n\
+         forwarded: for=203.0.113.7;host=\"my-app.localhost:3000\";proto=http\n\
+         via: 1.1 localhost"
+    );
+
+    // A trusted load balancer's view of the client is passed on
+    let balanced = from("10.0.0.2:50000", &[("x-forwarded-for", "198.51.100.4"), ("x-forwarded-proto", "https")]);
+    let response = app.clone().oneshot(balanced).await.unwrap();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let reported = std::str::from_utf8(&bytes).unwrap();
+    assert!(reported.contains("x-forwarded-for: 198.51.100.4, 10.0.0.2\n"), "{}", reported);
+    assert!(reported.contains("x-forwarded-proto: https\n"), "{}", reported);
+
+    // A tunnel pointing back at Sando would otherwise loop forever
+    let looped = from("127.0.0.1:50000", &[("via", "1.1 localhost")]);
+    let response = app.oneshot(looped).await.unwrap();
+    assert_eq!(response.status(), StatusCode::LOOP_DETECTED);
+}
+
+#[tokio::test]
+async fn test_localhost_urls_are_rewritten() {
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let upstream_app = Router::new()
+            .route(
+                "/login",
+                get(|| async {
+                    (
+                        StatusCode::FOUND,
+                        [
+                            (header::LOCATION, "http://localhost:3000/home"),
+                            (header::SET_COOKIE, "sid=1; Domain=localhost; Path=/"),
+                        ],
+                    )
+                }),
+            )
+            .route(
+                "/page",
+                get(|| async {
+                    ([(header::CONTENT_TYPE, "text/html")], "<a href=\"http://localhost:3000/next\">next</a>")
+                }),
+            );
+        axum::serve(upstream, upstream_app).await.unwrap();
+    });
+
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, "abcdef123456", upstream_port, "my-app").await;
+    let app = app(pool, StubTunnels::default());
+
+    // Headers are rewritten by default
+    let response = app.clone().oneshot(request("GET", "my-app.localhost:3000", "/login", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(response.headers()[header::LOCATION], "http://my-app.localhost:3000/home");
+    assert_eq!(response.headers()[header::SET_COOKIE], "sid=1; Path=/");
+    let response = app.clone().oneshot(request("GET", "my-app.localhost:3000", "/page", None)).await.unwrap();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&bytes[..], b"<a href=\"http://localhost:3000/next\">next</a>");
+
+    // Page content once the connection asks for it
+    let uri = format!("/api/v1/connections/{}", id);
+    let patch = |body: Value| {
+        let mut request = request("PATCH", "localhost", &uri, Some(body));
+        request.headers_mut().insert("x-sando-key", "abcdef123456".parse().unwrap());
+        request
+    };
+    let content = serde_json::json!({ "response_rewrite": "content" });
+    let response = app.clone().oneshot(patch(content)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["response_rewrite"], "content");
+    let response = app.clone().oneshot(request("GET", "my-app.localhost:3000", "/page", None)).await.unwrap();
+    assert!(response.headers().get(header::CONTENT_LENGTH).is_none());
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&bytes[..], b"<a href=\"http://my-app.localhost:3000/next\">next</a>");
+
+    // And nothing once it is turned off
+    let off = serde_json::json!({ "response_rewrite": "off" });
+    app.clone().oneshot(patch(off)).await.unwrap();
+    let response = app.oneshot(request("GET", "my-app.localhost:3000", "/login", None)).await.unwrap();
+    assert_eq!(response.headers()[header::LOCATION], "http://localhost:3000/home");
+}
+
+#[tokio::test]
+async fn test_tunnels_are_rate_limited_per_client_and_tier() {
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        axum::serve(upstream, Router::new().route("/", get(|| async { "ok" }))).await.unwrap();
+    });
+
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, "abcdef123456", upstream_port, "my-app").await;
+    let app = SandoBuilder::new(pool)
+        .host("localhost")
+        .admin_token("secret")
+        .tunnel_backend(StubTunnels::default())
+        .tunnel_limits(TunnelLimits { client_rate: Some(0.01), client_burst: Some(2), ..Default::default() })
+        .tier("pro", TunnelLimits { rate: Some(1000.0), ..Default::default() })
+        .build();
+    let from = |client: &str| {
+        let mut request = request("GET", "my-app.localhost:3000", "/", None);
+        request.extensions_mut().insert(ConnectInfo(client.parse::<SocketAddr>().unwrap()));
+        request
+    };
+
+    // Each client gets its own burst
+    for _ in 0..2 {
+        let response = app.clone().oneshot(from("203.0.113.7:5000")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let response = app.clone().oneshot(from("203.0.113.7:5001")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers()[header::RETRY_AFTER].to_str().unwrap().parse::<u64>().unwrap() >= 1);
+    let response = app.clone().oneshot(from("198.51.100.2:5000")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Owners can only set their own limits; tiers need the admin token
+    let uri = format!("/api/v1/connections/{}", id);
+    let response = app.clone().oneshot(request("PATCH", "localhost", &uri, Some(serde_json::json!({ "tier": "pro" })))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let mut invalid = request("PATCH", "localhost", &uri, Some(serde_json::json!({ "limits": { "max_in_flight": 0 } })));
+    invalid.headers_mut().insert("x-sando-key", "abcdef123456".parse().unwrap());
+    let response = app.clone().oneshot(invalid).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let mut unknown = request("PATCH", "localhost", &uri, Some(serde_json::json!({ "tier": "gold" })));
+    unknown.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    assert_eq!(app.clone().oneshot(unknown).await.unwrap().status(), StatusCode::BAD_REQUEST);
+
+    let mut upgrade = request("PATCH", "localhost", &uri, Some(serde_json::json!({ "tier": "pro" })));
+    upgrade.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    let response = app.clone().oneshot(upgrade).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let connection = json_body(response).await;
+    assert_eq!(connection["tier"], "pro");
+    assert!(connection["limits"]["client_rate"].is_null());
+
+    // The tier has no per-client limit, so the first client is let through again
+    let response = app.clone().oneshot(from("203.0.113.7:5002")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_protected_tunnels_need_credentials_or_a_signed_link() {
+    // Reports what reached it of the credentials
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let upstream_app = Router::new().route(
+            "/",
+            get(|headers: HeaderMap| async move {
+                let header = |name: &str| headers.get(name).map_or("", |value| value.to_str().unwrap()).to_string();
+                format!("authorization: {}\ncookie: {}", header("authorization"), header("cookie"))
+            }),
+        );
+        axum::serve(upstream, upstream_app).await.unwrap();
+    });
+
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, "abcdef123456", upstream_port, "staging").await;
+    let app = SandoBuilder::new(pool).host("localhost").admin_token("secret").tunnel_backend(StubTunnels::default()).build();
+    let admin = |mut request: Request<Body>| {
+        request.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        request
+    };
+    let visit = |uri: &str, headers: &[(header::HeaderName, String)]| {
+        let mut request = request("GET", "staging.localhost:3000", uri, None);
+        for (name, value) in headers {
+            request.headers_mut().insert(name.clone(), value.parse().unwrap());
+        }
+        request
+    };
+    let basic = |user: &str, password: &str| format!("Basic {}", STANDARD.encode(format!("{}:{}", user, password)));
+
+    // Tunnels are open until they get a credential; the app's own auth passes through
+    let response = app.clone().oneshot(visit("/", &[(header::AUTHORIZATION, "Bearer app".to_string())])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(std::str::from_utf8(&bytes).unwrap(), "authorization: Bearer app\ncookie: ");
+
+    let credentials = format!("/api/v1/connections/{}/credentials", id);
+    let user = serde_json::json!({ "kind": "basic", "name": "alice", "password": "hunter2hunter2" });
+    let response = app.clone().oneshot(admin(request("POST", "localhost", &credentials, Some(user)))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert!(json_body(response).await.get("token").is_none());
+    let ci = serde_json::json!({ "kind": "token", "name": "ci" });
+    let response = app.clone().oneshot(admin(request("POST", "localhost", &credentials, Some(ci)))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let token = json_body(response).await["token"].as_str().unwrap().to_string();
+    let listed = json_body(app.clone().oneshot(admin(request("GET", "localhost", &credentials, None))).await.unwrap()).await;
+    assert_eq!(listed["credentials"].as_array().unwrap().len(), 2);
+    assert!(!listed.to_string().contains(&token));
+
+    let response = app.clone().oneshot(visit("/", &[])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Basic realm=\"staging\", charset=\"UTF-8\"");
+    let wrong = visit("/", &[(header::AUTHORIZATION, basic("alice", "hunter3hunter3"))]);
+    assert_eq!(app.clone().oneshot(wrong).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+    // Sando's credentials aren't passed on to the tunnel
+    for authorization in [basic("alice", "hunter2hunter2"), format!("Bearer {}", token)] {
+        let response = app.clone().oneshot(visit("/", &[(header::AUTHORIZATION, authorization)])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(std::str::from_utf8(&bytes).unwrap(), "authorization: \ncookie: ");
+    }
+
+    // A signed link is traded for a cookie on the first visit
+    let links = format!("/api/v1/connections/{}/links", id);
+    let form = serde_json::json!({ "expires_in": 3600, "path": "/?page=2" });
+    let response = app.clone().oneshot(admin(request("POST", "localhost", &links, Some(form)))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let link = json_body(response).await;
+    let url = link["url"].as_str().unwrap();
+    let path = url.strip_prefix("https://staging.localhost").unwrap();
+    assert!(path.starts_with("/?page=2&sando_link="), "{}", url);
+
+    let response = app.clone().oneshot(visit(path, &[])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(response.headers()[header::LOCATION], "/?page=2");
+    let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap().split(';').next().unwrap().to_string();
+    assert!(cookie.starts_with("sando_access="));
+    let tampered = path.replace("sando_link=", "sando_link=1");
+    assert_eq!(app.clone().oneshot(visit(&tampered, &[])).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+    let signed_in = || visit("/", &[(header::COOKIE, format!("theme=dark; {}", cookie))]);
+    let response = app.clone().oneshot(signed_in()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(std::str::from_utf8(&bytes).unwrap(), "authorization: \ncookie: theme=dark");
+
+    // Revoking links signs every link holder out
+    let response = app.clone().oneshot(admin(request("DELETE", "localhost", &links, None))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(app.clone().oneshot(signed_in()).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_failed_sign_ins_are_limited() {
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, "abcdef123456", 4100, "staging").await;
+    let app = SandoBuilder::new(pool).host("localhost").admin_token("secret").tunnel_backend(StubTunnels::default()).build();
+    let credentials = format!("/api/v1/connections/{}/credentials", id);
+    let user = serde_json::json!({ "kind": "basic", "name": "alice", "password": "hunter2hunter2" });
+    let mut add = request("POST", "localhost", &credentials, Some(user));
+    add.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    assert_eq!(app.clone().oneshot(add).await.unwrap().status(), StatusCode::CREATED);
+    let sign_in = |password: &str| {
+        let mut request = request("GET", "staging.localhost:3000", "/", None);
+        let authorization = format!("Basic {}", STANDARD.encode(format!("alice:{}", password)));
+        request.headers_mut().insert(header::AUTHORIZATION, authorization.parse().unwrap());
+        request
+    };
+
+    for _ in 0..10 {
+        assert_eq!(app.clone().oneshot(sign_in("guess")).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    }
+    // Further guesses aren't checked at all, right or wrong
+    let response = app.clone().oneshot(sign_in("hunter2hunter2")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+}
+
+#[tokio::test]
+async fn test_protected_keys_are_never_shown() {
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, KEY, 3001, "staging").await;
+    // An old row without a subdomain, protected by a link secret
+    let other_key = KEY.replace('5', '6');
+    sqlx::query("INSERT INTO connections (connection_string, port, link_secret) VALUES (?, 3002, 'secret')")
+        .bind(&other_key)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let app = SandoBuilder::new(pool).host("localhost").admin_token("secret").tunnel_backend(StubTunnels::default()).build();
+    let admin = |method: &str, uri: &str, body: Option<Value>| {
+        let mut request = request(method, "localhost", uri, body);
+        request.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        request
+    };
+
+    let user = serde_json::json!({ "kind": "basic", "name": "alice", "password": "hunter2hunter2" });
+    let uri = format!("/api/v1/connections/{}/credentials", id);
+    assert_eq!(app.clone().oneshot(admin("POST", &uri, Some(user))).await.unwrap().status(), StatusCode::CREATED);
+
+    // Not even with the admin token
+    let list = json_body(app.clone().oneshot(admin("GET", "/api/v1/connections", None)).await.unwrap()).await;
+    assert_eq!(list["connections"].as_array().unwrap().len(), 2);
+    for connection in list["connections"].as_array().unwrap() {
+        assert!(connection.get("connection_string").is_none());
+    }
+    let uri = format!("/api/v1/connections/{}", id);
+    let connection = json_body(app.clone().oneshot(admin("GET", &uri, None)).await.unwrap()).await;
+    assert!(connection.get("connection_string").is_none());
+
+    for uri in ["/connections".to_string(), format!("/connections/{}/edit", id), "/connections/2/edit".to_string()] {
+        let response = app.clone().oneshot(admin("GET", &uri, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let page = std::str::from_utf8(&bytes).unwrap();
+        assert!(!page.contains(KEY) && !page.contains(&other_key), "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn test_access_routes_need_the_admin_token() {
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, "abcdef123456", 3001, "staging").await;
+    let app = SandoBuilder::new(pool).host("localhost").admin_token("secret").tunnel_backend(StubTunnels::default()).build();
+
+    let user = serde_json::json!({ "kind": "basic", "name": "mallory", "password": "hunter2hunter2" });
+    let link = serde_json::json!({ "expires_in": 3600 });
+    let json_routes = [
+        ("GET", format!("/api/v1/connections/{}/credentials", id), None),
+        ("POST", format!("/api/v1/connections/{}/credentials", id), Some(user)),
+        ("DELETE", format!("/api/v1/connections/{}/credentials/1", id), None),
+        ("POST", format!("/api/v1/connections/{}/links", id), Some(link)),
+        ("DELETE", format!("/api/v1/connections/{}/links", id), None),
+    ];
+    for (method, uri, body) in json_routes {
+        for authorization in [None, Some("Bearer wrong")] {
+            let mut request = request(method, "localhost", &uri, body.clone());
+            if let Some(authorization) = authorization {
+                request.headers_mut().insert(header::AUTHORIZATION, authorization.parse().unwrap());
+            }
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+            assert_eq!(json_body(response).await["error"]["code"], "unauthorized");
+        }
+    }
+
+    let form = |method: &str, uri: String, body: &'static str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::HOST, "localhost")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    };
+    let html_routes = [
+        form("POST", format!("/connections/{}/credentials", id), "kind=basic&name=mallory&password=hunter2hunter2"),
+        form("DELETE", format!("/connections/{}/credentials/1", id), ""),
+        form("POST", format!("/connections/{}/links", id), "expires_in=3600"),
+        form("DELETE", format!("/connections/{}/links", id), ""),
+    ];
+    for request in html_routes {
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Basic realm=\"Sando admin\"");
+    }
+
+    // Nothing was added, so the tunnel is still open, and no credentials are listed
+    let response = app.clone().oneshot(request("GET", "localhost", &format!("/connections/{}/edit", id), None)).await.unwrap();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(!std::str::from_utf8(&bytes).unwrap().contains("mallory"));
+    let mut listed = request("GET", "localhost", &format!("/api/v1/connections/{}/credentials", id), None);
+    listed.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    let listed = json_body(app.clone().oneshot(listed).await.unwrap()).await;
+    assert_eq!(listed["credentials"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn test_tunnels_filter_clients_by_address() {
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        axum::serve(upstream, Router::new().route("/", get(|| async { "ok" }))).await.unwrap();
+    });
+
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, "abcdef123456", upstream_port, "my-app").await;
+    let app = SandoBuilder::new(pool)
+        .host("localhost")
+        .tunnel_backend(StubTunnels::default())
+        .admin_token("secret")
+        .trusted_proxies(["10.0.0.0/8".parse().unwrap()])
+        .ip_blocklist(["198.51.100.0/24".parse().unwrap()])
+        .build();
+    let from = |peer: &str, host: &str, forwarded_for: Option<&str>| {
+        let mut request = request("GET", host, "/", None);
+        request.extensions_mut().insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        if let Some(forwarded_for) = forwarded_for {
+            request.headers_mut().insert("x-forwarded-for", forwarded_for.parse().unwrap());
+        }
+        request
+    };
+    let status = |request: Request<Body>| {
+        let app = app.clone();
+        async move { app.oneshot(request).await.unwrap().status() }
+    };
+
+    // The global blocklist covers the control plane too
+    assert_eq!(status(from("198.51.100.9:5000", "my-app.localhost", None)).await, StatusCode::FORBIDDEN);
+    assert_eq!(status(from("198.51.100.9:5000", "localhost", None)).await, StatusCode::FORBIDDEN);
+    assert_eq!(status(from("203.0.113.7:5000", "my-app.localhost", None)).await, StatusCode::OK);
+
+    // Only the office range, minus one address
+    let uri = format!("/api/v1/connections/{}", id);
+    let patch = |body: Value| {
+        let mut request = request("PATCH", "localhost", &uri, Some(body));
+        request.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        request
+    };
+    let rules = serde_json::json!({ "ip_rules": { "allow": ["192.0.2.0/24"], "deny": ["192.0.2.66"] } });
+    let response = app.clone().oneshot(patch(rules)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let connection = json_body(response).await;
+    assert_eq!(connection["ip_rules"], serde_json::json!({ "allow": ["192.0.2.0/24"], "deny": ["192.0.2.66/32"] }));
+    let invalid = serde_json::json!({ "ip_rules": { "allow": ["192.0.2.0/99"] } });
+    let response = app.clone().oneshot(patch(invalid)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    assert_eq!(status(from("192.0.2.10:5000", "my-app.localhost", None)).await, StatusCode::OK);
+    assert_eq!(status(from("192.0.2.66:5000", "my-app.localhost", None)).await, StatusCode::FORBIDDEN);
+    assert_eq!(status(from("203.0.113.7:5000", "my-app.localhost", None)).await, StatusCode::FORBIDDEN);
+    // Behind a trusted proxy the forwarded address counts; from anyone else it's ignored
+    assert_eq!(status(from("10.0.0.2:5000", "my-app.localhost", Some("192.0.2.10"))).await, StatusCode::OK);
+    assert_eq!(status(from("10.0.0.2:5000", "my-app.localhost", Some("198.51.100.9"))).await, StatusCode::FORBIDDEN);
+    assert_eq!(status(from("203.0.113.7:5000", "my-app.localhost", Some("192.0.2.10"))).await, StatusCode::FORBIDDEN);
+
+    let response = app.clone().oneshot(request("GET", "localhost", "/status/ip-filter", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let mut admin = request("GET", "localhost", "/status/ip-filter", None);
+    admin.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    let response = app.clone().oneshot(admin).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let counts = json_body(response).await;
+    assert_eq!(counts["blocklisted"], 3);
+    assert_eq!(counts["denied"], 1);
+    assert_eq!(counts["not_allowed"], 2);
+    assert_eq!(counts["connections"][0]["id"], id);
+}
+
+#[tokio::test]
+async fn test_ip_rules_need_the_admin_token() {
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, "abcdef123456", 3001, "staging").await;
+    let app = SandoBuilder::new(pool.clone())
+        .host("localhost")
+        .admin_token("secret")
+        .tunnel_backend(StubTunnels::default())
+        .build();
+    let without_token = SandoBuilder::new(pool).host("localhost").tunnel_backend(StubTunnels::default()).build();
+
+    let uri = format!("/api/v1/connections/{}", id);
+    let api = |rules: &str, authorization: Option<&str>| {
+        let body = serde_json::json!({ "ip_rules": { "allow": [rules] } });
+        let mut request = request("PATCH", "localhost", &uri, Some(body));
+        if let Some(authorization) = authorization {
+            request.headers_mut().insert(header::AUTHORIZATION, authorization.parse().unwrap());
+        }
+        request
+    };
+    let web = |body: &'static str, authorization: Option<&str>| {
+        let mut request = Request::builder()
+            .method("PATCH")
+            .uri(format!("/connections/{}", id))
+            .header(header::HOST, "localhost")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        request.body(Body::from(body)).unwrap()
+    };
+
+    for authorization in [None, Some("Bearer wrong")] {
+        let response = app.clone().oneshot(api("192.0.2.0/24", authorization)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(json_body(response).await["error"]["code"], "unauthorized");
+
+        let response = app.clone().oneshot(web("ip_rules=allow%3D192.0.2.0%2F24", authorization)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Basic realm=\"Sando admin\"");
+    }
+    let response = without_token.clone().oneshot(api("192.0.2.0/24", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(json_body(response).await["error"]["code"], "forbidden");
+    let response = without_token.clone().oneshot(web("ip_rules=allow%3D192.0.2.0%2F24", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // The edit page always sends the rules; unchanged ones don't need the token
+    let response = app.clone().oneshot(web("label=&ip_rules=", None)).await.unwrap();
+    assert!(response.status().is_redirection());
+
+    let response = app.clone().oneshot(api("192.0.2.0/24", Some("Bearer secret"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["ip_rules"]["allow"], serde_json::json!(["192.0.2.0/24"]));
+    let response = app.clone().oneshot(web("ip_rules=allow%3D203.0.113.0%2F24", Some("Bearer secret"))).await.unwrap();
+    assert!(response.status().is_redirection());
+    let response = app.clone().oneshot(request("GET", "localhost", &uri, None)).await.unwrap();
+    let connection = json_body(response).await;
+    assert_eq!(connection["ip_rules"]["allow"], serde_json::json!(["203.0.113.0/24"]));
+}
+
+// Answers every connection by writing `chunks` with `pause` in between,
+// then keeps the connection open
+async fn scripted_upstream(chunks: Vec<&'static [u8]>, pause: Duration) -> u16 {
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = upstream.accept().await.unwrap();
+            let chunks = chunks.clone();
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                let _ = socket.read(&mut request).await.unwrap();
+                for chunk in chunks {
+                    socket.write_all(chunk).await.unwrap();
+                    tokio::time::sleep(pause).await;
+                }
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            });
+        }
+    });
+    port
+}
+
+#[tokio::test]
+async fn test_bodies_are_streamed() {
+    // Echoes uploads and serves byte ranges of a fixed file
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let upstream_app = Router::new()
+            .route("/echo", post(|body: Body| async move { body }))
+            .route(
+                "/media",
+                get(|headers: HeaderMap| async move {
+                    let range = headers[header::RANGE].to_str().unwrap().to_string();
+                    (
+                        StatusCode::PARTIAL_CONTENT,
+                        [(header::CONTENT_RANGE, "bytes 0-3/1000")],
+                        format!("{} ok", range),
+                    )
+                }),
+            );
+        axum::serve(upstream, upstream_app).await.unwrap();
+    });
+    // Sends the first chunk of a response and then stalls
+    let stalling_port = scripted_upstream(
+        vec![b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n5\r\nfirst\r\n"],
+        Duration::ZERO,
+    )
+    .await;
+
+    let pool = test_pool().await;
+    insert_connection(&pool, "abcdef123456", upstream_port, "my-app").await;
+    insert_connection(&pool, "123456abcdef", stalling_port, "slow").await;
+    let app = app(pool, StubTunnels::default());
+
+    let upload: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let echo = Request::builder()
+        .method("POST")
+        .uri("/echo")
+        .header(header::HOST, "my-app.localhost:3000")
+        .body(Body::from(upload.clone()))
+        .unwrap();
+    let response = app.clone().oneshot(echo).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(to_bytes(response.into_body(), usize::MAX).await.unwrap(), upload);
+
+    let mut media = request("GET", "my-app.localhost:3000", "/media", None);
+    media.headers_mut().insert(header::RANGE, "bytes=0-3".parse().unwrap());
+    let response = app.clone().oneshot(media).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 0-3/1000");
+    assert_eq!(&to_bytes(response.into_body(), usize::MAX).await.unwrap()[..], b"bytes=0-3 ok");
+
+    // The first chunk arrives before the upstream has finished
+    let response = app.oneshot(request("GET", "slow.localhost:3000", "/", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut body = response.into_body();
+    let frame = tokio::time::timeout(
+        Duration::from_secs(5),
+        std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)),
+    )
+    .await
+    .expect("first chunk was buffered")
+    .unwrap()
+    .unwrap();
+    assert_eq!(&frame.into_data().unwrap()[..], b"first");
+}
+
+#[tokio::test]
+async fn test_event_streams_outlive_the_first_byte_timeout() {
+    let events = scripted_upstream(
+        vec![
+            b"HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ntransfer-encoding: chunked\r\n\r\n",
+            b"b\r\ndata: one\n\n\r\n",
+            b"b\r\ndata: two\n\n\r\n",
+            b"d\r\ndata: three\n\n\r\n0\r\n\r\n",
+        ],
+        Duration::from_millis(400),
+    )
+    .await;
+    let silent = scripted_upstream(vec![], Duration::ZERO).await;
+    let stalled = scripted_upstream(
+        vec![b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n5\r\nfirst\r\n"],
+        Duration::ZERO,
+    )
+    .await;
+
+    let pool = test_pool().await;
+    insert_connection(&pool, "abcdef123456", events, "events").await;
+    insert_connection(&pool, "123456abcdef", silent, "silent").await;
+    insert_connection(&pool, "abcdef654321", stalled, "stalled").await;
+    let timeouts = UpstreamTimeouts {
+        connect: Duration::from_secs(1),
+        first_byte: Duration::from_millis(500),
+        idle: Duration::from_millis(700),
+    };
+    let app = SandoBuilder::new(pool)
+        .host("localhost")
+        .tunnel_backend(StubTunnels::default())
+        .upstream_timeouts(timeouts)
+        .build();
+
+    // Takes longer than the first-byte timeout, but never idles for long
+    let response = app.clone().oneshot(request("GET", "events.localhost:3000", "/feed", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-accel-buffering"], "no");
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"data: one\n\ndata: two\n\ndata: three\n\n");
+
+    let response = app.clone().oneshot(request("GET", "silent.localhost:3000", "/", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+    // A body that stops flowing is cut off after the idle timeout
+    let response = app.oneshot(request("GET", "stalled.localhost:3000", "/", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = tokio::time::timeout(Duration::from_secs(5), to_bytes(response.into_body(), usize::MAX)).await.unwrap();
+    assert!(body.is_err());
+}
+
+#[tokio::test]
+async fn test_aliases_proxy_or_redirect() {
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let upstream_app = Router::new().route("/hello", get(|| async { "hello from upstream" }));
+        axum::serve(upstream, upstream_app).await.unwrap();
+    });
+
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, "abcdef123456", upstream_port, "my-app").await;
+    let app = app(pool, StubTunnels::default());
+
+    let uri = format!("/api/v1/connections/{}/hostnames", id);
+    for body in [serde_json::json!({ "hostname": "old-app", "redirect": true }), serde_json::json!({ "hostname": "mirror" })] {
+        let mut add = request("POST", "localhost", &uri, Some(body));
+        add.headers_mut().insert("x-sando-key", "abcdef123456".parse().unwrap());
+        let response = app.clone().oneshot(add).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = app.clone().oneshot(request("GET", "old-app.localhost:3000", "/hello?x=1", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(response.headers()[header::LOCATION], "//my-app.localhost:3000/hello?x=1");
+
+    let response = app.clone().oneshot(request("GET", "mirror.localhost:3000", "/hello", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app.oneshot(request("GET", "localhost", &uri, None)).await.unwrap();
+    let hostnames: Vec<Value> = json_body(response).await["hostnames"].as_array().unwrap().clone();
+    let names: Vec<&str> = hostnames.iter().map(|h| h["hostname"].as_str().unwrap()).collect();
+    assert_eq!(names, vec!["my-app", "mirror", "old-app"]);
+}
+
+#[tokio::test]
+async fn test_hostnames_change_only_for_their_owner() {
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, KEY, 4100, "my-app").await;
+    let app = SandoBuilder::new(pool).host("localhost").admin_token("secret").tunnel_backend(StubTunnels::default()).build();
+    let uri = format!("/api/v1/connections/{}/hostnames", id);
+    let add = |hostname: &str, header: Option<(&'static str, &str)>| {
+        let mut request = request("POST", "localhost", &uri, Some(serde_json::json!({ "hostname": hostname })));
+        if let Some((name, value)) = header {
+            request.headers_mut().insert(name, value.parse().unwrap());
+        }
+        request
+    };
+
+    // Attaching a free alias to someone else's connection
+    let other_key = KEY.replace('5', '6');
+    for header in [None, Some(("x-sando-key", other_key.as_str())), Some(("authorization", "Bearer wrong"))] {
+        let response = app.clone().oneshot(add("free-alias", header)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(json_body(response).await["error"]["code"], "unauthorized");
+    }
+    let web = Request::builder()
+        .method("POST")
+        .uri(format!("/connections/{}/hostnames", id))
+        .header(header::HOST, "localhost")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from("hostname=free-alias"))
+        .unwrap();
+    let response = app.clone().oneshot(web).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Basic realm=\"Sando admin\"");
+
+    let response = app.clone().oneshot(add("mirror", Some(("x-sando-key", KEY)))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let alias = json_body(response).await["id"].as_i64().unwrap();
+    let response = app.clone().oneshot(add("backup", Some(("authorization", "Bearer secret")))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Promoting or removing an alias takes the same proof
+    let alias_uri = format!("{}/{}", uri, alias);
+    let promote = serde_json::json!({ "canonical": true });
+    let response = app.clone().oneshot(request("PATCH", "localhost", &alias_uri, Some(promote))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = app.clone().oneshot(request("DELETE", "localhost", &alias_uri, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let mut remove = request("DELETE", "localhost", &alias_uri, None);
+    remove.headers_mut().insert("x-sando-key", KEY.parse().unwrap());
+    assert_eq!(app.clone().oneshot(remove).await.unwrap().status(), StatusCode::NO_CONTENT);
+
+    let response = app.oneshot(request("GET", "localhost", &uri, None)).await.unwrap();
+    let hostnames: Vec<Value> = json_body(response).await["hostnames"].as_array().unwrap().clone();
+    let names: Vec<&str> = hostnames.iter().map(|h| h["hostname"].as_str().unwrap()).collect();
+    assert_eq!(names, vec!["my-app", "backup"]);
+}
+
+#[tokio::test]
+async fn test_custom_domain_routed_after_verification() {
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let upstream_app = Router::new().route("/hello", get(|| async { "hello from upstream" }));
+        axum::serve(upstream, upstream_app).await.unwrap();
+    });
+
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, "abcdef123456", upstream_port, "my-app").await;
+    let resolver = Arc::new(StaticResolver::default());
+    let app = SandoBuilder::new(pool)
+        .host("localhost")
+        .tunnel_backend(StubTunnels::default())
+        .dns_resolver(resolver.clone())
+        .build();
+
+    let uri = format!("/api/v1/connections/{}/domains", id);
+    let body = serde_json::json!({ "domain": "app.example.com" });
+    let response = app.clone().oneshot(request("POST", "localhost", &uri, Some(body))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let domain = json_body(response).await;
+    let verify_uri = format!("{}/{}/verify", uri, domain["id"]);
+
+    // Pending domains fall through to the app, which has no /hello route
+    let response = app.clone().oneshot(request("GET", "app.example.com", "/hello", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app.clone().oneshot(request("POST", "localhost", &verify_uri, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(json_body(response).await["error"]["code"], "verification_failed");
+
+    let challenge = &domain["challenge"];
+    resolver.insert_txt(
+        challenge["dns_record_name"].as_str().unwrap(),
+        challenge["dns_record_value"].as_str().unwrap(),
+    );
+    let response = app.clone().oneshot(request("POST", "localhost", &verify_uri, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["verified"], true);
+
+    let response = app.oneshot(request("GET", "app.example.com", "/hello", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&bytes[..], b"hello from upstream");
+}
+
+#[tokio::test]
+async fn test_sando_never_answers_http_challenges() {
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, "abcdef123456", 4100, "my-app").await;
+    let app = app(pool, StubTunnels::default());
+
+    // A domain already pointed at Sando would otherwise go to whoever claims it first
+    let uri = format!("/api/v1/connections/{}/domains", id);
+    let body = serde_json::json!({ "domain": "app.example.com", "method": "http" });
+    let response = app.clone().oneshot(request("POST", "localhost", &uri, Some(body))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let domain = json_body(response).await;
+    let token = domain["challenge"]["http_body"].as_str().unwrap();
+
+    let challenge = format!("/.well-known/sando-challenge/{}", token);
+    let response = app.oneshot(request("GET", "app.example.com", &challenge, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(!String::from_utf8_lossy(&bytes).contains(token));
+}
+
+#[tokio::test]
+async fn test_nested_names_and_additional_base_hosts() {
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let upstream_app = Router::new().route("/hello", get(|| async { "hello from upstream" }));
+        axum::serve(upstream, upstream_app).await.unwrap();
+    });
+
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, "abcdef123456", upstream_port, "alice").await;
+    let app = SandoBuilder::new(pool)
+        .host("localhost")
+        .additional_host("tunnels.internal")
+        .tunnel_backend(StubTunnels::default())
+        .build();
+
+    let uri = format!("/api/v1/connections/{}/hostnames", id);
+    let mut add = request("POST", "localhost", &uri, Some(serde_json::json!({ "hostname": "svc.alice" })));
+    add.headers_mut().insert("x-sando-key", "abcdef123456".parse().unwrap());
+    let response = app.clone().oneshot(add).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    for host in ["svc.alice.localhost:3000", "svc.alice.tunnels.internal", "alice.tunnels.internal"] {
+        let response = app.clone().oneshot(request("GET", host, "/hello", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{}", host);
+    }
+    // Deeper hosts don't fall back to their parent
+    let response = app.clone().oneshot(request("GET", "x.svc.alice.localhost", "/hello", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Both base hosts serve the app itself
+    let response = app.oneshot(request("GET", "tunnels.internal", "/api/v1/connections", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_export_and_import_need_admin_token() {
+    let source_pool = test_pool().await;
+    insert_connection(&source_pool, "abcdef123456", 4100, "my-app").await;
+    let source = SandoBuilder::new(source_pool)
+        .host("localhost")
+        .admin_token("secret")
+        .tunnel_backend(StubTunnels::default())
+        .build();
+
+    // Disabled without a configured token, refused with a wrong one
+    let unconfigured = app(test_pool().await, StubTunnels::default());
+    let response = unconfigured.oneshot(request("GET", "localhost", "/api/v1/export", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let mut wrong_token = request("GET", "localhost", "/api/v1/export", None);
+    wrong_token.headers_mut().insert(header::AUTHORIZATION, "Bearer nope".parse().unwrap());
+    let response = source.clone().oneshot(wrong_token).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut export = request("GET", "localhost", "/api/v1/export", None);
+    export.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    export.headers_mut().insert("X-Sando-Passphrase", "correct horse".parse().unwrap());
+    let response = source.oneshot(export).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let document = json_body(response).await;
+    assert!(document["connections"][0]["connection_string"].as_str().unwrap().starts_with("sando-enc1:"));
+
+    let target_pool = test_pool().await;
+    let target = SandoBuilder::new(target_pool.clone())
+        .host("localhost")
+        .admin_token("secret")
+        .tunnel_backend(StubTunnels::default())
+        .build();
+    let mut import = request("POST", "localhost", "/api/v1/import", Some(document));
+    import.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    import.headers_mut().insert("X-Sando-Passphrase", "correct horse".parse().unwrap());
+    let response = target.oneshot(import).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["created"], serde_json::json!(["my-app"]));
+
+    let key: String = sqlx::query_scalar("SELECT connection_string FROM connections WHERE subdomain = 'my-app'")
+        .fetch_one(&target_pool)
+        .await
+        .unwrap();
+    assert_eq!(key, "abcdef123456");
+}
+
+#[tokio::test]
+async fn test_changes_are_audited() {
+    let pool = test_pool().await;
+    let app = SandoBuilder::new(pool.clone())
+        .host("localhost")
+        .admin_token("secret")
+        .tunnel_backend(StubTunnels::default())
+        .build();
+    let id = insert_connection(&pool, "abcdef123456", 4100, "my-app").await;
+
+    let uri = format!("/api/v1/connections/{}", id);
+    let mut rename = request("PATCH", "localhost", &uri, Some(serde_json::json!({ "subdomain": "renamed" })));
+    rename.headers_mut().insert("x-sando-key", "abcdef123456".parse().unwrap());
+    let response = app.clone().oneshot(rename).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let batch_delete = Request::builder()
+        .method("POST")
+        .uri("/connections/batch-delete")
+        .header(header::HOST, "localhost")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(format!("connection_ids={}", id)))
+        .unwrap();
+    app.clone().oneshot(batch_delete).await.unwrap();
+
+    let mut audit = request("GET", "localhost", "/api/v1/audit", None);
+    audit.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    let response = app.clone().oneshot(audit).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let entries = json_body(response).await["entries"].clone();
+    assert_eq!(entries[0]["action"], "batch_delete");
+    assert_eq!(entries[0]["actor"], "web");
+    assert_eq!(entries[0]["subdomain"], "renamed");
+    assert_eq!(entries[1]["action"], "update");
+    assert_eq!(entries[1]["actor"], "api");
+    assert_eq!(entries[1]["details"]["previous_subdomain"], "my-app");
+
+    // The admin page asks browsers for the token
+    let response = app.oneshot(request("GET", "localhost", "/admin/audit", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+}
+
+#[tokio::test]
+async fn test_websockets_are_relayed() {
+    // Accepts any upgrade and echoes what it receives
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = upstream.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut head = [0u8; 4096];
+                let read = socket.read(&mut head).await.unwrap();
+                let head = String::from_utf8_lossy(&head[..read]).to_lowercase();
+                if !head.contains("upgrade: websocket") || !head.contains("host: localhost:") {
+                    socket.write_all(b"HTTP/1.1 400 Bad Request\r\ncontent-length: 6\r\n\r\nno way").await.unwrap();
+                    return;
+                }
+                socket
+                    .write_all(b"HTTP/1.1 101 Switching Protocols\r\nupgrade: websocket\r\nconnection: upgrade\r\nsec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\nhi")
+                    .await
+                    .unwrap();
+                let mut buffer = [0u8; 1024];
+                loop {
+                    match socket.read(&mut buffer).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(read) => socket.write_all(&buffer[..read]).await.unwrap(),
+                    }
+                }
+            });
+        }
+    });
+
+    let pool = test_pool().await;
+    insert_connection(&pool, "abcdef123456", upstream_port, "chat").await;
+    let sando = SandoBuilder::new(pool)
+        .host("localhost")
+        .tunnel_backend(StubTunnels::default())
+        .websocket_idle_timeout(Duration::from_millis(500))
+        .tunnel_limits(TunnelLimits { max_in_flight: Some(1), ..Default::default() })
+        .build();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let sando_port = listener.local_addr().unwrap().port();
+    tokio::spawn({
+        let sando = sando.clone();
+        async move { axum::serve(listener, sando).await.unwrap() }
+    });
+
+    let mut client = tokio::net::TcpStream::connect(("127.0.0.1", sando_port)).await.unwrap();
+    let handshake = format!(
+        "GET /socket HTTP/1.1\r\nhost: chat.localhost:{}\r\nconnection: Upgrade\r\nupgrade: websocket\r\nsec-websocket-version: 13\r\nsec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+        sando_port
+    );
+    client.write_all(handshake.as_bytes()).await.unwrap();
+
+    // The 101 head, then the bytes the upstream sent right after it
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !received.ends_with(b"\r\n\r\nhi") {
+        let read = client.read(&mut buffer).await.unwrap();
+        assert!(read > 0, "closed early: {}", String::from_utf8_lossy(&received));
+        received.extend_from_slice(&buffer[..read]);
+    }
+    let head = String::from_utf8_lossy(&received).to_lowercase();
+    assert!(head.starts_with("http/1.1 101"));
+    assert!(head.contains("sec-websocket-accept: s3pplmbitxaq9kygzzhzrbk+xoo="));
+
+    client.write_all(b"ping").await.unwrap();
+    let read = client.read(&mut buffer).await.unwrap();
+    assert_eq!(&buffer[..read], b"ping");
+
+    // The open socket takes the tunnel's only in-flight slot
+    let response = sando.clone().oneshot(request("GET", "chat.localhost", "/", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Silent connections are closed after the idle timeout, freeing the slot
+    let read = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buffer)).await.unwrap().unwrap();
+    assert_eq!(read, 0);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let response = sando.oneshot(request("GET", "chat.localhost", "/", None)).await.unwrap();
+    assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn test_websocket_status_needs_the_admin_token() {
+    let app = SandoBuilder::new(test_pool().await)
+        .host("localhost")
+        .admin_token("secret")
+        .tunnel_backend(StubTunnels::default())
+        .build();
+
+    let response = app.clone().oneshot(request("GET", "localhost", "/status/websockets", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut admin = request("GET", "localhost", "/status/websockets", None);
+    admin.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    let response = app.oneshot(admin).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(json_body(response).await["open"].is_u64());
+}
+
+#[tokio::test]
+async fn test_resolve_proxy_target_for_other_data_planes() {
+    use sando::routes::proxy::{resolve_proxy_target, ProxyTarget};
+
+    let pool = test_pool().await;
+    let id = insert_connection(&pool, "abcdef123456", 4100, "my-app").await;
+    sqlx::query("INSERT INTO connection_hostnames (connection_id, hostname, redirect) VALUES (?, 'old-app', 1)")
+        .bind(id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO custom_domains (connection_id, domain, token, verified_at) VALUES (?, 'app.example.com', 't', CURRENT_TIMESTAMP)")
+        .bind(id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let state = SandoBuilder::new(pool).host("localhost").build_state();
+    let uri = "/docs?page=2".parse().unwrap();
+
+    assert!(resolve_proxy_target(&state, "localhost:3000", &uri).await.unwrap().is_none());
+    assert!(resolve_proxy_target(&state, "pending.example.org", &uri).await.unwrap().is_none());
+    assert!(matches!(
+        resolve_proxy_target(&state, "my-app.localhost:3000", &uri).await.unwrap(),
+        Some(ProxyTarget::Tunnel(connection)) if connection.id == id
+    ));
+    assert!(matches!(
+        resolve_proxy_target(&state, "APP.example.com", &uri).await.unwrap(),
+        Some(ProxyTarget::Tunnel(connection)) if connection.id == id
+    ));
+    assert!(matches!(
+        resolve_proxy_target(&state, "old-app.localhost:3000", &uri).await.unwrap(),
+        Some(ProxyTarget::Redirect { connection, location }) if connection.id == id && location == "//my-app.localhost:3000/docs?page=2"
+    ));
+    assert!(matches!(
+        resolve_proxy_target(&state, "unknown.localhost", &uri).await,
+        Err(StatusCode::NOT_FOUND)
+    ));
+}
+
+#[tokio::test]
+async fn test_admit_tunnel_request_for_other_data_planes() {
+    use sando::routes::proxy::{admit_redirect, admit_tunnel_request, resolve_proxy_target, Admission, ProxyTarget};
+
+    let pool = test_pool().await;
+    insert_connection(&pool, "abcdef123456", 4100, "my-app").await;
+    let state = SandoBuilder::new(pool)
+        .host("localhost")
+        .tunnel_backend(StubTunnels::default())
+        .ip_blocklist(["198.51.100.0/24".parse().unwrap()])
+        .tunnel_limits(TunnelLimits { max_in_flight: Some(1), ..Default::default() })
+        .build_state();
+    let uri = "/".parse().unwrap();
+    let Some(ProxyTarget::Tunnel(connection)) = resolve_proxy_target(&state, "my-app.localhost", &uri).await.unwrap() else {
+        panic!("my-app should resolve to its tunnel");
+    };
+    let client = Some("203.0.113.7".parse().unwrap());
+    let status = |admission: Admission| match admission {
+        Admission::Proxy { .. } => StatusCode::OK,
+        Admission::Refuse(response) => response.status(),
+    };
+
+    let admitted = admit_tunnel_request(&state, &connection, "my-app", &HeaderMap::new(), &uri, client).await.unwrap();
+    assert!(matches!(admitted, Admission::Proxy { strip_authorization: false, .. }));
+    // The permit above holds the only in-flight slot until it is dropped
+    let limited = admit_tunnel_request(&state, &connection, "my-app", &HeaderMap::new(), &uri, client).await.unwrap();
+    assert_eq!(status(limited), StatusCode::TOO_MANY_REQUESTS);
+    drop(admitted);
+
+    let blocked = Some("198.51.100.9".parse().unwrap());
+    let refused = admit_tunnel_request(&state, &connection, "my-app", &HeaderMap::new(), &uri, blocked).await.unwrap();
+    assert_eq!(status(refused), StatusCode::FORBIDDEN);
+    let mut looped = HeaderMap::new();
+    looped.insert(header::VIA, "1.1 localhost".parse().unwrap());
+    let result = admit_tunnel_request(&state, &connection, "my-app", &looped, &uri, client).await;
+    assert!(matches!(result, Err(StatusCode::LOOP_DETECTED)));
+    let admitted = admit_tunnel_request(&state, &connection, "my-app", &HeaderMap::new(), &uri, client).await.unwrap();
+    assert_eq!(status(admitted), StatusCode::OK);
+
+    // Alias redirects never reach the tunnel, but are filtered the same way
+    sqlx::query("INSERT INTO connection_hostnames (connection_id, hostname, redirect) VALUES (?, 'old-app', 1)")
+        .bind(connection.id)
+        .execute(state.pool.as_ref())
+        .await
+        .unwrap();
+    let Some(ProxyTarget::Redirect { connection, .. }) = resolve_proxy_target(&state, "old-app.localhost", &uri).await.unwrap() else {
+        panic!("old-app should redirect");
+    };
+    assert!(admit_redirect(&state, &connection, "old-app", &HeaderMap::new(), client).unwrap().is_none());
+    let refusal = admit_redirect(&state, &connection, "old-app", &HeaderMap::new(), blocked).unwrap().unwrap();
+    assert_eq!(refusal.status(), StatusCode::FORBIDDEN);
+}